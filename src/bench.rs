@@ -0,0 +1,111 @@
+//! Timing statistics for the `bench` form and `kk bench`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::value::Value;
+
+/// The timings of one `(bench ...)` form, in nanoseconds per iteration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub name: String,
+    /// Measured iterations, not counting warmup.
+    pub iterations: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl BenchResult {
+    /// Summarizes the measured durations of each iteration.
+    pub(crate) fn from_samples(name: &str, mut samples: Vec<f64>) -> BenchResult {
+        samples.sort_by(f64::total_cmp);
+
+        let count = samples.len().max(1) as f64;
+        let mean = samples.iter().sum::<f64>() / count;
+        let variance = samples
+            .iter()
+            .map(|sample| (sample - mean).powi(2))
+            .sum::<f64>()
+            / count;
+
+        let middle = samples.len() / 2;
+        let median = match samples.len() {
+            0 => 0.0,
+            len if len % 2 == 0 => (samples[middle - 1] + samples[middle]) / 2.0,
+            _ => samples[middle],
+        };
+
+        BenchResult {
+            name: name.to_string(),
+            iterations: samples.len(),
+            mean,
+            median,
+            stddev: variance.sqrt(),
+            min: samples.first().copied().unwrap_or_default(),
+            max: samples.last().copied().unwrap_or_default(),
+        }
+    }
+
+    /// The map returned by `bench`.
+    pub(crate) fn to_value(&self) -> Value {
+        Value::Map(Rc::new(BTreeMap::from([
+            ("name".to_string(), Value::String(self.name.as_str().into())),
+            ("iters".to_string(), Value::Int(self.iterations as i64)),
+            ("mean".to_string(), Value::Float(self.mean)),
+            ("median".to_string(), Value::Float(self.median)),
+            ("stddev".to_string(), Value::Float(self.stddev)),
+            ("min".to_string(), Value::Float(self.min)),
+            ("max".to_string(), Value::Float(self.max)),
+        ])))
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ± {} (median {}, {} iterations)",
+            self.name,
+            Nanos(self.mean),
+            Nanos(self.stddev),
+            Nanos(self.median),
+            self.iterations
+        )
+    }
+}
+
+/// A duration in nanoseconds, shown in the largest fitting unit.
+struct Nanos(f64);
+
+impl fmt::Display for Nanos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            ns if ns >= 1e9 => write!(f, "{:.2} s", ns / 1e9),
+            ns if ns >= 1e6 => write!(f, "{:.2} ms", ns / 1e6),
+            ns if ns >= 1e3 => write!(f, "{:.2} µs", ns / 1e3),
+            ns => write!(f, "{:.0} ns", ns),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_statistics() {
+        let result = BenchResult::from_samples("sum", vec![4.0, 1.0, 3.0, 2000.0]);
+
+        assert_eq!(result.mean, 502.0);
+        assert_eq!(result.median, 3.5);
+        assert_eq!((result.min, result.max), (1.0, 2000.0));
+        assert_eq!(
+            result.to_string(),
+            "sum: 502 ns ± 865 ns (median 4 ns, 4 iterations)"
+        );
+    }
+}
@@ -0,0 +1,427 @@
+//! Arbitrary-precision integers, for the ints that don't fit an `i64`. Int arithmetic
+//! promotes its result to a `BigInt` when it overflows, and results that fit again go back
+//! to `Value::Int`, so a `Value::BigInt` always holds an int outside the `i64` range.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// A signed integer of any size.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    /// The magnitude in base 2^32, least significant digit first, without leading zeros.
+    /// Zero has no digits and is never negative.
+    digits: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(negative: bool, mut digits: Vec<u32>) -> BigInt {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+
+        BigInt {
+            negative: negative && !digits.is_empty(),
+            digits,
+        }
+    }
+
+    /// Parses an optionally signed run of decimal digits.
+    pub fn parse(text: &str) -> Option<BigInt> {
+        BigInt::parse_radix(text, 10)
+    }
+
+    /// Parses an optionally signed run of digits in `radix`, from 2 to 36.
+    pub fn parse_radix(text: &str, radix: u32) -> Option<BigInt> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+
+        if digits.is_empty() {
+            return None;
+        }
+
+        let mut magnitude = vec![];
+
+        for char in digits.chars() {
+            mul_add_small(&mut magnitude, radix, char.to_digit(radix)?);
+        }
+
+        Some(BigInt::new(negative, magnitude))
+    }
+
+    /// The float truncated toward zero, exactly. `None` for NaN and the infinities.
+    pub fn from_f64(value: f64) -> Option<BigInt> {
+        if !value.is_finite() {
+            return None;
+        }
+
+        let value = value.trunc();
+
+        // Floats of at least 2^63 are whole: their mantissa shifted left by the exponent
+        if value.abs() < 9_223_372_036_854_775_808.0 {
+            return Some(BigInt::from(value as i64));
+        }
+
+        let bits = value.to_bits();
+        let mantissa = bits & ((1 << 52) - 1) | 1 << 52;
+        let exponent = ((bits >> 52) & 0x7ff) as u32 - 1075;
+
+        let magnitude = &BigInt::from(mantissa as i64) * &BigInt::from(2).pow(exponent);
+
+        Some(BigInt::new(value < 0.0, magnitude.digits))
+    }
+
+    /// The number of bits of the magnitude.
+    pub fn bits(&self) -> u64 {
+        match self.digits.last() {
+            Some(last) => self.digits.len() as u64 * 32 - last.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::new(false, self.digits.clone())
+    }
+
+    /// The value as an `i64`, when it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .fold(0u64, |magnitude, digit| magnitude << 32 | *digit as u64);
+
+        match self.negative {
+            true => 0i64.checked_sub_unsigned(magnitude),
+            false => i64::try_from(magnitude).ok(),
+        }
+    }
+
+    /// The nearest float, or an infinity when the value is beyond the float range.
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self.digits.iter().rev().fold(0.0, |magnitude, digit| {
+            magnitude * 4_294_967_296.0 + *digit as f64
+        });
+
+        match self.negative {
+            true => -magnitude,
+            false => magnitude,
+        }
+    }
+
+    /// The quotient truncated toward zero and the remainder, which has the sign of `self`,
+    /// like `/` and `%` on Rust ints. `None` when `divisor` is zero.
+    pub fn div_rem(&self, divisor: &BigInt) -> Option<(BigInt, BigInt)> {
+        if divisor.is_zero() {
+            return None;
+        }
+
+        let (quotient, remainder) = div_rem_magnitudes(&self.digits, &divisor.digits);
+
+        Some((
+            BigInt::new(self.negative != divisor.negative, quotient),
+            BigInt::new(self.negative, remainder),
+        ))
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> BigInt {
+        let mut result = BigInt::from(1);
+        let mut base = self.clone();
+
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+
+            exponent >>= 1;
+
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+
+        result
+    }
+
+    /// The digits of the magnitude in `radix`, from 2 to 36, in lowercase and without a
+    /// sign.
+    pub fn magnitude_radix(&self, radix: u32) -> String {
+        if self.is_zero() {
+            return "0".to_string();
+        }
+
+        let mut magnitude = self.digits.clone();
+        let mut digits = vec![];
+
+        while !magnitude.is_empty() {
+            let digit = div_rem_small(&mut magnitude, radix);
+            digits.extend(char::from_digit(digit, radix));
+        }
+
+        digits.iter().rev().collect()
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(value: i64) -> BigInt {
+        let magnitude = value.unsigned_abs();
+        BigInt::new(value < 0, vec![magnitude as u32, (magnitude >> 32) as u32])
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.digits, &other.digits),
+            (true, true) => compare_magnitudes(&other.digits, &self.digits),
+        }
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.digits.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_magnitudes(&self.digits, &other.digits));
+        }
+
+        // Opposite signs: the smaller magnitude is taken from the larger one
+        match compare_magnitudes(&self.digits, &other.digits) {
+            Ordering::Less => {
+                BigInt::new(other.negative, sub_magnitudes(&other.digits, &self.digits))
+            }
+            _ => BigInt::new(self.negative, sub_magnitudes(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        let mut product = vec![0u32; self.digits.len() + other.digits.len()];
+
+        for (i, a) in self.digits.iter().enumerate() {
+            let mut carry = 0u64;
+
+            for (j, b) in other.digits.iter().enumerate() {
+                let digit = product[i + j] as u64 + *a as u64 * *b as u64 + carry;
+                product[i + j] = digit as u32;
+                carry = digit >> 32;
+            }
+
+            product[i + other.digits.len()] = carry as u32;
+        }
+
+        BigInt::new(self.negative != other.negative, product)
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return f.write_str("0");
+        }
+
+        // Split into chunks of nine decimal digits, least significant first
+        let mut magnitude = self.digits.clone();
+        let mut chunks = vec![];
+
+        while !magnitude.is_empty() {
+            chunks.push(div_rem_small(&mut magnitude, 1_000_000_000));
+        }
+
+        if self.negative {
+            f.write_str("-")?;
+        }
+
+        let mut chunks = chunks.iter().rev();
+
+        if let Some(first) = chunks.next() {
+            write!(f, "{}", first)?;
+        }
+
+        chunks.try_for_each(|chunk| write!(f, "{:09}", chunk))
+    }
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+
+    for (i, digit) in long.iter().enumerate() {
+        let digit = *digit as u64 + short.get(i).copied().unwrap_or(0) as u64 + carry;
+        sum.push(digit as u32);
+        carry = digit >> 32;
+    }
+
+    sum.push(carry as u32);
+    sum
+}
+
+/// `a - b`, where `a` is at least `b`.
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+
+    for (i, digit) in a.iter().enumerate() {
+        let mut digit = *digit as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        borrow = (digit < 0) as i64;
+        digit += borrow << 32;
+        difference.push(digit as u32);
+    }
+
+    difference
+}
+
+/// `magnitude * factor + addend`, in place.
+fn mul_add_small(magnitude: &mut Vec<u32>, factor: u32, addend: u32) {
+    let mut carry = addend as u64;
+
+    for digit in magnitude.iter_mut() {
+        let product = *digit as u64 * factor as u64 + carry;
+        *digit = product as u32;
+        carry = product >> 32;
+    }
+
+    if carry > 0 {
+        magnitude.push(carry as u32);
+    }
+}
+
+/// Divides `magnitude` by `divisor` in place, returning the remainder.
+fn div_rem_small(magnitude: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+
+    for digit in magnitude.iter_mut().rev() {
+        let current = remainder << 32 | *digit as u64;
+        *digit = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+
+    remainder as u32
+}
+
+/// Long division one bit at a time, which is plenty for the sizes scripts reach.
+fn div_rem_magnitudes(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [divisor] = b {
+        let mut quotient = a.to_vec();
+        let remainder = div_rem_small(&mut quotient, *divisor);
+        return (quotient, vec![remainder]);
+    }
+
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = vec![];
+
+    for bit in (0..a.len() * 32).rev() {
+        mul_add_small(&mut remainder, 2, a[bit / 32] >> (bit % 32) & 1);
+
+        if compare_magnitudes(&remainder, b) != Ordering::Less {
+            remainder = sub_magnitudes(&remainder, b);
+
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+
+    (quotient, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        let big = |text: &str| BigInt::parse(text).unwrap();
+
+        let max = BigInt::from(i64::MAX);
+        assert_eq!((&max + &BigInt::from(1)).to_string(), "9223372036854775808");
+        assert_eq!((&max + &BigInt::from(1)).to_i64(), None);
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(
+            (-&BigInt::from(i64::MIN)).to_string(),
+            "9223372036854775808"
+        );
+
+        let a = big("-123456789012345678901234567890");
+        let b = big("987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "-1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        assert_eq!(
+            BigInt::from(3).pow(100).to_string(),
+            "515377520732011331036461129765621272702107522001"
+        );
+
+        let (quotient, remainder) = b.div_rem(&a).unwrap();
+        assert_eq!(
+            (quotient.to_string(), remainder.to_string()),
+            ("-8".to_string(), "9000000000900000000090".to_string())
+        );
+        assert_eq!(&(&quotient * &a) + &remainder, b);
+        assert!(b.div_rem(&BigInt::from(0)).is_none());
+
+        assert!(a < b && a < BigInt::from(0) && b > max);
+        assert_eq!(big("+0"), big("-0"));
+        assert!(BigInt::parse("1e5").is_none());
+        assert_eq!(b.to_f64(), 9.876543210987654e29);
+    }
+}
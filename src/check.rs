@@ -0,0 +1,843 @@
+//! `kk check`: errors found by walking the syntax tree without running it. Where `lint`
+//! warns about code that runs but may misbehave, every problem reported here fails at
+//! runtime once the form is reached: malformed special forms, calls with the wrong number
+//! of arguments, names that are never defined and literals of the wrong type.
+//!
+//! `check` looks at one file and trusts its imports to define any name. `analyze` follows
+//! the imports of a script through every module it loads, so that names and arities
+//! coming from modules are checked too.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::interpreter::{is_special_form, Interpreter};
+use crate::package;
+use crate::parser::{self, Parser};
+use crate::sexpr::{SExpr, Span};
+use crate::value::Value;
+
+/// An error found without running the code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub code: ErrorCode,
+    pub message: String,
+    pub span: Span,
+}
+
+/// The arguments a script function accepts: at least `required`, at most `max` unless
+/// it takes `&rest`.
+#[derive(Clone, Copy)]
+struct Arity {
+    required: usize,
+    max: Option<usize>,
+}
+
+/// The names a module makes visible to the scripts importing it.
+#[derive(Clone, Default)]
+struct Exports {
+    names: HashSet<String>,
+    functions: HashMap<String, Option<Arity>>,
+    /// The module couldn't be read or imports modules that couldn't, so any name may be
+    /// among its exports.
+    open: bool,
+}
+
+/// The modules of a program, each analyzed once.
+#[derive(Default)]
+struct Program {
+    /// By canonical path; `None` while the module is being analyzed, which lets cyclic
+    /// imports through `import-lazy` end.
+    modules: HashMap<PathBuf, Option<Exports>>,
+    problems: Vec<(PathBuf, Problem)>,
+}
+
+struct Checker<'a> {
+    /// Only asked for its builtins.
+    builtins: Interpreter,
+    globals: HashSet<String>,
+    /// Functions defined once at the top level; `None` for names defined several times.
+    functions: HashMap<String, Option<Arity>>,
+    /// Set by imports that may define any name, which then can't be reported as undefined.
+    open: bool,
+    /// Aliases of imports whose qualified names, `alias/name`, can't be checked.
+    open_aliases: HashSet<String>,
+    /// Followed by imports when analyzing a whole program; `None` when checking one file.
+    program: Option<&'a mut Program>,
+    /// Where the file's imports are resolved from.
+    base_dir: PathBuf,
+    /// The names of the file's `export` forms, `None` when it has none.
+    exports: Option<Vec<(String, Span)>>,
+    problems: Vec<Problem>,
+}
+
+/// Checks every form of a file, reporting all problems rather than stopping at the first.
+pub fn check(sexprs: &[SExpr]) -> Vec<Problem> {
+    let mut checker = Checker::new(None, PathBuf::new());
+    checker.run(sexprs);
+    checker.problems
+}
+
+/// Checks the script at `path` and every module it imports, with the names and arities
+/// each module exports known to its importers. Problems come with the file they are in,
+/// modules before the scripts importing them.
+pub fn analyze(path: &Path) -> Vec<(PathBuf, Problem)> {
+    let mut program = Program::default();
+    program.analyze(path, false);
+    program.problems
+}
+
+impl Program {
+    /// Analyzes the file at `path` unless it already was. `module` is unset for the
+    /// script the program starts from, where `export` forms do nothing.
+    fn analyze(&mut self, path: &Path, module: bool) -> Exports {
+        let key = std::fs::canonicalize(path).unwrap_or(path.to_path_buf());
+
+        match self.modules.get(&key) {
+            Some(Some(exports)) => return exports.clone(),
+            Some(None) => {
+                return Exports {
+                    open: true,
+                    ..Exports::default()
+                }
+            }
+            None => {}
+        }
+
+        self.modules.insert(key.clone(), None);
+        let exports = self.analyze_file(path, module);
+        self.modules.insert(key, Some(exports.clone()));
+
+        exports
+    }
+
+    fn analyze_file(&mut self, path: &Path, module: bool) -> Exports {
+        let open = Exports {
+            open: true,
+            ..Exports::default()
+        };
+
+        let problem = |code, message, span| {
+            let problem = Problem {
+                code,
+                message,
+                span,
+            };
+
+            (path.to_path_buf(), problem)
+        };
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => {
+                let message = format!("Unable to read {}: {}", path.display(), err);
+                self.problems
+                    .push(problem(ErrorCode::Io, message, Span::default()));
+                return open;
+            }
+        };
+
+        let sexprs = match Parser::new(&content).parse() {
+            Ok(sexprs) => sexprs,
+            Err(err) => {
+                self.problems
+                    .push(problem(ErrorCode::Parse, err.to_string(), err.span));
+                return open;
+            }
+        };
+
+        let base_dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut checker = Checker::new(Some(self), base_dir);
+        checker.run(&sexprs);
+
+        let exports = match module {
+            true => checker.exports(),
+            false => Exports::default(),
+        };
+        let problems = std::mem::take(&mut checker.problems);
+
+        self.problems.extend(
+            problems
+                .into_iter()
+                .map(|problem| (path.to_path_buf(), problem)),
+        );
+
+        exports
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn new(program: Option<&'a mut Program>, base_dir: PathBuf) -> Checker<'a> {
+        Checker {
+            builtins: Interpreter::new(),
+            globals: HashSet::new(),
+            functions: HashMap::new(),
+            open: false,
+            open_aliases: HashSet::new(),
+            program,
+            base_dir,
+            exports: None,
+            problems: vec![],
+        }
+    }
+
+    fn run(&mut self, sexprs: &[SExpr]) {
+        let mut globals = HashSet::new();
+
+        for sexpr in sexprs {
+            self.collect(sexpr, &mut globals, true);
+        }
+
+        self.globals = globals;
+
+        for sexpr in sexprs {
+            self.visit(sexpr, &[]);
+        }
+    }
+
+    /// What the checked file exports: the names of its `export` forms, or everything it
+    /// defines. Exported names that aren't defined are reported.
+    fn exports(&mut self) -> Exports {
+        let Some(exports) = self.exports.take() else {
+            return Exports {
+                names: self.globals.clone(),
+                functions: self.functions.clone(),
+                open: self.open,
+            };
+        };
+
+        let mut names = HashSet::new();
+
+        for (name, span) in exports {
+            if !self.open && !self.globals.contains(&name) {
+                let message = format!("Exported name is not defined: {}", name);
+                self.report(
+                    RuntimeError::with_code(ErrorCode::UndefinedExport, message),
+                    span,
+                );
+            }
+
+            names.insert(name);
+        }
+
+        let functions = self
+            .functions
+            .iter()
+            .filter(|(name, _)| names.contains(*name))
+            .map(|(name, arity)| (name.clone(), *arity))
+            .collect();
+
+        Exports {
+            names,
+            functions,
+            open: false,
+        }
+    }
+
+    /// Adds the names an import defines to `names`, as `alias/name` for aliased imports.
+    /// Without a program to follow, or when the module name isn't a literal, the import may
+    /// define anything.
+    fn import(&mut self, args: &[SExpr], names: &mut HashSet<String>, top_level: bool) {
+        let alias = match args {
+            [_, SExpr::Keyword(keyword, _), SExpr::Atom(alias, _)] if keyword == "as" => {
+                Some(alias.to_string())
+            }
+            _ => None,
+        };
+
+        let exports = match (self.program.as_deref_mut(), args.first()) {
+            (Some(program), Some(SExpr::String(module, span))) => {
+                match package::resolve_module(module, &self.base_dir) {
+                    Some(path) => Some(program.analyze(&path, true)),
+                    None => {
+                        let message = format!("Module not found: {}", module);
+                        let err = RuntimeError::with_code(ErrorCode::ModuleNotFound, message);
+                        self.report(err, *span);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let exports = match exports {
+            Some(exports) if !exports.open => exports,
+            _ => {
+                match alias {
+                    Some(alias) => {
+                        self.open_aliases.insert(alias);
+                    }
+                    None => self.open = true,
+                }
+
+                return;
+            }
+        };
+
+        let qualified = |name: &str| match &alias {
+            Some(alias) => format!("{}/{}", alias, name),
+            None => name.to_string(),
+        };
+
+        names.extend(exports.names.iter().map(|name| qualified(name)));
+
+        if top_level {
+            for (name, arity) in exports.functions {
+                self.functions
+                    .entry(qualified(&name))
+                    .and_modify(|arity| *arity = None)
+                    .or_insert(arity);
+            }
+        }
+    }
+
+    /// Adds the names `sexpr` binds in the current scope, outside of function bodies, to
+    /// `names`. Function arities are recorded for top-level definitions.
+    fn collect(&mut self, sexpr: &SExpr, names: &mut HashSet<String>, top_level: bool) {
+        let SExpr::List(list, _) = sexpr else {
+            return;
+        };
+
+        let Some((SExpr::Atom(head, _), args)) = list.split_first() else {
+            list.iter()
+                .for_each(|sexpr| self.collect(sexpr, names, top_level));
+            return;
+        };
+
+        match (head.as_str(), args) {
+            ("defn", [SExpr::Atom(name, _), SExpr::List(params, _), ..]) => {
+                names.insert(name.to_string());
+
+                if top_level {
+                    let arity = arity(params);
+
+                    self.functions
+                        .entry(name.to_string())
+                        .and_modify(|arity| *arity = None)
+                        .or_insert(arity);
+                }
+
+                return;
+            }
+            ("lambda", _) => return,
+            ("let" | "for", [pattern, ..]) => pattern_names(pattern, names),
+            ("set" | "count", [SExpr::Atom(name, _), ..]) => {
+                names.insert(name.to_string());
+            }
+            ("try", [.., SExpr::List(catch, _)]) => {
+                if let [SExpr::Atom(_, _), SExpr::Atom(name, _), ..] = catch.as_slice() {
+                    names.insert(name.to_string());
+                }
+            }
+            ("match", [_, clauses @ ..]) => {
+                for clause in clauses {
+                    if let SExpr::List(clause, _) = clause {
+                        clause
+                            .iter()
+                            .take(1)
+                            .for_each(|pattern| pattern_names(pattern, names));
+                    }
+                }
+            }
+            ("defenum", [SExpr::Atom(name, _), variants @ ..]) => {
+                names.insert(format!("{}?", name));
+
+                for variant in variants {
+                    let variant = match variant {
+                        SExpr::List(list, _) => list.first(),
+                        variant => Some(variant),
+                    };
+
+                    if let Some(SExpr::Atom(variant, _)) = variant {
+                        names.insert(variant.to_string());
+                        names.insert(format!("{}?", variant));
+                    }
+                }
+
+                return;
+            }
+            ("defprotocol", [_, methods @ ..]) => {
+                for method in methods {
+                    if let SExpr::List(method, _) = method {
+                        if let Some(SExpr::Atom(name, _)) = method.first() {
+                            names.insert(name.to_string());
+                        }
+                    }
+                }
+
+                return;
+            }
+            ("import" | "import-lazy", args) => {
+                self.import(args, names, top_level);
+                return;
+            }
+            ("export", args) => {
+                let exported = args.iter().filter_map(|arg| match arg {
+                    SExpr::Atom(name, span) => Some((name.to_string(), *span)),
+                    _ => None,
+                });
+
+                self.exports.get_or_insert_with(Vec::new).extend(exported);
+                return;
+            }
+            _ => {}
+        }
+
+        for sexpr in args {
+            self.collect(sexpr, names, false);
+        }
+    }
+
+    /// Checks a form in evaluation position. `locals` holds the scopes of the enclosing
+    /// functions, innermost last.
+    fn visit(&mut self, sexpr: &SExpr, locals: &[HashSet<String>]) {
+        let (list, span) = match sexpr {
+            SExpr::Atom(atom, span) => {
+                if !self.is_defined(atom, locals) {
+                    self.report(RuntimeError::UndefinedVariable(atom.to_string()), *span);
+                }
+
+                return;
+            }
+            SExpr::String(..) | SExpr::Keyword(..) => return,
+            SExpr::List(list, span) => (list, *span),
+        };
+
+        let Some((head, args)) = list.split_first() else {
+            return;
+        };
+
+        let SExpr::Atom(name, _) = head else {
+            self.report(RuntimeError::syntax("Expected function name here"), span);
+            return;
+        };
+
+        if !is_special_form(name) {
+            return self.visit_call(name, args, span, locals);
+        }
+
+        let malformed = |syntax: &str| RuntimeError::syntax(format!("Expected {} here", syntax));
+
+        match (name.as_str(), args) {
+            ("let", [SExpr::Atom(..) | SExpr::List(..), value]) => self.visit(value, locals),
+            ("let", _) => self.report(malformed("(let <name-or-pattern> <value>)"), span),
+            ("set", [SExpr::Atom(..), value]) => self.visit(value, locals),
+            ("set", _) => self.report(malformed("(set <name> <value>)"), span),
+            ("get" | "inc", [name @ SExpr::Atom(..)]) => self.visit(name, locals),
+            ("get" | "inc", _) => self.report(malformed(&format!("({} <name>)", name)), span),
+            ("eq" | "ne" | "neq", [left, right]) => {
+                self.visit(left, locals);
+                self.visit(right, locals);
+            }
+            // mod ignores anything after its operands
+            ("mod", [left, right, rest @ ..]) => {
+                self.expect_number(left);
+                self.expect_number(right);
+
+                [left, right]
+                    .into_iter()
+                    .chain(rest)
+                    .for_each(|sexpr| self.visit(sexpr, locals));
+            }
+            ("eq" | "ne" | "neq" | "mod", _) => {
+                self.report(malformed(&format!("({} <left> <right>)", name)), span)
+            }
+            ("add", args) => {
+                for arg in args {
+                    self.expect_number(arg);
+                    self.visit(arg, locals);
+                }
+            }
+            ("if" | "unless", args) if args.len() >= 2 => {
+                let mut condition = true;
+
+                for arg in args {
+                    match arg {
+                        SExpr::Atom(atom, _) if atom == "elif" => condition = true,
+                        SExpr::Atom(atom, _) if atom == "else" => {}
+                        arg if condition => {
+                            if let Some(value) = literal(arg) {
+                                if !matches!(value, Value::Bool(_)) {
+                                    self.report(
+                                        RuntimeError::type_mismatch("bool", &value),
+                                        arg.span(),
+                                    );
+                                }
+                            }
+
+                            self.visit(arg, locals);
+                            condition = false;
+                        }
+                        arg => self.visit(arg, locals),
+                    }
+                }
+            }
+            ("if" | "unless", _) => {
+                self.report(malformed(&format!("({} <condition> <expr>)", name)), span)
+            }
+            (
+                "count",
+                [SExpr::Atom(..), SExpr::Atom(from, _), start, SExpr::Atom(to, _), end, SExpr::List(body, _)],
+            ) if from == "from" && to == "to" => {
+                for bound in [start, end] {
+                    if let Some(value) = literal(bound) {
+                        if !matches!(value, Value::Int(_)) {
+                            self.report(RuntimeError::type_mismatch("int", &value), bound.span());
+                        }
+                    }
+
+                    self.visit(bound, locals);
+                }
+
+                body.iter().for_each(|sexpr| self.visit(sexpr, locals));
+            }
+            (
+                "for",
+                [SExpr::Atom(..) | SExpr::List(..), SExpr::Atom(keyword, _), collection, SExpr::List(body, _)],
+            ) if keyword == "in" => {
+                self.visit(collection, locals);
+                body.iter().for_each(|sexpr| self.visit(sexpr, locals));
+            }
+            ("for", _) => self.report(
+                malformed("(for <name-or-pattern> in <collection> (<body>...))"),
+                span,
+            ),
+            ("count", _) => self.report(
+                malformed("(count <name> from <start> to <end> (<body>...))"),
+                span,
+            ),
+            ("defn", [SExpr::Atom(..), SExpr::List(params, _), body @ ..])
+            | ("lambda", [SExpr::List(params, _), body @ ..]) => {
+                let mut scope = HashSet::new();
+
+                params
+                    .iter()
+                    .for_each(|param| param_names(param, &mut scope));
+
+                // Defaults are evaluated where the function is defined
+                for param in params {
+                    let SExpr::List(param, _) = param else {
+                        continue;
+                    };
+
+                    match param.as_slice() {
+                        [SExpr::Atom(name, _), SExpr::Atom(default, span)]
+                            if !self.is_defined(default, locals) =>
+                        {
+                            let message = format!(
+                                "The default {} of {} is not defined; to destructure a list \
+                                 argument, write (:list {} {})",
+                                default, name, name, default
+                            );
+
+                            self.report(
+                                RuntimeError::with_code(ErrorCode::UndefinedVariable, message),
+                                *span,
+                            );
+                        }
+                        [SExpr::Atom(..), default] => self.visit(default, locals),
+                        _ => {}
+                    }
+                }
+                body.iter()
+                    .for_each(|sexpr| self.collect(sexpr, &mut scope, false));
+
+                let locals = [locals, &[scope]].concat();
+                body.iter().for_each(|sexpr| self.visit(sexpr, &locals));
+            }
+            ("defn", _) => self.report(malformed("(defn <name> (<params>...) <body>...)"), span),
+            ("lambda", _) => self.report(malformed("(lambda (<params>...) <body>...)"), span),
+            ("try", [body @ .., SExpr::List(catch, _)]) => match catch.as_slice() {
+                [SExpr::Atom(keyword, _), SExpr::Atom(..), handler @ ..] if keyword == "catch" => {
+                    body.iter()
+                        .chain(handler)
+                        .for_each(|sexpr| self.visit(sexpr, locals));
+                }
+                _ => self.report(malformed("(catch <name> <handler>...)"), span),
+            },
+            ("try", _) => self.report(malformed("(catch <name> <handler>...)"), span),
+            ("match" | "case", [value, clauses @ ..]) => {
+                self.visit(value, locals);
+
+                for clause in clauses {
+                    let SExpr::List(clause, _) = clause else {
+                        self.report(malformed("(<pattern> [:when <guard>] <body>...)"), span);
+                        continue;
+                    };
+
+                    // The guard and the body follow the pattern, the keyword is skipped
+                    clause
+                        .iter()
+                        .skip(1)
+                        .for_each(|sexpr| self.visit(sexpr, locals));
+                }
+            }
+            (
+                "do" | "list" | "dict" | "interpolate" | "throw" | "??" | "?:" | "or-else"
+                | "async" | "spawn",
+                args,
+            ) => {
+                args.iter().for_each(|sexpr| self.visit(sexpr, locals));
+            }
+            ("deftest" | "bench" | "suppress-warnings", [_, body @ ..]) => {
+                body.iter().for_each(|sexpr| self.visit(sexpr, locals));
+            }
+            (".", [object, _, args @ ..]) => {
+                self.visit(object, locals);
+                args.iter().for_each(|sexpr| self.visit(sexpr, locals));
+            }
+            // The remaining forms take names, patterns or declarations rather than code
+            _ => {}
+        }
+    }
+
+    fn visit_call(&mut self, name: &str, args: &[SExpr], span: Span, locals: &[HashSet<String>]) {
+        if !self.is_defined(name, locals) {
+            self.report(RuntimeError::UnknownFunction(name.to_string()), span);
+        }
+
+        let named = args.iter().any(|arg| matches!(arg, SExpr::Keyword(..)));
+        let shadowed = locals.iter().any(|scope| scope.contains(name));
+
+        if let Some(Some(arity)) = self.functions.get(name).filter(|_| !named && !shadowed) {
+            let found = args.len();
+
+            let expected = match arity.max {
+                _ if found < arity.required => Some(format!("at least {}", arity.required)),
+                Some(max) if found > max => Some(format!("at most {}", max)),
+                _ => None,
+            };
+
+            if let Some(expected) = expected {
+                let function = name.to_string();
+                self.report(
+                    RuntimeError::Arity {
+                        function,
+                        expected,
+                        found,
+                    },
+                    span,
+                );
+            }
+        }
+
+        args.iter().for_each(|sexpr| self.visit(sexpr, locals));
+    }
+
+    fn is_defined(&self, name: &str, locals: &[HashSet<String>]) -> bool {
+        parser::literal(name).is_some()
+            || self.open
+            // Qualified names come from aliased imports, `(import "x" :as alias)`
+            || name.split_once('/').is_some_and(|(alias, _)| {
+                self.program.is_none() || self.open_aliases.contains(alias)
+            })
+            || locals.iter().any(|scope| scope.contains(name))
+            || self.globals.contains(name)
+            || self.builtins.native(name).is_some()
+    }
+
+    /// Reports a literal that `add` or `mod` would reject.
+    fn expect_number(&mut self, sexpr: &SExpr) {
+        if let Some(value) = literal(sexpr) {
+            if value.as_f64().is_none() {
+                self.report(
+                    RuntimeError::type_mismatch("int or float", &value),
+                    sexpr.span(),
+                );
+            }
+        }
+    }
+
+    fn report(&mut self, err: RuntimeError, span: Span) {
+        self.problems.push(Problem {
+            code: err.code(),
+            message: err.to_string(),
+            span,
+        });
+    }
+}
+
+/// The arity of a parameter list, as `Interpreter::function` reads it.
+fn arity(params: &[SExpr]) -> Option<Arity> {
+    let mut arity = Arity {
+        required: 0,
+        max: Some(0),
+    };
+
+    for param in params {
+        match param {
+            SExpr::Atom(atom, _) if atom == "&rest" => {
+                arity.max = None;
+                break;
+            }
+            SExpr::Atom(..) => arity.required += 1,
+            // `(name default)`, or a `(:list ...)` or `(:keys ...)` pattern
+            SExpr::List(list, _) => match list.as_slice() {
+                [SExpr::Atom(..), _] => {}
+                _ => arity.required += 1,
+            },
+            _ => return None,
+        }
+
+        arity.max = arity.max.map(|max| max + 1);
+    }
+
+    Some(arity)
+}
+
+/// Adds the variable names bound by a `let` or parameter pattern.
+fn pattern_names(pattern: &SExpr, names: &mut HashSet<String>) {
+    match pattern {
+        SExpr::Atom(atom, _) if atom != "&rest" && parser::literal(atom).is_none() => {
+            names.insert(atom.to_string());
+        }
+        SExpr::List(list, _) => list.iter().for_each(|sexpr| pattern_names(sexpr, names)),
+        _ => {}
+    }
+}
+
+/// Adds the variable names bound by a parameter: the name of `(name default)`, or those
+/// of a pattern.
+fn param_names(param: &SExpr, names: &mut HashSet<String>) {
+    match param {
+        SExpr::List(list, _) if matches!(list.first(), Some(SExpr::Atom(..))) => {
+            pattern_names(&list[0], names)
+        }
+        param => pattern_names(param, names),
+    }
+}
+
+/// The value of a literal form, `None` for code.
+fn literal(sexpr: &SExpr) -> Option<Value> {
+    match sexpr {
+        SExpr::Atom(atom, _) => parser::literal(atom),
+        SExpr::String(string, _) => Some(Value::String(string.as_str().into())),
+        SExpr::Keyword(keyword, _) => Some(Value::Keyword(keyword.as_str().into())),
+        SExpr::List(..) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_check() {
+        let source = "
+            (defn area (w h) (add w h))
+            (let total (area 1))
+            (let x 1 2)
+            (print totl)
+            (if 1 (shout x) else (print \"no\"))
+            (count i from 0 to \"10\" ((set last i)))
+            (defn each (xs) (try (print xs last) (catch e (print e missing))))
+            (print (add i :a))";
+
+        let sexprs = Parser::new(source).parse().unwrap();
+
+        let problems = check(&sexprs)
+            .into_iter()
+            .map(|problem| {
+                format!(
+                    "{}:{} {} {}",
+                    problem.span.line,
+                    problem.span.column,
+                    problem.code.code(),
+                    problem.message
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            problems,
+            [
+                "3:24 E0002 Function area expects at least 2 arguments, got 1",
+                "4:13 E0005 Expected (let <name-or-pattern> <value>) here",
+                "5:20 E0003 Variable not found: totl",
+                "6:17 E0004 Type mismatch: expected bool, found int",
+                "6:19 E0001 Unknown function: shout",
+                "7:32 E0004 Type mismatch: expected int, found string",
+                "8:68 E0003 Variable not found: missing",
+                "9:27 E0004 Type mismatch: expected int or float, found keyword",
+            ]
+        );
+
+        let sexprs = Parser::new("(import \"./util.kk\") (helper 1)")
+            .parse()
+            .unwrap();
+        assert!(check(&sexprs).is_empty());
+
+        let sexprs = Parser::new("(map (lambda (n) (add n 1)) (list n))")
+            .parse()
+            .unwrap();
+        let problems = check(&sexprs);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].message, "Variable not found: n");
+
+        let sexprs = Parser::new(
+            "(let base 10)
+             (defn dist ((x1 y1) (x2 y2)) (add x1 x2))
+             (defn offset (x (step base) (scale (add base unknown))) x)",
+        )
+        .parse()
+        .unwrap();
+        let problems = check(&sexprs)
+            .into_iter()
+            .map(|problem| problem.message)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                "The default y1 of x1 is not defined; to destructure a list argument, write (:list x1 y1)",
+                "The default y2 of x2 is not defined; to destructure a list argument, write (:list x2 y2)",
+                "Variable not found: unknown",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze() {
+        let dir = std::env::temp_dir().join(format!("kk-analyze-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("util.kk"),
+            "(export helper ghost) (defn helper (a b) (add a b)) (defn private () 1)",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.kk"),
+            "(import \"./util.kk\") (import \"./util.kk\" :as u) (import \"./gone.kk\" :as g)
+             (helper 1) (private) (u/helper 1 2) (u/nope) (g/anything) (helpr 1 2)",
+        )
+        .unwrap();
+
+        let problems = analyze(&dir.join("main.kk"))
+            .into_iter()
+            .map(|(file, problem)| {
+                format!(
+                    "{} {}:{} {}",
+                    file.file_name().unwrap().to_string_lossy(),
+                    problem.span.line,
+                    problem.span.column,
+                    problem.message
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            problems,
+            [
+                "util.kk 1:16 Exported name is not defined: ghost",
+                "main.kk 1:57 Module not found: ./gone.kk",
+                "main.kk 2:14 Function helper expects at least 2 arguments, got 1",
+                "main.kk 2:25 Unknown function: private",
+                "main.kk 2:50 Unknown function: u/nope",
+                "main.kk 2:72 Unknown function: helpr",
+            ]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
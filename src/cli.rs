@@ -0,0 +1,577 @@
+use std::time::Duration;
+
+use kk::testing::TestOptions;
+use kk::{manifest, DivMode, EqMode, Interpreter};
+
+pub(crate) const USAGE: &str = "\
+Usage: kk [run] [options] <script> [-- <args>...]
+       kk [options] -              (read the script from stdin)
+       kk [options] -e <expr>
+       kk                          (run the entry script named by the kk.toml of the
+                                   project in this directory or a parent)
+       kk new <name>               (create a project with kk.toml, main.kk and tests/)
+       kk add <git-url-or-path>
+       kk add --check              (check kk_modules/ against the hashes in kk.lock)
+       kk info <file>
+       kk lint <file>              (report unreachable or missing match/case arms)
+       kk check <file>             (report errors found without running the script,
+                                   following its imports)
+       kk fmt [--check] <file>     (rewrite the file in the canonical layout)
+       kk bench <file>             (run a script and report its bench forms)
+       kk test <file> [--filter <name>] [--retries <n>] [--jobs <n>]
+                                   (run its deftest forms, evaluating the file
+                                   again for every test and every retry)
+       kk repl [--session <file>]  (interactive; the session file keeps state)
+       kk transcript-to-script <file>
+                                   (print the successful inputs of a :transcript)
+       kk explain [<code>]         (describe an error code such as E0002)
+       kk serve-eval --socket <path>
+       kk dap                      (Debug Adapter Protocol server on stdio)
+       kk lsp                      (Language Server Protocol server on stdio)
+
+Options:
+  -e, --eval <expr>    Evaluate <expr> instead of a script
+      --allow <cap>    Grant a capability required by the script's manifest
+      --print-results  Print the value of every top-level form
+      --deny-warnings  Fail on the first warning
+      --lint           Report the warnings of kk lint before running
+      --debug          Open a debugger prompt at (breakpoint) forms
+      --trace          Log each form as it is evaluated, with its result
+      --trace-tasks    Prefix printed lines with [task <id>]: 0 for the script,
+                       counting up for the threads it spawns
+      --warn-slow <ms> Warn about builtin calls that take longer than <ms>
+                       milliseconds, once per call site
+      --dump-ast       Print the parsed syntax tree instead of running
+      --expand-env     Expand $VAR and $${VAR:-fallback} in string literals
+      --no-bytecode    Run loops on the tree-walking evaluator instead of
+                       compiling them to bytecode
+      --eq <mode>      How eq compares different types: strict (an error, the
+                       default), unequal (false) or loose (numbers and numeric
+                       strings compare as numbers)
+      --div <mode>     What / returns for two ints: float (the default),
+                       truncate (an int, like idiv) or strict (an error, so
+                       scripts must use idiv or fdiv)
+      --max-depth <n>  Fail when forms nest deeper than <n>, counting those of
+                       the functions being called (default 2000)
+  -h, --help           Print this help
+  -V, --version        Print the kk version
+
+Arguments after -- are passed as a list to the script's (defn main (args) ...), if it
+defines one; an int returned by main becomes the exit code.";
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    Run(RunOptions),
+    /// `kk new <name>`: create a project directory.
+    New(String),
+    /// `kk add <source>`: vendor a module.
+    Add(String),
+    /// `kk add --check`: check the vendored modules against the lockfile.
+    CheckModules,
+    /// `kk info <file>`: print a script's manifest.
+    Info(String),
+    /// `kk lint <file>`: print the static checks of `kk::lint`.
+    Lint(String),
+    /// `kk check <file>`: print the errors `kk::check::analyze` finds in a script and
+    /// its imports.
+    Check(String),
+    /// `kk fmt [--check] <file>`: format a file in place, or only check that it is.
+    Fmt(String, bool),
+    /// `kk bench <file>`: run a script and report the timings of its `bench` forms.
+    Bench(String),
+    /// `kk test <file>`: run the file's `deftest` blocks.
+    Test(String, TestOptions),
+    /// `kk repl [--session <file>]`: an interactive session, optionally persisted.
+    Repl(Option<String>),
+    /// `kk transcript-to-script <file>`: extract a script from a REPL transcript.
+    TranscriptToScript(String),
+    /// `kk explain [<code>]`: describe an error code, or list them all.
+    Explain(Option<String>),
+    /// `kk dap`: serve the Debug Adapter Protocol on stdin/stdout.
+    Dap,
+    /// `kk lsp`: serve the Language Server Protocol on stdin/stdout.
+    Lsp,
+    /// `kk serve-eval --socket <path>`: evaluate lines received on a Unix socket.
+    ServeEval(String),
+    Help,
+    Version,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Source {
+    File(String),
+    /// The entry script of the project in the working directory, see `package::find_entry`.
+    Project,
+    Expr(String),
+    Stdin,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct RunOptions {
+    pub(crate) source: Source,
+    pub(crate) allowed_capabilities: Vec<String>,
+    pub(crate) print_results: bool,
+    pub(crate) deny_warnings: bool,
+    pub(crate) lint: bool,
+    pub(crate) bytecode: bool,
+    pub(crate) expand_env: bool,
+    pub(crate) trace: bool,
+    pub(crate) trace_tasks: bool,
+    pub(crate) warn_slow: Option<Duration>,
+    pub(crate) debug: bool,
+    pub(crate) dump_ast: bool,
+    pub(crate) eq_mode: EqMode,
+    pub(crate) div_mode: DivMode,
+    pub(crate) max_depth: usize,
+    /// Arguments after `--`, passed to the script's `main`.
+    pub(crate) args: Vec<String>,
+}
+
+/// Parses the command line, without the program name. Without a script or `-e` the
+/// script is read from stdin when it is piped.
+pub(crate) fn parse(args: &[String], stdin_is_piped: bool) -> Result<Command, String> {
+    let mut it = args.iter();
+
+    match args.first().map(String::as_str) {
+        Some("new") => {
+            it.next();
+
+            return match (it.next(), it.next()) {
+                (Some(name), None) => Ok(Command::New(name.clone())),
+                _ => Err("Usage: kk new <name>".to_string()),
+            };
+        }
+        Some("add") => {
+            it.next();
+
+            return match (it.next(), it.next()) {
+                (Some(check), None) if check == "--check" => Ok(Command::CheckModules),
+                (Some(source), None) if !source.starts_with('-') => {
+                    Ok(Command::Add(source.clone()))
+                }
+                _ => Err("Usage: kk add <git-url-or-path> | kk add --check".to_string()),
+            };
+        }
+        Some("info") => {
+            it.next();
+
+            return match (it.next(), it.next()) {
+                (Some(file), None) => Ok(Command::Info(file.clone())),
+                _ => Err("Usage: kk info <file>".to_string()),
+            };
+        }
+        Some("lint") => {
+            it.next();
+
+            return match (it.next(), it.next()) {
+                (Some(file), None) => Ok(Command::Lint(file.clone())),
+                _ => Err("Usage: kk lint <file>".to_string()),
+            };
+        }
+        Some("check") => {
+            it.next();
+
+            return match (it.next(), it.next()) {
+                (Some(file), None) => Ok(Command::Check(file.clone())),
+                _ => Err("Usage: kk check <file>".to_string()),
+            };
+        }
+        Some("fmt") => {
+            it.next();
+
+            return match (it.next().map(String::as_str), it.next(), it.next()) {
+                (Some("--check"), Some(file), None) => Ok(Command::Fmt(file.clone(), true)),
+                (Some(file), None, _) if !file.starts_with('-') => {
+                    Ok(Command::Fmt(file.to_string(), false))
+                }
+                _ => Err("Usage: kk fmt [--check] <file>".to_string()),
+            };
+        }
+        Some("bench") => {
+            it.next();
+
+            return match (it.next(), it.next()) {
+                (Some(file), None) => Ok(Command::Bench(file.clone())),
+                _ => Err("Usage: kk bench <file>".to_string()),
+            };
+        }
+        Some("test") => {
+            it.next();
+
+            let mut file = None;
+            let mut options = TestOptions::default();
+
+            while let Some(arg) = it.next() {
+                match arg.as_str() {
+                    "--filter" => options.filter = it.next().cloned(),
+                    "--retries" | "--jobs" => {
+                        let Some(count) = it.next().and_then(|count| count.parse().ok()) else {
+                            return Err(format!("Expected a number after {}", arg));
+                        };
+
+                        match arg.as_str() {
+                            "--retries" => options.retries = count as u32,
+                            _ => options.jobs = count,
+                        }
+                    }
+                    option if option.starts_with('-') => {
+                        return Err(format!("Unknown option: {}", option));
+                    }
+                    path if file.is_none() => file = Some(path.to_string()),
+                    path => return Err(format!("Unexpected argument: {}", path)),
+                }
+            }
+
+            return match file {
+                Some(file) => Ok(Command::Test(file, options)),
+                None => Err(
+                    "Usage: kk test <file> [--filter <name>] [--retries <n>] [--jobs <n>]"
+                        .to_string(),
+                ),
+            };
+        }
+        Some("repl") => {
+            it.next();
+
+            return match (it.next().map(String::as_str), it.next(), it.next()) {
+                (None, _, _) => Ok(Command::Repl(None)),
+                (Some("--session"), Some(session), None) => {
+                    Ok(Command::Repl(Some(session.clone())))
+                }
+                _ => Err("Usage: kk repl [--session <file>]".to_string()),
+            };
+        }
+        Some("transcript-to-script") => {
+            it.next();
+
+            return match (it.next(), it.next()) {
+                (Some(file), None) => Ok(Command::TranscriptToScript(file.clone())),
+                _ => Err("Usage: kk transcript-to-script <file>".to_string()),
+            };
+        }
+        Some("explain") => {
+            it.next();
+
+            return match (it.next(), it.next()) {
+                (code, None) => Ok(Command::Explain(code.cloned())),
+                _ => Err("Usage: kk explain [<code>]".to_string()),
+            };
+        }
+        Some("dap") => {
+            return match args.len() {
+                1 => Ok(Command::Dap),
+                _ => Err("Usage: kk dap".to_string()),
+            };
+        }
+        Some("lsp") => {
+            return match args.len() {
+                1 => Ok(Command::Lsp),
+                _ => Err("Usage: kk lsp".to_string()),
+            };
+        }
+        Some("serve-eval") => {
+            it.next();
+
+            return match (it.next().map(String::as_str), it.next(), it.next()) {
+                (Some("--socket"), Some(socket), None) => Ok(Command::ServeEval(socket.clone())),
+                _ => Err("Usage: kk serve-eval --socket <path>".to_string()),
+            };
+        }
+        _ => {}
+    }
+
+    // `kk run <script>` is the same as `kk <script>`
+    if args.first().map(String::as_str) == Some("run") {
+        it.next();
+    }
+
+    let mut source = None;
+    let mut allowed_capabilities = vec![];
+    let mut print_results = false;
+    let mut deny_warnings = false;
+    let mut lint = false;
+    let mut bytecode = true;
+    let mut expand_env = false;
+    let mut trace = false;
+    let mut trace_tasks = false;
+    let mut warn_slow = None;
+    let mut debug = false;
+    let mut dump_ast = false;
+    let mut eq_mode = EqMode::Strict;
+    let mut div_mode = DivMode::Float;
+    let mut max_depth = Interpreter::DEFAULT_MAX_DEPTH;
+    let mut script_args = vec![];
+
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--" => {
+                script_args = it.by_ref().cloned().collect();
+            }
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--allow" => {
+                let Some(capability) = it.next() else {
+                    return Err("Expected capability after --allow".to_string());
+                };
+
+                if !manifest::CAPABILITIES.contains(&capability.as_str()) {
+                    return Err(format!("Unknown capability: {}", capability));
+                }
+
+                allowed_capabilities.push(capability.clone());
+            }
+            "--print-results" => print_results = true,
+            "--deny-warnings" => deny_warnings = true,
+            "--lint" => lint = true,
+            "--no-bytecode" => bytecode = false,
+            "--expand-env" => expand_env = true,
+            "--trace" => trace = true,
+            "--trace-tasks" => trace_tasks = true,
+            "--warn-slow" => {
+                warn_slow = match it.next().and_then(|ms| ms.parse().ok()) {
+                    Some(ms) => Some(Duration::from_millis(ms)),
+                    None => return Err("Expected milliseconds after --warn-slow".to_string()),
+                };
+            }
+            "--debug" => debug = true,
+            "--dump-ast" => dump_ast = true,
+            "--eq" => {
+                eq_mode = match it.next().map(String::as_str) {
+                    Some("strict") => EqMode::Strict,
+                    Some("unequal") => EqMode::Unequal,
+                    Some("loose") => EqMode::Loose,
+                    _ => return Err("Expected strict, unequal or loose after --eq".to_string()),
+                };
+            }
+            "--div" => {
+                div_mode = match it.next().map(String::as_str) {
+                    Some("float") => DivMode::Float,
+                    Some("truncate") => DivMode::Truncate,
+                    Some("strict") => DivMode::Strict,
+                    _ => return Err("Expected float, truncate or strict after --div".to_string()),
+                };
+            }
+            "--max-depth" => {
+                max_depth = match it.next().and_then(|depth| depth.parse().ok()) {
+                    Some(depth) if depth > 0 => depth,
+                    _ => return Err("Expected a positive number after --max-depth".to_string()),
+                };
+            }
+            "-e" | "--eval" => {
+                let Some(expr) = it.next() else {
+                    return Err(format!("Expected expression after {}", arg));
+                };
+
+                if source.is_some() {
+                    return Err("Expected either a script or -e, not both".to_string());
+                }
+
+                source = Some(Source::Expr(expr.clone()));
+            }
+            "-" => {
+                if source.is_some() {
+                    return Err(format!("Unexpected argument: {}", arg));
+                }
+
+                source = Some(Source::Stdin);
+            }
+            option if option.starts_with('-') => {
+                return Err(format!("Unknown option: {}", option));
+            }
+            script => {
+                if source.is_some() {
+                    return Err(format!("Unexpected argument: {}", script));
+                }
+
+                source = Some(Source::File(script.to_string()));
+            }
+        }
+    }
+
+    let source = match source {
+        Some(source) => source,
+        None if stdin_is_piped => Source::Stdin,
+        None => Source::Project,
+    };
+
+    Ok(Command::Run(RunOptions {
+        source,
+        allowed_capabilities,
+        print_results,
+        deny_warnings,
+        lint,
+        bytecode,
+        expand_env,
+        trace,
+        trace_tasks,
+        warn_slow,
+        debug,
+        dump_ast,
+        eq_mode,
+        div_mode,
+        max_depth,
+        args: script_args,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(args: &str) -> Result<Command, String> {
+        parse(
+            &args
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+            false,
+        )
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            parse_str("--allow net script.kk"),
+            Ok(Command::Run(RunOptions {
+                source: Source::File("script.kk".to_string()),
+                allowed_capabilities: vec!["net".to_string()],
+                print_results: false,
+                deny_warnings: false,
+                lint: false,
+                bytecode: true,
+                expand_env: false,
+                trace: false,
+                trace_tasks: false,
+                warn_slow: None,
+                debug: false,
+                dump_ast: false,
+                eq_mode: EqMode::Strict,
+                div_mode: DivMode::Float,
+                max_depth: Interpreter::DEFAULT_MAX_DEPTH,
+                args: vec![],
+            }))
+        );
+        assert_eq!(
+            parse_str("--print-results --deny-warnings --lint --no-bytecode --warn-slow 250 --eq loose --div strict --max-depth 500 -e (print)"),
+            Ok(Command::Run(RunOptions {
+                source: Source::Expr("(print)".to_string()),
+                allowed_capabilities: vec![],
+                print_results: true,
+                deny_warnings: true,
+                lint: true,
+                bytecode: false,
+                expand_env: false,
+                trace: false,
+                trace_tasks: false,
+                warn_slow: Some(Duration::from_millis(250)),
+                debug: false,
+                dump_ast: false,
+                eq_mode: EqMode::Loose,
+                div_mode: DivMode::Strict,
+                max_depth: 500,
+                args: vec![],
+            }))
+        );
+        assert_eq!(
+            parse_str("run script.kk -- a --b"),
+            Ok(Command::Run(RunOptions {
+                source: Source::File("script.kk".to_string()),
+                allowed_capabilities: vec![],
+                print_results: false,
+                deny_warnings: false,
+                lint: false,
+                bytecode: true,
+                expand_env: false,
+                trace: false,
+                trace_tasks: false,
+                warn_slow: None,
+                debug: false,
+                dump_ast: false,
+                eq_mode: EqMode::Strict,
+                div_mode: DivMode::Float,
+                max_depth: Interpreter::DEFAULT_MAX_DEPTH,
+                args: vec!["a".to_string(), "--b".to_string()],
+            }))
+        );
+        assert_eq!(
+            parse_str("add ../utils"),
+            Ok(Command::Add("../utils".to_string()))
+        );
+        assert_eq!(parse_str("add --check"), Ok(Command::CheckModules));
+        assert!(parse_str("add --force").is_err());
+        assert_eq!(
+            parse_str("serve-eval --socket /tmp/kk.sock"),
+            Ok(Command::ServeEval("/tmp/kk.sock".to_string()))
+        );
+        assert_eq!(
+            parse_str("explain E0002"),
+            Ok(Command::Explain(Some("E0002".to_string())))
+        );
+        assert_eq!(parse_str("explain"), Ok(Command::Explain(None)));
+        assert_eq!(parse_str("lsp"), Ok(Command::Lsp));
+        assert_eq!(
+            parse_str("lint l.kk"),
+            Ok(Command::Lint("l.kk".to_string()))
+        );
+        assert_eq!(
+            parse_str("check c.kk"),
+            Ok(Command::Check("c.kk".to_string()))
+        );
+        assert_eq!(
+            parse_str("fmt --check f.kk"),
+            Ok(Command::Fmt("f.kk".to_string(), true))
+        );
+        assert_eq!(
+            parse_str("bench b.kk"),
+            Ok(Command::Bench("b.kk".to_string()))
+        );
+        assert_eq!(
+            parse_str("test t.kk --filter parse --retries 2 --jobs 1"),
+            Ok(Command::Test(
+                "t.kk".to_string(),
+                TestOptions {
+                    filter: Some("parse".to_string()),
+                    retries: 2,
+                    jobs: 1,
+                }
+            ))
+        );
+        assert_eq!(
+            parse_str("repl --session .kk-session"),
+            Ok(Command::Repl(Some(".kk-session".to_string())))
+        );
+        assert_eq!(parse_str("script.kk --help"), Ok(Command::Help));
+        assert_eq!(parse_str("-V"), Ok(Command::Version));
+
+        assert_eq!(parse_str("new demo"), Ok(Command::New("demo".to_string())));
+        assert!(parse_str("new").is_err());
+        assert!(matches!(
+            parse_str(""),
+            Ok(Command::Run(RunOptions {
+                source: Source::Project,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            parse(&[], true),
+            Ok(Command::Run(RunOptions {
+                source: Source::Stdin,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            parse_str("--print-results -"),
+            Ok(Command::Run(RunOptions {
+                source: Source::Stdin,
+                ..
+            }))
+        ));
+        assert!(parse_str("a.kk b.kk").is_err());
+        assert!(parse_str("-e (print) a.kk").is_err());
+        assert!(parse_str("--allow root a.kk").is_err());
+        assert!(parse_str("--frobnicate").is_err());
+        assert!(parse_str("--eq sloppy a.kk").is_err());
+        assert!(parse_str("test t.kk --retries many").is_err());
+        assert!(parse_str("explain E0001 E0002").is_err());
+    }
+}
@@ -0,0 +1,762 @@
+//! A Debug Adapter Protocol server (`kk dap`), spoken over stdin/stdout.
+//!
+//! The script runs on the same thread as the adapter: while it is stopped, the interpreter
+//! sits in `Debugger::before_form` and the adapter answers requests from there.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde_json::{json, Value as Json};
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::interpreter::{Debugger, Interpreter};
+use crate::parser::Parser;
+use crate::sexpr::SExpr;
+use crate::value::{Scope, Value, Vars};
+
+/// The only thread reported to the client.
+const THREAD_ID: i64 = 1;
+
+/// Writes framed messages to the client. Shared with the interpreter's output sink.
+struct Sender {
+    writer: Box<dyn Write>,
+    seq: i64,
+}
+
+impl Sender {
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+
+        let body = message.to_string();
+
+        // A client that went away will show up as end of input on the next read
+        let _ = write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = self.writer.flush();
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+}
+
+/// How execution continues after a stop.
+#[derive(Clone, Copy)]
+enum Mode {
+    Run,
+    /// Stop at the first form (`stopOnEntry`).
+    Entry,
+    StepIn,
+    /// Stop on a new line in a frame at most this deep.
+    StepOver(usize),
+    /// Stop on a new line in a frame shallower than this.
+    StepOut(usize),
+}
+
+/// A module or function body being evaluated.
+struct Frame {
+    name: String,
+    file: Option<PathBuf>,
+    /// Line and column of the form evaluated last in this frame; 0 before the first one.
+    line: usize,
+    column: usize,
+    globals: Scope,
+    /// Index of the function's locals in `Env::frames`, `None` for modules.
+    locals: Option<usize>,
+}
+
+/// A `variablesReference` handed to the client, valid until execution resumes.
+enum Handle {
+    Locals(usize),
+    Globals(usize),
+    Value(Value),
+}
+
+struct Adapter {
+    reader: Box<dyn BufRead>,
+    sender: Rc<RefCell<Sender>>,
+    /// Breakpoint lines by canonical file path.
+    breakpoints: HashMap<PathBuf, BTreeSet<usize>>,
+    mode: Mode,
+    frames: Vec<Frame>,
+    handles: Vec<Handle>,
+    /// Set by `disconnect`/`terminate` to stop the script at the next form.
+    terminated: bool,
+}
+
+/// Outcome of handling one request.
+enum Flow {
+    Stay,
+    Resume,
+    Launch {
+        program: String,
+        stop_on_entry: bool,
+    },
+    Disconnect,
+}
+
+/// Runs the adapter until the client disconnects.
+pub fn run(reader: Box<dyn BufRead>, writer: Box<dyn Write>) -> Result<(), String> {
+    let sender = Rc::new(RefCell::new(Sender { writer, seq: 0 }));
+
+    let adapter = Rc::new(RefCell::new(Adapter {
+        reader,
+        sender: sender.clone(),
+        breakpoints: HashMap::new(),
+        mode: Mode::Run,
+        frames: vec![],
+        handles: vec![],
+        terminated: false,
+    }));
+
+    let mut launch = None;
+
+    loop {
+        let Some(request) = adapter.borrow_mut().read()? else {
+            return Ok(());
+        };
+
+        let flow = adapter.borrow_mut().handle(&request, None);
+
+        match flow {
+            Flow::Launch {
+                program,
+                stop_on_entry,
+            } => launch = Some((program, stop_on_entry)),
+            Flow::Disconnect => return Ok(()),
+            Flow::Stay | Flow::Resume => {}
+        }
+
+        if request["command"] == "configurationDone" {
+            if let Some((program, stop_on_entry)) = launch.take() {
+                if stop_on_entry {
+                    adapter.borrow_mut().mode = Mode::Entry;
+                }
+
+                launch_program(&adapter, &sender, &program);
+            }
+        }
+    }
+}
+
+fn launch_program(adapter: &Rc<RefCell<Adapter>>, sender: &Rc<RefCell<Sender>>, program: &str) {
+    let mut interpreter = Interpreter::new();
+
+    let output = sender.clone();
+    interpreter.set_output(move |text| {
+        output
+            .borrow_mut()
+            .event("output", json!({ "category": "stdout", "output": text }));
+    });
+
+    interpreter.debugger = Some(Box::new(Session(adapter.clone())));
+
+    let result = interpreter.eval_file(program);
+
+    let mut sender = sender.borrow_mut();
+
+    let exit_code = match result {
+        Ok(()) => 0,
+        Err(err) => {
+            let mut message = format!("error[{}]: {}\n", err.code(), err);
+
+            for frame in interpreter.take_trace() {
+                message.push_str(&format!("  {}\n", frame));
+            }
+
+            sender.event("output", json!({ "category": "stderr", "output": message }));
+
+            1
+        }
+    };
+
+    sender.event("exited", json!({ "exitCode": exit_code }));
+    sender.event("terminated", json!({}));
+}
+
+impl Adapter {
+    /// Reads the next request, `None` at end of input.
+    fn read(&mut self) -> Result<Option<Json>, String> {
+        let mut length = None;
+
+        loop {
+            let mut header = String::new();
+
+            if self
+                .reader
+                .read_line(&mut header)
+                .map_err(|err| err.to_string())?
+                == 0
+            {
+                return Ok(None);
+            }
+
+            let header = header.trim();
+
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let Some(length) = length else {
+            return Err("Expected Content-Length header".to_string());
+        };
+
+        let mut body = vec![0; length];
+
+        self.reader
+            .read_exact(&mut body)
+            .map_err(|err| err.to_string())?;
+
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|err| format!("Invalid message: {}", err))
+    }
+
+    /// Handles a request. `interpreter` is set while the script is stopped.
+    fn handle(&mut self, request: &Json, interpreter: Option<&mut Interpreter>) -> Flow {
+        let arguments = &request["arguments"];
+        let shared = self.sender.clone();
+        let mut sender = shared.borrow_mut();
+
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                sender.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                        "supportsTerminateRequest": true,
+                    }),
+                );
+                sender.event("initialized", json!({}));
+            }
+            "launch" => {
+                let Some(program) = arguments["program"].as_str() else {
+                    sender.fail(request, "Expected program to launch");
+                    return Flow::Stay;
+                };
+
+                sender.respond(request, json!({}));
+
+                return Flow::Launch {
+                    program: program.to_string(),
+                    stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+                };
+            }
+            "setBreakpoints" => {
+                let path = arguments["source"]["path"].as_str().unwrap_or_default();
+                let lines = form_lines(Path::new(path));
+
+                let mut active = BTreeSet::new();
+
+                // Breakpoints move to the first line at or after them where a form starts
+                let breakpoints = arguments["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|breakpoint| {
+                        let requested = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+
+                        match lines.range(requested..).next() {
+                            Some(&line) => {
+                                active.insert(line);
+                                json!({ "verified": true, "line": line })
+                            }
+                            None => json!({ "verified": false, "line": requested }),
+                        }
+                    })
+                    .collect::<Vec<Json>>();
+
+                self.breakpoints.insert(canonical(Path::new(path)), active);
+
+                sender.respond(request, json!({ "breakpoints": breakpoints }));
+            }
+            "threads" => {
+                sender.respond(
+                    request,
+                    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+                );
+            }
+            "stackTrace" => {
+                let frames = self
+                    .frames
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(id, frame)| {
+                        let source = frame.file.as_ref().map(|file| {
+                            json!({
+                                "name": file.file_name().map(|name| name.to_string_lossy()),
+                                "path": file.display().to_string(),
+                            })
+                        });
+
+                        json!({
+                            "id": id,
+                            "name": frame.name,
+                            "line": frame.line,
+                            "column": frame.column,
+                            "source": source,
+                        })
+                    })
+                    .collect::<Vec<Json>>();
+
+                sender.respond(
+                    request,
+                    json!({ "stackFrames": frames, "totalFrames": self.frames.len() }),
+                );
+            }
+            "scopes" => {
+                let id = arguments["frameId"].as_u64().unwrap_or(0) as usize;
+                let mut scopes = vec![];
+
+                if self
+                    .frames
+                    .get(id)
+                    .is_some_and(|frame| frame.locals.is_some())
+                {
+                    self.handles.push(Handle::Locals(id));
+                    scopes.push(json!({
+                        "name": "Locals",
+                        "variablesReference": self.handles.len(),
+                        "expensive": false,
+                    }));
+                }
+
+                if id < self.frames.len() {
+                    self.handles.push(Handle::Globals(id));
+                    scopes.push(json!({
+                        "name": "Globals",
+                        "variablesReference": self.handles.len(),
+                        "expensive": false,
+                    }));
+                }
+
+                sender.respond(request, json!({ "scopes": scopes }));
+            }
+            "variables" => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or(0) as usize;
+
+                let variables = match (reference.checked_sub(1), interpreter) {
+                    (Some(index), Some(interpreter)) if index < self.handles.len() => {
+                        self.variables(index, interpreter)
+                    }
+                    _ => vec![],
+                };
+
+                let variables = variables
+                    .into_iter()
+                    .map(|(name, value)| self.variable(name, value))
+                    .collect::<Vec<Json>>();
+
+                sender.respond(request, json!({ "variables": variables }));
+            }
+            "evaluate" => {
+                let Some(interpreter) = interpreter else {
+                    sender.fail(request, "The program is not stopped");
+                    return Flow::Stay;
+                };
+
+                let expression = arguments["expression"].as_str().unwrap_or_default();
+
+                // Printed output is sent as an event, so the sender must be free meanwhile
+                drop(sender);
+                let result = interpreter.eval_str(expression);
+                let mut sender = shared.borrow_mut();
+
+                match result {
+                    Ok(value) => {
+                        let variable = self.variable(String::new(), value);
+
+                        sender.respond(
+                            request,
+                            json!({
+                                "result": variable["value"],
+                                "type": variable["type"],
+                                "variablesReference": variable["variablesReference"],
+                            }),
+                        );
+                    }
+                    Err(err) => {
+                        interpreter.take_trace();
+                        sender.fail(request, &err.to_string());
+                    }
+                }
+            }
+            "continue" => {
+                self.mode = Mode::Run;
+                sender.respond(request, json!({ "allThreadsContinued": true }));
+                return Flow::Resume;
+            }
+            "next" => {
+                self.mode = Mode::StepOver(self.frames.len());
+                sender.respond(request, json!({}));
+                return Flow::Resume;
+            }
+            "stepIn" => {
+                self.mode = Mode::StepIn;
+                sender.respond(request, json!({}));
+                return Flow::Resume;
+            }
+            "stepOut" => {
+                self.mode = Mode::StepOut(self.frames.len());
+                sender.respond(request, json!({}));
+                return Flow::Resume;
+            }
+            "disconnect" | "terminate" => {
+                self.terminated = true;
+                sender.respond(request, json!({}));
+                return Flow::Disconnect;
+            }
+            "configurationDone" | "setExceptionBreakpoints" => {
+                sender.respond(request, json!({}));
+            }
+            command => {
+                sender.fail(request, &format!("Unsupported request: {}", command));
+            }
+        }
+
+        Flow::Stay
+    }
+
+    /// Whether to stop before a form starting at `line`, and why.
+    fn stop_reason(&self, line: usize, new_line: bool) -> Option<&'static str> {
+        if !new_line {
+            return None;
+        }
+
+        let depth = self.frames.len();
+
+        let on_breakpoint = self
+            .frames
+            .last()
+            .and_then(|frame| frame.file.as_ref())
+            .and_then(|file| self.breakpoints.get(&canonical(file)))
+            .is_some_and(|lines| lines.contains(&line));
+
+        if on_breakpoint {
+            return Some("breakpoint");
+        }
+
+        match self.mode {
+            Mode::Run => None,
+            Mode::Entry => Some("entry"),
+            Mode::StepIn => Some("step"),
+            Mode::StepOver(max) if depth <= max => Some("step"),
+            Mode::StepOut(above) if depth < above => Some("step"),
+            Mode::StepOver(_) | Mode::StepOut(_) => None,
+        }
+    }
+
+    /// The children of the handle at `index`, sorted by name.
+    fn variables(&self, index: usize, interpreter: &Interpreter) -> Vec<(String, Value)> {
+        let sorted = |vars: &Vars| {
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect::<BTreeMap<String, Value>>()
+                .into_iter()
+                .collect()
+        };
+
+        match &self.handles[index] {
+            Handle::Locals(frame) => self.frames[*frame]
+                .locals
+                .and_then(|locals| interpreter.env.frames.get(locals))
+                .map(sorted)
+                .unwrap_or_default(),
+            Handle::Globals(frame) => sorted(&self.frames[*frame].globals.borrow()),
+            Handle::Value(Value::List(list)) => list
+                .iter()
+                .enumerate()
+                .map(|(i, value)| (format!("[{}]", i), value.clone()))
+                .collect(),
+            Handle::Value(Value::Map(map)) => map
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            Handle::Value(_) => vec![],
+        }
+    }
+
+    fn variable(&mut self, name: String, value: Value) -> Json {
+        let display = match &value {
+            Value::String(string) => format!("{:?}", string),
+            value => value.to_string(),
+        };
+
+        let kind = value.type_name();
+
+        let reference = match &value {
+            Value::List(list) if !list.is_empty() => {
+                self.handles.push(Handle::Value(value));
+                self.handles.len()
+            }
+            Value::Map(map) if !map.is_empty() => {
+                self.handles.push(Handle::Value(value));
+                self.handles.len()
+            }
+            _ => 0,
+        };
+
+        json!({
+            "name": name,
+            "value": display,
+            "type": kind,
+            "variablesReference": reference,
+        })
+    }
+}
+
+/// The debugger attached to the interpreter, sharing the adapter with `run`.
+struct Session(Rc<RefCell<Adapter>>);
+
+impl Debugger for Session {
+    fn before_form(
+        &mut self,
+        interpreter: &mut Interpreter,
+        sexpr: &SExpr,
+    ) -> Result<(), RuntimeError> {
+        let mut adapter = self.0.borrow_mut();
+
+        if adapter.terminated {
+            return Err(RuntimeError::with_code(
+                ErrorCode::DebuggerTerminated,
+                "Terminated by the debugger",
+            ));
+        }
+
+        let span = sexpr.span();
+
+        let Some(frame) = adapter.frames.last_mut() else {
+            return Ok(());
+        };
+
+        let new_line = frame.line != span.line;
+        frame.line = span.line;
+        frame.column = span.column;
+
+        let Some(reason) = adapter.stop_reason(span.line, new_line) else {
+            return Ok(());
+        };
+
+        adapter.sender.borrow_mut().event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+
+        loop {
+            let request = match adapter.read() {
+                Ok(Some(request)) => request,
+                Ok(None) | Err(_) => {
+                    adapter.terminated = true;
+                    return Err(RuntimeError::with_code(
+                        ErrorCode::DebuggerTerminated,
+                        "Debugger disconnected",
+                    ));
+                }
+            };
+
+            match adapter.handle(&request, Some(interpreter)) {
+                Flow::Stay | Flow::Launch { .. } => {}
+                Flow::Resume => {
+                    adapter.handles.clear();
+                    return Ok(());
+                }
+                Flow::Disconnect => {
+                    return Err(RuntimeError::with_code(
+                        ErrorCode::DebuggerTerminated,
+                        "Terminated by the debugger",
+                    ));
+                }
+            }
+        }
+    }
+
+    fn enter(&mut self, interpreter: &mut Interpreter, name: &str, file: Option<&Path>) {
+        // Modules are evaluated without frames, functions are entered after theirs is pushed
+        let is_function = !interpreter.env.frames.is_empty();
+
+        self.0.borrow_mut().frames.push(Frame {
+            name: name.to_string(),
+            file: file.map(Path::to_path_buf),
+            line: 0,
+            column: 0,
+            globals: interpreter.env.vars.clone(),
+            locals: is_function.then(|| interpreter.env.frames.len() - 1),
+        });
+    }
+
+    fn exit(&mut self) {
+        self.0.borrow_mut().frames.pop();
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or(path.to_path_buf())
+}
+
+/// Lines on which a list form starts, where the interpreter can stop.
+fn form_lines(path: &Path) -> BTreeSet<usize> {
+    fn collect(sexpr: &SExpr, lines: &mut BTreeSet<usize>) {
+        if let SExpr::List(list, span) = sexpr {
+            lines.insert(span.line);
+
+            for sexpr in list {
+                collect(sexpr, lines);
+            }
+        }
+    }
+
+    let mut lines = BTreeSet::new();
+
+    let sexprs = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| Parser::new(&content).parse().ok())
+        .unwrap_or_default();
+
+    for sexpr in &sexprs {
+        collect(sexpr, &mut lines);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Buffer;
+
+    fn frame(seq: i64, command: &str, arguments: Json) -> String {
+        let body = json!({
+            "seq": seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn messages(output: &[u8]) -> Vec<Json> {
+        String::from_utf8_lossy(output)
+            .split("Content-Length: ")
+            .filter_map(|message| message.split_once("\r\n\r\n"))
+            .map(|(_, body)| serde_json::from_str(body).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_breakpoint_session() {
+        let dir = std::env::temp_dir().join(format!("kk-dap-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let program = dir.join("main.kk");
+        std::fs::write(
+            &program,
+            "(defn double (x)\n  (add x x))\n\n(let a (double 2))\n(print a)\n",
+        )
+        .unwrap();
+
+        let path = program.display().to_string();
+
+        let input = [
+            frame(1, "initialize", json!({})),
+            frame(2, "launch", json!({ "program": path })),
+            frame(
+                3,
+                "setBreakpoints",
+                json!({ "source": { "path": path }, "breakpoints": [{ "line": 2 }] }),
+            ),
+            frame(4, "configurationDone", json!({})),
+            frame(5, "stackTrace", json!({ "threadId": 1 })),
+            frame(6, "scopes", json!({ "frameId": 1 })),
+            frame(7, "variables", json!({ "variablesReference": 1 })),
+            frame(8, "evaluate", json!({ "expression": "(add x 1)" })),
+            frame(9, "continue", json!({})),
+            frame(10, "disconnect", json!({})),
+        ]
+        .concat();
+
+        let output = Buffer::default();
+        run(
+            Box::new(std::io::Cursor::new(input.into_bytes())),
+            Box::new(output.clone()),
+        )
+        .unwrap();
+
+        let messages = messages(&output.contents());
+
+        let find = |predicate: &dyn Fn(&Json) -> bool| {
+            messages
+                .iter()
+                .find(|message| predicate(message))
+                .unwrap_or_else(|| panic!("missing message in {:#?}", messages))
+                .clone()
+        };
+
+        let response = |seq: i64| find(&|message| message["request_seq"] == seq);
+
+        assert_eq!(response(3)["body"]["breakpoints"][0]["verified"], true);
+
+        let stopped = find(&|message| message["event"] == "stopped");
+        assert_eq!(stopped["body"]["reason"], "breakpoint");
+
+        let frames = &response(5)["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "double");
+        assert_eq!(frames[0]["line"], 2);
+        assert_eq!(frames[1]["line"], 4);
+
+        assert_eq!(response(6)["body"]["scopes"][0]["name"], "Locals");
+        let locals = response(7)["body"]["variables"].clone();
+        let x = locals
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|var| var["name"] == "x");
+        assert_eq!(x.unwrap()["value"], "2");
+        assert_eq!(response(8)["body"]["result"], "3");
+
+        let output = find(&|message| message["event"] == "output");
+        assert_eq!(output["body"]["output"], "4\n");
+
+        let exited = find(&|message| message["event"] == "exited");
+        assert_eq!(exited["body"]["exitCode"], 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! The terminal debugger of `kk --debug`.
+//!
+//! The script runs until it evaluates a `(breakpoint)` form, then a prompt opens where
+//! variables can be inspected, expressions evaluated and the script stepped one form at a
+//! time.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::interpreter::{Debugger, Interpreter};
+use crate::sexpr::SExpr;
+use crate::value::Value;
+
+const HELP: &str = "\
+Commands:
+  s, step          Evaluate up to the next form (also an empty line)
+  c, continue      Run until the next (breakpoint)
+  p, print <name>  Print a variable
+  e, eval <expr>   Evaluate an expression here
+  v, vars          List the variables of the current function, or the globals
+  w, where         Show the functions being evaluated
+  q, quit          Stop the script
+  h, help          Print this help";
+
+/// A function or module body being evaluated.
+struct Frame {
+    name: String,
+    file: Option<PathBuf>,
+}
+
+struct Prompt {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    /// Stop before the next form, after `step`.
+    stepping: bool,
+    frames: Vec<Frame>,
+}
+
+/// Attaches the debugger to `interpreter`, reading commands from `input` and writing the
+/// prompt to `output`.
+pub fn attach(interpreter: &mut Interpreter, input: Box<dyn BufRead>, output: Box<dyn Write>) {
+    interpreter.debugger = Some(Box::new(Prompt {
+        input,
+        output,
+        stepping: false,
+        frames: vec![],
+    }));
+}
+
+impl Prompt {
+    /// Reads and runs commands until one resumes the script.
+    fn stop(&mut self, interpreter: &mut Interpreter, sexpr: &SExpr) -> Result<(), RuntimeError> {
+        self.stepping = false;
+        self.location(sexpr);
+
+        loop {
+            let _ = write!(self.output, "(debug) ");
+            let _ = self.output.flush();
+
+            let mut line = String::new();
+
+            if !matches!(self.input.read_line(&mut line), Ok(n) if n > 0) {
+                return Err(terminated("Debugger input closed"));
+            }
+
+            let (command, argument) = match line.trim().split_once(' ') {
+                Some((command, argument)) => (command, argument.trim()),
+                None => (line.trim(), ""),
+            };
+
+            match command {
+                "" | "s" | "step" => {
+                    self.stepping = true;
+                    return Ok(());
+                }
+                "c" | "continue" => return Ok(()),
+                "q" | "quit" => return Err(terminated("Terminated by the debugger")),
+                "p" | "print" => match interpreter.env.get(argument) {
+                    Some(value) => self.print(&format!("{} = {}", argument, value.repr())),
+                    None => self.print(&format!("{} is not defined", argument)),
+                },
+                "e" | "eval" => match interpreter.eval_str(argument) {
+                    Ok(value) => self.print(&format!("=> {}", value.repr())),
+                    Err(err) => {
+                        interpreter.take_trace();
+                        self.print(&format!("error: {}", err));
+                    }
+                },
+                "v" | "vars" => {
+                    let vars = match interpreter.env.frames.last() {
+                        Some(frame) => frame.clone().into_iter().collect::<BTreeMap<_, _>>(),
+                        None => interpreter.env.vars.borrow().clone().into_iter().collect(),
+                    };
+
+                    for (name, value) in vars {
+                        // Builtins live among the globals
+                        if !matches!(value, Value::Native(_)) {
+                            self.print(&format!("{} = {}", name, value.repr()));
+                        }
+                    }
+                }
+                "w" | "where" => {
+                    let frames = self
+                        .frames
+                        .iter()
+                        .rev()
+                        .map(|frame| match &frame.file {
+                            Some(file) => format!("  in {} ({})", frame.name, file.display()),
+                            None => format!("  in {}", frame.name),
+                        })
+                        .collect::<Vec<_>>();
+
+                    self.location(sexpr);
+                    frames.iter().for_each(|frame| self.print(frame));
+                }
+                "h" | "help" => self.print(HELP),
+                command => self.print(&format!("Unknown command {:?}, try help", command)),
+            }
+        }
+    }
+
+    /// Prints where the script stopped: the file, position and form.
+    fn location(&mut self, sexpr: &SExpr) {
+        let span = sexpr.span();
+
+        let file = self
+            .frames
+            .iter()
+            .rev()
+            .find_map(|frame| frame.file.as_ref())
+            .map(|file| format!("{}:", file.display()))
+            .unwrap_or_default();
+
+        let mut form = sexpr.to_string();
+
+        if form.chars().count() > 60 {
+            form = form.chars().take(57).collect::<String>() + "...";
+        }
+
+        self.print(&format!("{}{}:{} {}", file, span.line, span.column, form));
+    }
+
+    fn print(&mut self, text: &str) {
+        let _ = writeln!(self.output, "{}", text);
+    }
+}
+
+impl Debugger for Prompt {
+    fn before_form(
+        &mut self,
+        interpreter: &mut Interpreter,
+        sexpr: &SExpr,
+    ) -> Result<(), RuntimeError> {
+        match self.stepping {
+            true => self.stop(interpreter, sexpr),
+            false => Ok(()),
+        }
+    }
+
+    fn enter(&mut self, _interpreter: &mut Interpreter, name: &str, file: Option<&Path>) {
+        self.frames.push(Frame {
+            name: name.to_string(),
+            file: file.map(Path::to_path_buf),
+        });
+    }
+
+    fn exit(&mut self) {
+        self.frames.pop();
+    }
+
+    fn breakpoint(
+        &mut self,
+        interpreter: &mut Interpreter,
+        sexpr: &SExpr,
+    ) -> Result<(), RuntimeError> {
+        self.stop(interpreter, sexpr)
+    }
+}
+
+fn terminated(message: &str) -> RuntimeError {
+    RuntimeError::with_code(ErrorCode::DebuggerTerminated, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Buffer;
+
+    #[test]
+    fn test_debug_prompt() {
+        let commands = "p n\ne (add n 1)\nvars\nstep\nstep\nc\n";
+        let output = Buffer::default();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(|_| {});
+        attach(
+            &mut interpreter,
+            Box::new(commands.as_bytes()),
+            Box::new(output.clone()),
+        );
+
+        let result = interpreter
+            .eval_str("(defn f (n) (breakpoint) (let m (add n 1)) m) (f 41)")
+            .unwrap();
+        assert_eq!(result.to_string(), "42");
+
+        let output = String::from_utf8(output.contents()).unwrap();
+
+        assert_eq!(
+            output,
+            "1:13 (breakpoint)\n\
+             (debug) n = 41\n\
+             (debug) => 42\n\
+             (debug) f = <fn f>\n\
+             n = 41\n\
+             (debug) 1:26 (let m (add n 1))\n\
+             (debug) 1:33 (add n 1)\n\
+             (debug) "
+        );
+
+        let err = interpreter.eval_str("(breakpoint)").unwrap_err();
+        assert_eq!(err.to_string(), "Debugger input closed");
+    }
+}
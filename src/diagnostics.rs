@@ -0,0 +1,407 @@
+//! Stable codes for every error and warning kk reports, and the longer explanations shown
+//! by `kk explain <code>`. Codes are never reused or renumbered.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::sexpr::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnknownFunction,
+    Arity,
+    UndefinedVariable,
+    TypeMismatch,
+    MalformedForm,
+    UncaughtException,
+    Parse,
+    Io,
+    ModuleNotFound,
+    CyclicImport,
+    UndefinedExport,
+    InvalidManifest,
+    CapabilityDenied,
+    VersionMismatch,
+    InvalidArgument,
+    Destructuring,
+    DebuggerTerminated,
+    Native,
+    DeniedWarning,
+    OutOfFuel,
+    AssertionFailed,
+    RecursionLimit,
+}
+
+impl ErrorCode {
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::UnknownFunction,
+        ErrorCode::Arity,
+        ErrorCode::UndefinedVariable,
+        ErrorCode::TypeMismatch,
+        ErrorCode::MalformedForm,
+        ErrorCode::UncaughtException,
+        ErrorCode::Parse,
+        ErrorCode::Io,
+        ErrorCode::ModuleNotFound,
+        ErrorCode::CyclicImport,
+        ErrorCode::UndefinedExport,
+        ErrorCode::InvalidManifest,
+        ErrorCode::CapabilityDenied,
+        ErrorCode::VersionMismatch,
+        ErrorCode::InvalidArgument,
+        ErrorCode::Destructuring,
+        ErrorCode::DebuggerTerminated,
+        ErrorCode::Native,
+        ErrorCode::DeniedWarning,
+        ErrorCode::OutOfFuel,
+        ErrorCode::AssertionFailed,
+        ErrorCode::RecursionLimit,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorCode::UnknownFunction => "E0001",
+            ErrorCode::Arity => "E0002",
+            ErrorCode::UndefinedVariable => "E0003",
+            ErrorCode::TypeMismatch => "E0004",
+            ErrorCode::MalformedForm => "E0005",
+            ErrorCode::UncaughtException => "E0006",
+            ErrorCode::Parse => "E0007",
+            ErrorCode::Io => "E0008",
+            ErrorCode::ModuleNotFound => "E0009",
+            ErrorCode::CyclicImport => "E0010",
+            ErrorCode::UndefinedExport => "E0011",
+            ErrorCode::InvalidManifest => "E0012",
+            ErrorCode::CapabilityDenied => "E0013",
+            ErrorCode::VersionMismatch => "E0014",
+            ErrorCode::InvalidArgument => "E0015",
+            ErrorCode::Destructuring => "E0016",
+            ErrorCode::DebuggerTerminated => "E0017",
+            ErrorCode::Native => "E0018",
+            ErrorCode::DeniedWarning => "E0019",
+            ErrorCode::OutOfFuel => "E0020",
+            ErrorCode::AssertionFailed => "E0021",
+            ErrorCode::RecursionLimit => "E0022",
+        }
+    }
+
+    /// Looks up a code such as `"E0002"`, case-insensitively.
+    pub fn from_code(code: &str) -> Option<ErrorCode> {
+        ErrorCode::ALL
+            .iter()
+            .copied()
+            .find(|error| error.code().eq_ignore_ascii_case(code))
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            ErrorCode::UnknownFunction => "unknown function",
+            ErrorCode::Arity => "wrong number of arguments",
+            ErrorCode::UndefinedVariable => "undefined variable",
+            ErrorCode::TypeMismatch => "type mismatch",
+            ErrorCode::MalformedForm => "malformed form",
+            ErrorCode::UncaughtException => "uncaught exception",
+            ErrorCode::Parse => "parse error",
+            ErrorCode::Io => "i/o error",
+            ErrorCode::ModuleNotFound => "module not found",
+            ErrorCode::CyclicImport => "cyclic import",
+            ErrorCode::UndefinedExport => "export of an undefined name",
+            ErrorCode::InvalidManifest => "invalid manifest",
+            ErrorCode::CapabilityDenied => "capability not granted",
+            ErrorCode::VersionMismatch => "unsupported kk version",
+            ErrorCode::InvalidArgument => "invalid named argument",
+            ErrorCode::Destructuring => "destructuring mismatch",
+            ErrorCode::DebuggerTerminated => "terminated by the debugger",
+            ErrorCode::Native => "error in a native function",
+            ErrorCode::DeniedWarning => "warning denied by --deny-warnings",
+            ErrorCode::OutOfFuel => "out of fuel",
+            ErrorCode::AssertionFailed => "assertion failed",
+            ErrorCode::RecursionLimit => "recursion limit exceeded",
+        }
+    }
+
+    /// The text shown by `kk explain`.
+    pub fn explanation(self) -> &'static str {
+        match self {
+            ErrorCode::UnknownFunction => {
+                "A list was evaluated as a call, but its first element is neither a builtin \
+                 nor a function in scope.\n\n    (greet \"kk\")   ; no (defn greet ...) before this\n\n\
+                 Check the spelling, define the function before calling it, or import the \
+                 module that defines it."
+            }
+            ErrorCode::Arity => {
+                "A function was called with too many or too few arguments.\n\n    \
+                 (defn sum (a b) (add a b))\n    (sum 2)   ; sum expects 2 arguments\n\n\
+                 Pass every required parameter, or give the parameter a default: \
+                 (defn sum (a (b 0)) ...)."
+            }
+            ErrorCode::UndefinedVariable => {
+                "A name was read before anything was bound to it.\n\n    (print (get total))   \
+                 ; no (let total ...) before this\n\nBind the variable with let first, or use \
+                 (?? x default) where the value is optional."
+            }
+            ErrorCode::TypeMismatch => {
+                "A form received a value of a different type than it works on.\n\n    \
+                 (add 1 \"2\")   ; add expects numbers\n\nConvert the value first, or check \
+                 which value reaches the form."
+            }
+            ErrorCode::MalformedForm => {
+                "A special form is missing a part or has one too many.\n\n    (let)   \
+                 ; expected (let <name> <value>)\n\nThe message names the part that was \
+                 expected at the reported location."
+            }
+            ErrorCode::UncaughtException => {
+                "A value raised with (throw value) was not caught.\n\n    (throw \"bad input\")\n\n\
+                 Wrap the code in (try ... (catch e ...)), or use (or-else expr fallback)."
+            }
+            ErrorCode::Parse => {
+                "The source could not be read as s-expressions, usually because of unbalanced \
+                 parentheses or an unterminated string.\n\n    (print \"hello)\n\nThe message \
+                 gives the line and column where reading failed."
+            }
+            ErrorCode::Io => {
+                "A file or stream could not be read or written.\n\n    kk missing.kk\n\n\
+                 Check that the path exists and is readable."
+            }
+            ErrorCode::ModuleNotFound => {
+                "An import names a module that could not be found.\n\n    (import \"utils\")\n\n\
+                 Relative paths (\"./utils.kk\") are resolved from the importing file; bare \
+                 names are looked up in kk_modules/. Use `kk add` to vendor a module."
+            }
+            ErrorCode::CyclicImport => {
+                "Two or more modules import each other, so none of them can finish loading.\n\n\
+                 \x20   ; a.kk: (import \"./b.kk\")\n    ; b.kk: (import \"./a.kk\")\n\n\
+                 Move the shared code into a third module, or defer one side with import-lazy."
+            }
+            ErrorCode::UndefinedExport => {
+                "A module lists a name in (export ...) that it never defines.\n\n    \
+                 (export helper)   ; but no (defn helper ...)\n\nDefine the name or remove \
+                 it from the export list."
+            }
+            ErrorCode::InvalidManifest => {
+                "The (manifest ...) form is malformed or is not the first form of the file.\n\n\
+                 \x20   (manifest :name \"tool\" :requires (net))\n\nKeys are keywords followed \
+                 by a value; :requires takes a list of capabilities."
+            }
+            ErrorCode::CapabilityDenied => {
+                "The script's manifest requires a capability that was not granted.\n\n    \
+                 (manifest :requires (net))\n\nRun it with `kk --allow net script.kk` if \
+                 you trust it."
+            }
+            ErrorCode::VersionMismatch => {
+                "The script requires a kk version this interpreter does not satisfy.\n\n    \
+                 (require-version \">=0.3\")\n\nUpgrade kk, or check the requirement with \
+                 `kk --version`."
+            }
+            ErrorCode::InvalidArgument => {
+                "A keyword argument does not match the called function's parameters, or \
+                 repeats a positional one.\n\n    (defn greet (name) ...)\n    (greet \
+                 :nmae \"kk\")\n\nUse the parameter names from the function's definition."
+            }
+            ErrorCode::Destructuring => {
+                "A value does not have the shape of the let or parameter pattern it is bound \
+                 to.\n\n    \
+                 (let (a b) (list 1 2 3))   ; three values, two names\n\nMatch the number \
+                 of names to the list, or the names to the map's keys."
+            }
+            ErrorCode::DebuggerTerminated => {
+                "The script was stopped by the attached debugger (a disconnect or terminate \
+                 request, or quit at the kk --debug prompt). Nothing in the script caused it."
+            }
+            ErrorCode::Native => {
+                "A function implemented in Rust (a builtin or one registered with \
+                 Interpreter::register_fn) reported an error. The message comes from that \
+                 function."
+            }
+            ErrorCode::DeniedWarning => {
+                "A warning was raised while running with --deny-warnings, which turns every \
+                 warning into an error.\n\n    kk --deny-warnings script.kk\n\nFix the code \
+                 the warning points at, or wrap it in (suppress-warnings \"<name>\" ...) if \
+                 it is intended. `kk explain` lists the warning codes."
+            }
+            ErrorCode::OutOfFuel => {
+                "The script used up the instruction budget its embedder gave it with \
+                 Interpreter::set_fuel. Every form costs one unit; builtins cost more \
+                 depending on their category and the size of their arguments. The error \
+                 cannot be caught with try or or-else."
+            }
+            ErrorCode::AssertionFailed => {
+                "An assert or assert-eq did not hold.\n\n    (deftest \"sum\" (assert-eq \
+                 (add 1 1) 3))\n\nassert-eq takes the actual value first and the expected \
+                 one second; the message shows both. For large lists and maps, \
+                 assert-eq-detailed shows only the places where they differ."
+            }
+            ErrorCode::RecursionLimit => {
+                "Forms nested deeper than the interpreter allows, usually because a function \
+                 called itself too many times without returning.\n\n    (defn down (n) (if \
+                 (eq n 0) 0 else (add 1 (down (add n -1)))))\n    (down 100000)\n\nEvery \
+                 form being evaluated counts, including those of the functions being called. \
+                 A call in tail position doesn't nest, so an accumulator avoids the limit; \
+                 kk --max-depth raises it."
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    ImplicitFloat,
+    Shadowing,
+    Deprecated,
+    UnreachableArm,
+    NonExhaustive,
+    SlowBuiltin,
+}
+
+impl WarningKind {
+    pub const ALL: &'static [WarningKind] = &[
+        WarningKind::ImplicitFloat,
+        WarningKind::Shadowing,
+        WarningKind::Deprecated,
+        WarningKind::UnreachableArm,
+        WarningKind::NonExhaustive,
+        WarningKind::SlowBuiltin,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            WarningKind::ImplicitFloat => "W0001",
+            WarningKind::Shadowing => "W0002",
+            WarningKind::Deprecated => "W0003",
+            WarningKind::UnreachableArm => "W0004",
+            WarningKind::NonExhaustive => "W0005",
+            WarningKind::SlowBuiltin => "W0006",
+        }
+    }
+
+    /// The name used by `suppress-warnings`.
+    pub fn name(self) -> &'static str {
+        match self {
+            WarningKind::ImplicitFloat => "implicit-float",
+            WarningKind::Shadowing => "shadowing",
+            WarningKind::Deprecated => "deprecated",
+            WarningKind::UnreachableArm => "unreachable-arm",
+            WarningKind::NonExhaustive => "non-exhaustive",
+            WarningKind::SlowBuiltin => "slow-builtin",
+        }
+    }
+
+    /// Looks up a warning by code (`"W0002"`) or name (`"shadowing"`).
+    pub fn find(code_or_name: &str) -> Option<WarningKind> {
+        WarningKind::ALL.iter().copied().find(|warning| {
+            warning.code().eq_ignore_ascii_case(code_or_name) || warning.name() == code_or_name
+        })
+    }
+
+    pub fn explanation(self) -> &'static str {
+        match self {
+            WarningKind::ImplicitFloat => {
+                "An int was mixed with a float and converted to a float, which may lose \
+                 precision or hide a missing conversion.\n\n    (add 1 0.5)\n\nWrite the \
+                 int as a float (1.0) where the mix is intended."
+            }
+            WarningKind::Shadowing => {
+                "A let inside a function binds a name that is also a global or a builtin, \
+                 hiding it for the rest of the function.\n\n    (let total 0)\n    (defn f () \
+                 (let total 1))\n\nRename the local variable."
+            }
+            WarningKind::Deprecated => {
+                "A deprecated function was called. The warning says what to use instead; the \
+                 function may be removed in a future version."
+            }
+            WarningKind::UnreachableArm => {
+                "An arm of a match or case can never be chosen, because an earlier arm without \
+                 a guard matches every value or the same literal.\n\n    (case n (else \
+                 \"any\") (0 \"zero\"))\n\nMove the catch-all arm last, or remove the \
+                 duplicate."
+            }
+            WarningKind::NonExhaustive => {
+                "A match or case over literals has no catch-all arm, so any other value falls \
+                 through and the form returns void.\n\n    (case color (\"red\" 1) (\"green\" \
+                 2))\n\nAdd an (else ...) arm to case or a (_ ...) arm to match. Arms for \
+                 both true and false count as exhaustive. A match over the variants of a \
+                 defenum must have an unguarded arm for each of them."
+            }
+            WarningKind::SlowBuiltin => {
+                "A single call to a builtin took longer than the threshold given to \
+                 --warn-slow, such as a regex over a huge string or a sort of a million \
+                 elements. Each call site is reported once.\n\n    kk --warn-slow 100 \
+                 script.kk\n\nCall the builtin on less data, or outside the hot loop."
+            }
+        }
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// How serious a diagnostic is. Warnings don't stop a script unless denied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A located message for the user, shared by runtime warnings and static checks.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<PathBuf>,
+    pub span: Option<Span>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+
+        match (&self.file, self.span) {
+            (Some(file), Some(span)) => {
+                write!(f, " ({}:{}:{})", file.display(), span.line, span.column)
+            }
+            (None, Some(span)) => write!(f, " ({})", span),
+            (Some(file), None) => write!(f, " ({})", file.display()),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_unique_and_sequential() {
+        for (i, error) in ErrorCode::ALL.iter().enumerate() {
+            assert_eq!(error.code(), format!("E{:04}", i + 1));
+            assert_eq!(ErrorCode::from_code(error.code()), Some(*error));
+        }
+
+        assert_eq!(ErrorCode::from_code("e0002"), Some(ErrorCode::Arity));
+        assert_eq!(ErrorCode::from_code("E9999"), None);
+
+        for (i, warning) in WarningKind::ALL.iter().enumerate() {
+            assert_eq!(warning.code(), format!("W{:04}", i + 1));
+            assert_eq!(WarningKind::find(warning.name()), Some(*warning));
+        }
+    }
+}
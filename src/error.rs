@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use crate::diagnostics::ErrorCode;
+use crate::sexpr::Span;
+use crate::value::Value;
+
+#[derive(Debug, Clone)]
+pub enum RuntimeError {
+    /// A call to a name that is neither a builtin nor a user function.
+    UnknownFunction(String),
+    /// A reference to a variable that isn't bound.
+    UndefinedVariable(String),
+    /// A value of the wrong type was passed to a form.
+    TypeMismatch { expected: String, found: String },
+    /// A function was called with the wrong number of arguments.
+    Arity {
+        function: String,
+        expected: String,
+        found: usize,
+    },
+    /// A form is malformed, e.g. a missing variable name in `let`. `span` is the form's
+    /// position in the source, filled in by the evaluator.
+    Syntax { message: String, span: Option<Span> },
+    /// Any other failure, such as I/O or import errors, with the code that classifies it.
+    Error { code: ErrorCode, message: String },
+    /// A value raised by the script with `(throw value)`.
+    Thrown(Value),
+}
+
+impl RuntimeError {
+    /// An error raised by a native function.
+    pub fn new(message: impl Into<String>) -> Self {
+        RuntimeError::with_code(ErrorCode::Native, message)
+    }
+
+    pub fn with_code(code: ErrorCode, message: impl Into<String>) -> Self {
+        RuntimeError::Error {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn syntax(message: impl Into<String>) -> Self {
+        RuntimeError::Syntax {
+            message: message.into(),
+            span: None,
+        }
+    }
+
+    /// Attaches the position of the form being evaluated to a syntax error that has none yet.
+    pub fn at(self, location: Span) -> Self {
+        match self {
+            RuntimeError::Syntax {
+                message,
+                span: None,
+            } if location != Span::default() => RuntimeError::Syntax {
+                message,
+                span: Some(location),
+            },
+            err => err,
+        }
+    }
+
+    pub fn type_mismatch(expected: &str, found: &Value) -> Self {
+        RuntimeError::TypeMismatch {
+            expected: expected.to_string(),
+            found: found.type_name().to_string(),
+        }
+    }
+
+    /// The stable code printed as `error[E0002]` and explained by `kk explain`.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            RuntimeError::UnknownFunction(_) => ErrorCode::UnknownFunction,
+            RuntimeError::UndefinedVariable(_) => ErrorCode::UndefinedVariable,
+            RuntimeError::TypeMismatch { .. } => ErrorCode::TypeMismatch,
+            RuntimeError::Arity { .. } => ErrorCode::Arity,
+            RuntimeError::Syntax { .. } => ErrorCode::MalformedForm,
+            RuntimeError::Error { code, .. } => *code,
+            RuntimeError::Thrown(_) => ErrorCode::UncaughtException,
+        }
+    }
+
+    /// Whether `try` and `or-else` may recover from the error. Running out of fuel and being
+    /// stopped by the debugger always end the script.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(
+            self.code(),
+            ErrorCode::OutOfFuel | ErrorCode::DebuggerTerminated
+        )
+    }
+
+    /// The value bound to the variable of a `catch` clause.
+    pub fn to_value(&self) -> Value {
+        match self {
+            RuntimeError::Thrown(value) => value.clone(),
+            err => Value::String(err.to_string().into()),
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::UnknownFunction(name) => write!(f, "Unknown function: {}", name),
+            RuntimeError::UndefinedVariable(name) => write!(f, "Variable not found: {}", name),
+            RuntimeError::TypeMismatch { expected, found } => {
+                write!(f, "Type mismatch: expected {}, found {}", expected, found)
+            }
+            RuntimeError::Arity {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Function {} expects {} arguments, got {}",
+                function, expected, found
+            ),
+            RuntimeError::Syntax { message, span } => match span {
+                Some(span) => write!(f, "{} at {}", message, span),
+                None => write!(f, "{}", message),
+            },
+            RuntimeError::Error { message, .. } => write!(f, "{}", message),
+            RuntimeError::Thrown(value) => write!(f, "Uncaught exception: {}", value),
+        }
+    }
+}
+
+/// A form a runtime error propagated through, used to print a trace of where it happened.
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    /// The form's source text, shortened to a single line.
+    pub form: String,
+    /// The file the form was read from, when it came from one.
+    pub file: Option<PathBuf>,
+    pub span: Span,
+}
+
+impl std::fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(
+                f,
+                "at {} ({}:{}:{})",
+                self.form,
+                file.display(),
+                self.span.line,
+                self.span.column
+            ),
+            None => write!(f, "at {} ({})", self.form, self.span),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! The canonical layout of kk source, printed by `kk fmt`.
+//!
+//! A form stays on one line when it fits in `WIDTH` columns. Otherwise its head and
+//! header arguments (such as the name and parameters of `defn`) stay on the first line and
+//! every other argument goes on its own line, indented by two spaces. Comments are kept:
+//! those on their own line before the next form, those after code at the end of its line.
+
+use crate::parser::Parser;
+use crate::sexpr::SExpr;
+
+/// Maximum line width before forms are broken over several lines.
+const WIDTH: usize = 80;
+
+/// Forms whose first arguments stay next to the head when the form is broken.
+const HEADERS: &[(&str, usize)] = &[
+    ("defn", 2),
+    ("fn", 1),
+    ("lambda", 1),
+    ("let", 1),
+    ("set", 1),
+    ("if", 1),
+    ("unless", 1),
+    ("count", 5),
+    ("for", 3),
+    ("match", 1),
+    ("case", 1),
+    ("catch", 1),
+    ("deftest", 1),
+    ("defenum", 1),
+    ("defprotocol", 1),
+    ("extend-protocol", 2),
+    ("bench", 1),
+    ("suppress-warnings", 1),
+];
+
+struct Comment {
+    offset: usize,
+    text: String,
+    /// Whether only whitespace precedes the comment on its line.
+    own_line: bool,
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    comments: Vec<Comment>,
+    /// Index of the first comment not printed yet.
+    next: usize,
+    out: String,
+}
+
+/// Formats `source`, failing with the parser's message when it doesn't parse.
+pub fn format(source: &str) -> Result<String, String> {
+    let sexprs = Parser::new(source).parse().map_err(|err| err.to_string())?;
+
+    let mut printer = Printer {
+        source,
+        comments: comments(source),
+        next: 0,
+        out: String::new(),
+    };
+
+    // The parser skips a `#!` line, which must stay first
+    if source.starts_with("#!") {
+        printer
+            .out
+            .push_str(source.lines().next().unwrap_or_default());
+        printer.out.push('\n');
+    }
+
+    let mut previous_end = None;
+
+    for sexpr in &sexprs {
+        let start = sexpr.span().offset;
+
+        // One blank line is kept where the source had any between top-level forms
+        if let Some(end) = previous_end {
+            let blank = source[end..start]
+                .lines()
+                .skip(1)
+                .any(|line| line.trim().is_empty());
+
+            printer.out.push_str(if blank { "\n\n" } else { "\n" });
+        }
+
+        printer.comments_before(start, 0);
+        printer.print(sexpr, 0);
+        previous_end = Some(printer.end(sexpr));
+    }
+
+    printer.comments_before(usize::MAX, 0);
+
+    let mut out = printer.out.trim_end().to_string();
+
+    if !out.is_empty() {
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+impl Printer<'_> {
+    /// Prints `sexpr` at the current position; `indent` is the column of its first
+    /// character, where broken arguments are indented from.
+    fn print(&mut self, sexpr: &SExpr, indent: usize) {
+        let flat = sexpr.to_string();
+
+        // Interpolated strings are lists printed as the literal, which is never broken
+        let SExpr::List(list, span) = sexpr else {
+            self.out.push_str(&flat);
+            return;
+        };
+
+        if flat.starts_with('"') {
+            self.out.push_str(&flat);
+            return;
+        }
+
+        let end = self.end(sexpr);
+
+        let has_comments = self.comments[self.next..]
+            .iter()
+            .any(|comment| comment.offset > span.offset && comment.offset < end);
+
+        if !has_comments && !flat.contains('\n') && self.column() + flat.len() <= WIDTH {
+            self.out.push_str(&flat);
+            return;
+        }
+
+        self.out.push('(');
+
+        // Lists of forms, like the body of count, are aligned after the parenthesis
+        let (header, child_indent) = match list.first() {
+            Some(SExpr::Atom(head, _)) => {
+                let header = HEADERS
+                    .iter()
+                    .find(|(name, _)| head == name)
+                    .map_or(0, |(_, header)| *header);
+
+                (header + 1, indent + 2)
+            }
+            _ => (1, indent + 1),
+        };
+
+        let mut i = 0;
+
+        while i < list.len() {
+            let child = &list[i];
+
+            if i > 0 {
+                match i < header {
+                    true => self.out.push(' '),
+                    false => self.newline(child_indent),
+                }
+            }
+
+            self.comments_before(child.span().offset, child_indent);
+            let column = self.column();
+            self.print(child, column);
+
+            // `else` stays in front of the branch it introduces
+            if matches!(child, SExpr::Atom(atom, _) if atom == "else") && i + 1 < list.len() {
+                i += 1;
+                self.out.push(' ');
+                let column = self.column();
+                self.print(&list[i], column);
+            }
+
+            i += 1;
+        }
+
+        if self.has_comments_before(end) {
+            self.newline(child_indent);
+            self.comments_before(end, child_indent);
+            self.out.truncate(self.out.trim_end().len());
+            self.newline(child_indent);
+        }
+
+        self.out.push(')');
+    }
+
+    /// Prints the comments starting before `offset`, leaving the output at `indent` on a
+    /// new line after them.
+    fn comments_before(&mut self, offset: usize, indent: usize) {
+        while self.has_comments_before(offset) {
+            let comment = &self.comments[self.next];
+            let text = comment.text.clone();
+
+            if comment.own_line || self.out.trim_end().is_empty() {
+                if !self.at_line_start() {
+                    self.newline(indent);
+                }
+
+                self.out.push_str(&text);
+            } else {
+                // Back onto the line of the code the comment followed, keeping a blank line
+                let code = self.out.trim_end().len();
+                let blank = self.out[code..].matches('\n').count() > 1;
+
+                self.out.truncate(code);
+                self.out.push(' ');
+                self.out.push_str(&text);
+
+                if blank {
+                    self.out.push('\n');
+                }
+            }
+
+            self.newline(indent);
+            self.next += 1;
+        }
+    }
+
+    fn has_comments_before(&self, offset: usize) -> bool {
+        self.comments
+            .get(self.next)
+            .is_some_and(|comment| comment.offset < offset)
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out
+            .truncate(self.out.trim_end_matches([' ', '\t']).len());
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+    }
+
+    fn at_line_start(&self) -> bool {
+        let line = self.out.rsplit('\n').next().unwrap_or_default();
+        line.trim().is_empty()
+    }
+
+    fn column(&self) -> usize {
+        let line = self.out.rsplit('\n').next().unwrap_or_default();
+        line.chars().count()
+    }
+
+    /// The offset just past `sexpr` in the source.
+    fn end(&self, sexpr: &SExpr) -> usize {
+        match sexpr {
+            SExpr::Atom(atom, span) => span.offset + atom.len(),
+            SExpr::Keyword(keyword, span) => span.offset + 1 + keyword.len(),
+            // An interpolated string reads as a list, and `$${` as two characters
+            SExpr::String(_, span) | SExpr::List(_, span)
+                if self.source[span.offset..].starts_with('"') =>
+            {
+                let text = &self.source[span.offset + 1..];
+                span.offset + text.find('"').map_or(text.len(), |end| end + 2)
+            }
+            SExpr::String(string, span) => span.offset + string.len() + 2,
+            SExpr::List(list, span) => {
+                let mut offset = list.last().map_or(span.offset + 1, |last| self.end(last));
+                let mut in_comment = false;
+
+                // Only whitespace and comments are left before the closing parenthesis
+                for char in self.source[offset..].chars() {
+                    match char {
+                        ')' if !in_comment => return offset + 1,
+                        ';' => in_comment = true,
+                        '\n' => in_comment = false,
+                        _ => {}
+                    }
+
+                    offset += char.len_utf8();
+                }
+
+                offset
+            }
+        }
+    }
+}
+
+/// The `;` comments of `source`, outside of string literals and the `#!` line.
+fn comments(source: &str) -> Vec<Comment> {
+    let mut comments = vec![];
+    let mut in_string = false;
+    let mut line_start = true;
+    let mut chars = source.char_indices().peekable();
+
+    if source.starts_with("#!") {
+        while chars.next_if(|(_, char)| *char != '\n').is_some() {}
+    }
+
+    while let Some((offset, char)) = chars.next() {
+        match char {
+            '"' => in_string = !in_string,
+            ';' if !in_string => {
+                let mut text = String::from(';');
+
+                while let Some((_, char)) = chars.next_if(|(_, char)| *char != '\n') {
+                    text.push(char);
+                }
+
+                comments.push(Comment {
+                    offset,
+                    text: text.trim_end().to_string(),
+                    own_line: line_start,
+                });
+            }
+            _ => {}
+        }
+
+        match char {
+            '\n' => line_start = true,
+            ' ' | '\t' | '\r' => {}
+            _ => line_start = false,
+        }
+    }
+
+    comments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let source = "#!/usr/bin/env kk\n\
+            ; Greets everyone\n\
+            (defn greet (names) (count i from 0 to (len names) ((print \"Hello, \" (nth names i) \"!\") (inc greeted))))\n\
+            \n\n\
+            (if   (eq x 1)\n  (print \"one\")   ; the usual case\n else (print \"other\"))\n\
+            (let  xs (list 1 2\n  ; three\n  3))\n";
+
+        let formatted = format(source).unwrap();
+
+        assert_eq!(
+            formatted,
+            "#!/usr/bin/env kk\n\
+             ; Greets everyone\n\
+             (defn greet (names)\n  \
+               (count i from 0 to (len names)\n    \
+                 ((print \"Hello, \" (nth names i) \"!\") (inc greeted))))\n\
+             \n\
+             (if (eq x 1)\n  \
+               (print \"one\") ; the usual case\n  \
+               else (print \"other\"))\n\
+             (let xs\n  \
+               (list\n    \
+                 1\n    \
+                 2\n    \
+                 ; three\n    \
+                 3))\n"
+        );
+        assert_eq!(format(&formatted).unwrap(), formatted);
+        assert!(format("(print").is_err());
+    }
+}
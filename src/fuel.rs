@@ -0,0 +1,136 @@
+//! Instruction budgets for sandboxed scripts. Every evaluated form costs one unit of fuel;
+//! a call to a builtin costs the multiplier of its category instead, scaled by the size of
+//! its largest argument.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::value::Value;
+
+/// A builtin's argument adds one more multiple of its category's cost per this many list
+/// elements or string bytes.
+const SIZE_UNIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FuelCategory {
+    /// Builtins without a category, including those registered by the embedder.
+    Core,
+    String,
+    Io,
+    Regex,
+    Http,
+    Exec,
+    Sort,
+}
+
+impl FuelCategory {
+    pub fn default_multiplier(self) -> u64 {
+        match self {
+            FuelCategory::Core => 1,
+            FuelCategory::String => 2,
+            FuelCategory::Io => 10,
+            FuelCategory::Regex => 20,
+            FuelCategory::Sort => 5,
+            FuelCategory::Http | FuelCategory::Exec => 100,
+        }
+    }
+}
+
+/// A budget and its costs. Clones draw from the same fuel left, so the threads a script
+/// spawns share its budget.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Fuel {
+    /// Fuel left, or `None` for unlimited.
+    remaining: Option<Arc<AtomicU64>>,
+    multipliers: HashMap<FuelCategory, u64>,
+    categories: HashMap<String, FuelCategory>,
+}
+
+impl Fuel {
+    /// The fuel left, or `None` when unlimited.
+    pub(crate) fn remaining(&self) -> Option<u64> {
+        self.remaining
+            .as_ref()
+            .map(|remaining| remaining.load(Ordering::Relaxed))
+    }
+
+    /// Sets a new budget, no longer shared with the clones of this one.
+    pub(crate) fn set_remaining(&mut self, remaining: Option<u64>) {
+        self.remaining = remaining.map(|remaining| Arc::new(AtomicU64::new(remaining)));
+    }
+
+    pub(crate) fn set_multiplier(&mut self, category: FuelCategory, multiplier: u64) {
+        self.multipliers.insert(category, multiplier);
+    }
+
+    pub(crate) fn categorize(&mut self, builtin: &str, category: FuelCategory) {
+        self.categories.insert(builtin.to_string(), category);
+    }
+
+    /// Charges the evaluation of one form.
+    pub(crate) fn consume_form(&self) -> Result<(), RuntimeError> {
+        self.consume(1)
+    }
+
+    /// Charges the evaluation of `count` forms at once.
+    pub(crate) fn consume_forms(&self, count: u64) -> Result<(), RuntimeError> {
+        self.consume(count)
+    }
+
+    /// Charges a call to the builtin `name`.
+    pub(crate) fn consume_call(&self, name: &str, args: &[Value]) -> Result<(), RuntimeError> {
+        if self.remaining.is_none() {
+            return Ok(());
+        }
+
+        let category = self
+            .categories
+            .get(name)
+            .copied()
+            .unwrap_or(FuelCategory::Core);
+
+        let multiplier = self
+            .multipliers
+            .get(&category)
+            .copied()
+            .unwrap_or_else(|| category.default_multiplier());
+
+        let size = args
+            .iter()
+            .map(|arg| match arg {
+                Value::String(s) => s.len(),
+                Value::Bytes(b) => b.len(),
+                Value::List(list) => list.len(),
+                Value::Map(map) => map.len(),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0);
+
+        self.consume(multiplier.saturating_mul(1 + (size / SIZE_UNIT) as u64))
+    }
+
+    fn consume(&self, amount: u64) -> Result<(), RuntimeError> {
+        let Some(remaining) = &self.remaining else {
+            return Ok(());
+        };
+
+        let charged = remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+            left.checked_sub(amount)
+        });
+
+        match charged {
+            Ok(_) => Ok(()),
+            Err(_) => {
+                remaining.store(0, Ordering::Relaxed);
+                Err(RuntimeError::with_code(
+                    ErrorCode::OutOfFuel,
+                    "Out of fuel: the script exceeded its instruction budget",
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,4237 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::bench::BenchResult;
+use crate::diagnostics::{Diagnostic, ErrorCode, Severity, WarningKind};
+use crate::error::{RuntimeError, TraceFrame};
+use crate::fuel::{Fuel, FuelCategory};
+use crate::manifest::Manifest;
+use crate::rational::Rational;
+use crate::sexpr::{SExpr, Span};
+use crate::symbol::Symbol;
+use crate::value::{Function, NativeFunction, Param, Scope, Value, Variant, Vars};
+use crate::{lint, manifest, package, parser, stdlib, telemetry, version};
+
+mod cycles;
+mod vm;
+
+pub(crate) struct Env {
+    /// Globals of the module currently being evaluated.
+    pub(crate) vars: Scope,
+    pub(crate) frames: Vec<Vars>,
+}
+
+impl Env {
+    pub(crate) fn get(&self, name: impl Into<Symbol>) -> Option<Value> {
+        let name = name.into();
+
+        if let Some(value) = self.frames.last().and_then(|frame| frame.get(&name)) {
+            return Some(value.clone());
+        }
+
+        self.vars.borrow().get(&name).cloned()
+    }
+
+    /// Binds a variable in the innermost function frame, or globally at the top level.
+    fn define(&mut self, name: impl Into<Symbol>, value: Value) {
+        let name = name.into();
+
+        match self.frames.last_mut() {
+            Some(frame) => frame.insert(name, value),
+            None => self.vars.borrow_mut().insert(name, value),
+        };
+    }
+
+    /// Updates the visible binding of a variable, defining it if it doesn't exist yet.
+    fn set(&mut self, name: impl Into<Symbol>, value: Value) {
+        let name = name.into();
+
+        if let Some(frame) = self.frames.last_mut() {
+            if let Some(slot) = frame.get_mut(&name) {
+                *slot = value;
+                return;
+            }
+        }
+
+        if let Some(slot) = self.vars.borrow_mut().get_mut(&name) {
+            *slot = value;
+            return;
+        }
+
+        self.define(name, value);
+    }
+}
+
+/// Evaluates kk source. Each interpreter has its own global scope, which persists across
+/// calls to `eval_str`, `eval_source` and `eval_file`.
+pub struct Interpreter {
+    pub(crate) env: Env,
+    allowed_capabilities: Vec<String>,
+    pub(crate) file_stack: Vec<PathBuf>,
+    /// Names listed by `(export ...)` in each module being imported.
+    export_stack: Vec<Option<Vec<String>>>,
+    /// Modules imported with `import-lazy`, loaded into their scope on first unbound lookup.
+    lazy_imports: Vec<(Scope, PathBuf)>,
+    /// Exports of every module loaded so far, by canonical path, so each file is evaluated once.
+    modules: HashMap<PathBuf, Vec<(String, Value)>>,
+    /// Call scheduled from tail position, performed by the caller's `call_function` loop.
+    tail_call: Option<TailCall>,
+    /// Forms the current error propagated through, innermost first.
+    trace: Vec<TraceFrame>,
+    /// Echo the value of every top-level form of the entry script (`--print-results`).
+    print_results: bool,
+    pub(crate) debugger: Option<Box<dyn Debugger>>,
+    /// Receives the text written by `print`; `console` when unset.
+    output: Option<OutputSink>,
+    /// Receives the text written by `eprint` and `eprintln`; stderr when unset.
+    error_output: Option<OutputSink>,
+    /// Text printed by spawned threads on its way to `output` and `error_output`, which
+    /// only this thread can call.
+    relay: Option<(Sender<Printed>, Receiver<Printed>)>,
+    /// Where printed text goes without an `output` sink, shared with spawned threads.
+    pub(crate) console: Arc<Console>,
+    /// The id `console` tags this interpreter's lines with: 0, or that of its thread.
+    pub(crate) task: u64,
+    /// Printed text after the last newline, not written to `console` yet.
+    pending_output: String,
+    /// Whether `flush_output` left a line unfinished on the console.
+    mid_line: bool,
+    /// Source of the lines read by `input`; stdin when unset.
+    input: Option<Box<dyn BufRead>>,
+    /// Functions implemented in Rust and builtin constants, visible from every module
+    /// unless shadowed.
+    natives: Vars,
+    /// Names of the natives registered by `new`, which every interpreter has, and not
+    /// replaced by the host since.
+    builtins: HashSet<Symbol>,
+    /// Emit a telemetry span for every function call.
+    telemetry: bool,
+    /// Instruction budget, unlimited by default.
+    pub(crate) fuel: Fuel,
+    /// How `eq`, `ne` and `contains` compare values of different types.
+    pub(crate) eq_mode: EqMode,
+    /// What `/` returns for two ints.
+    pub(crate) div_mode: DivMode,
+    /// Receives warnings; stderr when unset.
+    warnings: Option<WarningSink>,
+    /// Turn every warning into an error (`--deny-warnings`).
+    deny_warnings: bool,
+    /// Builtin calls taking longer warn with `WarningKind::SlowBuiltin` (`--warn-slow`).
+    pub(crate) slow_builtin_threshold: Option<Duration>,
+    /// The file and offset of each call already warned about as slow.
+    slow_builtin_sites: HashSet<(Option<PathBuf>, usize)>,
+    /// Report the static checks of `lint` before evaluating each file (`--lint`).
+    lint: bool,
+    /// Run `count` bodies as bytecode; off with `--no-bytecode`.
+    bytecode: bool,
+    /// Expand environment variables in string literals (`--expand-env`).
+    pub(crate) expand_env: bool,
+    /// Receives a line per form entered and left (`--trace`); no logging when unset.
+    form_log: Option<OutputSink>,
+    /// Number of list forms being evaluated, for `form_log`.
+    form_depth: usize,
+    /// Number of list forms being evaluated, checked against `max_depth`.
+    depth: usize,
+    /// Deepest nesting of forms allowed before failing with `ErrorCode::RecursionLimit`.
+    pub(crate) max_depth: usize,
+    /// Warnings silenced by the enclosing `suppress-warnings` forms.
+    suppressed_warnings: Vec<WarningKind>,
+    /// Deprecated function names and what to use instead.
+    deprecated: HashMap<String, String>,
+    /// Tests declared with `deftest`, run by `kk test`.
+    tests: Vec<TestCase>,
+    /// Results of the `bench` forms evaluated so far, reported by `kk bench`.
+    bench_results: Vec<BenchResult>,
+    /// Variants declared with `defenum`, by name: their enum and number of fields. `match`
+    /// uses them to tell `(Circle r)` from a list pattern.
+    pub(crate) variants: HashMap<String, (String, usize)>,
+    /// Methods declared with `defprotocol`, by protocol name.
+    pub(crate) protocols: HashMap<String, Vec<String>>,
+    /// Implementations added by `extend-protocol`, by method name and type.
+    pub(crate) methods: HashMap<(String, String), Rc<Function>>,
+    /// Compiled `count` loops, by the address of their form, with a copy of the form.
+    chunks: HashMap<usize, (SExpr, Rc<vm::Chunk>)>,
+}
+
+/// Text a spawned thread printed, relayed to the output sinks of the interpreter that
+/// spawned it.
+pub(crate) enum Printed {
+    Output(String),
+    Error(String),
+}
+
+struct TestCase {
+    name: String,
+    body: Vec<SExpr>,
+    file: Option<PathBuf>,
+}
+
+/// How values of different types compare, see `Interpreter::set_eq_mode`. Ints and floats
+/// compare by value in every mode, and elements of lists and maps of different types are
+/// unequal rather than an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EqMode {
+    /// Comparing different types is a type mismatch error.
+    Strict,
+    /// Values of different types are never equal.
+    Unequal,
+    /// Numbers, numeric strings and bools are compared as numbers, and null equals void,
+    /// like JavaScript's `==`. Other values of different types are never equal.
+    Loose,
+}
+
+/// What `/` returns for two ints, see `Interpreter::set_div_mode`. Any float operand makes
+/// it a float division.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DivMode {
+    /// Always a float, so `(/ 7 2)` is 3.5.
+    Float,
+    /// The quotient truncated toward zero, like `idiv`.
+    Truncate,
+    /// An error: the script must pick `idiv` or `fdiv`.
+    Strict,
+}
+
+/// The writer behind `print` when no output sink is set, stdout by default. An interpreter
+/// and the threads it spawns share one, and each writes whole lines to it under its lock, so
+/// lines printed by concurrent threads never mix.
+pub(crate) struct Console {
+    writer: Mutex<Box<dyn Write + Send>>,
+    /// Prefix each line with the id of the task that printed it (`--trace-tasks`).
+    trace_tasks: AtomicBool,
+    /// The id of the last spawned thread; the main interpreter is task 0.
+    last_task: AtomicU64,
+}
+
+impl Console {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Console {
+        Console {
+            writer: Mutex::new(writer),
+            trace_tasks: AtomicBool::new(false),
+            last_task: AtomicU64::new(0),
+        }
+    }
+
+    /// An id for a new thread.
+    pub(crate) fn next_task(&self) -> u64 {
+        self.last_task.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Writes `text` for `task`; `line_start` says whether it begins a line.
+    fn write(&self, task: u64, text: &str, line_start: bool) {
+        let mut writer = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        let trace_tasks = self.trace_tasks.load(Ordering::Relaxed);
+
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            if trace_tasks && (i > 0 || line_start) {
+                let _ = write!(writer, "[task {}] ", task);
+            }
+
+            let _ = writer.write_all(line.as_bytes());
+        }
+
+        let _ = writer.flush();
+    }
+}
+
+type OutputSink = Box<dyn FnMut(&str)>;
+type WarningSink = Box<dyn FnMut(&Diagnostic)>;
+
+/// Hooks called by the interpreter while a debugger, such as the DAP server, is attached.
+pub(crate) trait Debugger {
+    /// Called before every list form is evaluated. An error aborts the evaluation.
+    fn before_form(
+        &mut self,
+        interpreter: &mut Interpreter,
+        sexpr: &SExpr,
+    ) -> Result<(), RuntimeError>;
+
+    /// Called when a module or function body starts evaluating.
+    fn enter(&mut self, interpreter: &mut Interpreter, name: &str, file: Option<&Path>);
+
+    /// Called when the body entered last is done, successfully or not.
+    fn exit(&mut self);
+
+    /// Called when a `(breakpoint)` form is evaluated. An error aborts the evaluation.
+    fn breakpoint(
+        &mut self,
+        _interpreter: &mut Interpreter,
+        _sexpr: &SExpr,
+    ) -> Result<(), RuntimeError> {
+        Ok(())
+    }
+}
+
+type TailCall = (Rc<Function>, Vec<Value>, Vec<(String, Value)>);
+
+/// Forms longer than this are shortened in error traces and `--trace` logs.
+const TRACE_FORM_WIDTH: usize = 60;
+
+/// `sexpr` as source, cut to `TRACE_FORM_WIDTH` characters.
+fn abbreviate(sexpr: &SExpr) -> String {
+    let form = sexpr.to_string();
+
+    if form.chars().count() > TRACE_FORM_WIDTH {
+        return form.chars().take(TRACE_FORM_WIDTH - 3).collect::<String>() + "...";
+    }
+
+    form
+}
+
+/// Whether `name` is a form evaluated by the interpreter itself rather than a function.
+pub(crate) fn is_special_form(name: &str) -> bool {
+    vm::SPECIAL_FORMS.contains(&name)
+}
+
+/// The names of every special form, for completion.
+pub(crate) fn special_forms() -> &'static [&'static str] {
+    vm::SPECIAL_FORMS
+}
+
+/// `EqMode::Loose` comparison of values of different types.
+fn loosely_equal(left: &Value, right: &Value) -> bool {
+    let number = |value: &Value| match value {
+        Value::Int(_) | Value::BigInt(_) | Value::Rational(_) | Value::Float(_) => value.as_f64(),
+        Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    };
+
+    match (left, right) {
+        (Value::Null | Value::Void, Value::Null | Value::Void) => true,
+        _ => matches!((number(left), number(right)), (Some(left), Some(right)) if left == right),
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        self.flush_output();
+
+        let mut roots = vec![cycles::Node::Scope(self.env.vars.clone())];
+
+        roots.extend(
+            self.lazy_imports
+                .iter()
+                .map(|(scope, _)| cycles::Node::Scope(scope.clone())),
+        );
+
+        let values = self.env.frames.iter().flat_map(|frame| frame.values());
+        let values = values.chain(self.natives.values());
+        let values = values.chain(self.modules.values().flatten().map(|(_, value)| value));
+
+        roots.extend(values.map(|value| cycles::Node::Value(value.clone())));
+        roots.extend(
+            self.methods
+                .values()
+                .map(|method| cycles::Node::Value(Value::Function(method.clone()))),
+        );
+
+        cycles::free(roots);
+    }
+}
+
+impl Interpreter {
+    /// How deeply forms may nest, counting those of every function being called, unless
+    /// changed with `set_max_depth`.
+    pub const DEFAULT_MAX_DEPTH: usize = 2_000;
+
+    pub fn new() -> Self {
+        let mut interpreter = Interpreter {
+            env: Env {
+                vars: Rc::new(RefCell::new(Vars::default())),
+                frames: vec![],
+            },
+            allowed_capabilities: vec![],
+            file_stack: vec![],
+            export_stack: vec![],
+            lazy_imports: vec![],
+            modules: HashMap::new(),
+            tail_call: None,
+            trace: vec![],
+            print_results: false,
+            debugger: None,
+            output: None,
+            error_output: None,
+            relay: None,
+            console: Arc::new(Console::new(Box::new(std::io::stdout()))),
+            task: 0,
+            pending_output: String::new(),
+            mid_line: false,
+            input: None,
+            natives: Vars::default(),
+            builtins: HashSet::new(),
+            telemetry: false,
+            fuel: Fuel::default(),
+            eq_mode: EqMode::Strict,
+            div_mode: DivMode::Float,
+            warnings: None,
+            deny_warnings: false,
+            slow_builtin_threshold: None,
+            slow_builtin_sites: HashSet::new(),
+            lint: false,
+            bytecode: true,
+            expand_env: false,
+            form_log: None,
+            form_depth: 0,
+            depth: 0,
+            max_depth: Interpreter::DEFAULT_MAX_DEPTH,
+            suppressed_warnings: vec![],
+            deprecated: HashMap::new(),
+            chunks: HashMap::new(),
+            tests: vec![],
+            bench_results: vec![],
+            variants: HashMap::new(),
+            protocols: HashMap::new(),
+            methods: HashMap::new(),
+        };
+
+        stdlib::register(&mut interpreter);
+        interpreter.builtins = interpreter.natives.keys().copied().collect();
+
+        interpreter
+    }
+
+    /// Evaluates a script file.
+    pub fn eval_file(&mut self, filename: &str) -> Result<(), RuntimeError> {
+        let content = std::fs::read_to_string(filename).map_err(|err| {
+            RuntimeError::with_code(
+                ErrorCode::Io,
+                format!("Unable to read {}: {}", filename, err),
+            )
+        })?;
+
+        self.eval_source(&content, filename)
+    }
+
+    /// Evaluates source text. `filename` names it in errors and is the base directory
+    /// for relative imports.
+    pub fn eval_source(&mut self, content: &str, filename: &str) -> Result<(), RuntimeError> {
+        let mut parser = parser::Parser::new(content);
+
+        let sexprs = parser.parse().map_err(|err| {
+            RuntimeError::with_code(
+                ErrorCode::Parse,
+                format!("Failed to parse {}: {}", filename, err),
+            )
+        })?;
+
+        match Manifest::from_sexprs(&sexprs) {
+            Ok(Some(manifest)) => {
+                if let Err(err) = manifest.check_capabilities(&self.allowed_capabilities) {
+                    return Err(RuntimeError::with_code(ErrorCode::CapabilityDenied, err));
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                return Err(RuntimeError::with_code(ErrorCode::InvalidManifest, err));
+            }
+        }
+
+        self.file_stack.push(PathBuf::from(filename));
+        let depth = self.trace.len();
+
+        self.debug_event(|debugger, interpreter| {
+            debugger.enter(interpreter, filename, Some(Path::new(filename)))
+        });
+
+        let linted = match self.lint {
+            true => lint::check(&sexprs)
+                .into_iter()
+                .try_for_each(|lint| self.warn(lint.kind, lint.message, lint.span)),
+            false => Ok(()),
+        };
+
+        let result = linted.and_then(|_| {
+            sexprs.iter().try_for_each(|sexpr| {
+                let value = self.eval(sexpr)?;
+
+                // Only the entry script echoes its results, not the modules it imports
+                if self.print_results && self.file_stack.len() == 1 && !matches!(value, Value::Void)
+                {
+                    self.write_output(&format!("{}\n", value));
+                }
+
+                Ok(())
+            })
+        });
+
+        self.debug_event(|debugger, _| debugger.exit());
+
+        if result.is_err() {
+            self.attribute_trace(depth, self.file_stack.last().cloned().as_ref());
+        }
+
+        self.file_stack.pop();
+
+        result
+    }
+
+    /// Evaluates source text in the current scope, returning the value of its last form.
+    pub fn eval_str(&mut self, source: &str) -> Result<Value, RuntimeError> {
+        let sexprs = parser::Parser::new(source)
+            .parse()
+            .map_err(|err| RuntimeError::with_code(ErrorCode::Parse, err.to_string()))?;
+
+        self.eval_list(&sexprs, false)
+    }
+
+    /// Evaluates forms built in Rust, such as with `sexpr!`, in the current scope, returning
+    /// the value of the last one.
+    pub fn eval_forms(&mut self, sexprs: &[SExpr]) -> Result<Value, RuntimeError> {
+        self.eval_list(sexprs, false)
+    }
+
+    /// Returns the value of a global variable.
+    pub fn get_var(&self, name: &str) -> Option<Value> {
+        self.env.vars.borrow().get(&Symbol::intern(name)).cloned()
+    }
+
+    /// Defines or replaces a global variable.
+    pub fn set_var(&mut self, name: &str, value: Value) {
+        self.env
+            .vars
+            .borrow_mut()
+            .insert(Symbol::intern(name), value);
+    }
+
+    /// Calls the script's `(defn main (args) ...)`, if it defines one, with `args` as a list
+    /// of strings, and returns its result as an exit code: an int from 0 to 255 is returned
+    /// as is, a larger or negative int is an error, and any other result is 0. Scripts
+    /// without `main` also return 0.
+    pub fn run_main(&mut self, args: &[String]) -> Result<i32, RuntimeError> {
+        let Some(Value::Function(main)) = self.get_var("main") else {
+            return Ok(0);
+        };
+
+        let args = match main.params.is_empty() && main.rest.is_none() {
+            true => vec![],
+            false => vec![Value::List(Rc::new(
+                args.iter()
+                    .map(|arg| Value::String(arg.as_str().into()))
+                    .collect(),
+            ))],
+        };
+
+        match self.call_function(&main, args, vec![])? {
+            Value::Int(code @ 0..=255) => Ok(code as i32),
+            Value::Int(_) | Value::BigInt(_) => Err(RuntimeError::with_code(
+                ErrorCode::InvalidArgument,
+                "main must return an exit code from 0 to 255",
+            )),
+            _ => Ok(0),
+        }
+    }
+
+    /// Takes the results of the `bench` forms evaluated since the last call.
+    pub fn take_bench_results(&mut self) -> Vec<BenchResult> {
+        std::mem::take(&mut self.bench_results)
+    }
+
+    /// Names of the tests declared with `deftest` so far, in declaration order.
+    pub fn test_names(&self) -> Vec<String> {
+        self.tests.iter().map(|test| test.name.clone()).collect()
+    }
+
+    /// Runs the body of the test `name` in the global scope. A test passes when its body
+    /// doesn't raise an error.
+    pub fn run_test(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let Some(test) = self.tests.iter().find(|test| test.name == name) else {
+            return Err(RuntimeError::new(format!("No test named {}", name)));
+        };
+
+        let body = test.body.clone();
+        let file = test.file.clone();
+
+        if let Some(file) = &file {
+            self.file_stack.push(file.clone());
+        }
+
+        let depth = self.trace.len();
+        let result = self.eval_list(&body, false).map(|_| ());
+
+        if result.is_err() {
+            self.attribute_trace(depth, file.as_ref());
+        }
+
+        if file.is_some() {
+            self.file_stack.pop();
+        }
+
+        result
+    }
+
+    /// Returns every global variable whose name starts with `prefix`, so a host can harvest
+    /// the results of a run at once.
+    pub fn export_vars(&self, prefix: &str) -> HashMap<String, Value> {
+        self.env
+            .vars
+            .borrow()
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    /// Defines or replaces a global variable for every entry of `vars`.
+    pub fn import_vars(&mut self, vars: impl IntoIterator<Item = (String, Value)>) {
+        let vars = vars
+            .into_iter()
+            .map(|(name, value)| (Symbol::intern(&name), value));
+
+        self.env.vars.borrow_mut().extend(vars);
+    }
+
+    /// Like `export_vars`, as a JSON object. Functions, handles and variants are left out.
+    pub fn export_json(
+        &self,
+        prefix: &str,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, RuntimeError> {
+        self.export_vars(prefix)
+            .into_iter()
+            .filter(|(_, value)| {
+                !matches!(
+                    value,
+                    Value::Function(_) | Value::Native(_) | Value::Handle(_) | Value::Variant(_)
+                )
+            })
+            .map(|(name, value)| Ok((name, value.to_json()?)))
+            .collect()
+    }
+
+    /// Defines a global variable for every key of a JSON object.
+    pub fn import_json(&mut self, json: &serde_json::Value) -> Result<(), RuntimeError> {
+        let serde_json::Value::Object(map) = json else {
+            return Err(RuntimeError::type_mismatch("map", &Value::from_json(json)));
+        };
+
+        self.import_vars(
+            map.iter()
+                .map(|(name, value)| (name.clone(), Value::from_json(value))),
+        );
+
+        Ok(())
+    }
+
+    /// Grants a capability that scripts can require in their manifest (see
+    /// `manifest::CAPABILITIES`).
+    pub fn allow_capability(&mut self, capability: &str) {
+        self.allowed_capabilities.push(capability.to_string());
+    }
+
+    /// Echoes the value of every top-level form of the entry script.
+    pub fn set_print_results(&mut self, print_results: bool) {
+        self.print_results = print_results;
+    }
+
+    /// Registers a function implemented in Rust, callable from scripts as `(name args...)`.
+    ///
+    /// ```
+    /// # use kk::{Interpreter, Value};
+    /// let mut interpreter = Interpreter::new();
+    ///
+    /// interpreter.register_fn("twice", |args| match args {
+    ///     [Value::Int(n)] => Ok(Value::Int(n * 2)),
+    ///     _ => Err(kk::RuntimeError::new("twice expects an int")),
+    /// });
+    ///
+    /// assert_eq!(interpreter.eval_str("(twice 21)").unwrap().to_string(), "42");
+    /// ```
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) {
+        self.register_native(name, move |_, args| function(args));
+    }
+
+    /// Defines a builtin constant such as `pi`.
+    pub(crate) fn register_value(&mut self, name: &str, value: Value) {
+        self.natives.insert(Symbol::intern(name), value);
+    }
+
+    /// Returns a registered builtin.
+    pub(crate) fn native(&self, name: &str) -> Option<Value> {
+        self.natives.get(&Symbol::intern(name)).cloned()
+    }
+
+    /// The names of the registered builtins, in no particular order.
+    pub(crate) fn native_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.natives.keys().map(Symbol::as_str)
+    }
+
+    /// Like `register_fn`, for builtins that need the interpreter.
+    pub(crate) fn register_native(
+        &mut self,
+        name: &str,
+        function: impl Fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) {
+        let native = NativeFunction {
+            name: name.to_string(),
+            function: Box::new(function),
+        };
+
+        self.builtins.remove(&Symbol::intern(name));
+        self.natives
+            .insert(Symbol::intern(name), Value::Native(Rc::new(native)));
+    }
+
+    /// Whether another interpreter has `native` under the same name after inheriting the
+    /// enums and protocols of this one: a builtin the host didn't replace, or a variant
+    /// constructor, predicate or protocol method. Functions registered by the host can't be
+    /// sent to a thread, as they may not be `Send`.
+    pub(crate) fn is_inherited(&self, native: &Rc<NativeFunction>) -> bool {
+        let name = native.name.as_str();
+        let same = |value: Option<Value>| matches!(value, Some(Value::Native(value)) if Rc::ptr_eq(&value, native));
+
+        if self.builtins.contains(&Symbol::intern(name)) && same(self.native(name)) {
+            return true;
+        }
+
+        let declared = self.variants.contains_key(name)
+            || name.strip_suffix('?').is_some_and(|name| {
+                self.variants.contains_key(name)
+                    || self
+                        .variants
+                        .values()
+                        .any(|(enum_name, _)| enum_name == name)
+            })
+            || self
+                .protocols
+                .values()
+                .flatten()
+                .any(|method| method == name);
+
+        declared && same(self.env.vars.borrow().get(&Symbol::intern(name)).cloned())
+    }
+
+    /// Emits a `tracing` span with timing and an arguments summary for every call to a script
+    /// function or native builtin. Has no effect unless kk is built with the `telemetry`
+    /// feature.
+    pub fn set_telemetry(&mut self, enabled: bool) {
+        self.telemetry = enabled;
+    }
+
+    /// Sends the text written by `print` to `output` instead of stdout.
+    pub fn set_output(&mut self, output: impl FnMut(&str) + 'static) {
+        self.output = Some(Box::new(output));
+    }
+
+    /// Sends the text written by `eprint` and `eprintln` to `output` instead of stderr.
+    pub fn set_error_output(&mut self, output: impl FnMut(&str) + 'static) {
+        self.error_output = Some(Box::new(output));
+    }
+
+    /// Prefixes every line printed to stdout with `[task <id>]`, where the id is 0 for the
+    /// script and counts up for the threads it spawns, which share the setting.
+    pub fn set_trace_tasks(&mut self, enabled: bool) {
+        self.console.trace_tasks.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Limits the script to `fuel` units of work, or lifts the limit with `None`. A script
+    /// that runs out fails with an error that `try` cannot catch. The threads it spawns draw
+    /// from the same budget.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        // Loops only charge fuel when compiled with a limit
+        self.chunks.clear();
+        self.fuel.set_remaining(fuel);
+    }
+
+    /// The fuel left, or `None` when unlimited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel.remaining()
+    }
+
+    /// Sets what a call to a builtin of `category` costs, in units of one form.
+    pub fn set_fuel_multiplier(&mut self, category: FuelCategory, multiplier: u64) {
+        self.fuel.set_multiplier(category, multiplier);
+    }
+
+    /// Assigns a builtin, such as one added with `register_fn`, to a fuel category.
+    pub fn set_fuel_category(&mut self, builtin: &str, category: FuelCategory) {
+        self.fuel.categorize(builtin, category);
+    }
+
+    /// Sets how values of different types compare, `EqMode::Strict` by default.
+    pub fn set_eq_mode(&mut self, mode: EqMode) {
+        self.eq_mode = mode;
+    }
+
+    /// Sets what `/` returns for two ints, `DivMode::Float` by default.
+    pub fn set_div_mode(&mut self, mode: DivMode) {
+        self.div_mode = mode;
+    }
+
+    /// Sends warnings to `warnings` instead of stderr.
+    pub fn set_warnings(&mut self, warnings: impl FnMut(&Diagnostic) + 'static) {
+        self.warnings = Some(Box::new(warnings));
+    }
+
+    /// Makes every warning fail the script instead (`--deny-warnings`).
+    pub fn set_deny_warnings(&mut self, deny: bool) {
+        self.deny_warnings = deny;
+    }
+
+    /// Times every builtin call, warning once per call site about those that take longer
+    /// than `threshold`, such as a regex over a huge string (`--warn-slow`). Off by default,
+    /// as reading the clock around every call has a cost.
+    pub fn set_slow_builtin_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_builtin_threshold = threshold;
+    }
+
+    /// Reports the warnings of `kk lint` for each file before evaluating it.
+    pub fn set_lint(&mut self, lint: bool) {
+        self.lint = lint;
+    }
+
+    /// Fails with a catchable `ErrorCode::RecursionLimit` error when forms nest deeper than
+    /// `limit`, such as in a function recursing that many times, instead of overflowing the
+    /// Rust stack. The thread running the interpreter needs `stack_size(limit)` bytes of
+    /// stack for the limit to be reached first.
+    pub fn set_max_depth(&mut self, limit: usize) {
+        self.max_depth = limit;
+    }
+
+    /// The stack a thread needs for forms to nest `max_depth` deep, with room to spare for
+    /// the builtins called at that depth.
+    pub fn stack_size(max_depth: usize) -> usize {
+        // Measured at about 2 KiB per form, and 40 KiB in unoptimized builds
+        const PER_FORM: usize = if cfg!(debug_assertions) { 64 } else { 8 } * 1024;
+
+        max_depth
+            .saturating_mul(PER_FORM)
+            .saturating_add(4 * 1024 * 1024)
+    }
+
+    /// Compiles the bodies of `count` loops to bytecode, on by default. Turning it off runs
+    /// everything on the tree-walking evaluator, as when a debugger is attached.
+    pub fn set_bytecode(&mut self, enabled: bool) {
+        self.bytecode = enabled;
+    }
+
+    /// Expands `$VAR`, `${VAR}` and `${VAR:-fallback}` in every string literal, as
+    /// `expand-env` does, for using scripts as templated configuration. The braced forms
+    /// are written `$${VAR}` in literals, where `${` starts an interpolation.
+    pub fn set_expand_env(&mut self, enabled: bool) {
+        self.chunks.clear();
+        self.expand_env = enabled;
+    }
+
+    /// Logs every list form before evaluating it and its result afterwards to `log`, one
+    /// line each, numbered and indented by nesting depth. Loops then run on the
+    /// tree-walker, so that every form of their bodies shows up.
+    pub fn set_trace(&mut self, log: impl FnMut(&str) + 'static) {
+        self.form_log = Some(Box::new(log));
+    }
+
+    /// Warns whenever a function named `name` is called, suggesting `instead`.
+    pub fn deprecate(&mut self, name: &str, instead: &str) {
+        // Loops compile calls to deprecated functions to the tree-walker, which warns
+        self.chunks.clear();
+        self.deprecated
+            .insert(name.to_string(), instead.to_string());
+    }
+
+    /// Reads the lines returned by `input` from `input` instead of stdin.
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Some(Box::new(input));
+    }
+
+    pub(crate) fn eval(&mut self, sexpr: &SExpr) -> Result<Value, RuntimeError> {
+        self.eval_expr(sexpr, false)
+    }
+
+    /// Evaluates an expression. `tail` is set when the value of `sexpr` is the return value
+    /// of the enclosing function, which lets user function calls reuse the current frame.
+    fn eval_expr(&mut self, sexpr: &SExpr, tail: bool) -> Result<Value, RuntimeError> {
+        let result = match (&sexpr, &self.debugger) {
+            (SExpr::List(..), Some(_)) => self
+                .debug_event(|debugger, interpreter| debugger.before_form(interpreter, sexpr))
+                .unwrap_or(Ok(())),
+            _ => Ok(()),
+        };
+
+        let nested = matches!(sexpr, SExpr::List(..));
+
+        if nested && self.depth >= self.max_depth {
+            self.push_trace(sexpr);
+
+            return Err(RuntimeError::with_code(
+                ErrorCode::RecursionLimit,
+                format!(
+                    "Recursion limit of {} nested forms exceeded at {}",
+                    self.max_depth,
+                    sexpr.span()
+                ),
+            ));
+        }
+
+        self.depth += nested as usize;
+
+        let logged = self.form_log.is_some() && nested;
+
+        if logged {
+            self.form_depth += 1;
+            self.log_form(&abbreviate(sexpr));
+        }
+
+        let result = result
+            .and_then(|()| self.fuel.consume_form())
+            .and_then(|()| self.eval_form(sexpr, tail))
+            .map_err(|err| err.at(sexpr.span()));
+
+        if logged {
+            match &result {
+                Ok(_) if self.tail_call.is_some() => self.log_form("=> tail call"),
+                Ok(value) => self.log_form(&format!("=> {}", value)),
+                Err(err) => self.log_form(&format!("!! {}", err)),
+            }
+
+            self.form_depth -= 1;
+        }
+
+        self.depth -= nested as usize;
+
+        if result.is_err() {
+            self.push_trace(sexpr);
+        }
+
+        result
+    }
+
+    /// Records that an error propagated through `sexpr`, if it is a list form.
+    fn push_trace(&mut self, sexpr: &SExpr) {
+        if !matches!(sexpr, SExpr::List(..)) {
+            return;
+        }
+
+        self.trace.push(TraceFrame {
+            form: abbreviate(sexpr),
+            file: None,
+            span: sexpr.span(),
+        });
+    }
+
+    /// Writes a `--trace` line at the current depth.
+    fn log_form(&mut self, text: &str) {
+        let depth = self.form_depth;
+
+        if let Some(log) = &mut self.form_log {
+            log(&format!(
+                "{:>3} {}{}\n",
+                depth,
+                "  ".repeat(depth - 1),
+                text
+            ));
+        }
+    }
+
+    /// Passes an event to the attached debugger, if there is one.
+    fn debug_event<T>(
+        &mut self,
+        event: impl FnOnce(&mut dyn Debugger, &mut Interpreter) -> T,
+    ) -> Option<T> {
+        let mut debugger = self.debugger.take()?;
+        let result = event(debugger.as_mut(), self);
+        self.debugger = Some(debugger);
+
+        Some(result)
+    }
+
+    /// Writes text printed by the script. Without an output sink, only complete lines are
+    /// written to the console; the rest waits for its newline, or for `flush_output`.
+    pub(crate) fn write_output(&mut self, text: &str) {
+        self.relay_output();
+
+        if let Some(output) = &mut self.output {
+            return output(text);
+        }
+
+        self.pending_output.push_str(text);
+
+        if let Some(end) = self.pending_output.rfind('\n') {
+            let lines = self.pending_output.drain(..=end).collect::<String>();
+            self.console.write(self.task, &lines, !self.mid_line);
+            self.mid_line = false;
+        }
+    }
+
+    /// Writes text the script printed to stderr, unbuffered.
+    pub(crate) fn write_error_output(&mut self, text: &str) {
+        self.relay_output();
+
+        match &mut self.error_output {
+            Some(output) => output(text),
+            None => eprint!("{}", text),
+        }
+    }
+
+    /// Writes printed text still waiting for its newline, such as an `input` prompt, to
+    /// stdout. Whatever is printed next continues the same line.
+    pub fn flush_output(&mut self) {
+        self.relay_output();
+
+        if !self.pending_output.is_empty() {
+            let text = std::mem::take(&mut self.pending_output);
+            self.console.write(self.task, &text, !self.mid_line);
+            self.mid_line = true;
+        }
+    }
+
+    /// Where a spawned thread sends what it prints with `print` and with `eprint`, for
+    /// `relay_output` to pass to the output sinks. Each is `None` when its sink isn't set, and
+    /// the thread then prints to the console or stderr itself.
+    pub(crate) fn output_relays(&mut self) -> (Option<Sender<Printed>>, Option<Sender<Printed>>) {
+        if self.output.is_none() && self.error_output.is_none() {
+            return (None, None);
+        }
+
+        let (sender, _) = self.relay.get_or_insert_with(std::sync::mpsc::channel);
+
+        (
+            self.output.as_ref().map(|_| sender.clone()),
+            self.error_output.as_ref().map(|_| sender.clone()),
+        )
+    }
+
+    /// Writes the text spawned threads printed so far to the output sinks.
+    pub(crate) fn relay_output(&mut self) {
+        let Some((_, receiver)) = &self.relay else {
+            return;
+        };
+
+        for printed in receiver.try_iter().collect::<Vec<_>>() {
+            let (sink, text) = match printed {
+                Printed::Output(text) => (&mut self.output, text),
+                Printed::Error(text) => (&mut self.error_output, text),
+            };
+
+            if let Some(sink) = sink {
+                sink(&text);
+            }
+        }
+    }
+
+    /// Compares two values for `eq`, `ne` and `contains`, with `Value`'s `PartialEq`.
+    /// Values of types that never compare equal, such as an int and a string, are handled
+    /// as `eq_mode` says.
+    pub(crate) fn values_equal(&self, left: &Value, right: &Value) -> Result<bool, RuntimeError> {
+        let numbers = |value: &Value| value.as_f64().is_some();
+
+        if std::mem::discriminant(left) == std::mem::discriminant(right)
+            || numbers(left) && numbers(right)
+        {
+            return Ok(left == right);
+        }
+
+        match self.eq_mode {
+            EqMode::Strict => Err(RuntimeError::type_mismatch(left.type_name(), right)),
+            EqMode::Unequal => Ok(false),
+            EqMode::Loose => Ok(loosely_equal(left, right)),
+        }
+    }
+
+    /// Reports a warning about the form at `span`, or fails with it when warnings are denied.
+    pub(crate) fn warn(
+        &mut self,
+        kind: WarningKind,
+        message: String,
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        if self.suppressed_warnings.contains(&kind) {
+            return Ok(());
+        }
+
+        if self.deny_warnings {
+            return Err(RuntimeError::with_code(
+                ErrorCode::DeniedWarning,
+                format!("{} (warning {} is denied)", message, kind.name()),
+            ));
+        }
+
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            code: kind.code(),
+            message,
+            file: self.file_stack.last().cloned(),
+            span: Some(span),
+        };
+
+        match &mut self.warnings {
+            Some(warnings) => warnings(&diagnostic),
+            None => eprintln!("{}", diagnostic),
+        }
+
+        Ok(())
+    }
+
+    /// Whether the embedder redirected `print` with `set_output`.
+    pub(crate) fn has_output_sink(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Reads a line for `input`, without its line ending. Returns `None` at end of input.
+    pub(crate) fn read_line(&mut self) -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+
+        let read = match &mut self.input {
+            Some(input) => input.read_line(&mut line)?,
+            None => {
+                // A prompt printed without a newline would otherwise stay buffered
+                self.flush_output();
+                std::io::stdin().lock().read_line(&mut line)?
+            }
+        };
+
+        if read == 0 {
+            return Ok(None);
+        }
+
+        let trimmed = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(trimmed);
+
+        Ok(Some(line))
+    }
+
+    /// Takes the trace of the last error, innermost form first.
+    pub fn take_trace(&mut self) -> Vec<TraceFrame> {
+        std::mem::take(&mut self.trace)
+    }
+
+    /// Attributes the trace frames pushed since `depth` that have no file yet to `file`.
+    fn attribute_trace(&mut self, depth: usize, file: Option<&PathBuf>) {
+        for frame in self.trace.iter_mut().skip(depth) {
+            if frame.file.is_none() {
+                frame.file = file.cloned();
+            }
+        }
+    }
+
+    fn eval_form(&mut self, sexpr: &SExpr, tail: bool) -> Result<Value, RuntimeError> {
+        match sexpr {
+            SExpr::Atom(atom, _) => {
+                return self.eval_atom(*atom);
+            }
+            SExpr::String(string, _) if self.expand_env => {
+                return Ok(Value::String((stdlib::expand_env(string)?).into()));
+            }
+            SExpr::String(string, _) => {
+                return Ok(Value::String(string.as_str().into()));
+            }
+            SExpr::Keyword(keyword, _) => {
+                return Ok(Value::Keyword(keyword.as_str().into()));
+            }
+            SExpr::List(list, _) => {
+                let mut it = list.iter();
+
+                let name = match it.next() {
+                    Some(SExpr::Atom(atom, _)) => atom,
+                    _ => {
+                        return Err(RuntimeError::syntax("Expected function name here"));
+                    }
+                };
+
+                if let Some(instead) = self.deprecated.get(name.as_str()).cloned() {
+                    let message = format!("{} is deprecated, use {} instead", name, instead);
+                    self.warn(WarningKind::Deprecated, message, sexpr.span())?;
+                }
+
+                // Calls skip the comparisons against every form name
+                if !name.is_special_form() {
+                    return self.eval_call(*name, it, tail, sexpr.span());
+                }
+
+                match name.as_str() {
+                    "manifest" => {
+                        // Validated before evaluation by Manifest::from_sexprs
+                    }
+                    "import" | "import-lazy" => {
+                        let module = match it.next().map(|sexpr| self.eval(sexpr)).transpose()? {
+                            Some(Value::String(module)) => module,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected module name here"));
+                            }
+                        };
+
+                        // syntax: (import <module> [:as <alias>])
+                        let alias = match it.next() {
+                            Some(SExpr::Keyword(keyword, _)) if keyword == "as" => {
+                                match it.next() {
+                                    Some(SExpr::Atom(alias, _)) => Some(*alias),
+                                    _ => {
+                                        return Err(RuntimeError::syntax(
+                                            "Expected module alias here",
+                                        ));
+                                    }
+                                }
+                            }
+                            Some(_) => {
+                                return Err(RuntimeError::syntax(
+                                    "Expected :as or end of list here",
+                                ));
+                            }
+                            None => None,
+                        };
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        let base_dir = match self.file_stack.last() {
+                            Some(file) => file.parent().unwrap_or(Path::new("")).to_path_buf(),
+                            None => PathBuf::new(),
+                        };
+
+                        let path = match package::resolve_module(&module, &base_dir) {
+                            Some(path) => path,
+                            None => {
+                                return Err(RuntimeError::with_code(
+                                    ErrorCode::ModuleNotFound,
+                                    format!("Module not found: {}", module),
+                                ));
+                            }
+                        };
+
+                        if name == "import-lazy" {
+                            if alias.is_some() {
+                                return Err(RuntimeError::syntax(
+                                    "import-lazy does not support :as",
+                                ));
+                            }
+
+                            self.lazy_imports.push((self.env.vars.clone(), path));
+                            return Ok(Value::Void);
+                        }
+
+                        for (name, value) in self.import_module(&path)? {
+                            match &alias {
+                                Some(alias) => {
+                                    self.env.define(&format!("{}/{}", alias, name), value)
+                                }
+                                None => self.env.define(&name, value),
+                            }
+                        }
+                    }
+                    "export" => {
+                        let mut names = vec![];
+
+                        for sexpr in it {
+                            match sexpr {
+                                SExpr::Atom(atom, _) => names.push(atom.to_string()),
+                                _ => {
+                                    return Err(RuntimeError::syntax(
+                                        "Expected exported name here",
+                                    ));
+                                }
+                            }
+                        }
+
+                        match self.export_stack.last_mut() {
+                            Some(exports) => exports.get_or_insert_with(Vec::new).extend(names),
+                            None => {
+                                // The entry script has no importer; exports are a no-op there
+                            }
+                        }
+                    }
+                    "list" => {
+                        let values =
+                            it.map(|sexpr| self.eval(sexpr))
+                                .collect::<Result<Vec<Value>, RuntimeError>>()?;
+
+                        return Ok(Value::List(values.into()));
+                    }
+                    "dict" => {
+                        let mut map = BTreeMap::new();
+
+                        while let Some(key) = it.next() {
+                            let key = match self.eval(key)? {
+                                Value::String(key) => key,
+                                key => {
+                                    return Err(RuntimeError::type_mismatch("string", &key));
+                                }
+                            };
+
+                            let value = match it.next() {
+                                Some(value) => self.eval(value)?,
+                                None => {
+                                    return Err(RuntimeError::syntax(format!(
+                                        "Expected value for key: {}",
+                                        key
+                                    )));
+                                }
+                            };
+
+                            map.insert(key.to_string(), value);
+                        }
+
+                        return Ok(Value::Map(Rc::new(map)));
+                    }
+                    "interpolate" => {
+                        // syntax: (interpolate <value>...), what "text ${expr}" reads as
+                        let mut string = String::new();
+
+                        for sexpr in it {
+                            string.push_str(&self.eval(sexpr)?.to_string());
+                        }
+
+                        return Ok(Value::String(string.into()));
+                    }
+                    "get-in" => {
+                        // syntax: (get-in <collection> <path> [<default>])
+                        let (Some(collection), Some(path)) = (it.next(), it.next()) else {
+                            return Err(RuntimeError::syntax(
+                                "Expected (get-in <collection> <path> [<default>])",
+                            ));
+                        };
+
+                        let default = it.next();
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        let mut value = self.eval(collection)?;
+
+                        let path = match self.eval(path)? {
+                            Value::List(path) => path,
+                            path => return Err(RuntimeError::type_mismatch("list", &path)),
+                        };
+
+                        for key in path.iter() {
+                            value = Self::lookup_key(&value, key)?;
+                        }
+
+                        match (value, default) {
+                            (Value::Null, Some(default)) => return self.eval_expr(default, tail),
+                            (value, _) => return Ok(value),
+                        }
+                    }
+                    "maybe->" => {
+                        // syntax: (maybe-> <collection> <key>...)
+                        let mut value = match it.next() {
+                            Some(collection) => self.eval(collection)?,
+                            None => {
+                                return Err(RuntimeError::syntax("Expected collection here"));
+                            }
+                        };
+
+                        // Keys after the first missing one are not evaluated
+                        for key in it {
+                            if matches!(value, Value::Null) {
+                                break;
+                            }
+
+                            let key = self.eval(key)?;
+                            value = Self::lookup_key(&value, &key)?;
+                        }
+
+                        return Ok(value);
+                    }
+                    "let" => {
+                        // syntax: (let <name> <value>) or (let (<pattern>...) <value>)
+                        let pattern = match it.next() {
+                            Some(pattern @ (SExpr::Atom(..) | SExpr::List(..))) => pattern,
+                            _ => {
+                                return Err(RuntimeError::syntax(
+                                    "Expected variable name or pattern here",
+                                ));
+                            }
+                        };
+
+                        let value = match it.next() {
+                            Some(value) => value,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected value here"));
+                            }
+                        };
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        let value = self.eval(value)?;
+
+                        self.bind_pattern(pattern, value)?;
+                    }
+                    "suppress-warnings" => {
+                        // syntax: (suppress-warnings <name> <body>...), <name> may be "all"
+                        let kinds = match it.next().map(|sexpr| self.eval(sexpr)).transpose()? {
+                            Some(Value::String(name)) if name == "all".into() => {
+                                WarningKind::ALL.to_vec()
+                            }
+                            Some(Value::String(name)) => match WarningKind::find(&name) {
+                                Some(kind) => vec![kind],
+                                None => {
+                                    return Err(RuntimeError::syntax(format!(
+                                        "Unknown warning: {}",
+                                        name
+                                    )));
+                                }
+                            },
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected warning name here"));
+                            }
+                        };
+
+                        let depth = self.suppressed_warnings.len();
+                        self.suppressed_warnings.extend(kinds);
+
+                        let mut result = Ok(Value::Void);
+
+                        for sexpr in it {
+                            result = self.eval(sexpr);
+
+                            if result.is_err() {
+                                break;
+                            }
+                        }
+
+                        self.suppressed_warnings.truncate(depth);
+
+                        return result;
+                    }
+                    "set" => {
+                        let name = match it.next() {
+                            Some(SExpr::Atom(atom, _)) => atom,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected variable name here"));
+                            }
+                        };
+
+                        let value = match it.next() {
+                            Some(value) => value,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected value here"));
+                            }
+                        };
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        let value = self.eval(value)?;
+
+                        self.env.set(name, value.clone());
+
+                        return Ok(value);
+                    }
+                    "get" => {
+                        let name = match it.next() {
+                            Some(SExpr::Atom(atom, _)) => atom,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected variable name here"));
+                            }
+                        };
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        let value = self.lookup(name)?;
+
+                        let value = match value {
+                            Some(value) => value,
+                            None => {
+                                return Err(RuntimeError::UndefinedVariable(name.to_string()));
+                            }
+                        };
+
+                        return Ok(value.clone());
+                    }
+                    "inc" => {
+                        let name = match it.next() {
+                            Some(SExpr::Atom(atom, _)) => atom,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected variable name here"));
+                            }
+                        };
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        return self.increment(*name);
+                    }
+                    "add" => {
+                        let mut sum = Value::Int(0);
+                        let mut has_int = false;
+                        let mut has_float = false;
+
+                        for sexpr in it {
+                            let value = self.eval(sexpr)?;
+
+                            has_int |= matches!(
+                                value,
+                                Value::Int(_) | Value::BigInt(_) | Value::Rational(_)
+                            );
+                            has_float |= matches!(value, Value::Float(_));
+
+                            sum = Self::sum(sum, value)?;
+                        }
+
+                        if has_int && has_float {
+                            let message = "add mixes int and float, ints are converted".to_string();
+                            self.warn(WarningKind::ImplicitFloat, message, sexpr.span())?;
+                        }
+
+                        return Ok(sum);
+                    }
+                    "mod" => {
+                        let left = if let Some(left) = it.next() {
+                            self.eval(left)?
+                        } else {
+                            return Err(RuntimeError::syntax("Expected left value here"));
+                        };
+
+                        let right = if let Some(right) = it.next() {
+                            self.eval(right)?
+                        } else {
+                            return Err(RuntimeError::syntax("Expected right value here"));
+                        };
+
+                        if let (
+                            Value::Int(_) | Value::BigInt(_) | Value::Rational(_),
+                            Value::Float(_),
+                        )
+                        | (
+                            Value::Float(_),
+                            Value::Int(_) | Value::BigInt(_) | Value::Rational(_),
+                        ) = (&left, &right)
+                        {
+                            let message = "mod mixes int and float, ints are converted".to_string();
+                            self.warn(WarningKind::ImplicitFloat, message, sexpr.span())?;
+                        }
+
+                        return Self::modulo(left, right);
+                    }
+                    "eq" | "ne" | "neq" => {
+                        let left = if let Some(left) = it.next() {
+                            self.eval(left)?
+                        } else {
+                            return Err(RuntimeError::syntax("Expected left value here"));
+                        };
+
+                        let right = if let Some(right) = it.next() {
+                            self.eval(right)?
+                        } else {
+                            return Err(RuntimeError::syntax("Expected right value here"));
+                        };
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        let equal = self.values_equal(&left, &right)?;
+
+                        return Ok(Value::Bool(equal == (name == "eq")));
+                    }
+                    "do" => {
+                        return self.eval_list(it.as_slice(), tail);
+                    }
+                    "if" | "unless" => {
+                        // syntax: (if <condition> <expr> [elif <condition> <expr>]... [else <expr>])
+                        let mut negate = name == "unless";
+
+                        loop {
+                            let condition = if let Some(condition) = it.next() {
+                                self.eval(condition)?
+                            } else {
+                                return Err(RuntimeError::syntax("Expected condition here"));
+                            };
+
+                            let branch = match condition {
+                                Value::Bool(condition) => condition != negate,
+                                value => {
+                                    return Err(RuntimeError::type_mismatch("bool", &value));
+                                }
+                            };
+
+                            let true_branch = if let Some(true_branch) = it.next() {
+                                true_branch
+                            } else {
+                                return Err(RuntimeError::syntax("Expected true branch here"));
+                            };
+
+                            if branch {
+                                return self.eval_expr(true_branch, tail);
+                            }
+
+                            match it.next() {
+                                Some(SExpr::Atom(atom, _)) if atom == "elif" => {
+                                    negate = false;
+                                }
+                                Some(SExpr::Atom(atom, _)) if atom == "else" => {
+                                    let false_branch = if let Some(false_branch) = it.next() {
+                                        false_branch
+                                    } else {
+                                        return Err(RuntimeError::syntax(
+                                            "Expected false branch here",
+                                        ));
+                                    };
+
+                                    if it.next().is_some() {
+                                        return Err(RuntimeError::syntax(
+                                            "Expected end of list here",
+                                        ));
+                                    }
+
+                                    return self.eval_expr(false_branch, tail);
+                                }
+                                Some(_) => {
+                                    return Err(RuntimeError::syntax("Expected elif or else here"));
+                                }
+                                None => {
+                                    return Ok(Value::Void);
+                                }
+                            }
+                        }
+                    }
+                    "count" => {
+                        // sytnax: (count <var_name> from <start> to <end> (body))
+                        // The debugger needs a before_form event for every form
+                        if self.bytecode && self.debugger.is_none() && self.form_log.is_none() {
+                            if let Some(chunk) = vm::cached_chunk(self, sexpr) {
+                                return chunk.run(self, sexpr);
+                            }
+                        }
+
+                        let var_name = match it.next() {
+                            Some(SExpr::Atom(atom, _)) => atom,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected variable name here"));
+                            }
+                        };
+
+                        match it.next() {
+                            Some(SExpr::Atom(atom, _)) => {
+                                if atom != "from" {
+                                    return Err(RuntimeError::syntax("Expected from keyword here"));
+                                }
+                            }
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected from keyword here"));
+                            }
+                        };
+
+                        let start = if let Some(start) = it.next() {
+                            match self.eval(start)? {
+                                Value::Int(start) => start,
+                                value => {
+                                    return Err(RuntimeError::type_mismatch("int", &value));
+                                }
+                            }
+                        } else {
+                            return Err(RuntimeError::syntax("Expected start value here"));
+                        };
+
+                        match it.next() {
+                            Some(SExpr::Atom(atom, _)) => {
+                                if atom != "to" {
+                                    return Err(RuntimeError::syntax("Expected to keyword here"));
+                                }
+                            }
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected to keyword here"));
+                            }
+                        };
+
+                        let end = if let Some(end) = it.next() {
+                            match self.eval(end)? {
+                                Value::Int(end) => end,
+                                value => {
+                                    return Err(RuntimeError::type_mismatch("int", &value));
+                                }
+                            }
+                        } else {
+                            return Err(RuntimeError::syntax("Expected end value here"));
+                        };
+
+                        let body = if let Some(body) = it.next() {
+                            body
+                        } else {
+                            return Err(RuntimeError::syntax("Expected body here"));
+                        };
+
+                        match body {
+                            SExpr::List(list, _) => {
+                                for i in start..end {
+                                    self.env.define(var_name, Value::Int(i));
+                                    self.eval_list(list, false)?;
+                                }
+
+                                return Ok(Value::Void);
+                            }
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected list here"));
+                            }
+                        };
+                    }
+                    "for" => {
+                        // syntax: (for <name or pattern> in <collection> (body)), binding the
+                        // elements of a list, the (key value) pairs of a map, the chars of a
+                        // string or the bytes of bytes as ints
+                        let pattern = match it.next() {
+                            Some(pattern @ (SExpr::Atom(..) | SExpr::List(..))) => pattern,
+                            _ => {
+                                return Err(RuntimeError::syntax(
+                                    "Expected variable name or pattern here",
+                                ));
+                            }
+                        };
+
+                        match it.next() {
+                            Some(SExpr::Atom(atom, _)) if atom == "in" => {}
+                            _ => return Err(RuntimeError::syntax("Expected in keyword here")),
+                        }
+
+                        let collection = match it.next() {
+                            Some(collection) => self.eval(collection)?,
+                            None => return Err(RuntimeError::syntax("Expected collection here")),
+                        };
+
+                        let body = match it.next() {
+                            Some(SExpr::List(list, _)) => list,
+                            Some(_) => return Err(RuntimeError::syntax("Expected list here")),
+                            None => return Err(RuntimeError::syntax("Expected body here")),
+                        };
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        let Some(items) = collection.elements() else {
+                            return Err(RuntimeError::type_mismatch(
+                                "list, map, string or bytes",
+                                &collection,
+                            ));
+                        };
+
+                        for item in items {
+                            self.bind_pattern(pattern, item)?;
+                            self.eval_list(body, false)?;
+                        }
+
+                        return Ok(Value::Void);
+                    }
+                    "kk-version" => {
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        return Ok(Value::String(version::VERSION.into()));
+                    }
+                    "kk-features" => {
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        let features = manifest::CAPABILITIES
+                            .iter()
+                            .copied()
+                            .chain(cfg!(feature = "telemetry").then_some("telemetry"))
+                            .map(|feature| Value::String(feature.into()))
+                            .collect();
+
+                        return Ok(Value::List(Rc::new(features)));
+                    }
+                    "require-version" => {
+                        let requirement =
+                            match it.next().map(|sexpr| self.eval(sexpr)).transpose()? {
+                                Some(Value::String(requirement)) => requirement,
+                                _ => {
+                                    return Err(RuntimeError::syntax(
+                                        "Expected version requirement string here",
+                                    ));
+                                }
+                            };
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        match version::satisfies(&requirement) {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                return Err(RuntimeError::with_code(
+                                    ErrorCode::VersionMismatch,
+                                    format!(
+                                        "This script requires kk {} but this is kk {}",
+                                        requirement,
+                                        version::VERSION
+                                    ),
+                                ));
+                            }
+                            Err(err) => {
+                                return Err(RuntimeError::syntax(err));
+                            }
+                        }
+                    }
+                    "breakpoint" => {
+                        // syntax: (breakpoint), a no-op unless a debugger is attached
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        self.debug_event(|debugger, interpreter| {
+                            debugger.breakpoint(interpreter, sexpr)
+                        })
+                        .unwrap_or(Ok(()))?;
+
+                        return Ok(Value::Void);
+                    }
+                    "os" => {
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        return Ok(Value::String(std::env::consts::OS.into()));
+                    }
+                    "when-os" => {
+                        // syntax: (when-os <os-or-list-of-os> <body>...)
+                        let target = match it.next() {
+                            Some(target) => self.eval(target)?,
+                            None => {
+                                return Err(RuntimeError::syntax(
+                                    "Expected operating system name here",
+                                ));
+                            }
+                        };
+
+                        if !Self::matches_os(&target)? {
+                            return Ok(Value::Void);
+                        }
+
+                        return self.eval_list(it.as_slice(), tail);
+                    }
+                    "cond-os" => {
+                        // syntax: (cond-os (<os> <body>...)... [(else <body>...)])
+                        for clause in it {
+                            let (target, body) = match clause {
+                                SExpr::List(list, _) if !list.is_empty() => (&list[0], &list[1..]),
+                                _ => {
+                                    return Err(RuntimeError::syntax(
+                                        "Expected (<os> <body>...) clause here",
+                                    ));
+                                }
+                            };
+
+                            let matched = match target {
+                                SExpr::Atom(atom, _) if atom == "else" => true,
+                                target => {
+                                    let target = self.eval(target)?;
+                                    Self::matches_os(&target)?
+                                }
+                            };
+
+                            if matched {
+                                return self.eval_list(body, tail);
+                            }
+                        }
+
+                        return Ok(Value::Void);
+                    }
+                    "match" | "case" => {
+                        // syntax: (match <value> (<pattern> [:when <guard>] <body>...)...)
+                        // syntax: (case <value> (<value> [:when <guard>] <body>...)... [(else <body>...)])
+                        let value = match it.next() {
+                            Some(sexpr) => self.eval(sexpr)?,
+                            None => {
+                                return Err(RuntimeError::syntax(format!(
+                                    "Expected value to {} here",
+                                    name
+                                )));
+                            }
+                        };
+
+                        for clause in it {
+                            let (head, rest) = match clause {
+                                SExpr::List(list, _) if !list.is_empty() => (&list[0], &list[1..]),
+                                _ => {
+                                    return Err(RuntimeError::syntax(
+                                        "Expected (<pattern> [:when <guard>] <body>...) clause here",
+                                    ));
+                                }
+                            };
+
+                            let (guard, body) = match rest {
+                                [SExpr::Keyword(keyword, _), guard, body @ ..]
+                                    if keyword == "when" =>
+                                {
+                                    (Some(guard), body)
+                                }
+                                body => (None, body),
+                            };
+
+                            let matched = match head {
+                                SExpr::Atom(atom, _) if name == "case" && atom == "else" => true,
+                                head if name == "case" => {
+                                    let expected = self.eval(head)?;
+                                    self.values_equal(&value, &expected)?
+                                }
+                                pattern => {
+                                    let mut bindings = vec![];
+                                    let matched =
+                                        self.match_pattern(pattern, &value, &mut bindings)?;
+
+                                    // Bindings are visible to the guard and body, like `let`
+                                    if matched {
+                                        for (binding, bound) in bindings {
+                                            self.env.define(&binding, bound);
+                                        }
+                                    }
+
+                                    matched
+                                }
+                            };
+
+                            if !matched {
+                                continue;
+                            }
+
+                            let passes = match guard.map(|guard| self.eval(guard)).transpose()? {
+                                Some(Value::Bool(passes)) => passes,
+                                Some(value) => {
+                                    return Err(RuntimeError::type_mismatch("bool", &value));
+                                }
+                                None => true,
+                            };
+
+                            // A failed guard falls through to the next clause
+                            if passes {
+                                return self.eval_list(body, tail);
+                            }
+                        }
+
+                        return Ok(Value::Void);
+                    }
+                    "try" => {
+                        // syntax: (try <body>... (catch <name> <handler>...))
+                        let forms = it.as_slice();
+
+                        let (name, handler, body) = match forms.split_last() {
+                            Some((SExpr::List(catch, _), body)) => match catch.as_slice() {
+                                [SExpr::Atom(keyword, _), SExpr::Atom(name, _), handler @ ..]
+                                    if keyword == "catch" =>
+                                {
+                                    (name, handler, body)
+                                }
+                                _ => {
+                                    return Err(RuntimeError::syntax(
+                                        "Expected (catch <name> <handler>...) here",
+                                    ));
+                                }
+                            },
+                            _ => {
+                                return Err(RuntimeError::syntax(
+                                    "Expected (catch <name> <handler>...) here",
+                                ));
+                            }
+                        };
+
+                        // The body is not in tail position: its errors must be caught here
+                        match self.eval_list(body, false) {
+                            Ok(value) => return Ok(value),
+                            Err(err) if err.is_catchable() => {
+                                self.trace.clear();
+                                self.env.define(name, err.to_value());
+
+                                return self.eval_list(handler, tail);
+                            }
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    "throw" => {
+                        let value = match it.next() {
+                            Some(value) => self.eval(value)?,
+                            None => {
+                                return Err(RuntimeError::syntax("Expected value here"));
+                            }
+                        };
+
+                        if it.next().is_some() {
+                            return Err(RuntimeError::syntax("Expected end of list here"));
+                        }
+
+                        return Err(RuntimeError::Thrown(value));
+                    }
+                    "??" => {
+                        // syntax: (?? <value> <default>)
+                        let (Some(value), Some(default), None) = (it.next(), it.next(), it.next())
+                        else {
+                            return Err(RuntimeError::syntax("Expected (?? <value> <default>)"));
+                        };
+
+                        match self.eval(value)? {
+                            Value::Null | Value::Void => return self.eval_expr(default, tail),
+                            value => return Ok(value),
+                        }
+                    }
+                    "?:" => {
+                        // syntax: (?: <condition> <then> <else>)
+                        let (Some(condition), Some(then), Some(otherwise), None) =
+                            (it.next(), it.next(), it.next(), it.next())
+                        else {
+                            return Err(RuntimeError::syntax(
+                                "Expected (?: <condition> <then> <else>)",
+                            ));
+                        };
+
+                        match self.eval(condition)? {
+                            Value::Bool(true) => return self.eval_expr(then, tail),
+                            Value::Bool(false) => return self.eval_expr(otherwise, tail),
+                            value => return Err(RuntimeError::type_mismatch("bool", &value)),
+                        }
+                    }
+                    "or-else" => {
+                        // syntax: (or-else <expr> <fallback>)
+                        let (Some(expr), Some(fallback), None) = (it.next(), it.next(), it.next())
+                        else {
+                            return Err(RuntimeError::syntax(
+                                "Expected (or-else <expr> <fallback>)",
+                            ));
+                        };
+
+                        // Like the body of try, expr is not in tail position
+                        match self.eval(expr) {
+                            Ok(value) => return Ok(value),
+                            Err(err) if err.is_catchable() => {
+                                self.trace.clear();
+
+                                return self.eval_expr(fallback, tail);
+                            }
+                            Err(err) => return Err(err),
+                        }
+                    }
+                    "bench" => {
+                        // syntax: (bench <name> [:iters <n>] [:warmup <n>] <body>...)
+                        let name = match it.next().map(|sexpr| self.eval(sexpr)).transpose()? {
+                            Some(Value::String(name)) => name,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected benchmark name here"));
+                            }
+                        };
+
+                        let mut iterations = 100;
+                        let mut warmup = None;
+                        let mut body = it.as_slice();
+
+                        while let [SExpr::Keyword(option, _), value, rest @ ..] = body {
+                            let count = match self.eval(value)? {
+                                Value::Int(count) if count >= 0 => count as usize,
+                                value => return Err(RuntimeError::type_mismatch("int", &value)),
+                            };
+
+                            match option.as_str() {
+                                "iters" => iterations = count.max(1),
+                                "warmup" => warmup = Some(count),
+                                _ => {
+                                    return Err(RuntimeError::syntax(format!(
+                                        "Unknown bench option :{}",
+                                        option
+                                    )));
+                                }
+                            }
+
+                            body = rest;
+                        }
+
+                        // Warmup runs fill caches and are not measured
+                        for _ in 0..warmup.unwrap_or(iterations / 10) {
+                            self.eval_list(body, false)?;
+                        }
+
+                        let mut samples = Vec::with_capacity(iterations);
+
+                        for _ in 0..iterations {
+                            let start = std::time::Instant::now();
+                            self.eval_list(body, false)?;
+                            samples.push(start.elapsed().as_nanos() as f64);
+                        }
+
+                        let result = BenchResult::from_samples(&name, samples);
+                        let value = result.to_value();
+                        self.bench_results.push(result);
+
+                        return Ok(value);
+                    }
+                    "async" => {
+                        // syntax: (async <body>...), returns a task whose body runs on first await
+                        let body = Function {
+                            name: Symbol::intern("async"),
+                            params: vec![],
+                            rest: None,
+                            body: it.cloned().collect(),
+                            closure: self.env.frames.last().cloned().unwrap_or_default(),
+                            globals: self.env.vars.clone(),
+                            file: self.file_stack.last().cloned(),
+                        };
+
+                        return Ok(stdlib::async_task(Rc::new(body)));
+                    }
+                    "spawn" => {
+                        // syntax: (spawn <body>...), returns a thread handle for thread-join
+                        return stdlib::spawn(self, it.cloned().collect());
+                    }
+                    "deftest" => {
+                        // syntax: (deftest <name> <body>...), the body runs under `kk test`
+                        let name = match it.next() {
+                            Some(SExpr::String(name, _)) => name.clone(),
+                            Some(SExpr::Atom(name, _)) => name.to_string(),
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected test name here"));
+                            }
+                        };
+
+                        if self.tests.iter().any(|test| test.name == name) {
+                            return Err(RuntimeError::syntax(format!(
+                                "Test {} is declared twice",
+                                name
+                            )));
+                        }
+
+                        self.tests.push(TestCase {
+                            name,
+                            body: it.cloned().collect(),
+                            file: self.file_stack.last().cloned(),
+                        });
+                    }
+                    "defenum" => {
+                        // syntax: (defenum <name> <variant> | (<variant> <field>...)...)
+                        let enum_name = match it.next() {
+                            Some(SExpr::Atom(atom, _)) => atom.to_string(),
+                            _ => return Err(RuntimeError::syntax("Expected enum name here")),
+                        };
+
+                        for variant in it {
+                            let (name, fields) = match variant {
+                                SExpr::Atom(name, _) => (name, &[][..]),
+                                SExpr::List(list, _) => match list.as_slice() {
+                                    [SExpr::Atom(name, _), fields @ ..]
+                                        if fields
+                                            .iter()
+                                            .all(|field| matches!(field, SExpr::Atom(..))) =>
+                                    {
+                                        (name, fields)
+                                    }
+                                    _ => {
+                                        return Err(RuntimeError::syntax(
+                                            "Expected (<variant> <field>...) here",
+                                        ));
+                                    }
+                                },
+                                _ => return Err(RuntimeError::syntax("Expected variant here")),
+                            };
+
+                            self.define_variant(&enum_name, name, fields.len());
+                        }
+
+                        self.define_enum(&enum_name);
+
+                        return Ok(Value::Void);
+                    }
+                    "." => {
+                        // syntax: (. <object> <method> <arg>...)
+                        let object = match it.next() {
+                            Some(sexpr) => self.eval(sexpr)?,
+                            None => return Err(RuntimeError::syntax("Expected object here")),
+                        };
+
+                        let method = match it.next() {
+                            Some(SExpr::Atom(atom, _)) => atom,
+                            _ => return Err(RuntimeError::syntax("Expected method name here")),
+                        };
+
+                        let function = self.resolve_method(method, &object)?;
+
+                        let mut args = vec![object];
+                        for sexpr in it {
+                            args.push(self.eval(sexpr)?);
+                        }
+
+                        return self.call(&function, args);
+                    }
+                    "defprotocol" => {
+                        // syntax: (defprotocol <name> (<method> <param>...)...)
+                        let protocol = match it.next() {
+                            Some(SExpr::Atom(atom, _)) => atom.to_string(),
+                            _ => return Err(RuntimeError::syntax("Expected protocol name here")),
+                        };
+
+                        let mut methods = vec![];
+
+                        for signature in it {
+                            let method = match signature {
+                                SExpr::List(list, _) => match list.first() {
+                                    Some(SExpr::Atom(method, _)) if list.len() > 1 => method,
+                                    _ => {
+                                        return Err(RuntimeError::syntax(
+                                            "Expected (<method> <param>...) with at least one parameter here",
+                                        ));
+                                    }
+                                },
+                                _ => {
+                                    return Err(RuntimeError::syntax(
+                                        "Expected method signature here",
+                                    ))
+                                }
+                            };
+
+                            methods.push(method.to_string());
+                        }
+
+                        self.define_protocol(&protocol, methods);
+
+                        return Ok(Value::Void);
+                    }
+                    "extend-protocol" => {
+                        // syntax: (extend-protocol <protocol> <type> (<method> (<param>...) <body>...)... <type>...)
+                        let protocol = match it.next() {
+                            Some(SExpr::Atom(atom, _)) => atom.to_string(),
+                            _ => return Err(RuntimeError::syntax("Expected protocol name here")),
+                        };
+
+                        let Some(methods) = self.protocols.get(&protocol).cloned() else {
+                            return Err(RuntimeError::with_code(
+                                ErrorCode::UndefinedVariable,
+                                format!("Undefined protocol: {}", protocol),
+                            ));
+                        };
+
+                        let mut ty = None;
+
+                        for sexpr in it {
+                            let (method, params, body) = match (sexpr, &ty) {
+                                (SExpr::Atom(atom, _), _) => {
+                                    ty = Some(atom.to_string());
+                                    continue;
+                                }
+                                (SExpr::List(list, _), Some(_)) => match list.as_slice() {
+                                    [SExpr::Atom(method, _), SExpr::List(params, _), body @ ..] => {
+                                        (method, params, body)
+                                    }
+                                    _ => {
+                                        return Err(RuntimeError::syntax(
+                                            "Expected (<method> (<param>...) <body>...) here",
+                                        ));
+                                    }
+                                },
+                                _ => return Err(RuntimeError::syntax("Expected type name here")),
+                            };
+
+                            if !methods.iter().any(|name| method == name) {
+                                return Err(RuntimeError::syntax(format!(
+                                    "{} is not a method of {}",
+                                    method, protocol
+                                )));
+                            }
+
+                            let ty = ty.clone().unwrap_or_default();
+                            let name = format!("{}-{}", ty, method);
+                            let function = self.function(&name, params, body)?;
+
+                            self.methods
+                                .insert((method.to_string(), ty), Rc::new(function));
+                        }
+
+                        return Ok(Value::Void);
+                    }
+                    "defn" => {
+                        // syntax: (defn <name> (<param> | (<param> <default>) | (:list <pattern>...) | (:keys <name>...)... [&rest <name>]) <body>...)
+                        let name = match it.next() {
+                            Some(SExpr::Atom(atom, _)) => atom,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected function name here"));
+                            }
+                        };
+
+                        let params = match it.next() {
+                            Some(SExpr::List(params, _)) => params,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected parameter list here"));
+                            }
+                        };
+
+                        let body = it.cloned().collect::<Vec<_>>();
+                        let function =
+                            Value::Function(Rc::new(self.function(name, params, &body)?));
+
+                        self.env.define(name, function.clone());
+
+                        return Ok(function);
+                    }
+                    "lambda" => {
+                        // syntax: (lambda (<param>...) <body>...), a function without a name,
+                        // taking the same parameters as defn
+                        let params = match it.next() {
+                            Some(SExpr::List(params, _)) => params,
+                            _ => {
+                                return Err(RuntimeError::syntax("Expected parameter list here"));
+                            }
+                        };
+
+                        let body = it.cloned().collect::<Vec<_>>();
+
+                        return Ok(Value::Function(Rc::new(
+                            self.function("lambda", params, &body)?,
+                        )));
+                    }
+                    _ => return self.eval_call(*name, it, tail, sexpr.span()),
+                }
+            }
+        }
+
+        Ok(Value::Void)
+    }
+
+    /// Calls the function or builtin `name` with the arguments in `it`, evaluating them first.
+    fn eval_call(
+        &mut self,
+        name: Symbol,
+        mut it: std::slice::Iter<'_, SExpr>,
+        tail: bool,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let function = match self.lookup(name)? {
+            Some(Value::Function(function)) => function,
+            Some(Value::Native(native)) => {
+                let args = it
+                    .map(|sexpr| self.eval(sexpr))
+                    .collect::<Result<Vec<Value>, RuntimeError>>()?;
+
+                return self.call_native(&native, &name, &args, span);
+            }
+            _ => {
+                return Err(RuntimeError::UnknownFunction(name.to_string()));
+            }
+        };
+
+        let mut args = vec![];
+        let mut named = vec![];
+
+        while let Some(sexpr) = it.next() {
+            match sexpr {
+                SExpr::Keyword(keyword, _) => {
+                    let value = match it.next() {
+                        Some(value) => self.eval(value)?,
+                        None => {
+                            return Err(RuntimeError::syntax(format!(
+                                "Expected value for argument :{}",
+                                keyword
+                            )));
+                        }
+                    };
+
+                    named.push((keyword.to_string(), value));
+                }
+                sexpr => args.push(self.eval(sexpr)?),
+            }
+        }
+
+        if tail {
+            self.tail_call = Some((function, args, named));
+            return Ok(Value::Void);
+        }
+
+        self.call_function(&function, args, named)
+    }
+
+    /// Evaluates a module in its own global scope and returns the bindings it exports. A
+    /// module without an `(export ...)` form exports everything it defines. Modules are
+    /// evaluated on first import only; later imports share the same bindings.
+    fn import_module(&mut self, path: &Path) -> Result<Vec<(String, Value)>, RuntimeError> {
+        let canonical = |path: &Path| std::fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        let target = canonical(path);
+
+        if let Some(exports) = self.modules.get(&target) {
+            return Ok(exports.clone());
+        }
+
+        if let Some(start) = self
+            .file_stack
+            .iter()
+            .position(|file| canonical(file) == target)
+        {
+            let chain = self.file_stack[start..]
+                .iter()
+                .chain([&path.to_path_buf()])
+                .map(|file| file.display().to_string())
+                .collect::<Vec<String>>();
+
+            return Err(RuntimeError::with_code(
+                ErrorCode::CyclicImport,
+                format!(
+                    "Cyclic import: {} (use import-lazy to defer one of the imports)",
+                    chain.join(" → ")
+                ),
+            ));
+        }
+
+        let module_vars = Rc::new(RefCell::new(Vars::default()));
+        let importer_vars = std::mem::replace(&mut self.env.vars, module_vars.clone());
+        let importer_frames = std::mem::take(&mut self.env.frames);
+
+        self.export_stack.push(None);
+        let result = self.eval_file(&path.to_string_lossy());
+        let exports = self.export_stack.pop().flatten();
+
+        self.env.vars = importer_vars;
+        self.env.frames = importer_frames;
+
+        result?;
+
+        let module_vars = module_vars.borrow();
+
+        let exports = match exports {
+            Some(names) => names
+                .into_iter()
+                .map(|name| match module_vars.get(&Symbol::intern(&name)) {
+                    Some(value) => Ok((name, value.clone())),
+                    None => Err(RuntimeError::with_code(
+                        ErrorCode::UndefinedExport,
+                        format!("Module {} exports undefined name: {}", path.display(), name),
+                    )),
+                })
+                .collect::<Result<Vec<_>, RuntimeError>>()?,
+            None => module_vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+        };
+
+        self.modules.insert(target, exports.clone());
+
+        Ok(exports)
+    }
+
+    /// Looks up a variable, loading pending lazy imports of the current module if needed.
+    fn lookup(&mut self, name: impl Into<Symbol>) -> Result<Option<Value>, RuntimeError> {
+        let name = name.into();
+
+        if let Some(value) = self.env.get(name) {
+            return Ok(Some(value));
+        }
+
+        while let Some(index) = self
+            .lazy_imports
+            .iter()
+            .position(|(scope, _)| Rc::ptr_eq(scope, &self.env.vars))
+        {
+            let (scope, path) = self.lazy_imports.remove(index);
+
+            for (name, value) in self.import_module(&path)? {
+                scope.borrow_mut().insert(Symbol::intern(&name), value);
+            }
+
+            if let Some(value) = self.env.get(name) {
+                return Ok(Some(value));
+            }
+        }
+
+        Ok(self.natives.get(&name).cloned())
+    }
+
+    /// Calls a builtin by the name it was called with at `span`, charging fuel and timing
+    /// it when slow calls are reported.
+    pub(crate) fn call_native(
+        &mut self,
+        native: &NativeFunction,
+        name: &str,
+        args: &[Value],
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        self.fuel.consume_call(name, args)?;
+
+        let telemetry_span = telemetry::call_span(self.telemetry, "builtin", name, args);
+        let start = self.slow_builtin_threshold.map(|_| Instant::now());
+
+        let result = (native.function)(self, args);
+        telemetry_span.record(&result);
+
+        if let (Some(start), Some(threshold)) = (start, self.slow_builtin_threshold) {
+            let elapsed = start.elapsed();
+            let site = (self.file_stack.last().cloned(), span.offset);
+
+            if elapsed > threshold && self.slow_builtin_sites.insert(site) {
+                let message = format!(
+                    "{} took {} ms, over the {} ms slow-builtin threshold",
+                    name,
+                    elapsed.as_millis(),
+                    threshold.as_millis()
+                );
+                self.warn(WarningKind::SlowBuiltin, message, span)?;
+            }
+        }
+
+        result
+    }
+
+    /// Calls a script function or builtin, for builtins that take a function.
+    pub(crate) fn call(
+        &mut self,
+        function: &Value,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        match function {
+            Value::Function(function) => self.call_function(function, args, vec![]),
+            Value::Native(native) => {
+                self.fuel.consume_call(&native.name, &args)?;
+                (native.function)(self, &args)
+            }
+            value => Err(RuntimeError::type_mismatch("function", value)),
+        }
+    }
+
+    fn call_function(
+        &mut self,
+        function: &Rc<Function>,
+        args: Vec<Value>,
+        named: Vec<(String, Value)>,
+    ) -> Result<Value, RuntimeError> {
+        let mut call = (function.clone(), args, named);
+
+        // Calls made in tail position are trampolined here instead of growing the Rust stack
+        loop {
+            let (function, args, named) = call;
+            let depth = self.trace.len();
+
+            let span = telemetry::call_span(self.telemetry, "function", &function.name, &args);
+
+            let result = self
+                .bind_arguments(&function, args, named)
+                .and_then(|frame| {
+                    let caller_vars =
+                        std::mem::replace(&mut self.env.vars, function.globals.clone());
+                    self.env.frames.push(frame);
+
+                    self.debug_event(|debugger, interpreter| {
+                        debugger.enter(interpreter, &function.name, function.file.as_deref())
+                    });
+
+                    let result = self.eval_list(&function.body, true);
+
+                    self.debug_event(|debugger, _| debugger.exit());
+
+                    self.env.frames.pop();
+                    self.env.vars = caller_vars;
+
+                    result
+                });
+
+            span.record(&result);
+
+            if result.is_err() {
+                self.attribute_trace(depth, function.file.as_ref());
+            }
+
+            let result = result?;
+
+            match self.tail_call.take() {
+                Some(tail_call) => call = tail_call,
+                None => return Ok(result),
+            }
+        }
+    }
+
+    /// Builds the frame for a call, evaluating omitted defaults in the definition scope.
+    fn bind_arguments(
+        &mut self,
+        function: &Rc<Function>,
+        mut args: Vec<Value>,
+        mut named: Vec<(String, Value)>,
+    ) -> Result<Vars, RuntimeError> {
+        let arity = function.params.len();
+        let required = function
+            .params
+            .iter()
+            .filter(|param| param.default.is_none())
+            .count();
+
+        if function.rest.is_none() && args.len() > arity {
+            return Err(RuntimeError::Arity {
+                function: function.name.to_string(),
+                expected: format!("at most {}", arity),
+                found: args.len(),
+            });
+        }
+
+        for (name, _) in &named {
+            match function.params.iter().position(|param| &param.name == name) {
+                Some(index) if index < args.len() => {
+                    return Err(RuntimeError::with_code(
+                        ErrorCode::InvalidArgument,
+                        format!(
+                            "Function {} got multiple values for argument: {}",
+                            function.name, name
+                        ),
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    return Err(RuntimeError::with_code(
+                        ErrorCode::InvalidArgument,
+                        format!(
+                            "Function {} has no parameter named: {}",
+                            function.name, name
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let provided = args.len() + named.len();
+        let rest = args.split_off(arity.min(args.len()));
+        let mut args = args.into_iter();
+
+        let mut values = vec![];
+
+        for param in &function.params {
+            let keyword = named
+                .iter()
+                .position(|(name, _)| param.name == *name)
+                .map(|index| named.swap_remove(index).1);
+
+            match (args.next().or(keyword), &param.default) {
+                (Some(arg), _) => values.push(Some(arg)),
+                (None, Some(_)) => values.push(None),
+                (None, None) => {
+                    return Err(RuntimeError::Arity {
+                        function: function.name.to_string(),
+                        expected: format!("at least {} (missing: {})", required, param.name),
+                        found: provided,
+                    });
+                }
+            };
+        }
+
+        // Defaults are evaluated in the scope the function was defined in
+        let caller_vars = std::mem::replace(&mut self.env.vars, function.globals.clone());
+        self.env.frames.push(function.closure.clone());
+
+        let values = function
+            .params
+            .iter()
+            .zip(values)
+            .map(|(param, value)| match (value, &param.default) {
+                (Some(value), _) => Ok(value),
+                (None, Some(default)) => self.eval(default),
+                (None, None) => unreachable!(),
+            })
+            .collect::<Result<Vec<Value>, RuntimeError>>();
+
+        let mut frame = self.env.frames.pop().unwrap_or_default();
+        self.env.vars = caller_vars;
+
+        let values = values?;
+
+        frame.insert(function.name, Value::Function(function.clone()));
+
+        let mut patterns = vec![];
+
+        for (param, value) in function.params.iter().zip(values) {
+            match &param.pattern {
+                Some(pattern) => patterns.push((pattern, value)),
+                None => {
+                    frame.insert(param.name, value);
+                }
+            }
+        }
+
+        if !patterns.is_empty() {
+            let caller_vars = std::mem::replace(&mut self.env.vars, function.globals.clone());
+            self.env.frames.push(frame);
+
+            let bound = patterns.into_iter().try_for_each(|(pattern, value)| {
+                self.bind_pattern(pattern, value).map_err(|err| {
+                    RuntimeError::with_code(
+                        err.code(),
+                        format!("Argument {} of {}: {}", pattern, function.name, err),
+                    )
+                })
+            });
+
+            frame = self.env.frames.pop().unwrap_or_default();
+            self.env.vars = caller_vars;
+
+            bound?;
+        }
+
+        if let Some(name) = function.rest {
+            frame.insert(name, Value::List(rest.into()));
+        }
+
+        Ok(frame)
+    }
+
+    /// Matches an os name (`"linux"`), family (`"unix"`) or a list of them against the host.
+    fn matches_os(target: &Value) -> Result<bool, RuntimeError> {
+        match target {
+            Value::String(name) => {
+                Ok(**name == *std::env::consts::OS || **name == *std::env::consts::FAMILY)
+            }
+            Value::List(names) => {
+                for name in names.iter() {
+                    if Self::matches_os(name)? {
+                        return Ok(true);
+                    }
+                }
+
+                Ok(false)
+            }
+            value => Err(RuntimeError::type_mismatch("string or list", value)),
+        }
+    }
+
+    /// Looks up a map key (a string or keyword) or a list index, yielding null when it is
+    /// missing or `collection` is not a map or list.
+    fn lookup_key(collection: &Value, key: &Value) -> Result<Value, RuntimeError> {
+        let found = match (collection, key) {
+            (Value::Map(map), Value::String(key) | Value::Keyword(key)) => map.get(&**key),
+            (Value::List(list), Value::Int(index)) => usize::try_from(*index)
+                .ok()
+                .and_then(|index| list.get(index)),
+            (_, Value::String(_) | Value::Keyword(_) | Value::Int(_) | Value::BigInt(_)) => None,
+            (_, key) => return Err(RuntimeError::type_mismatch("string, keyword or int", key)),
+        };
+
+        Ok(found.cloned().unwrap_or(Value::Null))
+    }
+
+    /// Evaluates a list of forms, returning the value of the last one.
+    fn eval_list(&mut self, list: &[SExpr], tail: bool) -> Result<Value, RuntimeError> {
+        let Some((last, init)) = list.split_last() else {
+            return Ok(Value::Void);
+        };
+
+        for sexpr in init {
+            self.eval(sexpr)?;
+        }
+
+        self.eval_expr(last, tail)
+    }
+
+    /// `(inc name)`: adds one to a number variable and returns the new value.
+    fn increment(&mut self, name: Symbol) -> Result<Value, RuntimeError> {
+        let value = match self.lookup(name)? {
+            Some(
+                value @ (Value::Int(_) | Value::BigInt(_) | Value::Rational(_) | Value::Float(_)),
+            ) => Self::sum(value, Value::Int(1))?,
+            Some(value) => return Err(RuntimeError::type_mismatch("int or float", &value)),
+            None => return Err(RuntimeError::UndefinedVariable(name.to_string())),
+        };
+
+        self.env.set(name, value.clone());
+
+        Ok(value)
+    }
+
+    /// Adds two numbers for `add`, converting to float when either is a float. Ints that
+    /// overflow become a `BigInt`, and sums with a rational stay exact.
+    fn sum(left: Value, right: Value) -> Result<Value, RuntimeError> {
+        match (&left, &right) {
+            (Value::Int(left), Value::Int(right)) => {
+                if let Some(sum) = left.checked_add(*right) {
+                    return Ok(Value::Int(sum));
+                }
+            }
+            (Value::Float(_), _) | (_, Value::Float(_)) => {
+                if let (Some(left), Some(right)) = (left.as_f64(), right.as_f64()) {
+                    return Ok(Value::Float(left + right));
+                }
+            }
+            _ => {}
+        }
+
+        if let (Some(left), Some(right)) = (left.to_big_int(), right.to_big_int()) {
+            return Ok((&left + &right).into());
+        }
+
+        match (left.to_rational(), right.to_rational()) {
+            (Some(left), Some(right)) => Ok((&left + &right).into()),
+            _ => Err(RuntimeError::type_mismatch("int or float", &right)),
+        }
+    }
+
+    /// The remainder for `mod`, converting to float when either side is a float. Like the
+    /// quotient of `idiv`, that of rationals is truncated toward zero.
+    fn modulo(left: Value, right: Value) -> Result<Value, RuntimeError> {
+        match (&left, &right) {
+            (Value::Int(left), Value::Int(right)) if *right != 0 => {
+                // Only i64::MIN % -1 overflows, and its remainder is 0
+                return Ok(Value::Int(left.checked_rem(*right).unwrap_or(0)));
+            }
+            (Value::Float(_), _) | (_, Value::Float(_)) => {
+                if let (Some(left), Some(right)) = (left.as_f64(), right.as_f64()) {
+                    return Ok(Value::Float(left % right));
+                }
+            }
+            (Value::Rational(_), _) | (_, Value::Rational(_)) => {
+                if let (Some(left), Some(right)) = (left.to_rational(), right.to_rational()) {
+                    let Some(quotient) = left.checked_div(&right) else {
+                        return Err(RuntimeError::with_code(
+                            ErrorCode::InvalidArgument,
+                            "Division by zero",
+                        ));
+                    };
+
+                    let quotient = Rational::from(quotient.trunc());
+                    return Ok((&left - &(&right * &quotient)).into());
+                }
+            }
+            _ => {}
+        }
+
+        match (left.to_big_int(), right.to_big_int()) {
+            (Some(left), Some(right)) => match left.div_rem(&right) {
+                Some((_, remainder)) => Ok(remainder.into()),
+                None => Err(RuntimeError::with_code(
+                    ErrorCode::InvalidArgument,
+                    "Division by zero",
+                )),
+            },
+            (Some(_), None) => Err(RuntimeError::type_mismatch("int or float", &right)),
+            (None, _) => match left.as_f64() {
+                Some(_) => Err(RuntimeError::type_mismatch("int or float", &right)),
+                None => Err(RuntimeError::type_mismatch("int or float", &left)),
+            },
+        }
+    }
+
+    fn eval_atom(&mut self, atom: Symbol) -> Result<Value, RuntimeError> {
+        if let Some(value) = parser::literal(atom.as_str()) {
+            Ok(value)
+        } else if let Some(value) = self.lookup(atom)? {
+            Ok(value)
+        } else {
+            Err(RuntimeError::UndefinedVariable(atom.to_string()))
+        }
+    }
+
+    /// Whether `sexpr` is a variable name, as opposed to a literal such as `true` or `80`.
+    fn is_binding_name(sexpr: &SExpr) -> bool {
+        match sexpr {
+            SExpr::Atom(atom, _) => {
+                !matches!(atom.as_str(), "true" | "false" | "null") && atom.parse::<f64>().is_err()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether `sexpr` is a name or a valid destructuring pattern for `bind_pattern`.
+    fn is_pattern(sexpr: &SExpr) -> bool {
+        match sexpr {
+            SExpr::Atom(..) => Self::is_binding_name(sexpr),
+            SExpr::List(list, _) => match list.as_slice() {
+                [SExpr::Keyword(keyword, _), names @ ..] if keyword == "keys" => {
+                    names.iter().all(Self::is_binding_name)
+                }
+                [SExpr::Keyword(keyword, _), patterns @ ..] if keyword == "list" => {
+                    patterns.iter().all(Self::is_pattern)
+                }
+                patterns => patterns.iter().all(Self::is_pattern),
+            },
+            _ => false,
+        }
+    }
+
+    /// Builds a function from the parameter list and body of a `defn`-like form.
+    fn function(
+        &self,
+        name: &str,
+        params: &[SExpr],
+        body: &[SExpr],
+    ) -> Result<Function, RuntimeError> {
+        let mut function = Function {
+            name: Symbol::intern(name),
+            params: vec![],
+            rest: None,
+            body: body.to_vec(),
+            closure: self.env.frames.last().cloned().unwrap_or_default(),
+            globals: self.env.vars.clone(),
+            file: self.file_stack.last().cloned(),
+        };
+
+        let mut params = params.iter();
+
+        while let Some(param) = params.next() {
+            match param {
+                SExpr::Atom(atom, _) if atom == "&rest" => {
+                    function.rest = match (params.next(), params.next()) {
+                        (Some(SExpr::Atom(rest, _)), None) => Some(*rest),
+                        _ => {
+                            return Err(RuntimeError::syntax(
+                                "Expected a single parameter name after &rest",
+                            ));
+                        }
+                    };
+                }
+                SExpr::Atom(atom, _) => function.params.push(Param {
+                    name: *atom,
+                    default: None,
+                    pattern: None,
+                }),
+                SExpr::List(list, _) => match list.as_slice() {
+                    [SExpr::Atom(atom, _), default] => function.params.push(Param {
+                        name: *atom,
+                        default: Some(default.clone()),
+                        pattern: None,
+                    }),
+                    // Patterns are marked, so that `(x y)` is always a default
+                    [SExpr::Keyword(keyword, _), ..]
+                        if (keyword == "list" || keyword == "keys") && Self::is_pattern(param) =>
+                    {
+                        function.params.push(Param {
+                            name: Symbol::intern(&param.to_string()),
+                            default: None,
+                            pattern: Some(param.clone()),
+                        })
+                    }
+                    _ => {
+                        return Err(RuntimeError::syntax(
+                            "Expected (name default) parameter, or (:list ...) or (:keys ...) pattern here",
+                        ));
+                    }
+                },
+                _ => {
+                    return Err(RuntimeError::syntax("Expected parameter name here"));
+                }
+            }
+        }
+
+        Ok(function)
+    }
+
+    /// A protocol method, calling the implementation for the type of its first argument.
+    fn dispatcher(protocol: &str, method: &str) -> Value {
+        let (protocol, method) = (protocol.to_string(), method.to_string());
+
+        Value::Native(Rc::new(NativeFunction {
+            name: method.clone(),
+            function: Box::new(move |interpreter, args| {
+                let Some(value) = args.first() else {
+                    return Err(RuntimeError::Arity {
+                        function: method.clone(),
+                        expected: "at least 1".to_string(),
+                        found: 0,
+                    });
+                };
+
+                let Some(function) = interpreter.method(&method, value) else {
+                    return Err(RuntimeError::with_code(
+                        ErrorCode::TypeMismatch,
+                        format!(
+                            "No implementation of {}/{} for {}",
+                            protocol,
+                            method,
+                            Self::type_of(value)
+                        ),
+                    ));
+                };
+
+                interpreter.call_function(&function, args.to_vec(), vec![])
+            }),
+        }))
+    }
+
+    /// Finds the implementation of a protocol method for `value`, falling back to the
+    /// `default` implementation.
+    fn method(&self, method: &str, value: &Value) -> Option<Rc<Function>> {
+        let mut types = Self::dispatch_types(value);
+        types.push("default");
+
+        types.into_iter().find_map(|ty| {
+            self.methods
+                .get(&(method.to_string(), ty.to_string()))
+                .cloned()
+        })
+    }
+
+    /// The types a method is looked up under, most specific first: a variant's own name and
+    /// its enum's, then the type name.
+    fn dispatch_types(value: &Value) -> Vec<&str> {
+        let mut types = vec![];
+
+        if let Value::Variant(variant) = value {
+            types.extend([variant.name.as_str(), variant.enum_name.as_str()]);
+        }
+
+        types.push(value.type_name());
+        types
+    }
+
+    /// Resolves `(. object method)`: a `<Type>-<method>` function for one of the object's
+    /// dispatch types, or else a protocol implementation.
+    fn resolve_method(&mut self, method: &str, object: &Value) -> Result<Value, RuntimeError> {
+        for ty in Self::dispatch_types(object) {
+            if let Some(function) = self.lookup(&format!("{}-{}", ty, method))? {
+                return Ok(function);
+            }
+        }
+
+        match self.method(method, object) {
+            Some(function) => Ok(Value::Function(function)),
+            None => Err(RuntimeError::with_code(
+                ErrorCode::UnknownFunction,
+                format!("No method {} for {}", method, Self::type_of(object)),
+            )),
+        }
+    }
+
+    /// The name a value is dispatched on: the variant name for variants, otherwise its type.
+    fn type_of(value: &Value) -> &str {
+        match value {
+            Value::Variant(variant) => &variant.name,
+            value => value.type_name(),
+        }
+    }
+
+    /// Defines the predicate of an enum, after its variants.
+    pub(crate) fn define_enum(&mut self, enum_name: &str) {
+        let predicate = format!("{}?", enum_name);
+        let enum_name = enum_name.to_string();
+
+        self.env.define(
+            &predicate,
+            Self::predicate(&predicate, move |variant| variant.enum_name == enum_name),
+        );
+    }
+
+    /// Defines a protocol and the functions dispatching each of its methods.
+    pub(crate) fn define_protocol(&mut self, protocol: &str, methods: Vec<String>) {
+        for method in &methods {
+            self.env.define(method, Self::dispatcher(protocol, method));
+        }
+
+        self.protocols.insert(protocol.to_string(), methods);
+    }
+
+    /// Defines the constructor and predicate of an enum variant. A variant without fields is
+    /// a value rather than a constructor.
+    pub(crate) fn define_variant(&mut self, enum_name: &str, name: &str, arity: usize) {
+        self.variants
+            .insert(name.to_string(), (enum_name.to_string(), arity));
+
+        let (enum_name, variant_name) = (enum_name.to_string(), name.to_string());
+
+        let construct = move |fields: &[Value]| {
+            Value::Variant(Rc::new(Variant {
+                enum_name: enum_name.clone(),
+                name: variant_name.clone(),
+                fields: fields.to_vec(),
+            }))
+        };
+
+        if arity == 0 {
+            self.env.define(name, construct(&[]));
+        } else {
+            let variant_name = name.to_string();
+
+            let constructor = NativeFunction {
+                name: name.to_string(),
+                function: Box::new(move |_, args| match args.len() == arity {
+                    true => Ok(construct(args)),
+                    false => Err(RuntimeError::Arity {
+                        function: variant_name.clone(),
+                        expected: arity.to_string(),
+                        found: args.len(),
+                    }),
+                }),
+            };
+
+            self.env.define(name, Value::Native(Rc::new(constructor)));
+        }
+
+        let (predicate, variant_name) = (format!("{}?", name), name.to_string());
+        self.env.define(
+            &predicate,
+            Self::predicate(&predicate, move |variant| variant.name == variant_name),
+        );
+    }
+
+    /// A builtin returning whether its argument is a variant accepted by `test`.
+    fn predicate(name: &str, test: impl Fn(&Variant) -> bool + 'static) -> Value {
+        let function = name.to_string();
+
+        Value::Native(Rc::new(NativeFunction {
+            name: name.to_string(),
+            function: Box::new(move |_, args| match args {
+                [Value::Variant(variant)] => Ok(Value::Bool(test(variant))),
+                [_] => Ok(Value::Bool(false)),
+                _ => Err(RuntimeError::Arity {
+                    function: function.clone(),
+                    expected: "1".to_string(),
+                    found: args.len(),
+                }),
+            }),
+        }))
+    }
+
+    /// Matches `value` against a `match` pattern, collecting its bindings. Unlike
+    /// `bind_pattern`, a value of the wrong shape is a failed match rather than an error.
+    ///
+    /// Names bind anything (`_` binds nothing), literals match equal values, `(p... [&rest
+    /// name])` matches lists element-wise and `(:keys a b)` matches maps with those keys.
+    /// Variant names match variants, with `(Circle r)` matching their fields.
+    fn match_pattern(
+        &mut self,
+        pattern: &SExpr,
+        value: &Value,
+        bindings: &mut Vec<(String, Value)>,
+    ) -> Result<bool, RuntimeError> {
+        match pattern {
+            SExpr::Atom(name, _) if name == "_" => Ok(true),
+            SExpr::Atom(name, _) if self.variants.contains_key(name.as_str()) => Ok(matches!(
+                value,
+                Value::Variant(variant) if name == &variant.name && variant.fields.is_empty()
+            )),
+            SExpr::List(patterns, _) if self.is_variant_pattern(patterns) => {
+                let Value::Variant(variant) = value else {
+                    return Ok(false);
+                };
+
+                let (name, fields) = (patterns[0].to_string(), &patterns[1..]);
+
+                if variant.name != name || variant.fields.len() != fields.len() {
+                    return Ok(false);
+                }
+
+                for (pattern, value) in fields.iter().zip(&variant.fields) {
+                    if !self.match_pattern(pattern, value, bindings)? {
+                        return Ok(false);
+                    }
+                }
+
+                Ok(true)
+            }
+            SExpr::Atom(name, _) if Self::is_binding_name(pattern) => {
+                bindings.push((name.to_string(), value.clone()));
+                Ok(true)
+            }
+            SExpr::Atom(..) | SExpr::String(..) | SExpr::Keyword(..) => {
+                let expected = self.eval(pattern)?;
+                self.values_equal(value, &expected)
+            }
+            SExpr::List(patterns, _) => match (patterns.as_slice(), value) {
+                ([SExpr::Keyword(keyword, _), names @ ..], Value::Map(map))
+                    if keyword == "keys" =>
+                {
+                    for name in names {
+                        match (name, map.get(&name.to_string())) {
+                            (SExpr::Atom(name, _), Some(found)) => {
+                                bindings.push((name.to_string(), found.clone()));
+                            }
+                            _ => return Ok(false),
+                        }
+                    }
+
+                    Ok(true)
+                }
+                (patterns, Value::List(values)) => {
+                    let (patterns, rest) = match patterns {
+                        [init @ .., SExpr::Atom(marker, _), SExpr::Atom(rest, _)]
+                            if marker == "&rest" =>
+                        {
+                            (init, Some(rest))
+                        }
+                        patterns => (patterns, None),
+                    };
+
+                    let fits = match rest {
+                        Some(_) => values.len() >= patterns.len(),
+                        None => values.len() == patterns.len(),
+                    };
+
+                    if !fits {
+                        return Ok(false);
+                    }
+
+                    for (pattern, value) in patterns.iter().zip(values.iter()) {
+                        if !self.match_pattern(pattern, value, bindings)? {
+                            return Ok(false);
+                        }
+                    }
+
+                    if let Some(rest) = rest {
+                        let rest_values = values[patterns.len()..].to_vec();
+                        bindings.push((rest.to_string(), Value::List(rest_values.into())));
+                    }
+
+                    Ok(true)
+                }
+                _ => Ok(false),
+            },
+        }
+    }
+
+    /// Whether a list pattern starts with the name of a `defenum` variant.
+    fn is_variant_pattern(&self, patterns: &[SExpr]) -> bool {
+        matches!(patterns.first(), Some(SExpr::Atom(name, _)) if self.variants.contains_key(name.as_str()))
+    }
+
+    fn bind_pattern(&mut self, pattern: &SExpr, value: Value) -> Result<(), RuntimeError> {
+        match pattern {
+            // (:keys a b) only accepts maps and (:list a b) only lists, where a plain (a b)
+            // accepts both
+            SExpr::List(patterns, span) if matches!(patterns.first(), Some(SExpr::Keyword(keyword, _)) if keyword == "keys" || keyword == "list") =>
+            {
+                let unmarked = SExpr::List(patterns[1..].to_vec(), *span);
+
+                return match (&patterns[0], value) {
+                    (SExpr::Keyword(keyword, _), value @ Value::Map(_)) if keyword == "keys" => {
+                        self.bind_pattern(&unmarked, value)
+                    }
+                    (SExpr::Keyword(keyword, _), value @ Value::List(_)) if keyword == "list" => {
+                        self.bind_pattern(&unmarked, value)
+                    }
+                    (SExpr::Keyword(keyword, _), value) => Err(RuntimeError::type_mismatch(
+                        if keyword == "keys" { "map" } else { "list" },
+                        &value,
+                    )),
+                    _ => unreachable!(),
+                };
+            }
+            SExpr::Atom(name, _) if name == "_" => {}
+            SExpr::Atom(name, span) => {
+                if let Some(frame) = self.env.frames.last() {
+                    let shadows = !frame.contains_key(name)
+                        && (self.env.vars.borrow().contains_key(name)
+                            || self.natives.contains_key(name));
+
+                    if shadows {
+                        let message = format!("{} shadows a global of the same name", name);
+                        self.warn(WarningKind::Shadowing, message, *span)?;
+                    }
+                }
+
+                self.env.define(name, value);
+            }
+            SExpr::List(patterns, _) => match value {
+                Value::List(values) => {
+                    if patterns.len() != values.len() {
+                        return Err(RuntimeError::with_code(
+                            ErrorCode::Destructuring,
+                            format!(
+                                "Cannot destructure a list of {} values into a pattern of {} elements",
+                                values.len(),
+                                patterns.len()
+                            ),
+                        ));
+                    }
+
+                    for (pattern, value) in patterns.iter().zip(Rc::unwrap_or_clone(values)) {
+                        self.bind_pattern(pattern, value)?;
+                    }
+                }
+                Value::Map(map) => {
+                    for pattern in patterns {
+                        let name = match pattern {
+                            SExpr::Atom(name, _) => name,
+                            _ => {
+                                return Err(RuntimeError::syntax(
+                                    "Map patterns may only contain variable names",
+                                ));
+                            }
+                        };
+
+                        let value = match map.get(name.as_str()).cloned() {
+                            Some(value) => value,
+                            None => {
+                                return Err(RuntimeError::with_code(
+                                    ErrorCode::Destructuring,
+                                    format!("Cannot destructure map: missing key: {}", name),
+                                ));
+                            }
+                        };
+
+                        self.bind_pattern(pattern, value)?;
+                    }
+                }
+                value => {
+                    return Err(RuntimeError::type_mismatch("list or map", &value));
+                }
+            },
+            SExpr::String(..) | SExpr::Keyword(..) => {
+                return Err(RuntimeError::syntax(
+                    "Expected variable name or pattern here",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn eval_str(interpreter: &mut Interpreter, source: &str) -> Value {
+        let sexprs = parser::Parser::new(source).parse().unwrap();
+
+        let mut result = Value::Void;
+
+        for sexpr in sexprs {
+            result = interpreter
+                .eval(&sexpr)
+                .unwrap_or_else(|err| panic!("{}", err));
+        }
+
+        result
+    }
+
+    #[test]
+    fn test_if_expressions() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(
+            eval_str(
+                &mut interpreter,
+                "(let x (if (eq 1 2) \"no\" else (do (let y 1) (add y 1))))"
+            )
+            .to_string(),
+            "void"
+        );
+        assert_eq!(eval_str(&mut interpreter, "(get x)").to_string(), "2");
+        assert_eq!(
+            eval_str(&mut interpreter, "(unless (eq 1 2) \"yes\" else \"no\")").to_string(),
+            "yes"
+        );
+        assert_eq!(
+            eval_str(&mut interpreter, "(if false 1)").to_string(),
+            "void"
+        );
+    }
+
+    #[test]
+    fn test_structured_errors() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| {
+            let sexprs = parser::Parser::new(source).parse().unwrap();
+            interpreter.eval(&sexprs[0])
+        };
+
+        assert!(matches!(
+            eval("(nope 1)"),
+            Err(RuntimeError::UnknownFunction(name)) if name == "nope"
+        ));
+        assert!(matches!(
+            eval("(get nope)"),
+            Err(RuntimeError::UndefinedVariable(_))
+        ));
+        assert!(matches!(
+            eval("(add 1 \"2\")"),
+            Err(RuntimeError::TypeMismatch { found, .. }) if found == "string"
+        ));
+        assert!(matches!(
+            eval("(do (defn f (a) a) (f 1 2))"),
+            Err(RuntimeError::Arity { found: 2, .. })
+        ));
+        assert_eq!(
+            eval("(do\n  (let))").unwrap_err().to_string(),
+            "Expected variable name or pattern here at line 2, col 3"
+        );
+        assert_eq!(
+            eval("(do (defn f (a) a) (f :b 1))").unwrap_err().code(),
+            ErrorCode::InvalidArgument
+        );
+        assert_eq!(eval("(f)").unwrap_err().code().code(), "E0002");
+    }
+
+    #[test]
+    fn test_error_trace() {
+        let mut interpreter = Interpreter::new();
+
+        let sexprs =
+            parser::Parser::new("(do\n  (defn show (x)\n    (print (get foo)))\n  (show 1))")
+                .parse()
+                .unwrap();
+
+        assert!(interpreter.eval(&sexprs[0]).is_err());
+
+        let trace = interpreter
+            .take_trace()
+            .iter()
+            .map(|frame| frame.to_string())
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            trace,
+            [
+                "at (get foo) (line 3, col 12)",
+                "at (print (get foo)) (line 3, col 5)",
+                "at (show 1) (line 4, col 3)",
+                "at (do (defn show (x) (print (get foo))) (show 1)) (line 1, col 1)",
+            ]
+        );
+
+        eval_str(&mut interpreter, "(try (get foo) (catch e null))");
+        assert!(interpreter.take_trace().is_empty());
+    }
+
+    #[test]
+    fn test_elif_chains() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(
+            &mut interpreter,
+            "(defn sign (n) (if (eq n 0) \"zero\" elif (eq (mod n 2) 0) \"even\" elif (eq n 1) \"one\" else \"odd\"))",
+        );
+
+        assert_eq!(eval_str(&mut interpreter, "(sign 0)").to_string(), "zero");
+        assert_eq!(eval_str(&mut interpreter, "(sign 4)").to_string(), "even");
+        assert_eq!(eval_str(&mut interpreter, "(sign 1)").to_string(), "one");
+        assert_eq!(eval_str(&mut interpreter, "(sign 3)").to_string(), "odd");
+        assert_eq!(
+            eval_str(&mut interpreter, "(if false 1 elif false 2)").to_string(),
+            "void"
+        );
+    }
+
+    #[test]
+    fn test_try_catch() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(
+            eval_str(&mut interpreter, "(try (get missing) (catch e e))").to_string(),
+            "Variable not found: missing"
+        );
+        assert_eq!(
+            eval_str(
+                &mut interpreter,
+                "(defn fail (x) (throw (list \"bad\" x))) (try (fail 1) 2 (catch e e))"
+            )
+            .to_string(),
+            "[bad, 1]"
+        );
+        assert_eq!(
+            eval_str(&mut interpreter, "(try 1 (catch e 2))").to_string(),
+            "1"
+        );
+    }
+
+    #[test]
+    fn test_native_functions() {
+        let mut interpreter = Interpreter::new();
+
+        interpreter.register_fn("sum", |args| {
+            args.iter()
+                .try_fold(Value::Int(0), |sum, arg| match (sum, arg) {
+                    (Value::Int(sum), Value::Int(arg)) => Ok(Value::Int(sum + arg)),
+                    (_, arg) => Err(RuntimeError::type_mismatch("int", arg)),
+                })
+        });
+
+        assert_eq!(eval_str(&mut interpreter, "(sum 1 2 3)").to_string(), "6");
+        assert_eq!(
+            eval_str(&mut interpreter, "(let total sum) (get total)").to_string(),
+            "<native fn sum>"
+        );
+        assert_eq!(
+            eval_str(&mut interpreter, "(format \"{} + {}\" 1 2)").to_string(),
+            "1 + 2"
+        );
+
+        // Script definitions shadow natives
+        assert_eq!(
+            eval_str(&mut interpreter, "(defn sum (a b) a) (sum 1 2)").to_string(),
+            "1"
+        );
+    }
+
+    #[test]
+    fn test_default_operators() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| eval_str(&mut interpreter, source).to_string();
+
+        assert_eq!(eval("(?? null \"fallback\")"), "fallback");
+        assert_eq!(eval("(?? (print) 1)"), "1");
+        assert_eq!(eval("(?? false 1)"), "false");
+        assert_eq!(eval("(?: (eq 1 1) \"yes\" \"no\")"), "yes");
+        assert_eq!(eval("(?: false \"yes\" \"no\")"), "no");
+        assert_eq!(eval("(or-else (get missing) 0)"), "0");
+        assert_eq!(eval("(or-else (add 1 2) 0)"), "3");
+    }
+
+    #[test]
+    fn test_access_chains() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(
+            &mut interpreter,
+            "(let config (dict \"server\" (dict \"ports\" (list 80 443))))",
+        );
+
+        let mut eval = |source: &str| eval_str(&mut interpreter, source).to_string();
+
+        assert_eq!(eval("(get-in config (list \"server\" :ports 1))"), "443");
+        assert_eq!(eval("(get-in config (list \"server\" \"host\"))"), "null");
+        assert_eq!(
+            eval("(get-in config (list \"db\" \"host\") \"localhost\")"),
+            "localhost"
+        );
+        assert_eq!(eval("(maybe-> config \"server\" \"ports\" 0)"), "80");
+        assert_eq!(eval("(maybe-> config \"db\" (get missing))"), "null");
+        assert_eq!(
+            eval("(maybe-> config \"server\" \"ports\" 0 \"x\")"),
+            "null"
+        );
+    }
+
+    #[test]
+    fn test_let_destructuring() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(
+            &mut interpreter,
+            "(let (a (b c) _) (list 1 (list 2 3) 4))
+             (let (name age) (dict \"name\" \"kk\" \"age\" 3))",
+        );
+
+        assert_eq!(eval_str(&mut interpreter, "(get a)").to_string(), "1");
+        assert_eq!(eval_str(&mut interpreter, "(get c)").to_string(), "3");
+        assert_eq!(eval_str(&mut interpreter, "(get age)").to_string(), "3");
+        assert_eq!(eval_str(&mut interpreter, "(get name)").to_string(), "kk");
+    }
+
+    #[test]
+    fn test_for_each() {
+        let mut interpreter = Interpreter::new();
+
+        let result = eval_str(
+            &mut interpreter,
+            "(let out \"\")
+             (for x in (list 1 2) ((set out \"${out}${(add x 10)} \")))
+             (for (key value) in (dict \"b\" 2 \"a\" 1) ((set out \"${out}${key}=${value} \")))
+             (for c in \"hé\" ((set out \"${out}${c} \")))
+             (for byte in (bytes 7) ((set out \"${out}${byte}\")))
+             (list out)",
+        );
+
+        assert_eq!(result.to_string(), "[11 12 a=1 b=2 h é 7]");
+
+        let err = interpreter
+            .eval_str("(for x in 5 ((print x)))")
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::TypeMismatch);
+        assert!(interpreter.eval_str("(for x of (list) ())").is_err());
+    }
+
+    #[test]
+    fn test_rest_parameters() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(
+            &mut interpreter,
+            "(defn collect (first &rest more) (list first more))",
+        );
+
+        assert_eq!(
+            eval_str(&mut interpreter, "(collect 1 2 3)").to_string(),
+            "[1, [2, 3]]"
+        );
+        assert_eq!(
+            eval_str(&mut interpreter, "(collect 1)").to_string(),
+            "[1, []]"
+        );
+        assert_eq!(eval_str(&mut interpreter, "(add 1 2 3)").to_string(), "6");
+    }
+
+    #[test]
+    fn test_default_parameters() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(
+            &mut interpreter,
+            "(let greeting \"hello\")
+             (defn greet (who (name (format \"{} world\" greeting))) (list who name))",
+        );
+
+        assert_eq!(
+            eval_str(&mut interpreter, "(greet 1)").to_string(),
+            "[1, hello world]"
+        );
+        assert_eq!(
+            eval_str(&mut interpreter, "(greet 1 2)").to_string(),
+            "[1, 2]"
+        );
+    }
+
+    #[test]
+    fn test_keyword_arguments() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(
+            &mut interpreter,
+            "(defn connect (host (port 80) (secure false)) (list host port secure))",
+        );
+
+        assert_eq!(
+            eval_str(
+                &mut interpreter,
+                "(connect :secure true :host \"localhost\")"
+            )
+            .to_string(),
+            "[localhost, 80, true]"
+        );
+        assert_eq!(
+            eval_str(&mut interpreter, "(connect \"a\" :port 8080)").to_string(),
+            "[a, 8080, false]"
+        );
+    }
+
+    #[test]
+    fn test_platform_conditionals() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(
+            eval_str(
+                &mut interpreter,
+                "(cond-os (\"plan9\" (unknown-function)) ((list (os) \"plan9\") 1) (else 2))"
+            )
+            .to_string(),
+            "1"
+        );
+        assert_eq!(
+            eval_str(&mut interpreter, "(when-os \"plan9\" (unknown-function))").to_string(),
+            "void"
+        );
+    }
+
+    #[test]
+    fn test_trace_log() {
+        let log = Rc::new(RefCell::new(String::new()));
+        let sink = log.clone();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_trace(move |text| sink.borrow_mut().push_str(text));
+
+        eval_str(
+            &mut interpreter,
+            "(count i from 0 to 2 ((if (eq i 1) (add i 1) else 0)))",
+        );
+
+        let log = log.borrow();
+        let lines = log.lines().collect::<Vec<_>>();
+
+        assert_eq!(
+            lines[0],
+            "  1 (count i from 0 to 2 ((if (eq i 1) (add i 1) else 0)))"
+        );
+        assert_eq!(lines[1], "  2   (if (eq i 1) (add i 1) else 0)");
+        assert_eq!(lines[2], "  3     (eq i 1)");
+        assert_eq!(lines[3], "  3     => false");
+        assert_eq!(lines[4], "  2   => 0");
+        assert!(log.contains("  3     (add i 1)\n  3     => 2\n"));
+        assert_eq!(lines.last(), Some(&"  1 => void"));
+    }
+
+    #[test]
+    fn test_tail_calls_run_in_constant_stack() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(
+            &mut interpreter,
+            "(defn count-up (n acc)
+                (if (eq n 0) acc else (count-up (add n -1) (add acc 1))))",
+        );
+
+        assert_eq!(
+            eval_str(&mut interpreter, "(count-up 100000 0)").to_string(),
+            "100000"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "(missing: who)")]
+    fn test_missing_required_parameter() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(&mut interpreter, "(defn greet (who (name 1)) name) (greet)");
+    }
+
+    #[test]
+    fn test_bench() {
+        let mut interpreter = Interpreter::new();
+
+        let result = eval_str(
+            &mut interpreter,
+            "(let runs 0) (bench \"set\" :iters 20 :warmup 5 (set runs (add runs 1)))",
+        );
+
+        assert!(matches!(&result, Value::Map(map) if matches!(map["iters"], Value::Int(20))));
+        assert_eq!(eval_str(&mut interpreter, "(add runs 0)").to_string(), "25");
+
+        let results = interpreter.take_bench_results();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "set");
+        assert!(results[0].min <= results[0].median && results[0].median <= results[0].max);
+    }
+
+    #[test]
+    fn test_fuel() {
+        let mut interpreter = Interpreter::new();
+
+        interpreter.set_fuel(Some(10));
+        eval_str(&mut interpreter, "(add 1 2)");
+        assert_eq!(interpreter.fuel(), Some(7));
+
+        // Builtins cost their category's multiplier, scaled by argument size
+        interpreter.set_fuel(Some(1000));
+        interpreter.set_fuel_multiplier(FuelCategory::String, 50);
+        eval_str(&mut interpreter, "(upper \"kk\")");
+        assert_eq!(interpreter.fuel(), Some(948));
+
+        interpreter.set_var("long", Value::String("x".repeat(250).into()));
+        eval_str(&mut interpreter, "(upper long)");
+        assert_eq!(interpreter.fuel(), Some(796));
+
+        interpreter.register_fn("slow", |_| Ok(Value::Null));
+        interpreter.set_fuel_category("slow", FuelCategory::Http);
+        eval_str(&mut interpreter, "(slow)");
+        assert_eq!(interpreter.fuel(), Some(695));
+
+        interpreter.set_fuel(Some(50));
+        let err = interpreter
+            .eval_str("(defn loop () (loop)) (try (loop) (catch e 1))")
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::OutOfFuel);
+
+        interpreter.set_fuel(None);
+        eval_str(&mut interpreter, "(upper long)");
+    }
+
+    #[test]
+    fn test_eq_modes() {
+        let mut interpreter = Interpreter::new();
+
+        let sources = [
+            "(eq 1 \"1\")",
+            "(ne 1 1.0)",
+            "(eq (list 1 :a) (list 1 :a))",
+            "(eq true \"1\")",
+            "(contains (list 1 2) \"2\")",
+            "(neq (list 1 :a) (list 1.0 :a))",
+            "(eq (list 1) (list \"1\"))",
+        ];
+
+        let results = |interpreter: &mut Interpreter| {
+            sources
+                .iter()
+                .map(|source| match interpreter.eval_str(source) {
+                    Ok(value) => value.to_string(),
+                    Err(_) => "error".to_string(),
+                })
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(
+            results(&mut interpreter),
+            ["error", "false", "true", "error", "error", "false", "false"]
+        );
+
+        interpreter.set_eq_mode(EqMode::Unequal);
+        assert_eq!(
+            results(&mut interpreter),
+            ["false", "false", "true", "false", "false", "false", "false"]
+        );
+
+        interpreter.set_eq_mode(EqMode::Loose);
+        assert_eq!(
+            results(&mut interpreter),
+            ["true", "false", "true", "true", "true", "false", "false"]
+        );
+
+        assert_eq!(Value::Int(3), Value::Float(3.0));
+        assert_ne!(Value::Int(0), Value::Float(0.5));
+        assert_ne!(Value::Int(i64::MAX), Value::Float(i64::MAX as f64));
+        assert_ne!(Value::Int(1), Value::String("1".into()));
+    }
+
+    #[test]
+    fn test_warnings() {
+        let warnings = Rc::new(RefCell::new(vec![]));
+        let sink = warnings.clone();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_warnings(move |warning| sink.borrow_mut().push(warning.to_string()));
+        interpreter.register_fn("old", |_| Ok(Value::Null));
+        interpreter.deprecate("old", "new");
+
+        eval_str(
+            &mut interpreter,
+            "(let total (add 1 0.5))\n(defn f () (let total 1) (let x 2) (let x 3))\n(f) (old)",
+        );
+        eval_str(
+            &mut interpreter,
+            "(suppress-warnings \"implicit-float\" (add 1 0.5) (mod 1.0 2))",
+        );
+
+        assert_eq!(
+            *warnings.borrow(),
+            [
+                "warning[W0001]: add mixes int and float, ints are converted (line 1, col 12)",
+                "warning[W0002]: total shadows a global of the same name (line 2, col 17)",
+                "warning[W0003]: old is deprecated, use new instead (line 3, col 5)",
+            ]
+        );
+
+        interpreter.set_deny_warnings(true);
+
+        assert_eq!(
+            interpreter.eval_str("(add 1 0.5)").unwrap_err().code(),
+            ErrorCode::DeniedWarning
+        );
+        assert!(interpreter
+            .eval_str("(suppress-warnings \"all\" (add 1 0.5))")
+            .is_ok());
+        assert!(interpreter
+            .eval_str("(suppress-warnings \"nope\" 1)")
+            .is_err());
+    }
+
+    #[test]
+    fn test_slow_builtin_warnings() {
+        let warnings = Rc::new(RefCell::new(vec![]));
+        let sink = warnings.clone();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_warnings(move |warning| sink.borrow_mut().push(warning.to_string()));
+        interpreter.register_fn("nap", |args| {
+            std::thread::sleep(Duration::from_millis(20));
+            Ok(args.first().cloned().unwrap_or(Value::Void))
+        });
+
+        eval_str(&mut interpreter, "(nap)");
+        assert!(warnings.borrow().is_empty());
+
+        interpreter.set_slow_builtin_threshold(Some(Duration::from_millis(5)));
+        eval_str(
+            &mut interpreter,
+            "(count i from 0 to 3 ((nap i)))\n(nap (upper \"fast\"))",
+        );
+
+        let warnings = warnings.borrow();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("warning[W0006]: nap took "));
+        assert!(warnings[0].ends_with("over the 5 ms slow-builtin threshold (line 1, col 23)"));
+        assert!(warnings[1].ends_with("(line 2, col 1)"));
+    }
+
+    #[test]
+    fn test_run_main() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(interpreter.run_main(&[]).unwrap(), 0);
+
+        eval_str(
+            &mut interpreter,
+            "(defn main (args) (let (a b) args) (eq a \"ok\"))",
+        );
+
+        assert_eq!(
+            interpreter
+                .run_main(&["ok".to_string(), "x".to_string()])
+                .unwrap(),
+            0
+        );
+
+        eval_str(&mut interpreter, "(defn main () 3)");
+
+        assert_eq!(interpreter.run_main(&["ignored".to_string()]).unwrap(), 3);
+
+        eval_str(&mut interpreter, "(defn main () 300)");
+
+        let error = interpreter.run_main(&[]).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidArgument);
+    }
+
+    #[test]
+    fn test_export_import_vars() {
+        let mut interpreter = Interpreter::new();
+
+        interpreter
+            .import_json(&serde_json::json!({ "config_retries": 3, "config_hosts": ["a", "b"] }))
+            .unwrap();
+        interpreter.import_vars([("scale".to_string(), Value::Float(1.5))]);
+
+        eval_str(
+            &mut interpreter,
+            "(let out_total (add (get config_retries) 1)) (let out_hosts (get config_hosts)) \
+             (defn out_helper () 1)",
+        );
+
+        let mut names = interpreter
+            .export_vars("out_")
+            .into_keys()
+            .collect::<Vec<String>>();
+        names.sort();
+
+        assert_eq!(names, ["out_helper", "out_hosts", "out_total"]);
+        assert_eq!(
+            serde_json::Value::Object(interpreter.export_json("out_").unwrap()),
+            serde_json::json!({ "out_total": 4, "out_hosts": ["a", "b"] })
+        );
+        assert!(interpreter.import_json(&serde_json::json!([1])).is_err());
+    }
+
+    #[test]
+    fn test_import_exports() {
+        let dir = std::env::temp_dir().join(format!("kk-export-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("util.kk"),
+            "(export shout) (let suffix \"!\") (defn shout (s) (format \"{}{}\" s suffix))",
+        )
+        .unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.file_stack.push(dir.join("main.kk"));
+
+        eval_str(&mut interpreter, "(import \"./util.kk\")");
+
+        assert_eq!(
+            eval_str(&mut interpreter, "(shout \"hi\")").to_string(),
+            "hi!"
+        );
+        assert!(interpreter.env.get("suffix").is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_cache_and_alias() {
+        let dir = std::env::temp_dir().join(format!("kk-alias-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("counter.kk"),
+            "(print \"loaded\") (defn twice (n) (add n n))",
+        )
+        .unwrap();
+
+        let output = Rc::new(RefCell::new(String::new()));
+        let sink = output.clone();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(move |text| sink.borrow_mut().push_str(text));
+        interpreter.file_stack.push(dir.join("main.kk"));
+
+        eval_str(
+            &mut interpreter,
+            "(import \"./counter.kk\" :as c) (import \"./counter.kk\")",
+        );
+
+        assert_eq!(*output.borrow(), "loaded\n");
+        assert_eq!(eval_str(&mut interpreter, "(c/twice 4)").to_string(), "8");
+        assert_eq!(eval_str(&mut interpreter, "(twice 5)").to_string(), "10");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "b.kk → ")]
+    fn test_cyclic_import() {
+        let dir = std::env::temp_dir().join(format!("kk-cycle-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.kk"), "(import \"./b.kk\")").unwrap();
+        std::fs::write(dir.join("b.kk"), "(import \"./a.kk\")").unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.file_stack.push(dir.join("main.kk"));
+
+        eval_str(&mut interpreter, "(import \"./a.kk\")");
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot destructure a list of 2 values")]
+    fn test_let_destructuring_shape_mismatch() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(&mut interpreter, "(let (a b c) (list 1 2))");
+    }
+
+    #[test]
+    fn test_match_and_case_guards() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| eval_str(&mut interpreter, source).to_string();
+
+        eval(
+            "(defn order (pair)
+               (match pair
+                 ((x y) :when (eq x y) \"same\")
+                 ((x y) (format \"{} then {}\" x y))
+                 ((x &rest more) more)
+                 (_ \"other\")))",
+        );
+        assert_eq!(eval("(order (list 3 3))"), "same");
+        assert_eq!(eval("(order (list 1 3))"), "1 then 3");
+        assert_eq!(eval("(order (list 1 2 3))"), "[2, 3]");
+        assert_eq!(eval("(order \"x\")"), "other");
+
+        eval(
+            "(defn greet (user)
+               (match user
+                 ((:keys name admin) :when admin (format \"hi {}, admin\" name))
+                 ((:keys name) (format \"hi {}\" name))))",
+        );
+        assert_eq!(
+            eval("(greet (dict \"name\" \"ada\" \"admin\" true))"),
+            "hi ada, admin"
+        );
+        assert_eq!(
+            eval("(greet (dict \"name\" \"bob\" \"admin\" false))"),
+            "hi bob"
+        );
+
+        eval(
+            "(defn size (n)
+               (case n
+                 (0 \"none\")
+                 (1 :when false \"never\")
+                 (1 \"one\")
+                 (else \"many\")))",
+        );
+        assert_eq!(
+            eval("(list (size 0) (size 1) (size 7))"),
+            "[none, one, many]"
+        );
+    }
+
+    #[test]
+    fn test_method_calls() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| eval_str(&mut interpreter, source).to_string();
+
+        eval(
+            "(defenum Shape (Point x y) Origin)
+             (defn Point-translate (p dx dy)
+               (match p ((Point x y) (Point (add x dx) (add y dy)))))
+             (defn Shape-name (s) \"shape\")
+             (defn string-shout (s) (format \"{}!\" s))
+             (defprotocol Sized (size thing))
+             (extend-protocol Sized list (size (l) \"a list\"))",
+        );
+        assert_eq!(eval("(. (Point 1 2) translate 3 4)"), "(Point 4 6)");
+        assert_eq!(
+            eval("(list (. Origin name) (. \"hey\" shout))"),
+            "[shape, hey!]"
+        );
+        assert_eq!(eval("(. (list 1) size)"), "a list");
+
+        let error = interpreter
+            .eval_str("(. Origin translate 1 1)")
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::UnknownFunction);
+        assert!(error.to_string().contains("No method translate for Origin"));
+    }
+
+    #[test]
+    fn test_protocols() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| eval_str(&mut interpreter, source).to_string();
+
+        eval(
+            "(defenum Shape (Circle r) (Rect w h) Empty)
+             (defprotocol Drawable (draw thing) (scale thing by))
+             (extend-protocol Drawable
+               Circle
+               (draw (c) (format \"circle {}\" (match c ((Circle r) r))))
+               Shape
+               (draw (s) \"some shape\")
+               string
+               (draw (s) (format \"text {}\" s))
+               (scale (s by) (format \"{}x{}\" s by))
+               default
+               (draw (x) \"anything\"))",
+        );
+        assert_eq!(
+            eval("(list (draw (Circle 2)) (draw (Rect 1 2)) (draw \"hi\") (draw 1))"),
+            "[circle 2, some shape, text hi, anything]"
+        );
+        assert_eq!(eval("(scale \"hi\" 3)"), "hix3");
+
+        let error = interpreter.eval_str("(scale Empty 2)").unwrap_err();
+        assert_eq!(error.code(), ErrorCode::TypeMismatch);
+        assert!(error
+            .to_string()
+            .contains("No implementation of Drawable/scale for Empty"));
+        assert!(interpreter
+            .eval_str("(extend-protocol Drawable int (paint (x) x))")
+            .is_err());
+    }
+
+    #[test]
+    fn test_defenum() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| eval_str(&mut interpreter, source).to_string();
+
+        eval(
+            "(defenum Shape (Circle r) (Rect w h) Empty)
+             (defn area (shape)
+               (match shape
+                 ((Circle r) (add r r))
+                 ((Rect w 0) 0)
+                 ((Rect w h) (add w h))
+                 (Empty 0)))",
+        );
+        assert_eq!(eval("(area (Circle 2))"), "4");
+        assert_eq!(eval("(area (Rect 3 4))"), "7");
+        assert_eq!(eval("(area Empty)"), "0");
+        assert_eq!(
+            eval("(list (Rect 1 (Circle 2)) Empty)"),
+            "[(Rect 1 (Circle 2)), Empty]"
+        );
+        assert_eq!(
+            eval("(list (Shape? Empty) (Circle? (Rect 1 2)) (Rect? (Rect 1 2)) (Shape? 1))"),
+            "[true, false, true, false]"
+        );
+        assert_eq!(eval("(eq (Circle 1) (Circle 1))"), "true");
+
+        assert!(interpreter
+            .eval_str("(Rect 1)")
+            .unwrap_err()
+            .to_string()
+            .contains("Rect"));
+    }
+
+    #[test]
+    fn test_parameter_destructuring() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| eval_str(&mut interpreter, source).to_string();
+
+        eval("(defn span ((:list x1 y1) (:list x2 y2)) (list (add x1 x2) (add y1 y2)))");
+        assert_eq!(eval("(span (list 1 2) (list 4 6))"), "[5, 8]");
+
+        eval("(defn describe ((:keys name age) (suffix \"!\")) (format \"{} {}{}\" name age suffix))");
+        assert_eq!(
+            eval("(describe (dict \"name\" \"ada\" \"age\" 36))"),
+            "ada 36!"
+        );
+
+        eval("(defn nested ((:list (a b) c)) (list a b c))");
+        assert_eq!(eval("(nested (list (list 1 2) 3))"), "[1, 2, 3]");
+
+        // Without a marker, a name in second position is a default
+        eval("(let base 10) (defn offset ((x base)) x)");
+        assert_eq!(eval("(list (offset) (offset 1))"), "[10, 1]");
+
+        let err = interpreter
+            .eval_str("(span (list 1 2 3) (list 4 6))")
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Destructuring);
+        assert!(err
+            .to_string()
+            .starts_with("Argument (:list x1 y1) of span: "));
+
+        let err = interpreter.eval_str("(describe (list 1 2))").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::TypeMismatch);
+
+        let err = interpreter
+            .eval_str("(span (dict \"x1\" 1 \"y1\" 2) (list 4 6))")
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::TypeMismatch);
+
+        assert!(interpreter.eval_str("(defn bad ((a b c)) a)").is_err());
+    }
+
+    #[test]
+    fn test_self_referencing_function() {
+        let mut interpreter = Interpreter::new();
+
+        // The function's globals hold it, but functions print as `<fn name>` and compare by
+        // pointer, so neither walks into them
+        let fs = eval_str(
+            &mut interpreter,
+            "(defn self () self) (let fs (list self (dict \"f\" self))) (get fs)",
+        );
+
+        assert_eq!(fs.to_string(), "[<fn self>, {f: <fn self>}]");
+        assert!(format!("{:?}", fs).contains("self"));
+        assert_eq!(
+            eval_str(&mut interpreter, "(repr fs)").to_string(),
+            "(list <fn self> (dict \"f\" <fn self>))"
+        );
+        assert_eq!(
+            eval_str(
+                &mut interpreter,
+                "(list (eq self (self)) (eq fs (list self (dict \"f\" self))))"
+            )
+            .to_string(),
+            "[true, true]"
+        );
+        assert!(interpreter.eval_str("(json-stringify fs)").is_err());
+    }
+
+    #[test]
+    fn test_shared_values() {
+        let mut interpreter = Interpreter::new();
+
+        eval_str(&mut interpreter, "(let xs (list 1 2 3)) (let ys xs)");
+
+        let (Some(Value::List(xs)), Some(Value::List(ys))) =
+            (interpreter.env.get("xs"), interpreter.env.get("ys"))
+        else {
+            panic!("xs and ys should be lists");
+        };
+        assert!(Rc::ptr_eq(&xs, &ys));
+
+        // Editing a shared map copies it rather than changing what the other binding sees
+        eval_str(
+            &mut interpreter,
+            "(let m (dict \"a\" 1)) (let patched (apply-patch m (list (dict \"op\" \"add\" \"path\" \"/b\" \"value\" 2))))",
+        );
+        assert_eq!(interpreter.env.get("m").unwrap().to_string(), "{a: 1}");
+        assert_eq!(
+            interpreter.env.get("patched").unwrap().to_string(),
+            "{a: 1, b: 2}"
+        );
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_max_depth(20);
+
+        eval_str(
+            &mut interpreter,
+            "(defn down (n) (if (eq n 0) 0 else (add 1 (down (add n -1)))))
+             (defn loop (n acc) (if (eq n 0) acc else (loop (add n -1) (add acc 1))))",
+        );
+
+        assert_eq!(eval_str(&mut interpreter, "(down 3)").to_string(), "3");
+
+        let err = interpreter.eval_str("(down 100)").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::RecursionLimit);
+        assert!(err
+            .to_string()
+            .starts_with("Recursion limit of 20 nested forms exceeded at line 1, col "));
+        interpreter.take_trace();
+
+        let caught = eval_str(&mut interpreter, "(try (down 100) (catch e :caught))");
+        assert_eq!(caught.to_string(), ":caught");
+
+        // Calls in tail position don't nest
+        assert_eq!(
+            eval_str(&mut interpreter, "(loop 1000 0)").to_string(),
+            "1000"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Frees the reference cycles an interpreter leaves behind. A function holds the globals of
+//! the module it was defined in, and those globals hold the function, so `Rc` alone never
+//! frees a script's functions or its scopes. When the interpreter is dropped, `free` traces
+//! everything it holds and clears the scopes that nothing outside of it reaches any more,
+//! which breaks their cycles. Values the embedder kept, such as a function returned by
+//! `eval_str`, go on working with everything they reach.
+//!
+//! Only scopes are cleared: they are the only mutable values, so every cycle goes through
+//! one.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::value::{Scope, Value};
+
+/// A reference to a value that may be part of a cycle.
+pub(super) enum Node {
+    Scope(Scope),
+    Value(Value),
+}
+
+struct Traced {
+    /// The one reference the trace keeps, so strong counts can be compared.
+    node: Node,
+    /// References to the node from the roots and from other traced nodes.
+    internal: usize,
+    children: Vec<usize>,
+}
+
+/// Clears the scopes reachable from `roots`, the references held by the interpreter being
+/// dropped, that are reachable from nothing else.
+pub(super) fn free(roots: Vec<Node>) {
+    let mut traced: HashMap<usize, Traced> = HashMap::new();
+    let mut pending = roots;
+
+    while let Some(node) = pending.pop() {
+        let Some(key) = node.key() else {
+            continue;
+        };
+
+        if let Some(traced) = traced.get_mut(&key) {
+            traced.internal += 1;
+            continue;
+        }
+
+        let children = node.children();
+
+        traced.insert(
+            key,
+            Traced {
+                internal: 1,
+                children: children.iter().filter_map(Node::key).collect(),
+                node,
+            },
+        );
+
+        pending.extend(children);
+    }
+
+    // Whatever is referenced more often than the trace accounts for is held from outside,
+    // and keeps everything it reaches
+    let mut live = traced
+        .iter()
+        .filter(|(_, traced)| traced.node.strong_count() - 1 > traced.internal)
+        .map(|(key, _)| *key)
+        .collect::<Vec<_>>();
+    let mut reached = live.iter().copied().collect::<HashSet<_>>();
+
+    while let Some(key) = live.pop() {
+        for child in &traced[&key].children {
+            if reached.insert(*child) {
+                live.push(*child);
+            }
+        }
+    }
+
+    for (key, traced) in &traced {
+        if let (Node::Scope(scope), false) = (&traced.node, reached.contains(key)) {
+            scope.borrow_mut().clear();
+        }
+    }
+}
+
+impl Node {
+    /// The address of the shared allocation, `None` for values that can't be in a cycle.
+    fn key(&self) -> Option<usize> {
+        let pointer = match self {
+            Node::Scope(scope) => Rc::as_ptr(scope) as *const (),
+            Node::Value(Value::List(list)) => Rc::as_ptr(list) as *const (),
+            Node::Value(Value::Map(map)) => Rc::as_ptr(map) as *const (),
+            Node::Value(Value::Function(function)) => Rc::as_ptr(function) as *const (),
+            Node::Value(Value::Variant(variant)) => Rc::as_ptr(variant) as *const (),
+            Node::Value(_) => return None,
+        };
+
+        Some(pointer as usize)
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Scope(scope) => Rc::strong_count(scope),
+            Node::Value(Value::List(list)) => Rc::strong_count(list),
+            Node::Value(Value::Map(map)) => Rc::strong_count(map),
+            Node::Value(Value::Function(function)) => Rc::strong_count(function),
+            Node::Value(Value::Variant(variant)) => Rc::strong_count(variant),
+            Node::Value(_) => 1,
+        }
+    }
+
+    fn children(&self) -> Vec<Node> {
+        let values = |values: &mut dyn Iterator<Item = &Value>| {
+            values
+                .filter(|value| {
+                    matches!(
+                        value,
+                        Value::List(_) | Value::Map(_) | Value::Function(_) | Value::Variant(_)
+                    )
+                })
+                .map(|value| Node::Value(value.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        match self {
+            Node::Scope(scope) => values(&mut scope.borrow().values()),
+            Node::Value(Value::List(list)) => values(&mut list.iter()),
+            Node::Value(Value::Map(map)) => values(&mut map.values()),
+            Node::Value(Value::Variant(variant)) => values(&mut variant.fields.iter()),
+            Node::Value(Value::Function(function)) => {
+                let mut children = values(&mut function.closure.values());
+                children.push(Node::Scope(function.globals.clone()));
+                children
+            }
+            Node::Value(_) => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::value::Value;
+    use crate::Interpreter;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_free_cycles() {
+        let mut interpreter = Interpreter::new();
+
+        interpreter
+            .eval_str("(defn square (n) (pow n 2)) (let fs (list square))")
+            .unwrap();
+
+        let square = match interpreter.get_var("square") {
+            Some(Value::Function(square)) => Rc::downgrade(&square),
+            _ => panic!("square should be a function"),
+        };
+
+        drop(interpreter);
+        assert!(square.upgrade().is_none());
+
+        // A function still held keeps the globals it calls
+        let mut interpreter = Interpreter::new();
+
+        let kept = interpreter
+            .eval_str("(defn helper () 1) (defn twice () (list (helper) (helper)))")
+            .unwrap();
+
+        drop(interpreter);
+
+        let mut interpreter = Interpreter::new();
+        assert_eq!(
+            interpreter.call(&kept, vec![]).unwrap().to_string(),
+            "[1, 1]"
+        );
+    }
+}
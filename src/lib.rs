@@ -0,0 +1,41 @@
+//! kk, a small lisp-like scripting language.
+//!
+//! ```
+//! let mut interpreter = kk::Interpreter::new();
+//!
+//! interpreter.eval_str("(let answer (add 40 2))").unwrap();
+//!
+//! assert_eq!(interpreter.get_var("answer").unwrap().to_string(), "42");
+//! ```
+
+pub mod bench;
+pub mod bigint;
+pub mod check;
+pub mod dap;
+pub mod debug;
+pub mod diagnostics;
+pub mod error;
+pub mod format;
+pub mod fuel;
+mod interpreter;
+pub mod lint;
+pub mod lsp;
+pub mod manifest;
+pub mod package;
+pub mod parser;
+pub mod rational;
+pub mod repl;
+pub mod server;
+pub mod sexpr;
+mod stdlib;
+pub mod symbol;
+mod telemetry;
+#[cfg(test)]
+mod test_util;
+pub mod testing;
+pub mod value;
+pub mod version;
+
+pub use error::RuntimeError;
+pub use interpreter::{DivMode, EqMode, Interpreter};
+pub use value::Value;
@@ -0,0 +1,311 @@
+//! Static checks over parsed source. `kk lint` prints them, and `--lint` reports them as
+//! warnings before a script runs.
+
+use std::collections::HashMap;
+
+use crate::diagnostics::WarningKind;
+use crate::sexpr::{SExpr, Span};
+
+/// A problem found without running the code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub kind: WarningKind,
+    pub message: String,
+    pub span: Span,
+}
+
+/// Checks every `match` and `case` form for unreachable arms and missing catch-alls.
+/// Matches over the variants of a `defenum` must cover all of them.
+pub fn check(sexprs: &[SExpr]) -> Vec<Lint> {
+    let mut enums = Enums::default();
+
+    for sexpr in sexprs {
+        enums.collect(sexpr);
+    }
+
+    let mut lints = vec![];
+
+    for sexpr in sexprs {
+        visit(sexpr, &enums, &mut lints);
+    }
+
+    lints
+}
+
+/// The variants declared by `defenum` forms, by enum name and by variant name.
+#[derive(Default)]
+struct Enums {
+    variants: HashMap<String, Vec<String>>,
+    enum_of: HashMap<String, String>,
+}
+
+impl Enums {
+    fn collect(&mut self, sexpr: &SExpr) {
+        let SExpr::List(list, _) = sexpr else {
+            return;
+        };
+
+        if let [SExpr::Atom(form, _), SExpr::Atom(name, _), variants @ ..] = list.as_slice() {
+            if form == "defenum" {
+                for variant in variants {
+                    let variant = match variant {
+                        SExpr::List(fields, _) => fields.first(),
+                        _ => Some(variant),
+                    };
+
+                    if let Some(SExpr::Atom(variant, _)) = variant {
+                        self.enum_of.insert(variant.to_string(), name.to_string());
+                        self.variants
+                            .entry(name.to_string())
+                            .or_default()
+                            .push(variant.to_string());
+                    }
+                }
+            }
+        }
+
+        for sexpr in list {
+            self.collect(sexpr);
+        }
+    }
+}
+
+fn visit(sexpr: &SExpr, enums: &Enums, lints: &mut Vec<Lint>) {
+    let SExpr::List(list, span) = sexpr else {
+        return;
+    };
+
+    if let [SExpr::Atom(form, _), _, arms @ ..] = list.as_slice() {
+        if form == "match" || form == "case" {
+            check_arms(form, arms, *span, enums, lints);
+        }
+    }
+
+    for sexpr in list {
+        visit(sexpr, enums, lints);
+    }
+}
+
+/// What an arm's head matches, as far as can be told without running the code.
+enum Head {
+    /// Every value: `_` or a name in `match`, `else` in `case`.
+    Any,
+    /// Values equal to a literal, in its source form.
+    Literal(String),
+    /// A variant of a `defenum`; `total` when its field patterns match every value.
+    Variant { name: String, total: bool },
+    /// A structural pattern, or an expression evaluated by `case`.
+    Unknown,
+}
+
+fn classify(form: &str, head: &SExpr, enums: &Enums) -> Head {
+    match head {
+        SExpr::Atom(atom, _) if form == "case" && atom == "else" => Head::Any,
+        SExpr::Atom(atom, _) if form == "match" && enums.enum_of.contains_key(atom.as_str()) => {
+            Head::Variant {
+                name: atom.to_string(),
+                total: true,
+            }
+        }
+        SExpr::List(list, _) if form == "match" => match list.split_first() {
+            Some((SExpr::Atom(atom, _), fields)) if enums.enum_of.contains_key(atom.as_str()) => {
+                Head::Variant {
+                    name: atom.to_string(),
+                    total: fields
+                        .iter()
+                        .all(|field| matches!(classify(form, field, enums), Head::Any)),
+                }
+            }
+            _ => Head::Unknown,
+        },
+        SExpr::Atom(atom, _) if is_literal(atom) => Head::Literal(atom.to_string()),
+        SExpr::Atom(_, _) if form == "match" => Head::Any,
+        SExpr::String(string, _) => Head::Literal(format!("{:?}", string)),
+        SExpr::Keyword(keyword, _) => Head::Literal(format!(":{}", keyword)),
+        SExpr::Atom(..) | SExpr::List(..) => Head::Unknown,
+    }
+}
+
+fn is_literal(atom: &str) -> bool {
+    matches!(atom, "true" | "false" | "null") || atom.parse::<f64>().is_ok()
+}
+
+fn check_arms(form: &str, arms: &[SExpr], span: Span, enums: &Enums, lints: &mut Vec<Lint>) {
+    let mut literals: Vec<String> = vec![];
+    let mut variants: Vec<String> = vec![];
+    let mut matched_enums: Vec<&str> = vec![];
+    let mut catch_all = false;
+    let mut only_literals = true;
+
+    for arm in arms {
+        // Malformed arms are reported when the form runs
+        let SExpr::List(parts, _) = arm else {
+            continue;
+        };
+
+        let Some(head) = parts.first() else {
+            continue;
+        };
+
+        let guarded = matches!(parts.get(1), Some(SExpr::Keyword(keyword, _)) if keyword == "when");
+
+        if catch_all {
+            lints.push(Lint {
+                kind: WarningKind::UnreachableArm,
+                message: format!(
+                    "Unreachable arm: an earlier arm of this {} matches every value",
+                    form
+                ),
+                span: arm.span(),
+            });
+            continue;
+        }
+
+        match classify(form, head, enums) {
+            Head::Any => catch_all = !guarded,
+            Head::Literal(literal) if literals.contains(&literal) => lints.push(Lint {
+                kind: WarningKind::UnreachableArm,
+                message: format!(
+                    "Unreachable arm: an earlier arm already matches {}",
+                    literal
+                ),
+                span: arm.span(),
+            }),
+            Head::Literal(literal) if !guarded => literals.push(literal),
+            Head::Literal(_) => {}
+            Head::Variant { name, .. } if variants.contains(&name) => lints.push(Lint {
+                kind: WarningKind::UnreachableArm,
+                message: format!(
+                    "Unreachable arm: an earlier arm already matches every {}",
+                    name
+                ),
+                span: arm.span(),
+            }),
+            Head::Variant { name, total } => {
+                let enum_name = enums.enum_of[&name].as_str();
+
+                if !matched_enums.contains(&enum_name) {
+                    matched_enums.push(enum_name);
+                }
+
+                if total && !guarded {
+                    variants.push(name);
+                }
+            }
+            Head::Unknown => only_literals = false,
+        }
+    }
+
+    if let [enum_name] = matched_enums.as_slice() {
+        let missing = enums.variants[*enum_name]
+            .iter()
+            .filter(|variant| !variants.contains(variant))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if !catch_all && only_literals && literals.is_empty() && !missing.is_empty() {
+            lints.push(Lint {
+                kind: WarningKind::NonExhaustive,
+                message: format!(
+                    "This {} doesn't cover every {}: {} may return void",
+                    form,
+                    enum_name,
+                    missing.join(", ")
+                ),
+                span,
+            });
+        }
+
+        return;
+    }
+
+    if !matched_enums.is_empty() {
+        return;
+    }
+
+    let booleans = literals.iter().any(|literal| literal == "true")
+        && literals.iter().any(|literal| literal == "false");
+
+    if !catch_all && only_literals && !literals.is_empty() && !booleans {
+        lints.push(Lint {
+            kind: WarningKind::NonExhaustive,
+            message: format!(
+                "This {} has no catch-all arm: values other than {} return void",
+                form,
+                literals.join(", ")
+            ),
+            span,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_match_lints() {
+        let lint = |source: &str| {
+            check(&Parser::new(source).parse().unwrap())
+                .into_iter()
+                .map(|lint| (lint.kind.code(), lint.message, lint.span.line))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lint("(case c\n  (\"red\" 1)\n  (\"green\" 2))"),
+            [(
+                "W0005",
+                "This case has no catch-all arm: values other than \"red\", \"green\" return void"
+                    .to_string(),
+                1
+            )]
+        );
+        assert_eq!(
+            lint("(match x\n  (n 1)\n  (0 2))"),
+            [(
+                "W0004",
+                "Unreachable arm: an earlier arm of this match matches every value".to_string(),
+                3
+            )]
+        );
+        assert_eq!(
+            lint("(defn f (x) (case x (1 :when ok 0) (1 1) (1 2) (else 3)))"),
+            [(
+                "W0004",
+                "Unreachable arm: an earlier arm already matches 1".to_string(),
+                1
+            )]
+        );
+
+        assert!(lint("(match b (true 1) (false 0))").is_empty());
+        assert!(lint("(match p ((x y) 1) (_ :when ok 2))").is_empty());
+        assert!(lint("(case n (limit 1) (0 2))").is_empty());
+
+        let shapes = "(defenum Shape (Circle r) (Rect w h) Empty)\n";
+        assert_eq!(
+            lint(&format!(
+                "{}(match s\n  ((Circle r) :when (eq r 0) 0)\n  ((Circle 1) 1)\n  (Empty 0))",
+                shapes
+            )),
+            [(
+                "W0005",
+                "This match doesn't cover every Shape: Circle, Rect may return void".to_string(),
+                2
+            )]
+        );
+        assert_eq!(
+            lint(&format!(
+                "{}(match s ((Circle r) r) (Empty 0) ((Rect w h) w) ((Circle _) 1))",
+                shapes
+            )),
+            [(
+                "W0004",
+                "Unreachable arm: an earlier arm already matches every Circle".to_string(),
+                2
+            )]
+        );
+        assert!(lint(&format!("{}(match s (Empty 0) (_ 1))", shapes)).is_empty());
+    }
+}
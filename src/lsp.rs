@@ -0,0 +1,589 @@
+//! A Language Server Protocol server (`kk lsp`), spoken over stdin/stdout.
+//!
+//! Documents are re-parsed on every change. The server publishes the errors of the parser
+//! and `check` and the warnings of `lint`, and answers definition, hover and completion
+//! requests from the syntax tree alone: nothing is ever evaluated.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Read, Write};
+
+use serde_json::{json, Value as Json};
+
+use crate::check;
+use crate::diagnostics::ErrorCode;
+use crate::interpreter::{is_special_form, special_forms, Interpreter};
+use crate::lint;
+use crate::parser::Parser;
+use crate::sexpr::{SExpr, Span};
+use crate::symbol::Symbol;
+
+/// `CompletionItemKind` and `DiagnosticSeverity` values of the protocol.
+const FUNCTION: u8 = 3;
+const VARIABLE: u8 = 6;
+const KEYWORD: u8 = 14;
+const ERROR: u8 = 1;
+const WARNING: u8 = 2;
+
+struct Server {
+    reader: Box<dyn BufRead>,
+    writer: Box<dyn Write>,
+    /// The text of every open document, by URI.
+    documents: HashMap<String, String>,
+    /// Only asked for the names of the builtins.
+    builtins: Interpreter,
+}
+
+/// Where a name is bound, and how.
+struct Definition<'a> {
+    name: &'a str,
+    span: Span,
+    kind: DefinitionKind<'a>,
+}
+
+enum DefinitionKind<'a> {
+    Function {
+        params: &'a SExpr,
+    },
+    /// A `let` or `set`, with the value it was given.
+    Variable {
+        value: Option<&'a SExpr>,
+    },
+    /// A parameter, loop variable or pattern name.
+    Binding,
+}
+
+/// Runs the server until the client sends `exit` or closes the input.
+pub fn run(reader: Box<dyn BufRead>, writer: Box<dyn Write>) -> Result<(), String> {
+    let mut server = Server {
+        reader,
+        writer,
+        documents: HashMap::new(),
+        builtins: Interpreter::new(),
+    };
+
+    while let Some(message) = server.read()? {
+        if message["method"] == "exit" {
+            break;
+        }
+
+        server.handle(&message);
+    }
+
+    Ok(())
+}
+
+impl Server {
+    /// Reads the next message, `None` at end of input.
+    fn read(&mut self) -> Result<Option<Json>, String> {
+        let mut length = None;
+
+        loop {
+            let mut header = String::new();
+
+            if self
+                .reader
+                .read_line(&mut header)
+                .map_err(|err| err.to_string())?
+                == 0
+            {
+                return Ok(None);
+            }
+
+            let header = header.trim();
+
+            if header.is_empty() {
+                break;
+            }
+
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let Some(length) = length else {
+            return Err("Expected Content-Length header".to_string());
+        };
+
+        let mut body = vec![0; length];
+
+        self.reader
+            .read_exact(&mut body)
+            .map_err(|err| err.to_string())?;
+
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|err| format!("Invalid message: {}", err))
+    }
+
+    fn send(&mut self, mut message: Json) {
+        message["jsonrpc"] = json!("2.0");
+
+        let body = message.to_string();
+
+        // A client that went away will show up as end of input on the next read
+        let _ = write!(
+            self.writer,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = self.writer.flush();
+    }
+
+    fn handle(&mut self, message: &Json) {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "kk", "version": crate::version::VERSION },
+            }),
+            "shutdown" => Json::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.update(uri, text.to_string());
+                return;
+            }
+            "textDocument/didChange" => {
+                // Full synchronization: the last change holds the whole text
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                {
+                    self.update(uri, text.to_string());
+                }
+
+                return;
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return;
+            }
+            "textDocument/definition" => self.definition(uri, &params["position"]),
+            "textDocument/hover" => self.hover(uri, &params["position"]),
+            "textDocument/completion" => self.completion(uri),
+            method => {
+                // Notifications have no id and need no answer
+                if message.get("id").is_some() {
+                    let error = json!({ "code": -32601, "message": format!("Unsupported method: {}", method) });
+                    self.send(json!({ "id": message["id"], "error": error }));
+                }
+
+                return;
+            }
+        };
+
+        self.send(json!({ "id": message["id"], "result": result }));
+    }
+
+    /// Stores the new text of a document and publishes its diagnostics.
+    fn update(&mut self, uri: &str, text: String) {
+        let mut diagnostics = vec![];
+
+        match Parser::new(&text).parse() {
+            Ok(sexprs) => {
+                for problem in check::check(&sexprs) {
+                    let range = range(&text, problem.span);
+                    diagnostics.push(diagnostic(
+                        range,
+                        ERROR,
+                        problem.code.code(),
+                        &problem.message,
+                    ));
+                }
+
+                for lint in lint::check(&sexprs) {
+                    let range = range(&text, lint.span);
+                    diagnostics.push(diagnostic(range, WARNING, lint.kind.code(), &lint.message));
+                }
+            }
+            Err(err) => {
+                let message = format!("{}, {}", err, err.expectation());
+                let range = range(&text, err.span);
+                diagnostics.push(diagnostic(range, ERROR, ErrorCode::Parse.code(), &message));
+            }
+        }
+
+        self.documents.insert(uri.to_string(), text);
+
+        self.send(json!({
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }));
+    }
+
+    fn definition(&self, uri: &str, position: &Json) -> Json {
+        let Some((sexprs, name, span)) = self.name_at(uri, position) else {
+            return Json::Null;
+        };
+
+        match find_definition(&definitions(&sexprs), &name, span) {
+            Some(definition) => json!({
+                "uri": uri,
+                "range": range(&self.documents[uri], definition.span),
+            }),
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&self, uri: &str, position: &Json) -> Json {
+        let Some((sexprs, name, span)) = self.name_at(uri, position) else {
+            return Json::Null;
+        };
+
+        let definitions = definitions(&sexprs);
+
+        let kind = match find_definition(&definitions, &name, span) {
+            _ if is_special_form(&name) => "special form".to_string(),
+            Some(Definition {
+                kind: DefinitionKind::Function { params },
+                ..
+            }) => format!("function ({} {})", name, params),
+            Some(Definition {
+                kind: DefinitionKind::Variable { value },
+                ..
+            }) => match value.and_then(literal_kind) {
+                Some(kind) => format!("{}: {}", name, kind),
+                None => format!("{}: variable", name),
+            },
+            Some(_) => format!("{}: variable", name),
+            None if self.builtins.native(&name).is_some() => "builtin function".to_string(),
+            None => match literal_kind(&SExpr::Atom(name, span)) {
+                Some(kind) => kind.to_string(),
+                None => return Json::Null,
+            },
+        };
+
+        json!({ "contents": { "kind": "plaintext", "value": kind } })
+    }
+
+    fn completion(&self, uri: &str) -> Json {
+        let mut items = BTreeMap::new();
+
+        for name in special_forms() {
+            items.insert(name.to_string(), KEYWORD);
+        }
+
+        for name in self.builtins.native_names() {
+            items.insert(name.to_string(), FUNCTION);
+        }
+
+        let sexprs = self
+            .documents
+            .get(uri)
+            .and_then(|text| Parser::new(text).parse().ok())
+            .unwrap_or_default();
+
+        for definition in definitions(&sexprs) {
+            let kind = match definition.kind {
+                DefinitionKind::Function { .. } => FUNCTION,
+                _ => VARIABLE,
+            };
+
+            items.insert(definition.name.to_string(), kind);
+        }
+
+        let items = items
+            .into_iter()
+            .map(|(label, kind)| json!({ "label": label, "kind": kind }))
+            .collect::<Vec<_>>();
+
+        json!(items)
+    }
+
+    /// The parsed document and the name or literal under the cursor, with its span.
+    fn name_at(&self, uri: &str, position: &Json) -> Option<(Vec<SExpr>, Symbol, Span)> {
+        let sexprs = Parser::new(self.documents.get(uri)?).parse().ok()?;
+
+        // Positions are 0-based, spans 1-based
+        let line = position["line"].as_u64()? as usize + 1;
+        let column = position["character"].as_u64()? as usize + 1;
+
+        let (name, span) = atom_at(&sexprs, line, column)?;
+
+        Some((sexprs.clone(), name, span))
+    }
+}
+
+/// The atom covering `line` and `column`.
+fn atom_at(sexprs: &[SExpr], line: usize, column: usize) -> Option<(Symbol, Span)> {
+    sexprs.iter().find_map(|sexpr| match sexpr {
+        SExpr::Atom(atom, span)
+            if span.line == line
+                && (span.column..=span.column + atom.chars().count()).contains(&column) =>
+        {
+            Some((*atom, *span))
+        }
+        SExpr::List(list, _) => atom_at(list, line, column),
+        _ => None,
+    })
+}
+
+/// Every binding site in the tree, in source order.
+fn definitions(sexprs: &[SExpr]) -> Vec<Definition<'_>> {
+    fn binding<'a>(pattern: &'a SExpr, definitions: &mut Vec<Definition<'a>>) {
+        match pattern {
+            SExpr::Atom(name, span) if literal_kind(pattern).is_none() && name != "&rest" => {
+                definitions.push(Definition {
+                    name,
+                    span: *span,
+                    kind: DefinitionKind::Binding,
+                });
+            }
+            SExpr::List(list, _) => list.iter().for_each(|sexpr| binding(sexpr, definitions)),
+            _ => {}
+        }
+    }
+
+    /// The names bound by a parameter list, where `(name default)` binds only `name`.
+    fn parameters<'a>(params: &'a SExpr, definitions: &mut Vec<Definition<'a>>) {
+        let SExpr::List(params, _) = params else {
+            return;
+        };
+
+        for param in params {
+            match param {
+                SExpr::List(list, _) if matches!(list.first(), Some(SExpr::Atom(..))) => {
+                    binding(&list[0], definitions)
+                }
+                param => binding(param, definitions),
+            }
+        }
+    }
+
+    fn visit<'a>(sexpr: &'a SExpr, definitions: &mut Vec<Definition<'a>>) {
+        let SExpr::List(list, _) = sexpr else {
+            return;
+        };
+
+        match list.as_slice() {
+            [SExpr::Atom(head, _), SExpr::Atom(name, span), params @ SExpr::List(..), ..]
+                if head == "defn" =>
+            {
+                definitions.push(Definition {
+                    name,
+                    span: *span,
+                    kind: DefinitionKind::Function { params },
+                });
+                parameters(params, definitions);
+            }
+            [SExpr::Atom(head, _), SExpr::Atom(name, span), value]
+                if head == "let" || head == "set" =>
+            {
+                definitions.push(Definition {
+                    name,
+                    span: *span,
+                    kind: DefinitionKind::Variable { value: Some(value) },
+                });
+            }
+            [SExpr::Atom(head, _), pattern @ SExpr::List(..), _] if head == "let" => {
+                binding(pattern, definitions);
+            }
+            [SExpr::Atom(head, _), var @ SExpr::Atom(..), ..]
+                if head == "count" || head == "catch" =>
+            {
+                binding(var, definitions);
+            }
+            [SExpr::Atom(head, _), pattern @ (SExpr::Atom(..) | SExpr::List(..)), ..]
+                if head == "for" =>
+            {
+                binding(pattern, definitions);
+            }
+            [SExpr::Atom(head, _), params @ SExpr::List(..), ..] if head == "lambda" => {
+                parameters(params, definitions);
+            }
+            _ => {}
+        }
+
+        list.iter().for_each(|sexpr| visit(sexpr, definitions));
+    }
+
+    let mut definitions = vec![];
+    sexprs
+        .iter()
+        .for_each(|sexpr| visit(sexpr, &mut definitions));
+    definitions
+}
+
+/// The definition of `name` seen from `at`: the closest one before it, else the first.
+fn find_definition<'a, 'b>(
+    definitions: &'b [Definition<'a>],
+    name: &str,
+    at: Span,
+) -> Option<&'b Definition<'a>> {
+    let mut candidates = definitions
+        .iter()
+        .filter(|definition| definition.name == name);
+
+    let before = candidates
+        .clone()
+        .rfind(|definition| definition.span.offset <= at.offset);
+
+    before.or_else(|| candidates.next())
+}
+
+/// The type of a literal form, `None` for names and code.
+fn literal_kind(sexpr: &SExpr) -> Option<&'static str> {
+    match sexpr {
+        SExpr::String(..) => Some("string"),
+        SExpr::Keyword(..) => Some("keyword"),
+        SExpr::List(..) => None,
+        SExpr::Atom(atom, _) => match atom.as_str() {
+            "true" | "false" => Some("bool"),
+            "null" => Some("null"),
+            atom if atom.parse::<i64>().is_ok() => Some("int"),
+            atom if atom.parse::<f64>().is_ok() => Some("float"),
+            _ => None,
+        },
+    }
+}
+
+/// The range of the token starting at `span`; for a list, its `(` and head.
+fn range(text: &str, span: Span) -> Json {
+    let rest = text.get(span.offset..).unwrap_or_default();
+    let mut chars = rest.chars();
+
+    let length = match chars.next() {
+        Some('"') => chars.position(|char| char == '"').map_or(1, |i| i + 2),
+        Some(_) => {
+            let rest = chars
+                .take_while(|char| !matches!(char, '(' | ')' | '"' | ';') && !char.is_whitespace())
+                .count();
+
+            rest + 1
+        }
+        None => 0,
+    };
+
+    let start = json!({ "line": span.line - 1, "character": span.column - 1 });
+    let end = json!({ "line": span.line - 1, "character": span.column - 1 + length });
+
+    json!({ "start": start, "end": end })
+}
+
+fn diagnostic(range: Json, severity: u8, code: &str, message: &str) -> Json {
+    json!({
+        "range": range,
+        "severity": severity,
+        "code": code,
+        "source": "kk",
+        "message": message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Buffer;
+
+    fn frame(id: i64, method: &str, params: Json) -> String {
+        let body =
+            json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }).to_string();
+
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn messages(output: &[u8]) -> Vec<Json> {
+        String::from_utf8_lossy(output)
+            .split("Content-Length: ")
+            .filter_map(|message| message.split_once("\r\n\r\n"))
+            .map(|(_, body)| serde_json::from_str(body).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_language_server() {
+        let uri = "file:///main.kk";
+        let document = json!({ "uri": uri });
+        let at = |line: usize, character: usize| json!({ "textDocument": document, "position": { "line": line, "character": character } });
+
+        let input = [
+            frame(1, "initialize", json!({})),
+            frame(
+                0,
+                "textDocument/didOpen",
+                json!({ "textDocument": { "uri": uri, "text": "(defn double (x)\n  (add x x))\n(let a 2)\n(print (double a) b)\n" } }),
+            ),
+            frame(2, "textDocument/definition", at(3, 9)),
+            frame(3, "textDocument/hover", at(3, 9)),
+            frame(4, "textDocument/hover", at(3, 15)),
+            frame(5, "textDocument/completion", json!({ "textDocument": document })),
+            frame(
+                0,
+                "textDocument/didChange",
+                json!({ "textDocument": document, "contentChanges": [{ "text": "(print (add 1 2)" }] }),
+            ),
+            frame(6, "shutdown", Json::Null),
+            frame(0, "exit", Json::Null),
+        ]
+        .concat();
+
+        let output = Buffer::default();
+        run(
+            Box::new(std::io::Cursor::new(input.into_bytes())),
+            Box::new(output.clone()),
+        )
+        .unwrap();
+
+        let messages = messages(&output.contents());
+
+        let response = |id: i64| {
+            messages
+                .iter()
+                .find(|message| message["id"] == id)
+                .unwrap_or_else(|| panic!("missing response {} in {:#?}", id, messages))
+                .clone()
+        };
+
+        let diagnostics = messages
+            .iter()
+            .filter(|message| message["method"] == "textDocument/publishDiagnostics")
+            .map(|message| message["params"]["diagnostics"].clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(response(1)["result"]["capabilities"]["hoverProvider"], true);
+
+        assert_eq!(diagnostics[0][0]["code"], "E0003");
+        assert_eq!(
+            diagnostics[0][0]["range"]["start"],
+            json!({ "line": 3, "character": 18 })
+        );
+        assert_eq!(diagnostics[1][0]["code"], "E0007");
+        assert_eq!(
+            diagnostics[1][0]["range"]["start"],
+            json!({ "line": 0, "character": 0 })
+        );
+
+        assert_eq!(
+            response(2)["result"]["range"]["start"],
+            json!({ "line": 0, "character": 6 })
+        );
+        assert_eq!(
+            response(3)["result"]["contents"]["value"],
+            "function (double (x))"
+        );
+        assert_eq!(response(4)["result"]["contents"]["value"], "a: int");
+
+        let completions = response(5)["result"].clone();
+        let kind = |label: &str| {
+            completions
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|item| item["label"] == label)
+                .map(|item| item["kind"].clone())
+        };
+        assert_eq!(kind("defn"), Some(json!(KEYWORD)));
+        assert_eq!(kind("print"), Some(json!(FUNCTION)));
+        assert_eq!(kind("a"), Some(json!(VARIABLE)));
+
+        assert_eq!(response(6)["result"], Json::Null);
+    }
+}
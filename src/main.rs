@@ -1,421 +1,530 @@
-use std::collections::HashMap;
+use std::io::{IsTerminal, Read};
+use std::path::Path;
+
+use cli::{Command, RunOptions, Source};
+use kk::diagnostics::{Diagnostic, ErrorCode, Severity, WarningKind};
+use kk::manifest::Manifest;
+use kk::repl::{self, Repl};
+use kk::sexpr::SExpr;
+use kk::testing;
+use kk::{check, dap, lint, lsp, package, parser, server, version, Interpreter, RuntimeError};
+
+mod cli;
+
+/// Parses `content`, or prints the error under the line it is on and exits.
+fn parse(content: &str) -> Vec<SExpr> {
+    let err = match parser::Parser::new(content).parse() {
+        Ok(sexprs) => return sexprs,
+        Err(err) => err,
+    };
+
+    let line = content.lines().nth(err.span.line - 1).unwrap_or_default();
+    let number = err.span.line.to_string();
+    let margin = " ".repeat(number.len());
+
+    eprintln!("error[{}]: {}", ErrorCode::Parse, err);
+    eprintln!("{} |", margin);
+    eprintln!("{} | {}", number, line);
+    eprintln!(
+        "{} | {}^ {}",
+        margin,
+        " ".repeat(err.span.column - 1),
+        err.expectation()
+    );
+    std::process::exit(1);
+}
 
-use dyn_fmt::AsStrFormatExt;
-use sexpr::SExpr;
-use value::Value;
+fn print_info(filename: &str) {
+    let content = std::fs::read_to_string(filename).expect("Unable to read file");
 
-mod parser;
-mod sexpr;
-mod value;
+    let sexprs = parse(&content);
 
-struct Env {
-    vars: HashMap<String, Value>,
+    match Manifest::from_sexprs(&sexprs) {
+        Ok(Some(manifest)) => print!("{}", manifest),
+        Ok(None) => println!("{} has no manifest", filename),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
 }
 
-struct Interpreter {
-    env: Env,
+/// Prints the static checks of a file and exits with 1 if there were any.
+fn print_lints(filename: &str) {
+    let content = std::fs::read_to_string(filename).expect("Unable to read file");
+
+    let sexprs = parse(&content);
+
+    let lints = lint::check(&sexprs);
+
+    for lint in &lints {
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            code: lint.kind.code(),
+            message: lint.message.clone(),
+            file: Some(filename.into()),
+            span: Some(lint.span),
+        };
+
+        println!("{}", diagnostic);
+    }
+
+    if !lints.is_empty() {
+        std::process::exit(1);
+    }
 }
 
-impl Interpreter {
-    fn new() -> Self {
-        Interpreter {
-            env: Env {
-                vars: HashMap::new(),
-            },
+fn print_problems(filename: &str) {
+    let problems = check::analyze(Path::new(filename));
+
+    for (file, problem) in &problems {
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code: problem.code.code(),
+            message: problem.message.clone(),
+            file: Some(file.clone()),
+            span: Some(problem.span),
+        };
+
+        println!("{}", diagnostic);
+    }
+
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Rewrites `filename` in the canonical layout, or with `check` only reports whether it
+/// already is.
+fn format_file(filename: &str, check: bool) {
+    let content = std::fs::read_to_string(filename).expect("Unable to read file");
+
+    let formatted = match kk::format::format(&content) {
+        Ok(formatted) => formatted,
+        Err(err) => {
+            eprintln!("error[{}]: {}", ErrorCode::Parse, err);
+            std::process::exit(1);
         }
+    };
+
+    if formatted == content {
+        return;
+    }
+
+    if check {
+        println!("{} is not formatted", filename);
+        std::process::exit(1);
     }
 
-    fn eval_file(&mut self, filename: &str) {
-        let content = std::fs::read_to_string(filename).expect("Unable to read file");
+    if let Err(err) = std::fs::write(filename, formatted) {
+        eprintln!(
+            "error[{}]: Unable to write {}: {}",
+            ErrorCode::Io,
+            filename,
+            err
+        );
+        std::process::exit(1);
+    }
+}
 
-        let mut parser = parser::Parser::new(&content);
+/// Prints the syntax tree of the script for `--dump-ast`, without evaluating it.
+fn dump_ast(source: &Source) {
+    let content = match source {
+        Source::File(filename) => std::fs::read_to_string(filename),
+        Source::Project => unreachable!("main resolves the project to its entry script"),
+        Source::Expr(expr) => Ok(expr.clone()),
+        Source::Stdin => {
+            let mut content = String::new();
+            std::io::stdin()
+                .read_to_string(&mut content)
+                .map(|_| content)
+        }
+    };
+
+    let content = content.unwrap_or_else(|err| {
+        eprintln!("error[{}]: Unable to read script: {}", ErrorCode::Io, err);
+        std::process::exit(1);
+    });
+
+    parse(&content)
+        .iter()
+        .for_each(|sexpr| print!("{}", sexpr.dump()));
+}
+
+/// Prints the explanation of an error code, or the list of codes when none is given.
+fn explain(code: Option<&str>) {
+    let Some(code) = code else {
+        for error in ErrorCode::ALL {
+            println!("{}  {}", error, error.title());
+        }
+
+        for warning in WarningKind::ALL {
+            println!("{}  {} (warning)", warning, warning.name());
+        }
 
-        let sexprs = parser.parse().expect("Failed to parse file");
+        return;
+    };
+
+    if let Some(error) = ErrorCode::from_code(code) {
+        println!("{}: {}\n\n{}", error, error.title(), error.explanation());
+    } else if let Some(warning) = WarningKind::find(code) {
+        println!(
+            "{}: {}\n\n{}",
+            warning,
+            warning.name(),
+            warning.explanation()
+        );
+    } else {
+        eprintln!("Unknown error code: {}", code);
+        std::process::exit(1);
+    }
+}
 
-        for sexpr in sexprs {
-            self.eval(&sexpr);
+/// Runs `kk test` and exits with 1 if a test failed.
+fn run_tests(file: &str, options: &testing::TestOptions) {
+    let results = match testing::run(Path::new(file), options) {
+        Ok(results) => results,
+        Err(err) => {
+            eprintln!("error[{}]: {}", err.code(), err);
+            std::process::exit(1);
+        }
+    };
+
+    for result in &results {
+        match (&result.error, result.flaky()) {
+            (None, false) => println!("test {} ... ok", result.name),
+            (None, true) => println!(
+                "test {} ... ok (flaky, passed on attempt {})",
+                result.name, result.attempts
+            ),
+            (Some(_), _) => println!("test {} ... FAILED", result.name),
         }
     }
 
-    fn eval(&mut self, sexpr: &SExpr) -> Value {
-        match sexpr {
-            SExpr::Atom(atom) => {
-                return self.eval_atom(atom);
+    let failed = results
+        .iter()
+        .filter(|result| !result.passed())
+        .collect::<Vec<_>>();
+
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+
+    for result in &failed {
+        println!("\n---- {} ----", result.name);
+        print!("{}", result.output);
+
+        for line in result.error.as_deref().unwrap_or_default().lines() {
+            // The expected and actual sides of an assert-eq-detailed difference
+            match line {
+                _ if !color => println!("{}", line),
+                line if line.starts_with("    - ") => println!("\x1b[31m{}\x1b[0m", line),
+                line if line.starts_with("    + ") => println!("\x1b[32m{}\x1b[0m", line),
+                line => println!("{}", line),
             }
-            SExpr::List(list) => {
-                let mut it = list.iter();
+        }
+    }
 
-                let name = match it.next() {
-                    Some(SExpr::Atom(atom)) => atom,
-                    _ => {
-                        panic!("Expected function name here");
-                    }
-                };
+    let flaky = results.iter().filter(|result| result.flaky()).count();
 
-                match name.as_str() {
-                    "print" => {
-                        it.for_each(|sexpr| {
-                            println!("{}", self.eval(sexpr));
-                        });
-                    }
-                    "format" => {
-                        let format = match it.next() {
-                            Some(SExpr::Atom(atom)) => atom,
-                            _ => {
-                                panic!("Expected format string here");
-                            }
-                        };
+    println!(
+        "\n{} passed; {} failed; {} flaky",
+        results.len() - failed.len(),
+        failed.len(),
+        flaky
+    );
 
-                        let args = it
-                            .collect::<Vec<&SExpr>>()
-                            .iter()
-                            .map(|sexpr| self.eval(sexpr))
-                            .collect::<Vec<Value>>();
+    if !failed.is_empty() {
+        std::process::exit(1);
+    }
+}
 
-                        let formatted = format.format(&args);
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    let stdin_is_piped = !std::io::stdin().is_terminal();
+
+    let mut options = match cli::parse(&args, stdin_is_piped) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::New(name)) => {
+            match package::new_project(Path::new(&name)) {
+                Ok(project) => println!("Created {}, run it with: cd {} && kk", project.name, name),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
 
-                        let value = Value::String(formatted);
+            return;
+        }
+        Ok(Command::Add(source)) => {
+            match package::add(&source) {
+                Ok(entry) => println!("Added {} ({})", entry.name, entry.hash),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
 
-                        return value;
-                    }
-                    "let" => {
-                        let name = match it.next() {
-                            Some(SExpr::Atom(atom)) => atom,
-                            _ => {
-                                panic!("Expected variable name here");
-                            }
-                        };
-
-                        let value = match it.next() {
-                            Some(value) => value,
-                            _ => {
-                                panic!("Expected value here");
-                            }
-                        };
-
-                        if it.next().is_some() {
-                            panic!("Expected end of list here");
-                        }
-
-                        let value = self.eval(value);
-
-                        self.env.vars.insert(name.to_string(), value);
-                    }
-                    "set" => {
-                        let name = match it.next() {
-                            Some(SExpr::Atom(atom)) => atom,
-                            _ => {
-                                panic!("Expected variable name here");
-                            }
-                        };
-
-                        let value = match it.next() {
-                            Some(value) => value,
-                            _ => {
-                                panic!("Expected value here");
-                            }
-                        };
-
-                        if it.next().is_some() {
-                            panic!("Expected end of list here");
-                        }
-
-                        let value = self.eval(value);
-
-                        self.env.vars.insert(name.to_string(), value.clone());
-
-                        return value;
-                    }
-                    "get" => {
-                        let name = match it.next() {
-                            Some(SExpr::Atom(atom)) => atom,
-                            _ => {
-                                panic!("Expected variable name here");
-                            }
-                        };
-
-                        if it.next().is_some() {
-                            panic!("Expected end of list here");
-                        }
-
-                        let value = self.env.vars.get(&name.to_string());
-
-                        let value = match value {
-                            Some(value) => value,
-                            None => {
-                                panic!("Variable not found: {}", name);
-                            }
-                        };
-
-                        return value.clone();
-                    }
-                    "inc" => {
-                        let name = match it.next() {
-                            Some(SExpr::Atom(atom)) => atom,
-                            _ => {
-                                panic!("Expected variable name here");
-                            }
-                        };
-
-                        if it.next().is_some() {
-                            panic!("Expected end of list here");
-                        }
-
-                        let value = self.env.vars.get(&name.to_string());
-
-                        let value = match value {
-                            Some(value) => value,
-                            None => {
-                                panic!("Variable not found: {}", name);
-                            }
-                        };
-
-                        let value = value.clone();
-
-                        let value = match value {
-                            Value::Int(value) => Value::Int(value + 1),
-                            Value::Float(value) => Value::Float(value + 1.0),
-                            _ => {
-                                panic!("Variable is not an integer: {}", name);
-                            }
-                        };
-
-                        self.env.vars.insert(name.to_string(), value.clone());
-
-                        return value;
-                    }
-                    "mod" => {
-                        let left = if let Some(left) = it.next() {
-                            self.eval(left)
-                        } else {
-                            panic!("Expected left value here");
-                        };
-
-                        let right = if let Some(right) = it.next() {
-                            self.eval(right)
-                        } else {
-                            panic!("Expected right value here");
-                        };
-
-                        let value = match (left, right) {
-                            (Value::Int(left), Value::Int(right)) => Value::Int(left % right),
-                            (Value::Float(left), Value::Float(right)) => Value::Float(left % right),
-                            (Value::Int(left), Value::Float(right)) => {
-                                Value::Float(left as f64 % right)
-                            }
-                            (Value::Float(left), Value::Int(right)) => {
-                                Value::Float(left % right as f64)
-                            }
-                            _ => {
-                                panic!("Expected integer or float values here");
-                            }
-                        };
-
-                        return value;
-                    }
-                    "eq" => {
-                        let left = if let Some(left) = it.next() {
-                            self.eval(left)
-                        } else {
-                            panic!("Expected left value here");
-                        };
-
-                        let right = if let Some(right) = it.next() {
-                            self.eval(right)
-                        } else {
-                            panic!("Expected right value here");
-                        };
-
-                        let value = match (left, right) {
-                            (Value::Int(left), Value::Int(right)) => Value::Bool(left == right),
-                            (Value::Float(left), Value::Float(right)) => Value::Bool(left == right),
-                            (Value::String(left), Value::String(right)) => {
-                                Value::Bool(left == right)
-                            }
-                            (Value::Bool(left), Value::Bool(right)) => Value::Bool(left == right),
-                            (Value::Null, Value::Null) => Value::Bool(true),
-                            (Value::Void, Value::Void) => Value::Bool(true),
-                            _ => {
-                                panic!("Expected integer or float values here");
-                            }
-                        };
-
-                        return value;
-                    }
-                    "if" => {
-                        let condition = if let Some(condition) = it.next() {
-                            self.eval(condition)
-                        } else {
-                            panic!("Expected condition here");
-                        };
-
-                        let branch: bool;
-
-                        match condition {
-                            Value::Bool(condition) => {
-                                branch = condition;
-                            }
-                            _ => {
-                                panic!("Expected boolean value here");
-                            }
-                        };
-
-                        let true_branch = if let Some(true_branch) = it.next() {
-                            true_branch
-                        } else {
-                            panic!("Expected true branch here");
-                        };
-
-                        if branch {
-                            match true_branch {
-                                SExpr::List(list) => {
-                                    return self.eval_list(list);
-                                }
-                                SExpr::Atom(atom) => {
-                                    return self.eval_atom(atom);
-                                }
-                            }
-                        }
-
-                        if let Some(SExpr::Atom(atom)) = it.next() {
-                            if !branch && atom == "else" {
-                                let false_branch = if let Some(false_branch) = it.next() {
-                                    false_branch
-                                } else {
-                                    panic!("Expected false branch here");
-                                };
-
-                                if !branch {
-                                    match false_branch {
-                                        SExpr::List(list) => {
-                                            return self.eval_list(list);
-                                        }
-                                        SExpr::Atom(atom) => {
-                                            return self.eval_atom(atom);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        return Value::Void;
-                    }
-                    "count" => {
-                        // sytnax: (count <var_name> from <start> to <end> (body))
-                        let var_name = match it.next() {
-                            Some(SExpr::Atom(atom)) => atom,
-                            _ => {
-                                panic!("Expected variable name here");
-                            }
-                        };
-
-                        match it.next() {
-                            Some(SExpr::Atom(atom)) => {
-                                if atom != "from" {
-                                    panic!("Expected from keyword here");
-                                }
-                            }
-                            _ => {
-                                panic!("Expected from keyword here");
-                            }
-                        };
-
-                        let start = if let Some(start) = it.next() {
-                            match self.eval(start) {
-                                Value::Int(start) => start,
-                                _ => {
-                                    panic!("Expected integer value here");
-                                }
-                            }
-                        } else {
-                            panic!("Expected start value here");
-                        };
-
-                        match it.next() {
-                            Some(SExpr::Atom(atom)) => {
-                                if atom != "to" {
-                                    panic!("Expected to keyword here");
-                                }
-                            }
-                            _ => {
-                                panic!("Expected to keyword here");
-                            }
-                        };
-
-                        let end = if let Some(end) = it.next() {
-                            match self.eval(end) {
-                                Value::Int(end) => end,
-                                _ => {
-                                    panic!("Expected integer value here");
-                                }
-                            }
-                        } else {
-                            panic!("Expected end value here");
-                        };
-
-                        let body = if let Some(body) = it.next() {
-                            body
-                        } else {
-                            panic!("Expected body here");
-                        };
-
-                        match body {
-                            SExpr::List(list) => {
-                                for i in start..end {
-                                    self.env.vars.insert(var_name.to_string(), Value::Int(i));
-                                    self.eval_list(list);
-                                }
-
-                                return Value::Void;
-                            }
-                            _ => {
-                                panic!("Expected list here");
-                            }
-                        };
-                    }
-                    _ => {
-                        panic!("Unknown function: {}", name);
+            return;
+        }
+        Ok(Command::CheckModules) => {
+            match package::check(Path::new("")) {
+                Ok(problems) if problems.is_empty() => {
+                    println!(
+                        "The modules in {} match {}",
+                        package::MODULES_DIR,
+                        package::LOCKFILE
+                    )
+                }
+                Ok(problems) => {
+                    for problem in problems {
+                        eprintln!("{}", problem);
                     }
+
+                    std::process::exit(1);
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
                 }
             }
+
+            return;
+        }
+        Ok(Command::Info(filename)) => {
+            print_info(&filename);
+            return;
         }
+        Ok(Command::Dap) => {
+            with_stack(Interpreter::DEFAULT_MAX_DEPTH, || {
+                let stdin = Box::new(std::io::BufReader::new(std::io::stdin()));
 
-        return Value::Void;
-    }
+                if let Err(err) = dap::run(stdin, Box::new(std::io::stdout())) {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            });
 
-    fn eval_list(&mut self, list: &Vec<SExpr>) -> Value {
-        for sexpr in list {
-            self.eval(sexpr);
+            return;
         }
+        Ok(Command::Lsp) => {
+            let stdin = Box::new(std::io::BufReader::new(std::io::stdin()));
 
-        return Value::Void;
-    }
+            if let Err(err) = lsp::run(stdin, Box::new(std::io::stdout())) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+        Ok(Command::ServeEval(socket)) => {
+            with_stack(Interpreter::DEFAULT_MAX_DEPTH, move || {
+                if let Err(err) = server::serve(&mut Interpreter::new(), Path::new(&socket)) {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            });
+
+            return;
+        }
+        Ok(Command::Lint(file)) => {
+            print_lints(&file);
+            return;
+        }
+        Ok(Command::Check(file)) => {
+            print_problems(&file);
+            return;
+        }
+        Ok(Command::Fmt(file, check)) => {
+            format_file(&file, check);
+            return;
+        }
+        Ok(Command::Bench(file)) => {
+            with_stack(Interpreter::DEFAULT_MAX_DEPTH, move || {
+                let mut interpreter = Interpreter::new();
+
+                if let Err(err) = interpreter.eval_file(&file) {
+                    eprintln!("error[{}]: {}", err.code(), err);
+                    std::process::exit(1);
+                }
 
-    fn eval_atom(&mut self, atom: &str) -> Value {
-        match atom {
-            "true" => Value::Bool(true),
-            "false" => Value::Bool(false),
-            str => {
-                let value: Value;
-
-                if str.parse::<i64>().is_ok() {
-                    value = Value::Int(str.parse::<i64>().unwrap());
-                } else if str.parse::<f64>().is_ok() {
-                    value = Value::Float(str.parse::<f64>().unwrap());
-                } else {
-                    panic!("Unknown atom: {}", atom);
+                for result in interpreter.take_bench_results() {
+                    println!("{}", result);
                 }
+            });
 
-                return value;
+            return;
+        }
+        Ok(Command::Test(file, options)) => {
+            with_stack(Interpreter::DEFAULT_MAX_DEPTH, move || {
+                run_tests(&file, &options)
+            });
+            return;
+        }
+        Ok(Command::Repl(session)) => {
+            with_stack(Interpreter::DEFAULT_MAX_DEPTH, move || {
+                let stdin = std::io::stdin().lock();
+                let mut repl = Repl::new(Interpreter::new());
+
+                if let Some(session) = session {
+                    repl.set_session(session);
+                }
+
+                if let Err(err) = repl.run(stdin, std::io::stdout()) {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            });
+
+            return;
+        }
+        Ok(Command::TranscriptToScript(file)) => {
+            match std::fs::read_to_string(&file) {
+                Ok(transcript) => print!("{}", repl::transcript_to_script(&transcript)),
+                Err(err) => {
+                    eprintln!("error[{}]: Unable to read {}: {}", ErrorCode::Io, file, err);
+                    std::process::exit(1);
+                }
             }
+
+            return;
+        }
+        Ok(Command::Explain(code)) => {
+            explain(code.as_deref());
+            return;
+        }
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
         }
+        Ok(Command::Version) => {
+            println!("kk {}", version::VERSION);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    if options.source == Source::Project {
+        match project_entry() {
+            Ok(entry) => options.source = Source::File(entry),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if options.dump_ast {
+        dump_ast(&options.source);
+        return;
     }
+
+    with_stack(options.max_depth, move || run(options));
 }
 
-fn main() {
-    let start_time = std::time::Instant::now();
+/// Runs a command that evaluates code on a thread with the stack forms nesting up to
+/// `max_depth` need, so that they fail with an error rather than overflow the stack.
+fn with_stack(max_depth: usize, command: impl FnOnce() + Send + 'static) {
+    let thread = std::thread::Builder::new()
+        .stack_size(Interpreter::stack_size(max_depth))
+        .spawn(command);
+
+    match thread.map(|thread| thread.join()) {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) => std::process::exit(101),
+        Err(err) => {
+            eprintln!("Unable to start the interpreter thread: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The entry script of the project in the working directory, relative to it.
+fn project_entry() -> Result<String, String> {
+    let dir = std::env::current_dir()
+        .map_err(|err| format!("Unable to read the working directory: {}", err))?;
+    let entry = package::find_entry(&dir)?;
+
+    Ok(entry
+        .strip_prefix(&dir)
+        .unwrap_or(&entry)
+        .display()
+        .to_string())
+}
+
+/// Runs a script with `options` and exits with its status.
+fn run(options: RunOptions) {
     let mut interpreter = Interpreter::new();
-    interpreter.eval_file("test.sl");
-    let end_time = std::time::Instant::now();
-    println!("Time taken: {:?}", end_time.duration_since(start_time));
+    interpreter.set_print_results(options.print_results);
+    interpreter.set_deny_warnings(options.deny_warnings);
+    interpreter.set_lint(options.lint);
+    interpreter.set_bytecode(options.bytecode);
+    interpreter.set_expand_env(options.expand_env);
+
+    if options.trace {
+        interpreter.set_trace(|line| eprint!("{}", line));
+    }
+
+    interpreter.set_trace_tasks(options.trace_tasks);
+    interpreter.set_slow_builtin_threshold(options.warn_slow);
+
+    if options.debug {
+        kk::debug::attach(
+            &mut interpreter,
+            Box::new(std::io::stdin().lock()),
+            Box::new(std::io::stderr()),
+        );
+    }
+    interpreter.set_eq_mode(options.eq_mode);
+    interpreter.set_div_mode(options.div_mode);
+    interpreter.set_max_depth(options.max_depth);
+
+    for capability in &options.allowed_capabilities {
+        interpreter.allow_capability(capability);
+    }
+
+    let result = match &options.source {
+        Source::File(filename) => interpreter.eval_file(filename),
+        Source::Project => unreachable!("main resolves the project to its entry script"),
+        Source::Expr(expr) => interpreter.eval_source(expr, "<eval>"),
+        Source::Stdin => {
+            let mut content = String::new();
+
+            match std::io::stdin().read_to_string(&mut content) {
+                Ok(_) => interpreter.eval_source(&content, "<stdin>"),
+                Err(err) => Err(RuntimeError::with_code(
+                    ErrorCode::Io,
+                    format!("Unable to read stdin: {}", err),
+                )),
+            }
+        }
+    };
+
+    let result = result.and_then(|_| interpreter.run_main(&options.args));
+    interpreter.flush_output();
+
+    match result {
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            eprintln!("error[{}]: {}", err.code(), err);
+
+            // A runaway recursion leaves thousands of frames, the same few over and over
+            let trace = interpreter.take_trace();
+
+            for (i, frame) in trace.iter().enumerate() {
+                match i {
+                    _ if trace.len() <= 40 || i < 20 || i >= trace.len() - 10 => {
+                        eprintln!("  {}", frame)
+                    }
+                    20 => eprintln!("  ... {} more", trace.len() - 30),
+                    _ => {}
+                }
+            }
+
+            std::process::exit(1);
+        }
+    }
 }
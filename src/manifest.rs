@@ -0,0 +1,157 @@
+use crate::sexpr::SExpr;
+
+/// Capabilities a script may declare in `:requires` and a host may grant with `--allow`.
+pub const CAPABILITIES: &[&str] = &["fs-read", "fs-write", "net", "env", "exec"];
+
+/// Metadata declared by a leading `(manifest :key value ...)` form.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub requires: Vec<String>,
+    pub metadata: Vec<(String, String)>,
+}
+
+impl Manifest {
+    /// Finds the manifest of a parsed file. Only the first form may be a manifest.
+    pub fn from_sexprs(sexprs: &[SExpr]) -> Result<Option<Manifest>, String> {
+        for (i, sexpr) in sexprs.iter().enumerate() {
+            let SExpr::List(list, _) = sexpr else {
+                continue;
+            };
+
+            if !matches!(list.first(), Some(SExpr::Atom(atom, _)) if atom == "manifest") {
+                continue;
+            }
+
+            if i != 0 {
+                return Err("The manifest must be the first form of the file".to_string());
+            }
+
+            return Manifest::parse(&list[1..]).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    fn parse(fields: &[SExpr]) -> Result<Manifest, String> {
+        let mut manifest = Manifest::default();
+        let mut it = fields.iter();
+
+        while let Some(key) = it.next() {
+            let key = match key {
+                SExpr::Keyword(key, _) => key.as_str(),
+                _ => {
+                    return Err("Expected manifest key like :name here".to_string());
+                }
+            };
+
+            let Some(value) = it.next() else {
+                return Err(format!("Expected value for manifest key :{}", key));
+            };
+
+            match (key, value) {
+                ("requires", SExpr::List(list, _)) => {
+                    for capability in list {
+                        let SExpr::Atom(capability, _) = capability else {
+                            return Err("Expected capability name here".to_string());
+                        };
+
+                        if !CAPABILITIES.contains(&capability.as_str()) {
+                            return Err(format!("Unknown capability: {}", capability));
+                        }
+
+                        manifest.requires.push(capability.to_string());
+                    }
+                }
+                ("requires", _) => {
+                    return Err("Expected a list of capabilities for :requires".to_string());
+                }
+                (key, SExpr::Atom(..) | SExpr::String(..)) => {
+                    let value = match value {
+                        SExpr::String(string, _) => string.clone(),
+                        atom => atom.to_string(),
+                    };
+
+                    match key {
+                        "name" => manifest.name = Some(value),
+                        "version" => manifest.version = Some(value),
+                        _ => manifest.metadata.push((key.to_string(), value)),
+                    }
+                }
+                (key, SExpr::List(..) | SExpr::Keyword(..)) => {
+                    return Err(format!("Expected a string value for manifest key :{}", key));
+                }
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Checks that every required capability has been granted.
+    pub fn check_capabilities(&self, allowed: &[String]) -> Result<(), String> {
+        let missing = self
+            .requires
+            .iter()
+            .filter(|capability| !allowed.contains(capability))
+            .map(|capability| capability.as_str())
+            .collect::<Vec<&str>>();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        Err(format!(
+            "Script requires capabilities that were not granted: {} (use --allow <capability>)",
+            missing.join(", ")
+        ))
+    }
+}
+
+impl std::fmt::Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "name: {}", self.name.as_deref().unwrap_or("<unnamed>"))?;
+        writeln!(
+            f,
+            "version: {}",
+            self.version.as_deref().unwrap_or("<none>")
+        )?;
+        writeln!(f, "requires: {}", self.requires.join(", "))?;
+
+        for (key, value) in &self.metadata {
+            writeln!(f, "{}: {}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_manifest_parse() {
+        let sexprs = Parser::new(
+            "(manifest :name \"tool\" :version \"0.1\" :author me :requires (fs-read net))",
+        )
+        .parse()
+        .unwrap();
+
+        let manifest = Manifest::from_sexprs(&sexprs).unwrap().unwrap();
+
+        assert_eq!(manifest.name.as_deref(), Some("tool"));
+        assert_eq!(manifest.version.as_deref(), Some("0.1"));
+        assert_eq!(manifest.requires, vec!["fs-read", "net"]);
+        assert_eq!(
+            manifest.metadata,
+            vec![("author".to_string(), "me".to_string())]
+        );
+
+        assert!(manifest.check_capabilities(&["net".to_string()]).is_err());
+        assert!(manifest
+            .check_capabilities(&["net".to_string(), "fs-read".to_string()])
+            .is_ok());
+    }
+}
@@ -0,0 +1,562 @@
+use std::path::{Component, Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+pub const MODULES_DIR: &str = "kk_modules";
+pub const LOCKFILE: &str = "kk.lock";
+pub const PROJECT_FILE: &str = "kk.toml";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockEntry {
+    pub name: String,
+    pub source: String,
+    /// The commit a git source was cloned at.
+    pub commit: Option<String>,
+    pub hash: String,
+}
+
+/// Vendors a module from a git url or a local path into `kk_modules/` and records it in
+/// the lockfile. Returns the recorded entry.
+pub fn add(source: &str) -> Result<LockEntry, String> {
+    let name = module_name(source)?;
+
+    std::fs::create_dir_all(MODULES_DIR)
+        .map_err(|err| format!("Unable to create {}: {}", MODULES_DIR, err))?;
+
+    let is_file = Path::new(source).is_file();
+
+    let target = if is_file {
+        Path::new(MODULES_DIR).join(format!("{}.kk", name))
+    } else {
+        Path::new(MODULES_DIR).join(&name)
+    };
+
+    remove_path(&target)?;
+
+    let mut commit = None;
+
+    if is_git_url(source) {
+        let status = std::process::Command::new("git")
+            .args(["clone", "--depth", "1", "--quiet", source])
+            .arg(&target)
+            .status()
+            .map_err(|err| format!("Unable to run git: {}", err))?;
+
+        if !status.success() {
+            return Err(format!("git clone failed for {}", source));
+        }
+
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(&target)
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .map_err(|err| format!("Unable to run git: {}", err))?;
+
+        if !output.status.success() {
+            return Err(format!("Unable to read the commit cloned from {}", source));
+        }
+
+        commit = Some(String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+        remove_path(&target.join(".git"))?;
+    } else if is_file {
+        std::fs::copy(source, &target)
+            .map_err(|err| format!("Unable to copy {}: {}", source, err))?;
+    } else if Path::new(source).is_dir() {
+        copy_dir(Path::new(source), &target)?;
+    } else {
+        return Err(format!("Module source not found: {}", source));
+    }
+
+    let entry = LockEntry {
+        name,
+        source: source.to_string(),
+        commit,
+        hash: hash_path(&target)?,
+    };
+
+    let mut entries = read_lockfile(Path::new(LOCKFILE))?;
+    entries.retain(|existing| existing.name != entry.name);
+    entries.push(entry.clone());
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    write_lockfile(Path::new(LOCKFILE), &entries)?;
+
+    Ok(entry)
+}
+
+/// Checks the modules in the `kk_modules/` of `dir` against the hashes `kk add` recorded in
+/// its lockfile. Returns a problem for each module that is missing or was changed since.
+pub fn check(dir: &Path) -> Result<Vec<String>, String> {
+    let mut problems = vec![];
+
+    for entry in read_lockfile(&dir.join(LOCKFILE))? {
+        let modules = dir.join(MODULES_DIR);
+        let file = modules.join(format!("{}.kk", entry.name));
+
+        let path = match file.is_file() {
+            true => file,
+            false => modules.join(&entry.name),
+        };
+
+        if !path.exists() {
+            problems.push(format!("{} is missing from {}", entry.name, MODULES_DIR));
+        } else if hash_path(&path)? != entry.hash {
+            problems.push(format!(
+                "{} differs from the version recorded in {}",
+                entry.name, LOCKFILE
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// The `[package]` table of a project's `kk.toml`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Project {
+    pub name: String,
+    pub version: String,
+    /// The script `kk` runs when given none, relative to the project directory.
+    pub entry: String,
+}
+
+/// Creates a project in the new directory `dir`, named after it: a `kk.toml`, an entry
+/// script with a `main` function, a test for it in `tests/` and a `.gitignore`.
+pub fn new_project(dir: &Path) -> Result<Project, String> {
+    let name = match dir.file_name().and_then(|name| name.to_str()) {
+        Some(name) if !name.starts_with('.') => name.to_string(),
+        _ => return Err(format!("Invalid project name: {}", dir.display())),
+    };
+
+    if dir.exists() {
+        return Err(format!("{} already exists", dir.display()));
+    }
+
+    let project = Project {
+        name: name.clone(),
+        version: "0.1.0".to_string(),
+        entry: "main.kk".to_string(),
+    };
+
+    let files = [
+        (PROJECT_FILE, project_content(&project)),
+        (
+            "main.kk",
+            format!(
+                "(manifest :name \"{name}\" :version \"{}\")\n\n\
+                 (defn greeting (who) (format \"Hello, {{}}!\" who))\n\n\
+                 (defn main (args)\n  (print (greeting \"{name}\")))\n",
+                project.version
+            ),
+        ),
+        (
+            "tests/main_test.kk",
+            "(import \"../main.kk\")\n\n\
+             (deftest \"greeting\"\n  (assert-eq (greeting \"kk\") \"Hello, kk!\"))\n"
+                .to_string(),
+        ),
+        (".gitignore", ".kk-session\n".to_string()),
+    ];
+
+    for (file, content) in files {
+        let path = dir.join(file);
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("Unable to create {}: {}", parent.display(), err))?;
+        }
+
+        std::fs::write(&path, content)
+            .map_err(|err| format!("Unable to write {}: {}", path.display(), err))?;
+    }
+
+    Ok(project)
+}
+
+/// The entry script of the project `dir` belongs to, named by the nearest `kk.toml` in `dir`
+/// or one of its parents.
+pub fn find_entry(dir: &Path) -> Result<PathBuf, String> {
+    let Some(root) = dir.ancestors().find(|dir| dir.join(PROJECT_FILE).is_file()) else {
+        return Err(format!(
+            "No script given, and no {} found in {} or its parents. Run a script with \
+             `kk <script>`, or create a project with `kk new <name>`.",
+            PROJECT_FILE,
+            dir.display()
+        ));
+    };
+
+    let path = root.join(PROJECT_FILE);
+    let entry = root.join(read_project(&path)?.entry);
+
+    match entry.is_file() {
+        true => Ok(entry),
+        false => Err(format!(
+            "{} names the entry script {}, which doesn't exist",
+            path.display(),
+            entry.display()
+        )),
+    }
+}
+
+/// Reads a `kk.toml`. The entry script defaults to `main.kk`.
+pub fn read_project(path: &Path) -> Result<Project, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+
+    let mut project = Project {
+        name: String::new(),
+        version: String::new(),
+        entry: "main.kk".to_string(),
+    };
+
+    for line in content.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once(" = ") else {
+            continue;
+        };
+
+        let value = value.trim_matches('"').to_string();
+
+        match key {
+            "name" => project.name = value,
+            "version" => project.version = value,
+            "entry" => project.entry = value,
+            _ => {}
+        }
+    }
+
+    Ok(project)
+}
+
+fn project_content(project: &Project) -> String {
+    format!(
+        "[package]\nname = \"{}\"\nversion = \"{}\"\nentry = \"{}\"\n",
+        project.name, project.version, project.entry
+    )
+}
+
+/// Resolves an import name to a file. Relative paths are resolved against `base_dir`,
+/// bare names are searched for in the `kk_modules/` of `base_dir` and of its parents, up
+/// to the root of the project, where `kk add` puts them.
+pub(crate) fn resolve_module(name: &str, base_dir: &Path) -> Option<PathBuf> {
+    if name.starts_with("./") || name.starts_with("../") || name.ends_with(".kk") {
+        let path = base_dir
+            .join(name)
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect::<PathBuf>();
+
+        return path.is_file().then_some(path);
+    }
+
+    for dir in base_dir.ancestors() {
+        let modules = dir.join(MODULES_DIR);
+
+        let found = [
+            modules.join(format!("{}.kk", name)),
+            modules.join(name).join("main.kk"),
+            modules.join(name).join(format!("{}.kk", name)),
+        ]
+        .into_iter()
+        .find(|path| path.is_file());
+
+        if found.is_some() || dir.join(PROJECT_FILE).is_file() {
+            return found;
+        }
+    }
+
+    None
+}
+
+fn is_git_url(source: &str) -> bool {
+    source.ends_with(".git")
+        || source.starts_with("git@")
+        || source.starts_with("https://")
+        || source.starts_with("http://")
+}
+
+fn module_name(source: &str) -> Result<String, String> {
+    let last = source
+        .trim_end_matches('/')
+        .rsplit(['/', '\\', ':'])
+        .next()
+        .unwrap_or_default();
+
+    let name = last.trim_end_matches(".git").trim_end_matches(".kk");
+
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("Unable to determine module name from {}", source));
+    }
+
+    Ok(name.to_string())
+}
+
+fn remove_path(path: &Path) -> Result<(), String> {
+    let result = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else if path.exists() {
+        std::fs::remove_file(path)
+    } else {
+        return Ok(());
+    };
+
+    result.map_err(|err| format!("Unable to remove {}: {}", path.display(), err))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), String> {
+    for relative in list_files(from)? {
+        let target = to.join(&relative);
+
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("Unable to create {}: {}", parent.display(), err))?;
+        }
+
+        std::fs::copy(from.join(&relative), &target)
+            .map_err(|err| format!("Unable to copy {}: {}", relative.display(), err))?;
+    }
+
+    Ok(())
+}
+
+/// Lists the files under `root` relative to it, sorted, skipping `.git`.
+fn list_files(root: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = vec![];
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(relative) = pending.pop() {
+        let entries = std::fs::read_dir(root.join(&relative))
+            .map_err(|err| format!("Unable to read {}: {}", root.display(), err))?;
+
+        for entry in entries {
+            let entry = entry.map_err(|err| err.to_string())?;
+
+            if entry.file_name() == ".git" {
+                continue;
+            }
+
+            let path = relative.join(entry.file_name());
+
+            if entry.path().is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Content hash of a vendored module: every file's relative path and bytes, in sorted order.
+pub(crate) fn hash_path(path: &Path) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+
+    let read = |file: &Path| {
+        std::fs::read(file).map_err(|err| format!("Unable to read {}: {}", file.display(), err))
+    };
+
+    if path.is_file() {
+        hasher.update(read(path)?);
+    } else {
+        for relative in list_files(path)? {
+            hasher.update(relative.to_string_lossy().replace('\\', "/").as_bytes());
+            hasher.update([0]);
+            hasher.update(read(&path.join(&relative))?);
+            hasher.update([0]);
+        }
+    }
+
+    let digest = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    Ok(format!("sha256:{}", digest))
+}
+
+pub(crate) fn read_lockfile(path: &Path) -> Result<Vec<LockEntry>, String> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+
+    let mut entries = vec![];
+
+    for line in content.lines().map(str::trim) {
+        if line == "[[module]]" {
+            entries.push(LockEntry {
+                name: String::new(),
+                source: String::new(),
+                commit: None,
+                hash: String::new(),
+            });
+
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(" = ") else {
+            continue;
+        };
+
+        let Some(entry) = entries.last_mut() else {
+            return Err(format!("Malformed lockfile {}", path.display()));
+        };
+
+        let value = value.trim_matches('"').to_string();
+
+        match key {
+            "name" => entry.name = value,
+            "source" => entry.source = value,
+            "commit" => entry.commit = Some(value),
+            "hash" => entry.hash = value,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+fn write_lockfile(path: &Path, entries: &[LockEntry]) -> Result<(), String> {
+    let mut content = String::from("# This file is generated by `kk add`. Do not edit.\n");
+
+    for entry in entries {
+        content.push_str(&format!(
+            "\n[[module]]\nname = \"{}\"\nsource = \"{}\"\n",
+            entry.name, entry.source
+        ));
+
+        if let Some(commit) = &entry.commit {
+            content.push_str(&format!("commit = \"{}\"\n", commit));
+        }
+
+        content.push_str(&format!("hash = \"{}\"\n", entry.hash));
+    }
+
+    std::fs::write(path, content)
+        .map_err(|err| format!("Unable to write {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn test_module_name() {
+        assert_eq!(
+            module_name("https://github.com/someone/utils.git").unwrap(),
+            "utils"
+        );
+        assert_eq!(module_name("../libs/strings/").unwrap(), "strings");
+        assert_eq!(module_name("./helpers.kk").unwrap(), "helpers");
+        assert!(module_name("..").is_err());
+    }
+
+    #[test]
+    fn test_new_project() {
+        let dir = std::env::temp_dir().join(format!("kk-new-test-{}", std::process::id()));
+        let project = new_project(&dir.join("demo")).unwrap();
+
+        assert_eq!(project.name, "demo");
+        assert_eq!(read_project(&dir.join("demo/kk.toml")).unwrap(), project);
+        assert_eq!(
+            find_entry(&dir.join("demo/tests")).unwrap(),
+            dir.join("demo/main.kk")
+        );
+        assert!(new_project(&dir.join("demo")).is_err());
+        assert!(find_entry(&dir)
+            .unwrap_err()
+            .contains("create a project with `kk new <name>`"));
+
+        let output = Rc::new(RefCell::new(String::new()));
+        let sink = output.clone();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(move |text| sink.borrow_mut().push_str(text));
+        let main = dir.join("demo/main.kk");
+        interpreter.eval_file(main.to_str().unwrap()).unwrap();
+
+        assert_eq!(interpreter.run_main(&[]).unwrap(), 0);
+        assert_eq!(*output.borrow(), "Hello, demo!\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_vendored_modules() {
+        let dir = std::env::temp_dir().join(format!("kk-modules-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("tests")).unwrap();
+        std::fs::create_dir_all(dir.join(MODULES_DIR)).unwrap();
+
+        std::fs::write(dir.join(PROJECT_FILE), "[package]\nname = \"demo\"\n").unwrap();
+        let util = dir.join(MODULES_DIR).join("util.kk");
+        std::fs::write(&util, "(defn one () 1)\n").unwrap();
+
+        assert_eq!(
+            resolve_module("util", &dir.join("tests")),
+            Some(util.clone())
+        );
+        assert_eq!(resolve_module("nope", &dir.join("tests")), None);
+
+        let entries = vec![
+            LockEntry {
+                name: "util".to_string(),
+                source: "../util.kk".to_string(),
+                commit: None,
+                hash: hash_path(&util).unwrap(),
+            },
+            LockEntry {
+                name: "gone".to_string(),
+                source: "../gone".to_string(),
+                commit: None,
+                hash: "sha256:00".to_string(),
+            },
+        ];
+
+        write_lockfile(&dir.join(LOCKFILE), &entries).unwrap();
+        assert_eq!(check(&dir).unwrap(), ["gone is missing from kk_modules"]);
+
+        std::fs::write(&util, "(defn one () 2)\n").unwrap();
+        assert_eq!(
+            check(&dir).unwrap()[0],
+            "util differs from the version recorded in kk.lock"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_lockfile_roundtrip() {
+        let path = std::env::temp_dir().join(format!("kk-lock-test-{}", std::process::id()));
+
+        let entries = vec![
+            LockEntry {
+                name: "strings".to_string(),
+                source: "https://example.com/strings.git".to_string(),
+                commit: Some("0123abc".to_string()),
+                hash: "sha256:01".to_string(),
+            },
+            LockEntry {
+                name: "utils".to_string(),
+                source: "../utils".to_string(),
+                commit: None,
+                hash: "sha256:00".to_string(),
+            },
+        ];
+
+        write_lockfile(&path, &entries).unwrap();
+        assert_eq!(read_lockfile(&path).unwrap(), entries);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,151 +1,511 @@
-use crate::sexpr::SExpr;
+use std::fmt;
+
+use crate::bigint::BigInt;
+use crate::sexpr::{SExpr, Span, INTERPOLATE};
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Atom(String),
+    String(String),
+    /// A string literal that reached the end of the input before its closing quote.
+    UnterminatedString,
+}
+
+/// The kinds of token a `ParseError` reports finding or expecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    OpenParen,
+    CloseParen,
+    Atom,
+    String,
+    /// A string literal that reached the end of the input before its closing quote.
+    UnterminatedString,
+    /// A `${...}` in a string literal that isn't one expression closed by `}`.
+    Interpolation,
+    /// A `(` opening a list nested deeper than `MAX_NESTING`.
+    Nesting,
+    EndOfInput,
+}
+
+/// How deeply lists may nest. Checking, linting, formatting and evaluating forms recurse
+/// on their nesting, so the limit keeps them from overflowing the stack on any input.
+pub const MAX_NESTING: usize = 1_000;
+
+/// Why source text failed to parse.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// The unexpected token, or for input that ends too early the `(` or `"` left open.
+    pub span: Span,
+    pub found: TokenKind,
+    /// The source text of the unexpected token, empty at the end of the input.
+    pub text: String,
+    /// The tokens that would have been accepted instead.
+    pub expected: Vec<TokenKind>,
+}
+
+/// What may follow inside a list.
+const IN_LIST: &[TokenKind] = &[
+    TokenKind::OpenParen,
+    TokenKind::CloseParen,
+    TokenKind::Atom,
+    TokenKind::String,
+];
+
+/// Named character literals, for the characters that can't be written after `#\` as
+/// themselves.
+const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+];
+
+/// The character of a literal atom such as `#\a`, `#\(` or `#\space`, or `None` when the
+/// atom isn't one.
+pub fn char_literal(atom: &str) -> Option<char> {
+    let rest = atom.strip_prefix("#\\")?;
+    let mut chars = rest.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(char), None) => Some(char),
+        _ => CHAR_NAMES
+            .iter()
+            .find(|(name, _)| *name == rest)
+            .map(|(_, char)| *char),
+    }
+}
+
+/// The literal atom for `char`, which `char_literal` reads back.
+pub fn char_atom(char: char) -> String {
+    match CHAR_NAMES.iter().find(|(_, named)| *named == char) {
+        Some((name, _)) => format!("#\\{}", name),
+        None => format!("#\\{}", char),
+    }
+}
+
+/// The value of a literal atom such as `true`, `#\a`, `80`, a bigint or `2.5`, or `None`
+/// when the atom is a name.
+pub fn literal(atom: &str) -> Option<Value> {
+    match atom {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        "null" => Some(Value::Null),
+        atom if atom.starts_with("#\\") => char_literal(atom).map(Value::Char),
+        atom => match (
+            atom.parse::<i64>(),
+            BigInt::parse(atom),
+            atom.parse::<f64>(),
+        ) {
+            (Ok(int), _, _) => Some(Value::Int(int)),
+            (_, Some(big), _) => Some(Value::from(big)),
+            (_, _, Ok(float)) => Some(Value::Float(float)),
+            _ => None,
+        },
+    }
+}
+
+/// What may follow between top-level forms.
+const TOP_LEVEL: &[TokenKind] = &[TokenKind::OpenParen, TokenKind::EndOfInput];
 
-pub(crate) struct Parser {
-    source: String,
+pub struct Parser {
+    source: Vec<char>,
     position: usize,
+    offset: usize,
+    line: usize,
+    column: usize,
+    /// Where the token most recently returned by `next_token` starts.
+    token_span: Span,
+    /// Number of lists open around the position, counted against `MAX_NESTING`.
+    depth: usize,
 }
 
 impl Parser {
-    pub(crate) fn new(source: &str) -> Parser {
+    pub fn new(source: &str) -> Parser {
         Parser {
-            source: source.to_string(),
+            source: source.chars().collect(),
             position: 0,
+            offset: 0,
+            line: 1,
+            column: 1,
+            token_span: Span::default(),
+            depth: 0,
         }
     }
 
-    pub(crate) fn parse(&mut self) -> Result<Vec<SExpr>, String> {
+    /// A parser of `source` found at `start` in a larger source, so spans point into it,
+    /// inside `depth` lists.
+    fn at(source: &str, start: Span, depth: usize) -> Parser {
+        Parser {
+            offset: start.offset,
+            line: start.line,
+            column: start.column,
+            depth,
+            ..Parser::new(source)
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<Vec<SExpr>, ParseError> {
         let mut sexprs = vec![];
 
-        loop {
-            let Some(sexpr) = self.parse_sexp()? else {
-                break;
-            };
+        // A `#!/usr/bin/env kk` line lets scripts be executed directly
+        if self.position == 0 && self.source.starts_with(&['#', '!']) {
+            while self.peek().is_some_and(|char| char != '\n') {
+                self.bump();
+            }
+        }
 
-            sexprs.push(sexpr);
+        while let Some(token) = self.next_token() {
+            let span = self.token_span;
+
+            match token {
+                Token::LParen => sexprs.push(self.parse_list(span)?),
+                token => return Err(unexpected(token, span, TOP_LEVEL)),
+            }
         }
 
         Ok(sexprs)
     }
 
-    fn parse_sexp(&mut self) -> Result<Option<SExpr>, String> {
-        let Some(token) = self.next_token() else {
-            return Ok(None);
-        };
-
-        if token == "(" {
-            let mut args = vec![];
-
-            loop {
-                let Some(token) = self.next_token() else {
-                    return Err("Unexpected end of input".to_string());
-                };
+    /// Parses the rest of a list whose `(` was just read at `start`. The lists inside it are
+    /// kept on a stack rather than parsed recursively, so that deep input fails with an
+    /// error instead of overflowing the Rust stack.
+    fn parse_list(&mut self, start: Span) -> Result<SExpr, ParseError> {
+        // The lists still open, innermost last, with the forms read in each so far
+        let mut open = vec![];
+        let mut token = Token::LParen;
+        let mut span = start;
 
-                if token == ")" {
-                    break;
+        loop {
+            let form = match token {
+                Token::LParen if self.depth >= MAX_NESTING => {
+                    return Err(ParseError {
+                        span,
+                        found: TokenKind::Nesting,
+                        text: "(".to_string(),
+                        expected: vec![TokenKind::CloseParen, TokenKind::Atom, TokenKind::String],
+                    });
+                }
+                Token::LParen => {
+                    self.depth += 1;
+                    open.push((span, vec![]));
+                    None
                 }
+                Token::RParen => {
+                    self.depth -= 1;
+                    open.pop().map(|(start, args)| SExpr::List(args, start))
+                }
+                token => Some(self.parse_item(token, span)?),
+            };
 
-                if token == "(" {
-                    self.position -= 1;
+            match (form, open.last_mut()) {
+                (Some(form), Some((_, args))) => args.push(form),
+                (Some(form), None) => return Ok(form),
+                (None, _) => {}
+            }
 
-                    let Some(inner_sexpr) = self.parse_sexp()? else {
-                        return Err("Expected S-expression".to_string());
-                    };
+            token = match self.next_token() {
+                Some(token) => token,
+                None => {
+                    return Err(ParseError {
+                        span: open.last().map_or(start, |(start, _)| *start),
+                        found: TokenKind::EndOfInput,
+                        text: String::new(),
+                        expected: IN_LIST.to_vec(),
+                    });
+                }
+            };
+            span = self.token_span;
+        }
+    }
 
-                    args.push(inner_sexpr);
-                } else {
-                    args.push(SExpr::Atom(token));
+    /// Parses the form starting with `token`, read at `span`.
+    fn parse_item(&mut self, token: Token, span: Span) -> Result<SExpr, ParseError> {
+        match token {
+            Token::LParen => self.parse_list(span),
+            Token::Atom(atom) => match atom.strip_prefix(':') {
+                Some(keyword) if !keyword.is_empty() => {
+                    Ok(SExpr::Keyword(keyword.to_string(), span))
                 }
+                _ => Ok(SExpr::Atom(Symbol::intern(&atom), span)),
+            },
+            Token::String(string) => string_literal(&string, span, self.depth),
+            token @ (Token::RParen | Token::UnterminatedString) => {
+                Err(unexpected(token, span, IN_LIST))
             }
-
-            Ok(Some(SExpr::List(args)))
-        } else {
-            return Err(format!("Unexpected token: {}", token));
         }
     }
 
-    fn next_token(&mut self) -> Option<String> {
-        let mut token = String::new();
-
-        loop {
-            let Some(char) = self.source.chars().nth(self.position) else {
-                break;
-            };
-
-            match char {
-                '(' | ')' => {
-                    if !token.is_empty() {
-                        break;
-                    }
-
-                    token.push(char);
+    fn peek(&self) -> Option<char> {
+        self.source.get(self.position).copied()
+    }
 
-                    self.position += 1;
-                    break;
-                }
-                '"' => {
-                    if !token.is_empty() {
-                        break;
-                    }
+    fn bump(&mut self) -> Option<char> {
+        let char = self.peek()?;
 
-                    self.position += 1;
+        self.position += 1;
+        self.offset += char.len_utf8();
 
-                    loop {
-                        let Some(char) = self.source.chars().nth(self.position) else {
-                            return None;
-                        };
+        if char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
 
-                        if char == '"' {
-                            self.position += 1;
-                            break;
-                        }
+        Some(char)
+    }
 
-                        token.push(char);
-                        self.position += 1;
-                    }
+    fn span(&self) -> Span {
+        Span {
+            offset: self.offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
 
-                    break;
+    fn next_token(&mut self) -> Option<Token> {
+        // Skip whitespace and `;` comments.
+        while let Some(char) = self.peek() {
+            match char {
+                ' ' | '\n' | '\r' | '\t' => {
+                    self.bump();
                 }
                 ';' => {
-                    if !token.is_empty() {
-                        break;
+                    while self.peek().is_some_and(|char| char != '\n') {
+                        self.bump();
                     }
+                }
+                _ => break,
+            }
+        }
 
-                    self.position += 1;
-
-                    loop {
-                        let Some(char) = self.source.chars().nth(self.position) else {
-                            return None;
-                        };
+        self.token_span = self.span();
 
-                        if char == '\n' {
-                            break;
-                        }
+        match self.bump()? {
+            '(' => Some(Token::LParen),
+            ')' => Some(Token::RParen),
+            '"' => {
+                let mut string = String::new();
 
-                        self.position += 1;
+                loop {
+                    match self.bump() {
+                        Some('"') => return Some(Token::String(string)),
+                        Some(char) => string.push(char),
+                        None => return Some(Token::UnterminatedString),
                     }
                 }
-                ' ' | '\n' | '\r' | '\t' => {
-                    if !token.is_empty() {
+            }
+            char => {
+                let mut atom = String::from(char);
+
+                // The character after `#\` is part of the literal, even a paren or quote
+                if char == '#' && self.peek() == Some('\\') {
+                    atom.push(self.bump()?);
+                    atom.extend(self.bump());
+                }
+
+                while let Some(char) = self.peek() {
+                    if matches!(char, '(' | ')' | '"' | ';' | ' ' | '\n' | '\r' | '\t') {
                         break;
                     }
 
-                    self.position += 1;
-                }
-                _ => {
-                    token.push(char);
-                    self.position += 1;
+                    atom.push(char);
+                    self.bump();
                 }
+
+                Some(Token::Atom(atom))
             }
         }
+    }
+}
+
+/// The form of a string literal whose text is `text`, inside `depth` lists. Each `${expr}`
+/// in it makes it an `(interpolate ...)` form of its text and expressions, and `$${` is a
+/// literal `${`.
+fn string_literal(text: &str, span: Span, depth: usize) -> Result<SExpr, ParseError> {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    let mut rest = text;
+    // Where `rest` and the text going into `literal` start in the source
+    let mut position = advance(span, "\"");
+    let mut literal_start = position;
+
+    while let Some(start) = rest.find("${") {
+        let (before, after) = rest.split_at(start);
+
+        if let Some(before) = before.strip_suffix('$') {
+            literal.push_str(before);
+            literal.push_str("${");
+            position = advance(position, &rest[..start + 2]);
+            rest = &after[2..];
+            continue;
+        }
+
+        literal.push_str(before);
+        position = advance(position, before);
+
+        let Some(end) = after.find('}') else {
+            return Err(invalid_interpolation(after, position));
+        };
+
+        let expr = interpolated(&after[2..end], advance(position, "${"), depth)
+            .ok_or_else(|| invalid_interpolation(&after[..=end], position))?;
+
+        if !literal.is_empty() {
+            parts.push(SExpr::String(std::mem::take(&mut literal), literal_start));
+        }
+
+        parts.push(expr);
+
+        position = advance(position, &after[..=end]);
+        literal_start = position;
+        rest = &after[end + 1..];
+    }
+
+    literal.push_str(rest);
+
+    if parts.is_empty() {
+        return Ok(SExpr::String(literal, span));
+    }
+
+    if !literal.is_empty() {
+        parts.push(SExpr::String(literal, literal_start));
+    }
+
+    parts.insert(0, SExpr::Atom(Symbol::intern(INTERPOLATE), span));
+
+    Ok(SExpr::List(parts, span))
+}
+
+/// The one expression between `${` and `}`, whose source starts at `start`.
+fn interpolated(source: &str, start: Span, depth: usize) -> Option<SExpr> {
+    let mut parser = Parser::at(source, start, depth);
+
+    let token = parser.next_token()?;
+    let expr = parser.parse_item(token, parser.token_span).ok()?;
+
+    match parser.next_token() {
+        Some(_) => None,
+        None => Some(expr),
+    }
+}
+
+fn invalid_interpolation(text: &str, span: Span) -> ParseError {
+    ParseError {
+        span,
+        found: TokenKind::Interpolation,
+        text: text.to_string(),
+        expected: vec![TokenKind::OpenParen, TokenKind::Atom],
+    }
+}
+
+/// The span just past `text`, when it starts at `span`.
+fn advance(mut span: Span, text: &str) -> Span {
+    for char in text.chars() {
+        span.offset += char.len_utf8();
 
-        if token.is_empty() {
-            None
+        if char == '\n' {
+            span.line += 1;
+            span.column = 1;
         } else {
-            Some(token)
+            span.column += 1;
+        }
+    }
+
+    span
+}
+
+fn unexpected(token: Token, span: Span, expected: &[TokenKind]) -> ParseError {
+    let (found, text) = match token {
+        Token::LParen => (TokenKind::OpenParen, "(".to_string()),
+        Token::RParen => (TokenKind::CloseParen, ")".to_string()),
+        Token::Atom(atom) => (TokenKind::Atom, atom),
+        Token::String(string) => (TokenKind::String, format!("\"{}\"", string)),
+        Token::UnterminatedString => (TokenKind::UnterminatedString, String::new()),
+    };
+
+    ParseError {
+        span,
+        found,
+        text,
+        expected: expected.to_vec(),
+    }
+}
+
+impl ParseError {
+    /// Whether more input could complete the source, as when a list or string is still
+    /// open. The REPL keeps reading lines while this holds.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self.found,
+            TokenKind::EndOfInput | TokenKind::UnterminatedString
+        )
+    }
+
+    /// The accepted tokens as a sentence, like "expected '(' or end of input".
+    pub fn expectation(&self) -> String {
+        let names = self
+            .expected
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        match names.split_last() {
+            Some((last, [])) => format!("expected {}", last),
+            Some((last, rest)) => format!("expected {} or {}", rest.join(", "), last),
+            None => "expected nothing".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.found {
+            TokenKind::EndOfInput => write!(
+                f,
+                "Unexpected end of input: '(' at {} is never closed",
+                self.span
+            ),
+            TokenKind::UnterminatedString => {
+                write!(f, "Unterminated string starting at {}", self.span)
+            }
+            TokenKind::String => write!(f, "Unexpected string {} at {}", self.text, self.span),
+            TokenKind::Interpolation => write!(
+                f,
+                "Invalid interpolation {} at {}: expected one expression closed by '}}'",
+                self.text, self.span
+            ),
+            TokenKind::Nesting => write!(
+                f,
+                "Lists nest deeper than {} levels at {}",
+                MAX_NESTING, self.span
+            ),
+            _ => write!(f, "Unexpected '{}' at {}", self.text, self.span),
         }
     }
 }
 
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TokenKind::OpenParen => "'('",
+            TokenKind::CloseParen => "')'",
+            TokenKind::Atom => "an atom",
+            TokenKind::String => "a string",
+            TokenKind::UnterminatedString => "an unterminated string",
+            TokenKind::Interpolation => "an interpolation",
+            TokenKind::Nesting => "a list nested too deeply",
+            TokenKind::EndOfInput => "end of input",
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,12 +514,170 @@ mod tests {
     fn test_parser_next_token() {
         let mut parser = Parser::new(" test(fn add);Hello\nfield.get\"Test\"");
 
-        assert_eq!(parser.next_token(), Some("test".to_string()));
-        assert_eq!(parser.next_token(), Some("(".to_string()));
-        assert_eq!(parser.next_token(), Some("fn".to_string()));
-        assert_eq!(parser.next_token(), Some("add".to_string()));
-        assert_eq!(parser.next_token(), Some(")".to_string()));
-        assert_eq!(parser.next_token(), Some("field.get".to_string()));
-        assert_eq!(parser.next_token(), Some("Test".to_string()));
+        assert_eq!(parser.next_token(), Some(Token::Atom("test".to_string())));
+        assert_eq!(parser.next_token(), Some(Token::LParen));
+        assert_eq!(parser.next_token(), Some(Token::Atom("fn".to_string())));
+        assert_eq!(parser.next_token(), Some(Token::Atom("add".to_string())));
+        assert_eq!(parser.next_token(), Some(Token::RParen));
+        assert_eq!(
+            parser.next_token(),
+            Some(Token::Atom("field.get".to_string()))
+        );
+        assert_eq!(parser.next_token(), Some(Token::String("Test".to_string())));
+    }
+
+    #[test]
+    fn test_parser_string_literals() {
+        let mut parser = Parser::new("(print \"(not a list)\" name)");
+
+        let sexprs = parser.parse().unwrap();
+
+        let SExpr::List(list, _) = &sexprs[0] else {
+            panic!("Expected list");
+        };
+
+        assert!(matches!(&list[1], SExpr::String(s, _) if s == "(not a list)"));
+        assert!(matches!(&list[2], SExpr::Atom(a, _) if a == "name"));
+    }
+
+    #[test]
+    fn test_parser_interpolation() {
+        let source = "(print \"n ${x}, ${(add x 1)}$${y}\" \"$${z}\")";
+        let sexprs = Parser::new(source).parse().unwrap();
+
+        let SExpr::List(list, _) = &sexprs[0] else {
+            panic!("Expected list");
+        };
+        let SExpr::List(parts, _) = &list[1] else {
+            panic!("Expected an interpolation");
+        };
+
+        assert_eq!(parts[0].to_string(), INTERPOLATE);
+        assert!(matches!(&parts[1], SExpr::String(s, _) if s == "n "));
+        assert_eq!(parts[2].span().to_string(), "line 1, col 13");
+        assert!(matches!(&parts[5], SExpr::String(s, _) if s == "${y}"));
+        assert!(matches!(&list[2], SExpr::String(s, _) if s == "${z}"));
+        assert_eq!(sexprs[0].to_string(), source);
+
+        let mut interpreter = crate::Interpreter::new();
+        let result = interpreter
+            .eval_str("(let x 1) (list \"x is ${x}, not ${(add x 1)}\")")
+            .unwrap();
+        assert_eq!(result.to_string(), "[x is 1, not 2]");
+
+        let error = |source: &str| Parser::new(source).parse().unwrap_err().to_string();
+
+        assert_eq!(
+            error("(print \"${a b}\")"),
+            "Invalid interpolation ${a b} at line 1, col 9: expected one expression closed by '}'"
+        );
+        assert!(error("(print \"${a\")").starts_with("Invalid interpolation ${a "));
+    }
+
+    #[test]
+    fn test_parser_keywords() {
+        let sexprs = Parser::new("(connect :host \":port\")").parse().unwrap();
+
+        let SExpr::List(list, _) = &sexprs[0] else {
+            panic!("Expected list");
+        };
+
+        assert!(matches!(&list[1], SExpr::Keyword(k, _) if k == "host"));
+        assert!(matches!(&list[2], SExpr::String(s, _) if s == ":port"));
+    }
+
+    #[test]
+    fn test_parser_spans() {
+        let sexprs = Parser::new("; header\n(print\n  (add 1 2))")
+            .parse()
+            .unwrap();
+
+        let SExpr::List(list, span) = &sexprs[0] else {
+            panic!("Expected list");
+        };
+
+        assert_eq!((span.line, span.column, span.offset), (2, 1, 9));
+        assert_eq!(list[1].span().to_string(), "line 3, col 3");
+    }
+
+    #[test]
+    fn test_parser_shebang() {
+        let sexprs = Parser::new("#!/usr/bin/env kk\n(print 1)").parse().unwrap();
+
+        assert_eq!(sexprs.len(), 1);
+        assert_eq!(sexprs[0].span().line, 2);
+    }
+
+    #[test]
+    fn test_parser_error_positions() {
+        let error = |source: &str| Parser::new(source).parse().unwrap_err().to_string();
+
+        assert_eq!(
+            error("(print 1)\n\n  (print 2))"),
+            "Unexpected ')' at line 3, col 12"
+        );
+        assert_eq!(
+            error("(print\n  (add 1 2)"),
+            "Unexpected end of input: '(' at line 1, col 1 is never closed"
+        );
+        assert_eq!(
+            error("(print \"oops)"),
+            "Unterminated string starting at line 1, col 8"
+        );
+    }
+
+    #[test]
+    fn test_parse_error_details() {
+        let err = Parser::new("(print 1)\n  x").parse().unwrap_err();
+
+        assert_eq!(err.found, TokenKind::Atom);
+        assert_eq!(err.text, "x");
+        assert_eq!(
+            (err.span.line, err.span.column, err.span.offset),
+            (2, 3, 12)
+        );
+        assert_eq!(err.expected, [TokenKind::OpenParen, TokenKind::EndOfInput]);
+        assert_eq!(err.expectation(), "expected '(' or end of input");
+        assert!(!err.is_incomplete());
+
+        let err = Parser::new("(print\n  (add 1").parse().unwrap_err();
+
+        assert_eq!(err.found, TokenKind::EndOfInput);
+        assert_eq!(err.span.line, 2);
+        assert!(err.expected.contains(&TokenKind::CloseParen));
+        assert!(err.is_incomplete());
+        assert!(Parser::new("(print \"open")
+            .parse()
+            .unwrap_err()
+            .is_incomplete());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}1{}", "(list ".repeat(depth), ")".repeat(depth));
+
+        let err = Parser::new(&nested(100_000)).parse().unwrap_err();
+        assert_eq!(err.found, TokenKind::Nesting);
+        assert_eq!(err.span.column, MAX_NESTING * "(list ".len() + 1);
+        assert!(!err.is_incomplete());
+
+        // The deepest nesting allowed is walked without overflowing a stack the size of the
+        // main thread's, where the command line and the language server run
+        let source = nested(MAX_NESTING);
+
+        std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(move || {
+                let sexprs = Parser::new(&source).parse().unwrap();
+
+                assert!(crate::check::check(&sexprs).is_empty());
+                assert!(crate::lint::check(&sexprs).is_empty());
+                assert!(crate::format::format(&source).is_ok());
+                assert_eq!(sexprs[0].to_string(), source);
+                assert!(sexprs[0].dump().contains("1"));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
     }
 }
@@ -0,0 +1,266 @@
+//! Exact fractions, the results of `div` on ints that don't divide evenly. A `Rational` is
+//! always in lowest terms with a positive denominator, and results whose denominator is 1
+//! go back to ints, so a `Value::Rational` never holds a whole number.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::bigint::BigInt;
+
+/// A fraction of two ints of any size.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numerator: BigInt,
+    /// Always positive, and without a factor in common with the numerator.
+    denominator: BigInt,
+}
+
+impl Rational {
+    /// The fraction in lowest terms. `None` when `denominator` is zero.
+    pub fn new(numerator: BigInt, denominator: BigInt) -> Option<Rational> {
+        if denominator.is_zero() {
+            return None;
+        }
+
+        let divisor = gcd(numerator.abs(), denominator.abs());
+        let divide = |value: &BigInt| value.div_rem(&divisor).map(|(quotient, _)| quotient);
+
+        let (mut numerator, mut denominator) = (divide(&numerator)?, divide(&denominator)?);
+
+        if denominator.is_negative() {
+            numerator = -&numerator;
+            denominator = -&denominator;
+        }
+
+        Some(Rational {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// The float as a fraction, exactly. `None` for NaN and the infinities.
+    pub fn from_f64(value: f64) -> Option<Rational> {
+        if !value.is_finite() {
+            return None;
+        }
+
+        let bits = value.to_bits();
+        let (mantissa, exponent) = match (bits >> 52) & 0x7ff {
+            0 => (bits & ((1 << 52) - 1), -1074),
+            exponent => (bits & ((1 << 52) - 1) | 1 << 52, exponent as i64 - 1075),
+        };
+
+        let mantissa = match value < 0.0 {
+            true => BigInt::from(-(mantissa as i64)),
+            false => BigInt::from(mantissa as i64),
+        };
+        let power = BigInt::from(2).pow(exponent.unsigned_abs() as u32);
+
+        match exponent < 0 {
+            true => Rational::new(mantissa, power),
+            false => Rational::new(&mantissa * &power, BigInt::from(1)),
+        }
+    }
+
+    pub fn numerator(&self) -> &BigInt {
+        &self.numerator
+    }
+
+    pub fn denominator(&self) -> &BigInt {
+        &self.denominator
+    }
+
+    /// Whether the denominator is 1, so the fraction is the int `numerator`.
+    pub fn is_whole(&self) -> bool {
+        self.denominator == BigInt::from(1)
+    }
+
+    /// The quotient truncated toward zero.
+    pub fn trunc(&self) -> BigInt {
+        match self.numerator.div_rem(&self.denominator) {
+            Some((quotient, _)) => quotient,
+            None => unreachable!("the denominator is never zero"),
+        }
+    }
+
+    /// The float nearest the fraction, even when its numerator and denominator are beyond the
+    /// float range.
+    pub fn to_f64(&self) -> f64 {
+        // Scales the numerator so the quotient keeps 64 significant bits
+        let shift = 64 + self.denominator.bits() as i64 - self.numerator.bits() as i64;
+        let power = BigInt::from(2).pow(shift.unsigned_abs() as u32);
+
+        let quotient = match shift < 0 {
+            true => self.numerator.div_rem(&(&self.denominator * &power)),
+            false => (&self.numerator * &power).div_rem(&self.denominator),
+        };
+
+        match quotient {
+            // In two steps, as 2^-shift alone may be below the float range
+            Some((quotient, _)) => {
+                let half = -shift as i32 / 2;
+                quotient.to_f64() * 2f64.powi(half) * 2f64.powi(-shift as i32 - half)
+            }
+            None => unreachable!("the denominator is never zero"),
+        }
+    }
+
+    pub fn abs(&self) -> Rational {
+        Rational {
+            numerator: self.numerator.abs(),
+            denominator: self.denominator.clone(),
+        }
+    }
+
+    /// The quotient, exactly. `None` when `divisor` is zero.
+    pub fn checked_div(&self, divisor: &Rational) -> Option<Rational> {
+        Rational::new(
+            &self.numerator * &divisor.denominator,
+            &self.denominator * &divisor.numerator,
+        )
+    }
+
+    /// The largest int not above the fraction.
+    pub fn floor(&self) -> BigInt {
+        let quotient = self.trunc();
+
+        match self.numerator.is_negative() && !self.is_whole() {
+            true => &quotient - &BigInt::from(1),
+            false => quotient,
+        }
+    }
+
+    /// The smallest int not below the fraction.
+    pub fn ceil(&self) -> BigInt {
+        -&(-self).floor()
+    }
+
+    /// The nearest int, rounding halves away from zero like `f64::round`.
+    pub fn round(&self) -> BigInt {
+        let half = Rational {
+            numerator: BigInt::from(1),
+            denominator: BigInt::from(2),
+        };
+
+        match self.numerator.is_negative() {
+            true => -&(&self.abs() + &half).floor(),
+            false => (self + &half).floor(),
+        }
+    }
+}
+
+fn gcd(mut a: BigInt, mut b: BigInt) -> BigInt {
+    while let Some((_, remainder)) = a.div_rem(&b) {
+        a = b;
+        b = remainder;
+    }
+
+    a
+}
+
+impl From<BigInt> for Rational {
+    fn from(int: BigInt) -> Rational {
+        Rational {
+            numerator: int,
+            denominator: BigInt::from(1),
+        }
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        // The denominators are positive, so cross-multiplying keeps the order
+        (&self.numerator * &other.denominator).cmp(&(&other.numerator * &self.denominator))
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+
+    fn neg(self) -> Rational {
+        Rational {
+            numerator: -&self.numerator,
+            denominator: self.denominator.clone(),
+        }
+    }
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+
+    fn add(self, other: &Rational) -> Rational {
+        let numerator =
+            &(&self.numerator * &other.denominator) + &(&other.numerator * &self.denominator);
+
+        match Rational::new(numerator, &self.denominator * &other.denominator) {
+            Some(sum) => sum,
+            None => unreachable!("the denominators are never zero"),
+        }
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+
+    fn sub(self, other: &Rational) -> Rational {
+        self + &-other
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+
+    fn mul(self, other: &Rational) -> Rational {
+        let numerator = &self.numerator * &other.numerator;
+
+        match Rational::new(numerator, &self.denominator * &other.denominator) {
+            Some(product) => product,
+            None => unreachable!("the denominators are never zero"),
+        }
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rational(numerator: i64, denominator: i64) -> Rational {
+        Rational::new(BigInt::from(numerator), BigInt::from(denominator)).unwrap()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(rational(6, -4).to_string(), "-3/2");
+        assert_eq!(rational(0, 5).to_string(), "0/1");
+        assert!(Rational::new(BigInt::from(1), BigInt::from(0)).is_none());
+
+        assert_eq!((&rational(1, 3) + &rational(1, 6)).to_string(), "1/2");
+        assert_eq!((&rational(1, 3) - &rational(1, 2)).to_string(), "-1/6");
+        assert_eq!((&rational(2, 3) * &rational(3, 4)).to_string(), "1/2");
+        assert_eq!(rational(-7, 2).trunc().to_string(), "-3");
+        assert_eq!(rational(-7, 2).floor().to_string(), "-4");
+        assert_eq!(rational(-7, 2).ceil().to_string(), "-3");
+        assert_eq!(rational(-7, 2).round().to_string(), "-4");
+        assert_eq!(rational(7, 3).round().to_string(), "2");
+        assert!(rational(1, 3) < rational(1, 2));
+        assert!(rational(-1, 2) < rational(-1, 3));
+
+        assert_eq!(rational(1, 3).to_f64(), 1.0 / 3.0);
+        assert_eq!(Rational::from_f64(0.375).unwrap().to_string(), "3/8");
+        assert_eq!(Rational::from_f64(-5e-324).unwrap().to_f64(), -5e-324);
+        assert_eq!(Rational::from_f64(1e300).unwrap().to_f64(), 1e300);
+    }
+}
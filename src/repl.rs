@@ -0,0 +1,501 @@
+//! The read-eval-print loop of `kk repl`, also usable to give a host application its own
+//! kk console.
+//!
+//! ```
+//! let mut interpreter = kk::Interpreter::new();
+//! interpreter.set_var("lives", kk::Value::Int(3));
+//!
+//! let mut repl = kk::repl::Repl::new(interpreter);
+//! repl.set_prompt("game> ");
+//! repl.set_banner("Debug console, :quit to close");
+//!
+//! let mut output = vec![];
+//! repl.run("(add lives 1)\n".as_bytes(), &mut output).unwrap();
+//!
+//! assert_eq!(
+//!     String::from_utf8(output).unwrap(),
+//!     "Debug console, :quit to close\ngame> 4\ngame> \n"
+//! );
+//! ```
+
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::sexpr::SExpr;
+use crate::{parser, Interpreter, Value};
+
+/// Inputs whose top-level forms all start with one of these are replayed when a session is
+/// restored, since functions, enums and protocols can't be saved as values.
+const REPLAYED_FORMS: &[&str] = &[
+    "defn",
+    "defenum",
+    "defprotocol",
+    "extend-protocol",
+    "deftest",
+    "import",
+    "import-lazy",
+];
+
+/// What `kk repl --session <file>` keeps between runs.
+#[derive(Debug, Default)]
+struct Session {
+    history: Vec<String>,
+    definitions: Vec<String>,
+    /// A `(let name value)` form for each global variable.
+    vars: Vec<String>,
+}
+
+impl Session {
+    fn load(path: &Path) -> Result<Session, String> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Session::default()),
+            Err(err) => return Err(format!("Unable to read {}: {}", path.display(), err)),
+        };
+
+        let json = serde_json::from_str::<serde_json::Value>(&content)
+            .map_err(|err| format!("Malformed session {}: {}", path.display(), err))?;
+
+        let strings = |key: &str| {
+            json[key]
+                .as_array()
+                .map(|list| {
+                    list.iter()
+                        .filter_map(|item| item.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Ok(Session {
+            history: strings("history"),
+            definitions: strings("definitions"),
+            vars: strings("vars"),
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let json = json!({
+            "history": self.history,
+            "definitions": self.definitions,
+            "vars": self.vars,
+        });
+
+        let content = serde_json::to_string_pretty(&json).map_err(|err| err.to_string())?;
+
+        std::fs::write(path, content + "\n")
+            .map_err(|err| format!("Unable to write {}: {}", path.display(), err))
+    }
+}
+
+/// Prompts written before the lines of an input in transcripts, whatever the REPL shows.
+const PROMPT: &str = "kk> ";
+const CONTINUATION: &str = "... ";
+
+/// An interactive session over an interpreter. Lines are read from any `BufRead` and
+/// answers written to any `Write`; text printed by the script still goes where the
+/// interpreter's `set_output` sends it.
+///
+/// Besides kk forms, a line can be one of the commands `:quit` (or `:q`), `:history`, and
+/// `:transcript <file>`, which records every input and its result to `file` until
+/// `:transcript` stops it.
+pub struct Repl {
+    interpreter: Interpreter,
+    prompt: String,
+    /// Shown instead of `prompt` while a form spans several lines.
+    continuation: String,
+    /// Written once when `run` starts.
+    banner: Option<String>,
+    /// Where `run` restores the session from and saves it to.
+    session_path: Option<PathBuf>,
+    session: Session,
+    /// Lines of a form that isn't complete yet.
+    pending: String,
+    transcript: Option<File>,
+}
+
+impl Repl {
+    pub fn new(interpreter: Interpreter) -> Repl {
+        Repl {
+            interpreter,
+            prompt: PROMPT.to_string(),
+            continuation: CONTINUATION.to_string(),
+            banner: None,
+            session_path: None,
+            session: Session::default(),
+            pending: String::new(),
+            transcript: None,
+        }
+    }
+
+    /// Sets the prompt written before each input, `kk> ` by default.
+    pub fn set_prompt(&mut self, prompt: impl Into<String>) {
+        self.prompt = prompt.into();
+    }
+
+    /// Sets the prompt written before the next lines of an unfinished form, `... ` by
+    /// default.
+    pub fn set_continuation(&mut self, continuation: impl Into<String>) {
+        self.continuation = continuation.into();
+    }
+
+    /// Sets a line written when `run` starts. There is none by default.
+    pub fn set_banner(&mut self, banner: impl Into<String>) {
+        self.banner = Some(banner.into());
+    }
+
+    /// Keeps the session in `path`, as `kk repl --session` does: `run` restores the
+    /// bindings, definitions and history of the previous session first and saves them again
+    /// on exit.
+    pub fn set_session(&mut self, path: impl Into<PathBuf>) {
+        self.session_path = Some(path.into());
+    }
+
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    pub fn into_interpreter(self) -> Interpreter {
+        self.interpreter
+    }
+
+    /// The prompt to show before the next line: the continuation prompt while a form is
+    /// unfinished.
+    pub fn prompt(&self) -> &str {
+        match self.pending.is_empty() {
+            true => &self.prompt,
+            false => &self.continuation,
+        }
+    }
+
+    /// Runs the session until `:quit` or the end of `input`.
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> Result<(), String> {
+        if let Some(path) = &self.session_path {
+            self.session = Session::load(path)?;
+            self.restore(&mut output)?;
+        }
+
+        if let Some(banner) = &self.banner {
+            writeln!(output, "{}", banner).map_err(|err| err.to_string())?;
+        }
+
+        let mut lines = input.lines();
+
+        loop {
+            write!(output, "{}", self.prompt()).map_err(|err| err.to_string())?;
+            output.flush().map_err(|err| err.to_string())?;
+
+            let Some(line) = lines.next() else {
+                writeln!(output).map_err(|err| err.to_string())?;
+                break;
+            };
+
+            let line = line.map_err(|err| err.to_string())?;
+
+            if !self.feed(&line, &mut output)? {
+                break;
+            }
+        }
+
+        if let Some(path) = &self.session_path {
+            self.session.vars = self.saved_vars(&mut output)?;
+            self.session.save(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles one line of input, for hosts that read lines themselves, writing any answer
+    /// to `output`. A line that leaves a form unfinished is kept until the form is complete.
+    /// Returns false after `:quit`.
+    pub fn feed(&mut self, line: &str, output: &mut dyn Write) -> Result<bool, String> {
+        if self.pending.is_empty() {
+            match line.trim() {
+                "" => return Ok(true),
+                ":quit" | ":q" => return Ok(false),
+                ":history" => {
+                    for entry in &self.session.history {
+                        writeln!(output, "{}", entry).map_err(|err| err.to_string())?;
+                    }
+
+                    return Ok(true);
+                }
+                ":transcript" => {
+                    self.transcript = None;
+                    return Ok(true);
+                }
+                command if command.starts_with(":transcript ") => {
+                    let path = command[":transcript ".len()..].trim();
+
+                    match File::create(path) {
+                        Ok(file) => self.transcript = Some(file),
+                        Err(err) => writeln!(output, "error: unable to create {}: {}", path, err)
+                            .map_err(|err| err.to_string())?,
+                    }
+
+                    return Ok(true);
+                }
+                _ => {}
+            }
+        }
+
+        self.pending.push_str(line);
+        self.pending.push('\n');
+
+        let sexprs = match parser::Parser::new(&self.pending).parse() {
+            Ok(sexprs) => sexprs,
+            // Keep reading until the form is complete
+            Err(err) if err.is_incomplete() => return Ok(true),
+            Err(_) => vec![],
+        };
+
+        let source = std::mem::take(&mut self.pending);
+        let source = source.trim_end().to_string();
+
+        let response = match self.interpreter.eval_str(&source) {
+            Ok(Value::Void) => None,
+            Ok(value) => Some(value.to_string()),
+            Err(err) => {
+                self.interpreter.take_trace();
+
+                Some(format!("error[{}]: {}", err.code(), err))
+            }
+        };
+
+        if let Some(response) = &response {
+            writeln!(output, "{}", response).map_err(|err| err.to_string())?;
+        }
+
+        if let Some(file) = &mut self.transcript {
+            let mut entry = source.replace('\n', &format!("\n{}", CONTINUATION));
+            entry.insert_str(0, PROMPT);
+            entry.push('\n');
+
+            if let Some(response) = &response {
+                entry.push_str(response);
+                entry.push('\n');
+            }
+
+            if let Err(err) = file.write_all(entry.as_bytes()) {
+                writeln!(output, "error: unable to write the transcript: {}", err)
+                    .map_err(|err| err.to_string())?;
+                self.transcript = None;
+            }
+        }
+
+        let replayed = !sexprs.is_empty()
+            && sexprs.iter().all(|sexpr| {
+                matches!(sexpr, SExpr::List(list, _)
+                    if matches!(list.first(), Some(SExpr::Atom(name, _))
+                        if REPLAYED_FORMS.contains(&name.as_str())))
+            });
+
+        if replayed {
+            self.session.definitions.push(source.clone());
+        }
+
+        self.session.history.push(source);
+
+        Ok(true)
+    }
+
+    /// Replays the definitions of the restored session and binds its variables.
+    fn restore(&mut self, output: &mut impl Write) -> Result<(), String> {
+        for form in self.session.definitions.iter().chain(&self.session.vars) {
+            if let Err(err) = self.interpreter.eval_str(form) {
+                writeln!(output, "warning: unable to restore {}: {}", form, err)
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The global variables as `let` forms, in name order. Functions and builtins are left
+    /// out, since their definitions are replayed; other values without a source form are
+    /// skipped with a warning.
+    fn saved_vars(&self, output: &mut impl Write) -> Result<Vec<String>, String> {
+        let mut vars = self
+            .interpreter
+            .export_vars("")
+            .into_iter()
+            .collect::<Vec<_>>();
+        vars.sort_by(|(left, _), (right, _)| left.cmp(right));
+
+        let mut saved = vec![];
+
+        for (name, value) in vars {
+            if matches!(value, Value::Function(_) | Value::Native(_)) {
+                continue;
+            }
+
+            match has_source(&value) {
+                true => saved.push(format!("(let {} {})", name, value.repr())),
+                false => writeln!(output, "warning: unable to save {}: {}", name, value)
+                    .map_err(|err| err.to_string())?,
+            }
+        }
+
+        Ok(saved)
+    }
+}
+
+/// Whether `repr` writes `value` as source that evaluates back to it.
+fn has_source(value: &Value) -> bool {
+    match value {
+        Value::Float(fl) => fl.is_finite(),
+        Value::List(list) => list.iter().all(has_source),
+        Value::Map(map) => map.values().all(has_source),
+        Value::Variant(variant) => variant.fields.iter().all(has_source),
+        Value::Function(_) | Value::Native(_) | Value::Handle(_) | Value::Void => false,
+        _ => true,
+    }
+}
+
+/// The inputs of a `:transcript` that evaluated without an error, as a script.
+pub fn transcript_to_script(transcript: &str) -> String {
+    let mut script = String::new();
+    let mut input = None::<String>;
+    let mut failed = false;
+
+    for line in transcript.lines().chain([PROMPT]) {
+        if let Some(rest) = line.strip_prefix(CONTINUATION) {
+            if let Some(input) = &mut input {
+                input.push('\n');
+                input.push_str(rest);
+                continue;
+            }
+        }
+
+        let Some(rest) = line.strip_prefix(PROMPT) else {
+            failed |= line.starts_with("error[");
+            continue;
+        };
+
+        if let Some(input) = input.take().filter(|_| !failed) {
+            script.push_str(&input);
+            script.push('\n');
+        }
+
+        input = Some(rest.to_string());
+        failed = false;
+    }
+
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_session() {
+        let path = std::env::temp_dir().join(format!("kk-session-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let repl = |input: &str| {
+            let mut output = vec![];
+
+            let mut repl = Repl::new(Interpreter::new());
+            repl.set_session(&path);
+            repl.run(input.as_bytes(), &mut output).unwrap();
+
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            repl("(let a 40)\n(defn f (x)\n  (add x a))\n(f 2)\n(nope)\n"),
+            "kk> kk> ... <fn f>\nkk> 42\nkk> error[E0001]: Unknown function: nope\nkk> \n"
+        );
+        assert_eq!(
+            repl("(f 1)\n:history\n:quit\n"),
+            "kk> 41\nkk> (let a 40)\n(defn f (x)\n  (add x a))\n(f 2)\n(nope)\n(f 1)\nkk> "
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_session_values() {
+        let path = std::env::temp_dir().join(format!("kk-values-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let repl = |input: &str| {
+            let mut output = vec![];
+
+            let mut repl = Repl::new(Interpreter::new());
+            repl.set_session(&path);
+            repl.run(input.as_bytes(), &mut output).unwrap();
+
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            repl(
+                "(defenum Color Red (Rgb r g b))
+                 (let values (list #\\a :key (div 1 3) 0.1 99999999999999999999999 \"text\"))
+                 (let colors (list Red (Rgb 1 2 3)))
+                 (let nan (sqrt -1))
+                 (let fns (list (lambda (x) x)))"
+            ),
+            "kk> kk> kk> kk> kk> kk> \nwarning: unable to save fns: [<fn lambda>]\nwarning: unable to save nan: NaN\n"
+        );
+        assert_eq!(
+            repl("(map type-of values)\n(get values)\n(map (lambda (c) (Color? c)) colors)\n(get colors)\n"),
+            "kk> [char, keyword, rational, float, int, string]\nkk> [a, :key, 1/3, 0.1, 99999999999999999999999, text]\nkk> [true, true]\nkk> [Red, (Rgb 1 2 3)]\nkk> \n"
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_embedded_repl() {
+        let mut repl = Repl::new(Interpreter::new());
+        repl.set_prompt("> ");
+        repl.set_continuation("| ");
+
+        let mut output = vec![];
+
+        assert_eq!(repl.prompt(), "> ");
+        assert!(repl.feed("(let x", &mut output).unwrap());
+        assert_eq!(repl.prompt(), "| ");
+        assert!(repl.feed("  5)", &mut output).unwrap());
+        assert!(repl.feed("(add x 1)", &mut output).unwrap());
+        assert!(!repl.feed(":quit", &mut output).unwrap());
+
+        assert_eq!(String::from_utf8(output).unwrap(), "6\n");
+        assert_eq!(
+            repl.into_interpreter().get_var("x").unwrap().to_string(),
+            "5"
+        );
+    }
+
+    #[test]
+    fn test_transcript() {
+        let path = std::env::temp_dir().join(format!("kk-transcript-{}.txt", std::process::id()));
+
+        let input = format!(
+            "(let a 1)\n:transcript {}\n(let b 2)\n(defn f (x)\n  (add x b))\n(nope)\n:transcript\n(f 1)\n",
+            path.display()
+        );
+
+        Repl::new(Interpreter::new())
+            .run(input.as_bytes(), vec![])
+            .unwrap();
+
+        let transcript = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            transcript,
+            "kk> (let b 2)\nkk> (defn f (x)\n...   (add x b))\n<fn f>\nkk> (nope)\nerror[E0001]: Unknown function: nope\n"
+        );
+        assert_eq!(
+            transcript_to_script(&transcript),
+            "(let b 2)\n(defn f (x)\n  (add x b))\n"
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
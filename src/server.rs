@@ -0,0 +1,115 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::Interpreter;
+
+/// Serves `kk serve-eval`: every line received on the socket is evaluated by one long-lived
+/// interpreter and answered with a single line, `ok <value>` or `error <code> <message>`.
+/// Connections are handled one at a time and share the interpreter's globals.
+#[cfg(unix)]
+pub fn serve(interpreter: &mut Interpreter, socket: &Path) -> Result<(), String> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    // A socket left behind by a previous server would make bind fail
+    if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(socket)
+                .map_err(|err| format!("Unable to remove {}: {}", socket.display(), err))?;
+        }
+    }
+
+    let listener = UnixListener::bind(socket)
+        .map_err(|err| format!("Unable to listen on {}: {}", socket.display(), err))?;
+
+    eprintln!("kk: listening on {}", socket.display());
+
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|err| err.to_string())?;
+
+        let reader = match stream.try_clone() {
+            Ok(reader) => BufReader::new(reader),
+            Err(err) => {
+                eprintln!("kk: {}", err);
+                continue;
+            }
+        };
+
+        if let Err(err) = handle(interpreter, reader, stream) {
+            eprintln!("kk: connection closed: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve(_interpreter: &mut Interpreter, _socket: &Path) -> Result<(), String> {
+    Err("serve-eval requires Unix domain sockets".to_string())
+}
+
+/// Answers every line read from `reader` until it is closed.
+fn handle(
+    interpreter: &mut Interpreter,
+    reader: impl BufRead,
+    mut writer: impl Write,
+) -> std::io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match interpreter.eval_str(&line) {
+            Ok(value) => format!("ok {}", value),
+            Err(err) => {
+                interpreter.take_trace();
+
+                format!("error {} {}", err.code(), err)
+            }
+        };
+
+        writeln!(writer, "{}", escape(&response))?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Keeps a response on one line.
+fn escape(response: &str) -> String {
+    response
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_requests() {
+        let mut interpreter = Interpreter::new();
+        let mut output = vec![];
+
+        let input = "(let a 2)\n(add a 1)\n\n(get b)\n(format \"{}\\n\" a)\n(add 1\n";
+
+        handle(&mut interpreter, input.as_bytes(), &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>(),
+            [
+                "ok void",
+                "ok 3",
+                "error E0003 Variable not found: b",
+                "ok 2\\\\n",
+                "error E0007 Unexpected end of input: '(' at line 1, col 1 is never closed",
+            ]
+        );
+    }
+}
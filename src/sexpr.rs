@@ -1,5 +1,347 @@
-#[derive(Debug, Clone)]
+use std::fmt;
+
+use crate::symbol::Symbol;
+
+/// Where a node starts in its source: byte offset plus 1-based line and column.
+/// Nodes built outside the parser carry `Span::default()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, col {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum SExpr {
-    Atom(String),
-    List(Vec<SExpr>),
+    Atom(Symbol, Span),
+    /// A `:name` atom, stored without the leading colon.
+    Keyword(String, Span),
+    String(String, Span),
+    List(Vec<SExpr>, Span),
+}
+
+/// The special form a string literal with `${expr}` segments reads as, joining its text
+/// and the displayed values of its expressions.
+pub const INTERPOLATE: &str = "interpolate";
+
+/// Characters that end an atom in the source, so that no atom may contain them.
+const DELIMITERS: &[char] = &['(', ')', '"', ';', ' ', '\n', '\r', '\t'];
+
+impl SExpr {
+    /// An atom, such as a name or a number, for building programs in Rust. See also
+    /// `sexpr!`.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is empty, starts with `:` or contains a delimiter such as a parenthesis
+    /// or whitespace, as it would not read back as the same atom.
+    pub fn atom(name: &str) -> SExpr {
+        assert!(
+            !name.is_empty() && !name.starts_with(':') && !name.contains(DELIMITERS),
+            "{:?} cannot be written as an atom",
+            name
+        );
+
+        SExpr::Atom(Symbol::intern(name), Span::default())
+    }
+
+    /// A `:name` keyword, given without the colon.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is empty or contains a delimiter.
+    pub fn keyword(name: &str) -> SExpr {
+        assert!(
+            !name.is_empty() && !name.contains(DELIMITERS),
+            "{:?} cannot be written as a keyword",
+            name
+        );
+
+        SExpr::Keyword(name.to_string(), Span::default())
+    }
+
+    /// A string literal.
+    ///
+    /// # Panics
+    ///
+    /// If `string` contains `"`: kk strings have no escapes besides `$${` for `${`, so it
+    /// has no source form.
+    pub fn string(string: &str) -> SExpr {
+        assert!(
+            !string.contains('"'),
+            "{:?} cannot be written as a string literal",
+            string
+        );
+
+        SExpr::String(string.to_string(), Span::default())
+    }
+
+    pub fn list(items: impl IntoIterator<Item = SExpr>) -> SExpr {
+        SExpr::List(items.into_iter().collect(), Span::default())
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            SExpr::Atom(_, span)
+            | SExpr::Keyword(_, span)
+            | SExpr::String(_, span)
+            | SExpr::List(_, span) => *span,
+        }
+    }
+
+    /// The tree as indented text, one node per line with its kind and `line:column`, as
+    /// printed by `kk --dump-ast`.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(&mut out, 0);
+        out
+    }
+
+    fn dump_into(&self, out: &mut String, depth: usize) {
+        let span = self.span();
+        let indent = "  ".repeat(depth);
+
+        let node = match self {
+            SExpr::Atom(atom, _) => format!("Atom {}", atom),
+            SExpr::Keyword(keyword, _) => format!("Keyword :{}", keyword),
+            SExpr::String(string, _) => format!("String {:?}", string),
+            SExpr::List(..) => "List".to_string(),
+        };
+
+        out.push_str(&format!(
+            "{}{} {}:{}\n",
+            indent, node, span.line, span.column
+        ));
+
+        if let SExpr::List(list, _) = self {
+            for sexpr in list {
+                sexpr.dump_into(out, depth + 1);
+            }
+        }
+    }
+}
+
+impl fmt::Display for SExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SExpr::Atom(atom, _) => write!(f, "{}", atom),
+            SExpr::Keyword(keyword, _) => write!(f, ":{}", keyword),
+            SExpr::String(string, _) => write!(f, "\"{}\"", string.replace("${", "$${")),
+            SExpr::List(list, _) => {
+                if let Some(template) = template(list) {
+                    return f.write_str(&template);
+                }
+
+                write!(f, "(")?;
+
+                for (i, sexpr) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+
+                    write!(f, "{}", sexpr)?;
+                }
+
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// The string literal an `(interpolate ...)` form reads back from, if it has one.
+fn template(list: &[SExpr]) -> Option<String> {
+    let [SExpr::Atom(head, _), parts @ ..] = list else {
+        return None;
+    };
+
+    if head != INTERPOLATE || parts.iter().all(|part| matches!(part, SExpr::String(..))) {
+        return None;
+    }
+
+    let mut template = String::from('"');
+
+    for (i, part) in parts.iter().enumerate() {
+        match part {
+            SExpr::String(string, _) => {
+                // A `$` just before `${` would read as the escape `$${`
+                let before_expr = parts
+                    .get(i + 1)
+                    .is_some_and(|next| !matches!(next, SExpr::String(..)));
+
+                if string.ends_with('$') && before_expr {
+                    return None;
+                }
+
+                template.push_str(&string.replace("${", "$${"));
+            }
+            expr => {
+                let expr = expr.to_string();
+
+                if expr.contains(['"', '}']) {
+                    return None;
+                }
+
+                template.push_str(&format!("${{{}}}", expr));
+            }
+        }
+    }
+
+    template.push('"');
+
+    Some(template)
+}
+
+impl From<i64> for SExpr {
+    fn from(int: i64) -> SExpr {
+        SExpr::atom(&int.to_string())
+    }
+}
+
+impl From<f64> for SExpr {
+    fn from(float: f64) -> SExpr {
+        // Debug formatting keeps the `.0` that makes the atom read back as a float
+        SExpr::atom(&format!("{:?}", float))
+    }
+}
+
+impl From<bool> for SExpr {
+    fn from(bool: bool) -> SExpr {
+        SExpr::atom(if bool { "true" } else { "false" })
+    }
+}
+
+impl From<&str> for SExpr {
+    fn from(string: &str) -> SExpr {
+        SExpr::string(string)
+    }
+}
+
+impl From<String> for SExpr {
+    fn from(string: String) -> SExpr {
+        SExpr::string(&string)
+    }
+}
+
+impl From<Symbol> for SExpr {
+    fn from(symbol: Symbol) -> SExpr {
+        SExpr::Atom(symbol, Span::default())
+    }
+}
+
+impl From<Vec<SExpr>> for SExpr {
+    fn from(items: Vec<SExpr>) -> SExpr {
+        SExpr::list(items)
+    }
+}
+
+/// Builds a list form from kk-like syntax: names become atoms, Rust literals become
+/// numbers, strings and booleans, `:name` a keyword and `( ... )` a nested list. A Rust
+/// expression in braces is inserted with `SExpr::from`.
+///
+/// Rust doesn't keep the spacing between tokens, so `a-b` and `a - b` are both the atom
+/// `a-b`, and `error?` is read as one name.
+///
+/// ```
+/// use kk::sexpr;
+///
+/// let name = "kk";
+/// let form = sexpr![defn greet (who) (print :greeting "hi" who {name} (add 1 -2))];
+///
+/// assert_eq!(
+///     form.to_string(),
+///     "(defn greet (who) (print :greeting \"hi\" who \"kk\" (add 1 -2)))"
+/// );
+/// assert_eq!(
+///     sexpr![merge a b :on-conflict :last].to_string(),
+///     "(merge a b :on-conflict :last)"
+/// );
+/// ```
+#[macro_export]
+macro_rules! sexpr {
+    ($($item:tt)*) => {
+        $crate::sexpr::SExpr::list($crate::__sexpr_items!([] $($item)*))
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __sexpr_items {
+    ([$($done:expr,)*]) => {
+        vec![$($done),*]
+    };
+    // A name being read, which `-` and `?` may continue
+    ([$($done:expr,)*] @$kind:ident ($($name:tt)*) - $next:ident $($rest:tt)*) => {
+        $crate::__sexpr_items!([$($done,)*] @$kind ($($name)* "-", stringify!($next),) $($rest)*)
+    };
+    ([$($done:expr,)*] @$kind:ident ($($name:tt)*) ? $($rest:tt)*) => {
+        $crate::__sexpr_items!([$($done,)*] @$kind ($($name)* "?",) $($rest)*)
+    };
+    ([$($done:expr,)*] @$kind:ident ($($name:tt)*) $($rest:tt)*) => {
+        $crate::__sexpr_items!(
+            [$($done,)* $crate::sexpr::SExpr::$kind(concat!($($name)*)),] $($rest)*
+        )
+    };
+    ([$($done:expr,)*] : $keyword:ident $($rest:tt)*) => {
+        $crate::__sexpr_items!([$($done,)*] @keyword (stringify!($keyword),) $($rest)*)
+    };
+    ([$($done:expr,)*] $atom:ident $($rest:tt)*) => {
+        $crate::__sexpr_items!([$($done,)*] @atom (stringify!($atom),) $($rest)*)
+    };
+    ([$($done:expr,)*] ($($list:tt)*) $($rest:tt)*) => {
+        $crate::__sexpr_items!([$($done,)* $crate::sexpr!($($list)*),] $($rest)*)
+    };
+    ([$($done:expr,)*] {$value:expr} $($rest:tt)*) => {
+        $crate::__sexpr_items!([$($done,)* $crate::sexpr::SExpr::from($value),] $($rest)*)
+    };
+    ([$($done:expr,)*] - $literal:literal $($rest:tt)*) => {
+        $crate::__sexpr_items!([$($done,)* $crate::sexpr::SExpr::from(-$literal),] $($rest)*)
+    };
+    ([$($done:expr,)*] $literal:literal $($rest:tt)*) => {
+        $crate::__sexpr_items!([$($done,)* $crate::sexpr::SExpr::from($literal),] $($rest)*)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::Interpreter;
+
+    #[test]
+    fn test_dump() {
+        let sexprs = Parser::new("(print :a\n  (add 1 \"x\"))").parse().unwrap();
+
+        assert_eq!(
+            sexprs[0].dump(),
+            "List 1:1\n  Atom print 1:2\n  Keyword :a 1:8\n  List 2:3\n    Atom add 2:4\n    Atom 1 2:8\n    String \"x\" 2:10\n"
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let body = SExpr::list([SExpr::atom("add"), SExpr::atom("n"), 1.into()]);
+        let form = sexpr![defn add-one (n) {body}];
+
+        assert_eq!(form.to_string(), "(defn add-one (n) (add n 1))");
+
+        let call = sexpr![list (error? x) (41) 1.5 true "x" :k {SExpr::atom("a")}];
+        assert_eq!(
+            call.to_string(),
+            "(list (error? x) (41) 1.5 true \"x\" :k a)"
+        );
+
+        // What Display writes parses back to the same tree
+        let parsed = Parser::new(&call.to_string()).parse().unwrap();
+        assert_eq!(parsed[0].to_string(), call.to_string());
+
+        let mut interpreter = Interpreter::new();
+        let result = interpreter.eval_forms(&[form, sexpr![add-one 41]]).unwrap();
+        assert_eq!(result.to_string(), "42");
+    }
 }
@@ -0,0 +1,171 @@
+//! Builtins implemented as native functions.
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::value::Value;
+use crate::Interpreter;
+
+mod bytes;
+mod diff;
+mod env;
+mod format;
+mod http;
+mod io;
+mod json;
+mod list;
+mod map;
+mod math;
+mod net;
+mod parse;
+mod process;
+mod random;
+mod regex;
+mod result;
+mod string;
+mod task;
+mod thread;
+mod types;
+
+pub(crate) use env::expand as expand_env;
+pub(crate) use format::format_string;
+pub(crate) use task::async_task;
+pub(crate) use thread::spawn;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    interpreter.register_native("print", |interpreter, args| {
+        for value in args {
+            interpreter.write_output(&format!("{}\n", value));
+        }
+
+        Ok(Value::Void)
+    });
+
+    // syntax: (prin <value>...), on one line separated by spaces, without a newline
+    interpreter.register_native("prin", |interpreter, args| {
+        interpreter.write_output(&joined(args));
+        interpreter.flush_output();
+
+        Ok(Value::Void)
+    });
+
+    // syntax: (printf <format> <value>...), the formatted string and a newline
+    interpreter.register_native("printf", |interpreter, args| match args {
+        [Value::String(format), args @ ..] => {
+            interpreter.write_output(&format!("{}\n", format_string(format, args)?));
+            Ok(Value::Void)
+        }
+        [value, ..] => Err(RuntimeError::type_mismatch("string", value)),
+        [] => Err(arity("printf", "at least 1", 0)),
+    });
+
+    // syntax: (eprint <value>...), like prin but to stderr
+    interpreter.register_native("eprint", |interpreter, args| {
+        interpreter.write_error_output(&joined(args));
+        Ok(Value::Void)
+    });
+
+    // syntax: (eprintln <value>...), like print but to stderr
+    interpreter.register_native("eprintln", |interpreter, args| {
+        for value in args {
+            interpreter.write_error_output(&format!("{}\n", value));
+        }
+
+        Ok(Value::Void)
+    });
+
+    format::register(interpreter);
+    env::register(interpreter);
+    // syntax: (assert <condition> [message])
+    interpreter.register_fn("assert", |args| match args {
+        [Value::Bool(true)] | [Value::Bool(true), _] => Ok(Value::Void),
+        [Value::Bool(false)] => Err(assertion_failed("Assertion failed".to_string())),
+        [Value::Bool(false), message] => {
+            Err(assertion_failed(format!("Assertion failed: {}", message)))
+        }
+        [value] | [value, _] => Err(RuntimeError::type_mismatch("bool", value)),
+        _ => Err(arity("assert", "1 or 2", args.len())),
+    });
+
+    // syntax: (assert-eq <actual> <expected>)
+    interpreter.register_native("assert-eq", |interpreter, args| match args {
+        [actual, expected] => match interpreter.values_equal(actual, expected)? {
+            true => Ok(Value::Void),
+            false => Err(assertion_failed(format!(
+                "Assertion failed: expected {}, got {}",
+                expected, actual
+            ))),
+        },
+        _ => Err(arity("assert-eq", "2", args.len())),
+    });
+
+    bytes::register(interpreter);
+    diff::register(interpreter);
+    http::register(interpreter);
+    io::register(interpreter);
+    json::register(interpreter);
+    list::register(interpreter);
+    map::register(interpreter);
+    math::register(interpreter);
+    net::register(interpreter);
+    parse::register(interpreter);
+    process::register(interpreter);
+    random::register(interpreter);
+    regex::register(interpreter);
+    string::register(interpreter);
+    task::register(interpreter);
+    thread::register(interpreter);
+    types::register(interpreter);
+    result::register(interpreter);
+}
+
+/// The values as `print` displays them, separated by spaces.
+fn joined(values: &[Value]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn assertion_failed(message: String) -> RuntimeError {
+    RuntimeError::with_code(ErrorCode::AssertionFailed, message)
+}
+
+fn arity(function: &str, expected: &str, found: usize) -> RuntimeError {
+    RuntimeError::Arity {
+        function: function.to_string(),
+        expected: expected.to_string(),
+        found,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn test_print_variants() {
+        let stdout = Rc::new(RefCell::new(String::new()));
+        let stderr = Rc::new(RefCell::new(String::new()));
+        let (out, err) = (stdout.clone(), stderr.clone());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(move |text| out.borrow_mut().push_str(text));
+        interpreter.set_error_output(move |text| err.borrow_mut().push_str(text));
+
+        interpreter
+            .eval_str(
+                "(prin \"loading\" 1) (prin \".\") (print \"\") \
+                 (printf \"{:>4}|{:<3}|\" 7 :a) \
+                 (eprint \"warn:\" 2) (eprintln \"\" \"done\")",
+            )
+            .unwrap();
+
+        assert_eq!(*stdout.borrow(), "loading 1.\n   7|:a |\n");
+        assert_eq!(*stderr.borrow(), "warn: 2\ndone\n");
+        assert!(interpreter.eval_str("(printf 1)").is_err());
+    }
+}
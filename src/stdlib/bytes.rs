@@ -0,0 +1,218 @@
+//! Bytes builtins, for binary files and network protocols. Bytes are copied on write like
+//! lists, so `bytes-set` returns a new value and leaves the one it was given unchanged.
+//! Text converts to and from bytes with `encode` and `decode`, in UTF-8 unless another
+//! encoding is named.
+
+use std::rc::Rc;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+/// The encodings `encode` and `decode` accept.
+const ENCODINGS: &[&str] = &["utf-8", "latin-1", "ascii"];
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    for name in ["bytes-slice", "encode", "decode"] {
+        interpreter.set_fuel_category(name, FuelCategory::String);
+    }
+
+    // syntax: (bytes <int>...), each from 0 to 255
+    interpreter.register_fn("bytes", |args| {
+        let bytes = args.iter().map(byte).collect::<Result<Vec<u8>, _>>()?;
+        Ok(Value::Bytes(Rc::new(bytes)))
+    });
+
+    // syntax: (bytes-length <bytes>)
+    interpreter.register_fn("bytes-length", |args| match args {
+        [Value::Bytes(bytes)] => Ok(Value::Int(bytes.len() as i64)),
+        [value] => Err(RuntimeError::type_mismatch("bytes", value)),
+        _ => Err(arity("bytes-length", "1", args.len())),
+    });
+
+    // syntax: (bytes-get <bytes> <index>), the byte as an int, or null past the end
+    interpreter.register_fn("bytes-get", |args| match args {
+        [Value::Bytes(bytes), Value::Int(index)] => Ok(usize::try_from(*index)
+            .ok()
+            .and_then(|index| bytes.get(index))
+            .map_or(Value::Null, |byte| Value::Int(*byte as i64))),
+        [Value::Bytes(_), value] => Err(RuntimeError::type_mismatch("int", value)),
+        [value, _] => Err(RuntimeError::type_mismatch("bytes", value)),
+        _ => Err(arity("bytes-get", "2", args.len())),
+    });
+
+    // syntax: (bytes-set <bytes> <index> <byte>), a copy with the byte at index replaced
+    interpreter.register_fn("bytes-set", |args| match args {
+        [Value::Bytes(bytes), Value::Int(index), value] => {
+            let index = position(*index, bytes.len())?;
+            let mut bytes = bytes.clone();
+
+            Rc::make_mut(&mut bytes)[index] = byte(value)?;
+            Ok(Value::Bytes(bytes))
+        }
+        [Value::Bytes(_), value, _] => Err(RuntimeError::type_mismatch("int", value)),
+        [value, _, _] => Err(RuntimeError::type_mismatch("bytes", value)),
+        _ => Err(arity("bytes-set", "3", args.len())),
+    });
+
+    // syntax: (bytes-slice <bytes> <start> [end]), the bytes from start up to end
+    interpreter.register_fn("bytes-slice", |args| {
+        let (bytes, start, end) = match args {
+            [Value::Bytes(bytes), Value::Int(start)] => (bytes, *start, bytes.len() as i64),
+            [Value::Bytes(bytes), Value::Int(start), Value::Int(end)] => (bytes, *start, *end),
+            [Value::Bytes(_), Value::Int(_), value] | [Value::Bytes(_), value, ..]
+                if args.len() <= 3 =>
+            {
+                return Err(RuntimeError::type_mismatch("int", value));
+            }
+            [value, _] | [value, _, _] => return Err(RuntimeError::type_mismatch("bytes", value)),
+            _ => return Err(arity("bytes-slice", "2 or 3", args.len())),
+        };
+
+        let end = position(end, bytes.len() + 1)?;
+        let start = position(start, end + 1)?;
+
+        Ok(Value::Bytes(Rc::new(bytes[start..end].to_vec())))
+    });
+
+    // syntax: (encode <string> [encoding]), the bytes of the string
+    interpreter.register_fn("encode", |args| {
+        let (string, encoding) = match args {
+            [Value::String(string)] => (string, "utf-8"),
+            [Value::String(string), Value::String(encoding)] => (string, &**encoding),
+            [Value::String(_), value] | [value, ..] if args.len() <= 2 => {
+                return Err(RuntimeError::type_mismatch("string", value));
+            }
+            _ => return Err(arity("encode", "1 or 2", args.len())),
+        };
+
+        let bytes = match encoding {
+            "utf-8" => string.as_bytes().to_vec(),
+            encoding => {
+                let max = single_byte_max(encoding)?;
+
+                string
+                    .chars()
+                    .map(|char| match u8::try_from(char) {
+                        Ok(byte) if byte <= max => Ok(byte),
+                        _ => Err(invalid(format!("{} can't encode {:?}", encoding, char))),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+        };
+
+        Ok(Value::Bytes(Rc::new(bytes)))
+    });
+
+    // syntax: (decode <bytes> [encoding]), the string the bytes encode
+    interpreter.register_fn("decode", |args| {
+        let (bytes, encoding) = match args {
+            [Value::Bytes(bytes)] => (bytes, "utf-8"),
+            [Value::Bytes(bytes), Value::String(encoding)] => (bytes, &**encoding),
+            [Value::Bytes(_), value] => return Err(RuntimeError::type_mismatch("string", value)),
+            [value, ..] if args.len() <= 2 => {
+                return Err(RuntimeError::type_mismatch("bytes", value));
+            }
+            _ => return Err(arity("decode", "1 or 2", args.len())),
+        };
+
+        let string = match encoding {
+            "utf-8" => std::str::from_utf8(bytes)
+                .map_err(|err| invalid(format!("Invalid UTF-8: {}", err)))?
+                .to_string(),
+            encoding => {
+                let max = single_byte_max(encoding)?;
+
+                bytes
+                    .iter()
+                    .map(|byte| match *byte <= max {
+                        true => Ok(*byte as char),
+                        false => Err(invalid(format!("Invalid {} byte: {}", encoding, byte))),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+        };
+
+        Ok(Value::String(string.into()))
+    });
+}
+
+/// The value as a byte, failing unless it is an int from 0 to 255.
+fn byte(value: &Value) -> Result<u8, RuntimeError> {
+    match value {
+        Value::Int(int) => {
+            u8::try_from(*int).map_err(|_| invalid(format!("{} is not a byte, from 0 to 255", int)))
+        }
+        value => Err(RuntimeError::type_mismatch("int", value)),
+    }
+}
+
+/// The index as a position below `len`.
+fn position(index: i64, len: usize) -> Result<usize, RuntimeError> {
+    match usize::try_from(index) {
+        Ok(index) if index < len => Ok(index),
+        _ => Err(invalid(format!("Index {} is out of range", index))),
+    }
+}
+
+/// The largest byte of an encoding with one byte per char, whose bytes are the chars' code
+/// points.
+fn single_byte_max(encoding: &str) -> Result<u8, RuntimeError> {
+    match encoding {
+        "latin-1" => Ok(u8::MAX),
+        "ascii" => Ok(0x7f),
+        encoding => Err(invalid(format!(
+            "Unknown encoding {:?}, expected one of {}",
+            encoding,
+            ENCODINGS.join(", ")
+        ))),
+    }
+}
+
+fn invalid(message: String) -> RuntimeError {
+    RuntimeError::with_code(ErrorCode::InvalidArgument, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(
+            eval("(let b (encode \"héj\")) (list b)"),
+            "[<bytes 68 c3 a9 6a>]"
+        );
+        assert_eq!(
+            eval("(list (bytes-length b) (bytes-get b 1) (bytes-get b 4) (decode b))"),
+            "[4, 195, null, héj]"
+        );
+        assert_eq!(
+            eval("(list (bytes-set b 0 72) b (bytes-slice b 1 3) (bytes-slice b 4))"),
+            "[<bytes 48 c3 a9 6a>, <bytes 68 c3 a9 6a>, <bytes c3 a9>, <bytes>]"
+        );
+        assert_eq!(
+            eval("(list (repr (bytes 0 255)) (decode (encode \"é\" \"latin-1\") \"latin-1\"))"),
+            "[(bytes 0 255), é]"
+        );
+        assert_eq!(
+            eval("(list (eq (bytes 1 2) (bytes 1 2)) (compare (bytes 1) (bytes 1 0)))"),
+            "[true, -1]"
+        );
+
+        assert!(interpreter.eval_str("(bytes 256)").is_err());
+        assert!(interpreter.eval_str("(bytes-set b 4 0)").is_err());
+        assert!(interpreter.eval_str("(bytes-slice b 3 2)").is_err());
+        assert!(interpreter.eval_str("(decode (bytes 255))").is_err());
+        assert!(interpreter.eval_str("(encode \"é\" \"ascii\")").is_err());
+        assert!(interpreter.eval_str("(encode \"a\" \"utf-16\")").is_err());
+    }
+}
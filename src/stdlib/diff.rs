@@ -0,0 +1,193 @@
+//! `assert-eq-detailed`, which reports where two values differ instead of printing both.
+//! Lists are compared index by index and maps key by key, so a failure in a large nested
+//! value shows only the paths that differ, as lines marked `-` for the expected value and
+//! `+` for the actual one. `kk test` colors them on a terminal.
+
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::{arity, assertion_failed};
+
+/// Differences listed before the rest are only counted.
+const MAX_DIFFERENCES: usize = 20;
+
+/// Where two values differ: the path to the place, and what each side has there, `None`
+/// when the side has nothing, like a list that is too short.
+struct Difference {
+    path: String,
+    expected: Option<String>,
+    actual: Option<String>,
+}
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    // syntax: (assert-eq-detailed <actual> <expected>)
+    interpreter.register_native("assert-eq-detailed", |interpreter, args| match args {
+        [actual, expected] => {
+            let mut differences = vec![];
+            diff(interpreter, "", actual, expected, &mut differences);
+
+            match differences.is_empty() {
+                true => Ok(Value::Void),
+                false => Err(assertion_failed(report(&differences))),
+            }
+        }
+        _ => Err(arity("assert-eq-detailed", "2", args.len())),
+    });
+}
+
+fn diff(
+    interpreter: &Interpreter,
+    path: &str,
+    actual: &Value,
+    expected: &Value,
+    differences: &mut Vec<Difference>,
+) {
+    match (actual, expected) {
+        (Value::List(actual), Value::List(expected)) => {
+            for i in 0..actual.len().max(expected.len()) {
+                let path = format!("{}[{}]", path, i);
+                side_by_side(
+                    interpreter,
+                    path,
+                    actual.get(i),
+                    expected.get(i),
+                    differences,
+                );
+            }
+        }
+        (Value::Map(actual), Value::Map(expected)) => {
+            let mut keys = actual.keys().chain(expected.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+
+            for key in keys {
+                let path = match key
+                    .chars()
+                    .all(|char| char.is_alphanumeric() || char == '-')
+                {
+                    true => format!("{}.{}", path, key),
+                    false => format!("{}[\"{}\"]", path, key),
+                };
+
+                side_by_side(
+                    interpreter,
+                    path,
+                    actual.get(key),
+                    expected.get(key),
+                    differences,
+                );
+            }
+        }
+        (Value::Variant(actual), Value::Variant(expected))
+            if actual.enum_name == expected.enum_name && actual.name == expected.name =>
+        {
+            for (i, (actual, expected)) in actual.fields.iter().zip(&expected.fields).enumerate() {
+                let path = format!("{}[{}]", path, i);
+                diff(interpreter, &path, actual, expected, differences);
+            }
+        }
+        // Values of different types differ rather than fail, whatever the eq mode
+        _ if interpreter.values_equal(actual, expected).unwrap_or(false) => {}
+        _ => differences.push(Difference {
+            path: path.to_string(),
+            expected: Some(expected.repr()),
+            actual: Some(actual.repr()),
+        }),
+    }
+}
+
+fn side_by_side(
+    interpreter: &Interpreter,
+    path: String,
+    actual: Option<&Value>,
+    expected: Option<&Value>,
+    differences: &mut Vec<Difference>,
+) {
+    match (actual, expected) {
+        (Some(actual), Some(expected)) => diff(interpreter, &path, actual, expected, differences),
+        (actual, expected) => differences.push(Difference {
+            path,
+            expected: expected.map(Value::repr),
+            actual: actual.map(Value::repr),
+        }),
+    }
+}
+
+fn report(differences: &[Difference]) -> String {
+    let mut report = format!(
+        "Assertion failed: {} difference{} (- expected, + actual)",
+        differences.len(),
+        if differences.len() == 1 { "" } else { "s" }
+    );
+
+    for difference in differences.iter().take(MAX_DIFFERENCES) {
+        let path = match difference.path.as_str() {
+            "" => "the value",
+            path => path,
+        };
+
+        report.push_str(&format!("\n  at {}:", path));
+
+        match &difference.expected {
+            Some(expected) => report.push_str(&format!("\n    - {}", expected)),
+            None => report.push_str("\n    - (missing)"),
+        }
+
+        match &difference.actual {
+            Some(actual) => report.push_str(&format!("\n    + {}", actual)),
+            None => report.push_str("\n    + (missing)"),
+        }
+    }
+
+    if differences.len() > MAX_DIFFERENCES {
+        report.push_str(&format!(
+            "\n  ... {} more",
+            differences.len() - MAX_DIFFERENCES
+        ));
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assert_eq_detailed() {
+        let mut interpreter = Interpreter::new();
+
+        interpreter
+            .eval_str("(assert-eq-detailed (list 1 (dict \"a\" 2)) (list 1.0 (dict \"a\" 2)))")
+            .unwrap();
+
+        let err = interpreter
+            .eval_str(
+                "(assert-eq-detailed
+                   (dict \"users\" (list (dict \"name\" \"al\" \"age\" 30)) \"tags\" (list :a :b))
+                   (dict \"users\" (list (dict \"name\" \"bo\" \"age\" 30)) \"tags\" (list :a) \"x y\" 1))",
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Assertion failed: 3 differences (- expected, + actual)\n  \
+               at .tags[1]:\n    \
+                 - (missing)\n    \
+                 + :b\n  \
+               at .users[0].name:\n    \
+                 - \"bo\"\n    \
+                 + \"al\"\n  \
+               at [\"x y\"]:\n    \
+                 - 1\n    \
+                 + (missing)"
+        );
+
+        let err = interpreter
+            .eval_str("(assert-eq-detailed 1 \"1\")")
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("at the value:\n    - \"1\"\n    + 1"));
+    }
+}
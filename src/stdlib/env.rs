@@ -0,0 +1,151 @@
+//! Environment variable builtins.
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    // syntax: (getenv <name>), null when unset or not valid unicode
+    interpreter.register_fn("getenv", |args| match args {
+        [Value::String(name)] => Ok(match std::env::var(&**name) {
+            Ok(value) => Value::String(value.into()),
+            Err(_) => Value::Null,
+        }),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("getenv", "1", args.len())),
+    });
+
+    // syntax: (setenv <name> <value>), also seen by processes the script starts
+    interpreter.register_fn("setenv", |args| match args {
+        [Value::String(name), Value::String(value)] => {
+            if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+                return Err(RuntimeError::new(format!(
+                    "Invalid environment variable: {:?}",
+                    name
+                )));
+            }
+
+            std::env::set_var(&**name, &**value);
+
+            Ok(Value::Void)
+        }
+        [Value::String(_), value] | [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("setenv", "2", args.len())),
+    });
+
+    // syntax: (expand-env <string>), replacing $VAR, ${VAR} and ${VAR:-fallback}
+    interpreter.register_fn("expand-env", |args| match args {
+        [Value::String(text)] => Ok(Value::String((expand(text)?).into())),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("expand-env", "1", args.len())),
+    });
+}
+
+/// Replaces `$VAR`, `${VAR}` and `${VAR:-fallback}` in `text` with environment variables.
+/// `$$` is a literal `$`, as is a `$` not followed by a name. Also used on every string
+/// literal when `Interpreter::set_expand_env` is on. In a literal, `${VAR}` is written
+/// `$${VAR}`, as `${` starts an interpolation there.
+pub(crate) fn expand(text: &str) -> Result<String, RuntimeError> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+        } else if let Some(braced) = rest.strip_prefix('{') {
+            let Some(end) = braced.find('}') else {
+                return Err(RuntimeError::with_code(
+                    ErrorCode::InvalidArgument,
+                    format!("Unclosed ${{ in {:?}", text),
+                ));
+            };
+
+            let (name, fallback) = match braced[..end].split_once(":-") {
+                Some((name, fallback)) => (name, Some(fallback)),
+                None => (&braced[..end], None),
+            };
+
+            out.push_str(&lookup(name, fallback)?);
+            rest = &braced[end + 1..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+
+            if end == 0 {
+                out.push('$');
+            } else {
+                out.push_str(&lookup(&rest[..end], None)?);
+            }
+
+            rest = &rest[end..];
+        }
+    }
+
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// The value of `name`, or `fallback` when it is unset or empty.
+fn lookup(name: &str, fallback: Option<&str>) -> Result<String, RuntimeError> {
+    match (std::env::var(name), fallback) {
+        (Ok(value), Some(fallback)) if value.is_empty() => Ok(fallback.to_string()),
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(fallback)) => Ok(fallback.to_string()),
+        (Err(_), None) => Err(RuntimeError::with_code(
+            ErrorCode::UndefinedVariable,
+            format!("Environment variable {} is not set", name),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        let name = format!("KK_ENV_TEST_{}", std::process::id());
+        interpreter.set_var("name", Value::String(name.as_str().into()));
+
+        let result = interpreter
+            .eval_str("(let before (getenv name)) (setenv name \"1\") (list before (getenv name))")
+            .unwrap();
+
+        assert_eq!(result.to_string(), "[null, 1]");
+        assert_eq!(std::env::var(&name).unwrap(), "1");
+        assert!(interpreter.eval_str("(setenv \"A=B\" \"1\")").is_err());
+
+        let result = interpreter
+            .eval_str(&format!(
+                "(expand-env \"$${{{0}}}/$${0} $${{KK_UNSET_VAR:-none}} $ $$\")",
+                name
+            ))
+            .unwrap();
+
+        assert_eq!(result.to_string(), format!("1/${} none $ $", name));
+        assert!(interpreter
+            .eval_str("(expand-env \"$KK_UNSET_VAR\")")
+            .is_err());
+
+        interpreter.set_expand_env(true);
+        let result = interpreter
+            .eval_str(&format!(
+                "(count i from 0 to 1 ((set path \"${}/data\"))) (get path)",
+                name
+            ))
+            .unwrap();
+
+        assert_eq!(result.to_string(), "1/data");
+    }
+}
@@ -0,0 +1,330 @@
+//! The `format` builtin and its placeholders. `{}` is replaced by the next argument as
+//! `print` displays it, and `{:spec}` formats it with a spec like Rust's:
+//! `[[fill]align][+][0][width][.precision][type]`. Align is `<`, `^` or `>`, numbers go
+//! right by default and everything else left. Precision is the digits after the point of
+//! a number, or the most characters of anything else; width and precision go up to 65535.
+//! The type is `x`, `X`, `o` or `b` for an int in another base, or `e` for a number in
+//! scientific notation. `{1}` and `{1:>4}` pick an argument by index, and `{{` and `}}`
+//! are literal braces.
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+/// The largest width or precision a placeholder can ask for, so a typo can't make the
+/// interpreter allocate more memory than it has.
+const MAX_WIDTH: usize = 65_535;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// The part of a placeholder after `:`.
+#[derive(Debug, PartialEq)]
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    plus: bool,
+    /// Pads numbers with zeros after their sign, rather than with `fill`.
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    // syntax: (format <string> <value>...), see the module docs for the placeholders
+    interpreter.register_fn("format", |args| match args {
+        [Value::String(format), args @ ..] => {
+            Ok(Value::String(format_string(format, args)?.into()))
+        }
+        [value, ..] => Err(RuntimeError::type_mismatch("string", value)),
+        [] => Err(arity("format", "at least 1", 0)),
+    });
+}
+
+/// Fills the placeholders in `format` with `args`, as the `format` builtin does.
+/// Placeholders past the last argument are left empty.
+pub(crate) fn format_string(format: &str, args: &[Value]) -> Result<String, RuntimeError> {
+    let mut out = String::with_capacity(format.len());
+    let mut chars = format.chars();
+    let mut next = 0;
+
+    while let Some(char) = chars.next() {
+        match char {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+
+                let Some(end) = rest.find('}') else {
+                    return Err(invalid(format!(
+                        "Unclosed {{ in format string {:?}",
+                        format
+                    )));
+                };
+
+                let placeholder = &rest[..end];
+                chars = rest[end + 1..].chars();
+
+                let (index, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+
+                let index = match index {
+                    "" => {
+                        next += 1;
+                        next - 1
+                    }
+                    index => index
+                        .parse::<usize>()
+                        .map_err(|_| invalid(format!("Invalid placeholder {{{}}}", placeholder)))?,
+                };
+
+                let spec = Spec::parse(spec)
+                    .ok_or_else(|| invalid(format!("Invalid placeholder {{{}}}", placeholder)))?;
+
+                if spec.width.max(spec.precision.unwrap_or(0)) > MAX_WIDTH {
+                    return Err(invalid(format!(
+                        "The width and precision in {{{}}} can be at most {}",
+                        placeholder, MAX_WIDTH
+                    )));
+                }
+
+                if let Some(value) = args.get(index) {
+                    out.push_str(&spec.apply(value)?);
+                }
+            }
+            char => out.push(char),
+        }
+    }
+
+    Ok(out)
+}
+
+impl Spec {
+    fn parse(spec: &str) -> Option<Spec> {
+        let align = |char: Option<char>| match char {
+            Some('<') => Some(Align::Left),
+            Some('^') => Some(Align::Center),
+            Some('>') => Some(Align::Right),
+            _ => None,
+        };
+
+        let mut chars = spec.chars().peekable();
+        let mut fill = ' ';
+
+        let align = match (align(spec.chars().next()), align(spec.chars().nth(1))) {
+            (_, Some(align)) => {
+                fill = chars.next()?;
+                chars.next();
+                Some(align)
+            }
+            (Some(align), None) => {
+                chars.next();
+                Some(align)
+            }
+            (None, None) => None,
+        };
+
+        let plus = chars.next_if_eq(&'+').is_some();
+        let zero = chars.next_if_eq(&'0').is_some();
+
+        let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let mut digits = String::new();
+
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+
+            digits.parse::<usize>().ok()
+        };
+
+        let width = number(&mut chars).unwrap_or(0);
+
+        let precision = match chars.next_if_eq(&'.') {
+            Some(_) => Some(number(&mut chars)?),
+            None => None,
+        };
+
+        let kind = chars.next_if(|char| matches!(char, 'x' | 'X' | 'o' | 'b' | 'e'));
+
+        match chars.next() {
+            Some(_) => None,
+            None => Some(Spec {
+                fill,
+                align,
+                plus,
+                zero,
+                width,
+                precision,
+                kind,
+            }),
+        }
+    }
+
+    fn apply(&self, value: &Value) -> Result<String, RuntimeError> {
+        let number = value.as_f64();
+
+        let mut body = match (self.kind, value, number) {
+            (Some(kind @ ('x' | 'X' | 'o' | 'b')), Value::Int(int), _) => {
+                let magnitude = int.unsigned_abs();
+
+                let digits = match kind {
+                    'x' => format!("{:x}", magnitude),
+                    'X' => format!("{:X}", magnitude),
+                    'o' => format!("{:o}", magnitude),
+                    _ => format!("{:b}", magnitude),
+                };
+
+                match *int < 0 {
+                    true => format!("-{}", digits),
+                    false => digits,
+                }
+            }
+            (Some(kind @ ('x' | 'X' | 'o' | 'b')), Value::BigInt(big), _) => {
+                let digits = match kind {
+                    'x' => big.magnitude_radix(16),
+                    'X' => big.magnitude_radix(16).to_uppercase(),
+                    'o' => big.magnitude_radix(8),
+                    _ => big.magnitude_radix(2),
+                };
+
+                match big.is_negative() {
+                    true => format!("-{}", digits),
+                    false => digits,
+                }
+            }
+            (Some('x' | 'X' | 'o' | 'b'), value, _) => {
+                return Err(RuntimeError::type_mismatch("int", value));
+            }
+            (Some(_), _, Some(number)) => match self.precision {
+                Some(precision) => format!("{:.*e}", precision, number),
+                None => format!("{:e}", number),
+            },
+            (Some(_), value, None) => return Err(RuntimeError::type_mismatch("number", value)),
+            // Exact, where going through a float would round large ints
+            (None, Value::Int(_) | Value::BigInt(_), _) => match self.precision {
+                Some(precision) if precision > 0 => {
+                    format!("{}.{}", value, "0".repeat(precision))
+                }
+                _ => value.to_string(),
+            },
+            (None, _, Some(number)) => match self.precision {
+                Some(precision) => format!("{:.*}", precision, number),
+                None => value.to_string(),
+            },
+            (None, value, None) => match self.precision {
+                Some(precision) => value.to_string().chars().take(precision).collect(),
+                None => value.to_string(),
+            },
+        };
+
+        if self.plus && number.is_some() && !body.starts_with('-') {
+            body.insert(0, '+');
+        }
+
+        let padding = self.width.saturating_sub(body.chars().count());
+
+        if self.zero && number.is_some() && self.align.is_none() {
+            let sign = match body.starts_with(['+', '-']) {
+                true => 1,
+                false => 0,
+            };
+
+            body.insert_str(sign, &"0".repeat(padding));
+            return Ok(body);
+        }
+
+        let align = match (self.align, number) {
+            (Some(align), _) => align,
+            (None, Some(_)) => Align::Right,
+            (None, None) => Align::Left,
+        };
+
+        let (before, after) = match align {
+            Align::Left => (0, padding),
+            Align::Center => (padding / 2, padding - padding / 2),
+            Align::Right => (padding, 0),
+        };
+
+        let fill = |count: usize| self.fill.to_string().repeat(count);
+
+        Ok(format!("{}{}{}", fill(before), body, fill(after)))
+    }
+}
+
+fn invalid(message: String) -> RuntimeError {
+    RuntimeError::with_code(ErrorCode::InvalidArgument, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_specifiers() {
+        let mut interpreter = Interpreter::new();
+
+        let mut format = |source: &str| {
+            let source = format!("(format {})", source);
+            interpreter.eval_str(&source).unwrap().to_string()
+        };
+
+        assert_eq!(format("\"{} + {} {{}}\" 1 :a"), "1 + :a {}");
+        assert_eq!(format("\"{} {}\" 1"), "1 ");
+        assert_eq!(format("\"{1}-{0}-{}\" :a :b"), ":b-:a-:a");
+        assert_eq!(
+            format("\"[{:08.2}] [{:+.1}]\" 3.14159 -2"),
+            "[00003.14] [-2.0]"
+        );
+        assert_eq!(
+            format("\"[{:>6}] [{:<4}] [{:*^7}]\" 42 \"ab\" \"mid\""),
+            "[    42] [ab  ] [**mid**]"
+        );
+        assert_eq!(
+            format("\"[{:6}] [{:6}]\" 1.5 \"left\""),
+            "[   1.5] [left  ]"
+        );
+        assert_eq!(
+            format("\"{:x} {:X} {:#>4o} {:08b} {:x}\" 255 255 8 5 -16"),
+            "ff FF ##10 00000101 -10"
+        );
+        assert_eq!(
+            format("\"{:.2e} {:+05} {:.3}\" 1234.5 7 \"truncated\""),
+            "1.23e3 +0007 tru"
+        );
+        assert_eq!(format("\"{:.3}\" (div 1 3)"), "0.333");
+        assert_eq!(
+            format("\"{:x} {:X} {:o}\" 99999999999999999999 -99999999999999999999 18446744073709551616"),
+            "56bc75e2d630fffff -56BC75E2D630FFFFF 2000000000000000000000"
+        );
+        assert_eq!(
+            format("\"{:.1} {:b}\" 99999999999999999999 18446744073709551616"),
+            format!("99999999999999999999.0 1{}", "0".repeat(64))
+        );
+
+        assert!(interpreter.eval_str("(format \"{:x}\" 1.5)").is_err());
+        assert!(interpreter.eval_str("(format \"{:q}\" 1)").is_err());
+        assert!(interpreter.eval_str("(format \"{name}\" 1)").is_err());
+        assert!(interpreter.eval_str("(format \"{\" 1)").is_err());
+
+        let err = interpreter
+            .eval_str("(format \"{:>99999999999}\" 1)")
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidArgument);
+        assert!(interpreter
+            .eval_str("(format \"{:.99999999999999999999}\" 1.5)")
+            .is_err());
+    }
+}
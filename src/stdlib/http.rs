@@ -0,0 +1,196 @@
+//! HTTP client builtins. Both return a map `{status, headers, body}` for any response,
+//! including 4xx and 5xx statuses; only transport failures are raised.
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    for name in ["http-get", "http-post"] {
+        interpreter.set_fuel_category(name, FuelCategory::Http);
+    }
+
+    // syntax: (http-get <url> [headers])
+    interpreter.register_fn("http-get", |args| match args {
+        [Value::String(url)] => send("GET", url, None, &BTreeMap::new()),
+        [Value::String(url), Value::Map(headers)] => send("GET", url, None, headers),
+        [Value::String(_), value] => Err(RuntimeError::type_mismatch("map", value)),
+        [value] | [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("http-get", "1 or 2", args.len())),
+    });
+
+    // syntax: (http-post <url> <body> [headers])
+    interpreter.register_fn("http-post", |args| match args {
+        [Value::String(url), Value::String(body)] => {
+            send("POST", url, Some(body), &BTreeMap::new())
+        }
+        [Value::String(url), Value::String(body), Value::Map(headers)] => {
+            send("POST", url, Some(body), headers)
+        }
+        [Value::String(_), Value::String(_), value] => {
+            Err(RuntimeError::type_mismatch("map", value))
+        }
+        [Value::String(_), value] | [Value::String(_), value, _] | [value, _] | [value, _, _] => {
+            Err(RuntimeError::type_mismatch("string", value))
+        }
+        _ => Err(arity("http-post", "2 or 3", args.len())),
+    });
+}
+
+fn send(
+    method: &str,
+    url: &str,
+    body: Option<&str>,
+    headers: &BTreeMap<String, Value>,
+) -> Result<Value, RuntimeError> {
+    let mut request = ureq::request(method, url);
+
+    for (name, value) in headers {
+        request = match value {
+            Value::String(value) => request.set(name, value),
+            value => request.set(name, &value.to_string()),
+        };
+    }
+
+    let result = match body {
+        Some(body) => request.send_string(body),
+        None => request.call(),
+    };
+
+    let response = match result {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(err) => {
+            return Err(RuntimeError::with_code(
+                ErrorCode::Io,
+                format!("{} {} failed: {}", method, url, err),
+            ));
+        }
+    };
+
+    let status = response.status();
+    let headers = response
+        .headers_names()
+        .into_iter()
+        .filter_map(|name| {
+            let value = response.header(&name)?.to_string();
+            Some((name.to_lowercase(), Value::String(value.into())))
+        })
+        .collect::<BTreeMap<String, Value>>();
+
+    let body = response.into_string().map_err(|err| {
+        RuntimeError::with_code(
+            ErrorCode::Io,
+            format!("Failed to read the response of {}: {}", url, err),
+        )
+    })?;
+
+    Ok(response_map(status, headers, body))
+}
+
+/// The map returned for a response. Header names are lowercase.
+pub(super) fn response_map(status: u16, headers: BTreeMap<String, Value>, body: String) -> Value {
+    Value::Map(Rc::new(BTreeMap::from([
+        ("status".to_string(), Value::Int(status as i64)),
+        ("headers".to_string(), Value::Map(Rc::new(headers))),
+        ("body".to_string(), Value::String(body.into())),
+    ])))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_http_builtins() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        // Answers each request with its method, the x-token header and the request body
+        let server = std::thread::spawn(move || {
+            for stream in listener.incoming().take(2) {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                let (mut token, mut length) = (String::new(), 0);
+
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+
+                    match line.trim().split_once(": ") {
+                        Some((name, value)) if name.eq_ignore_ascii_case("x-token") => {
+                            token = value.to_string();
+                        }
+                        Some((name, value)) if name.eq_ignore_ascii_case("content-length") => {
+                            length = value.parse().unwrap();
+                        }
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let method = request_line.split(' ').next().unwrap();
+                let content = format!("{} {} {}", method, token, String::from_utf8(body).unwrap());
+                let status = if method == "GET" {
+                    "200 OK"
+                } else {
+                    "404 Not Found"
+                };
+
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nX-Kind: test\r\n\r\n{}",
+                    status,
+                    content.len(),
+                    content
+                )
+                .unwrap();
+            }
+        });
+
+        let mut interpreter = Interpreter::new();
+
+        let response = interpreter
+            .eval_str(&format!("(http-get \"{}\" (dict \"x-token\" \"t1\"))", url))
+            .unwrap();
+        let Value::Map(response) = response else {
+            panic!("expected a map, got {}", response);
+        };
+        assert_eq!(response["status"].to_string(), "200");
+        assert_eq!(response["body"].to_string(), "GET t1 ");
+        let Value::Map(headers) = &response["headers"] else {
+            panic!("expected a header map");
+        };
+        assert_eq!(headers["x-kind"].to_string(), "test");
+
+        let response = interpreter
+            .eval_str(&format!("(http-post \"{}\" \"data\")", url))
+            .unwrap();
+        let Value::Map(response) = response else {
+            panic!("expected a map, got {}", response);
+        };
+        assert_eq!(response["status"].to_string(), "404");
+        assert_eq!(response["body"].to_string(), "POST  data");
+
+        server.join().unwrap();
+
+        let err = interpreter
+            .eval_str("(http-get \"http://127.0.0.1:1\")")
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Io);
+    }
+}
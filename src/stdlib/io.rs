@@ -0,0 +1,163 @@
+//! File I/O builtins. Failures are raised as catchable runtime errors.
+
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    for name in [
+        "read-file",
+        "write-file",
+        "read-bytes",
+        "write-bytes",
+        "append-file",
+        "input",
+        "file-exists?",
+    ] {
+        interpreter.set_fuel_category(name, FuelCategory::Io);
+    }
+
+    interpreter.register_fn("read-file", |args| match args {
+        [Value::String(path)] => std::fs::read_to_string(&**path)
+            .map(|text| Value::String(text.into()))
+            .map_err(|err| io_error("read", path, err)),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("read-file", "1", args.len())),
+    });
+
+    interpreter.register_fn("write-file", |args| match args {
+        [Value::String(path), Value::String(content)] => std::fs::write(&**path, &**content)
+            .map(|_| Value::Void)
+            .map_err(|err| io_error("write", path, err)),
+        [Value::String(_), value] | [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("write-file", "2", args.len())),
+    });
+
+    // syntax: (read-bytes <path>), the file's contents as bytes
+    interpreter.register_fn("read-bytes", |args| match args {
+        [Value::String(path)] => std::fs::read(&**path)
+            .map(|bytes| Value::Bytes(Rc::new(bytes)))
+            .map_err(|err| io_error("read", path, err)),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("read-bytes", "1", args.len())),
+    });
+
+    // syntax: (write-bytes <path> <bytes>)
+    interpreter.register_fn("write-bytes", |args| match args {
+        [Value::String(path), Value::Bytes(bytes)] => std::fs::write(&**path, &**bytes)
+            .map(|_| Value::Void)
+            .map_err(|err| io_error("write", path, err)),
+        [Value::String(_), value] => Err(RuntimeError::type_mismatch("bytes", value)),
+        [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("write-bytes", "2", args.len())),
+    });
+
+    interpreter.register_fn("append-file", |args| match args {
+        [Value::String(path), Value::String(content)] => std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&**path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .map(|_| Value::Void)
+            .map_err(|err| io_error("append to", path, err)),
+        [Value::String(_), value] | [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("append-file", "2", args.len())),
+    });
+
+    // syntax: (input [prompt]), returns null at end of input
+    interpreter.register_native("input", |interpreter, args| {
+        match args {
+            [] => {}
+            [Value::String(prompt)] => interpreter.write_output(prompt),
+            [value] => return Err(RuntimeError::type_mismatch("string", value)),
+            _ => return Err(arity("input", "0 or 1", args.len())),
+        }
+
+        match interpreter.read_line() {
+            Ok(Some(line)) => Ok(Value::String(line.into())),
+            Ok(None) => Ok(Value::Null),
+            Err(err) => Err(RuntimeError::with_code(
+                ErrorCode::Io,
+                format!("Unable to read input: {}", err),
+            )),
+        }
+    });
+
+    interpreter.register_fn("file-exists?", |args| match args {
+        [Value::String(path)] => Ok(Value::Bool(std::path::Path::new(&**path).exists())),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("file-exists?", "1", args.len())),
+    });
+}
+
+fn io_error(action: &str, path: &str, err: std::io::Error) -> RuntimeError {
+    RuntimeError::with_code(
+        ErrorCode::Io,
+        format!("Unable to {} {}: {}", action, path, err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_builtins() {
+        let path = std::env::temp_dir().join(format!("kk-io-test-{}.txt", std::process::id()));
+        let path = path.display().to_string().replace('\\', "/");
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_var("path", Value::String(path.as_str().into()));
+
+        let result = interpreter
+            .eval_str(
+                "(write-file path \"a\") (append-file path \"b\") \
+                 (list (file-exists? path) (read-file path))",
+            )
+            .unwrap();
+
+        assert_eq!(result.to_string(), "[true, ab]");
+
+        let result = interpreter
+            .eval_str("(write-bytes path (bytes 0 255 10)) (read-bytes path)")
+            .unwrap();
+
+        assert_eq!(result.to_string(), "<bytes 00 ff 0a>");
+
+        std::fs::remove_file(&path).unwrap();
+
+        let result = interpreter
+            .eval_str("(try (read-file path) (catch e \"missing\"))")
+            .unwrap();
+
+        assert_eq!(result.to_string(), "missing");
+        assert_eq!(
+            interpreter.eval_str("(read-file path)").unwrap_err().code(),
+            ErrorCode::Io
+        );
+    }
+
+    #[test]
+    fn test_input() {
+        let prompts = std::rc::Rc::new(std::cell::RefCell::new(String::new()));
+        let sink = prompts.clone();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(move |text| sink.borrow_mut().push_str(text));
+        interpreter.set_input(std::io::Cursor::new("kk\r\n"));
+
+        let result = interpreter
+            .eval_str("(list (input \"name> \") (input))")
+            .unwrap();
+
+        assert_eq!(result.to_string(), "[kk, null]");
+        assert_eq!(*prompts.borrow(), "name> ");
+    }
+}
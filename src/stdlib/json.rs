@@ -0,0 +1,120 @@
+//! JSON builtins, using the conversions of `Value::to_json` and `Value::from_json`.
+
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::{NonFinite, Value};
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    interpreter.set_fuel_category("json-parse", FuelCategory::String);
+    interpreter.set_fuel_category("json-stringify", FuelCategory::String);
+
+    interpreter.register_fn("json-parse", |args| match args {
+        [Value::String(text)] => serde_json::from_str::<serde_json::Value>(text)
+            .map(|json| Value::from_json(&json))
+            .map_err(|err| RuntimeError::new(format!("Invalid JSON: {}", err))),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("json-parse", "1", args.len())),
+    });
+
+    // syntax: (json-stringify <value> [:pretty <bool>] [:non-finite :error|:null|:string])
+    interpreter.register_fn("json-stringify", |args| {
+        let Some((value, options)) = args.split_first() else {
+            return Err(arity("json-stringify", "1 plus options", 0));
+        };
+
+        let mut pretty = false;
+        let mut non_finite = NonFinite::Error;
+
+        for option in options.chunks(2) {
+            match option {
+                [Value::Keyword(keyword), Value::Bool(value)] if &**keyword == "pretty" => {
+                    pretty = *value;
+                }
+                [Value::Keyword(keyword), Value::Keyword(policy)] if &**keyword == "non-finite" => {
+                    non_finite = match &**policy {
+                        "error" => NonFinite::Error,
+                        "null" => NonFinite::Null,
+                        "string" => NonFinite::String,
+                        _ => {
+                            return Err(RuntimeError::type_mismatch(
+                                ":error, :null or :string",
+                                &option[1],
+                            ));
+                        }
+                    };
+                }
+                _ => {
+                    return Err(arity(
+                        "json-stringify",
+                        "1 plus :pretty and :non-finite options",
+                        args.len(),
+                    ));
+                }
+            }
+        }
+
+        let json = value.to_json_with(non_finite)?;
+
+        let text = match pretty {
+            true => serde_json::to_string_pretty(&json),
+            false => serde_json::to_string(&json),
+        };
+
+        text.map(|text| Value::String(text.into()))
+            .map_err(|err| RuntimeError::new(err.to_string()))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        interpreter.set_var(
+            "text",
+            Value::String(r#"{"name": "kk", "tags": [1, 2.5, true, null], "nested": {}}"#.into()),
+        );
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(
+            eval("(json-parse text)"),
+            "{name: kk, nested: {}, tags: [1, 2.5, true, null]}"
+        );
+        assert_eq!(
+            eval("(json-stringify (json-parse text))"),
+            r#"{"name":"kk","nested":{},"tags":[1,2.5,true,null]}"#
+        );
+        assert_eq!(
+            eval("(json-stringify (list :a \"b\") :pretty true)"),
+            "[\n  \"a\",\n  \"b\"\n]"
+        );
+
+        // Floats round-trip exactly, and stay floats
+        assert_eq!(
+            eval("(json-stringify (list 2.0 0.1 1e300 -0.000001 (json-parse \"0.30000000000000004\")))"),
+            "[2.0,0.1,1e+300,-1e-6,0.30000000000000004]"
+        );
+        assert_eq!(eval("(float? (json-parse (json-stringify 2.0)))"), "true");
+        assert_eq!(
+            eval("(json-stringify (list NaN inf) :non-finite :null)"),
+            "[null,null]"
+        );
+        assert_eq!(
+            eval("(json-stringify (list NaN -inf) :non-finite :string :pretty false)"),
+            r#"["NaN","-Infinity"]"#
+        );
+
+        assert!(interpreter.eval_str("(json-stringify inf)").is_err());
+        assert!(interpreter.eval_str("(json-parse \"{\")").is_err());
+        assert!(interpreter
+            .eval_str("(json-stringify (list (defn f () 1)))")
+            .is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Builtins that call a function on each element of a collection: `map`, `filter`,
+//! `reduce`, `any?` and `all?`, and `sort` with an optional comparator. They go over the
+//! same elements as `for`, so a map gives `(key value)` pairs and a string its chars, and
+//! predicates must return a bool, as the condition of `if` does. The function is a name, or
+//! one written in place with `(lambda (<param>...) <body>...)`.
+
+use std::cmp::Ordering;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    interpreter.set_fuel_category("sort", FuelCategory::Sort);
+
+    // syntax: (map <function> <collection>), the list of what the function returns
+    interpreter.register_native("map", |interpreter, args| {
+        let (function, elements) = function_and_elements("map", args)?;
+
+        let mapped = elements
+            .into_iter()
+            .map(|element| interpreter.call(function, vec![element]))
+            .collect::<Result<Vec<Value>, _>>()?;
+
+        Ok(Value::List(mapped.into()))
+    });
+
+    // syntax: (filter <predicate> <collection>), the list of elements it holds for
+    interpreter.register_native("filter", |interpreter, args| {
+        let (predicate, elements) = function_and_elements("filter", args)?;
+        let mut kept = vec![];
+
+        for element in elements {
+            if holds(interpreter, predicate, element.clone())? {
+                kept.push(element);
+            }
+        }
+
+        Ok(Value::List(kept.into()))
+    });
+
+    // syntax: (reduce <function> [initial] <collection>), calling (function acc element)
+    // from the left, starting from the first element when no initial value is given
+    interpreter.register_native("reduce", |interpreter, args| {
+        let (function, initial, collection) = match args {
+            [function, collection] => (function, None, collection),
+            [function, initial, collection] => (function, Some(initial.clone()), collection),
+            _ => return Err(arity("reduce", "2 or 3", args.len())),
+        };
+
+        let mut elements = elements(function, collection)?.into_iter();
+
+        let Some(mut acc) = initial.or_else(|| elements.next()) else {
+            return Err(RuntimeError::with_code(
+                ErrorCode::InvalidArgument,
+                "reduce of an empty collection needs an initial value",
+            ));
+        };
+
+        for element in elements {
+            acc = interpreter.call(function, vec![acc, element])?;
+        }
+
+        Ok(acc)
+    });
+
+    // syntax: (any? <predicate> <collection>), stopping at the first element it holds for
+    interpreter.register_native("any?", |interpreter, args| {
+        let (predicate, elements) = function_and_elements("any?", args)?;
+
+        for element in elements {
+            if holds(interpreter, predicate, element)? {
+                return Ok(Value::Bool(true));
+            }
+        }
+
+        Ok(Value::Bool(false))
+    });
+
+    // syntax: (all? <predicate> <collection>), stopping at the first element it fails for
+    interpreter.register_native("all?", |interpreter, args| {
+        let (predicate, elements) = function_and_elements("all?", args)?;
+
+        for element in elements {
+            if !holds(interpreter, predicate, element)? {
+                return Ok(Value::Bool(false));
+            }
+        }
+
+        Ok(Value::Bool(true))
+    });
+
+    // syntax: (sort <collection> [comparator]), a new list in the order of `compare`, or of
+    // (comparator a b) returning a negative int, 0 or a positive int, such as
+    // (lambda (a b) (compare b a)) for descending order. Equal elements keep their order.
+    interpreter.register_native("sort", |interpreter, args| {
+        let sorted = match args {
+            [collection] => {
+                let elements = collection.elements().ok_or_else(|| {
+                    RuntimeError::type_mismatch("list, map, string or bytes", collection)
+                })?;
+
+                merge_sort(elements, &mut |a, b| a.compare(b))?
+            }
+            [collection, comparator] => {
+                let elements = elements(comparator, collection)?;
+
+                merge_sort(elements, &mut |a, b| match interpreter
+                    .call(comparator, vec![a.clone(), b.clone()])?
+                {
+                    Value::Int(order) => Ok(order.cmp(&0)),
+                    value => Err(RuntimeError::type_mismatch("int", &value)),
+                })?
+            }
+            _ => return Err(arity("sort", "1 or 2", args.len())),
+        };
+
+        Ok(Value::List(sorted.into()))
+    });
+}
+
+/// Sorts `values` stably, failing with the first error of `compare`. Unlike `sort_by`, it
+/// can't panic when a comparator written in kk isn't a total order.
+fn merge_sort(
+    mut values: Vec<Value>,
+    compare: &mut dyn FnMut(&Value, &Value) -> Result<Ordering, RuntimeError>,
+) -> Result<Vec<Value>, RuntimeError> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, compare)?;
+    let right = merge_sort(right, compare)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Ties are taken from the left, which keeps equal elements in order
+        let next = match compare(a, b)? {
+            Ordering::Greater => right.next(),
+            _ => left.next(),
+        };
+
+        merged.extend(next);
+    }
+
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+fn function_and_elements<'a>(
+    name: &str,
+    args: &'a [Value],
+) -> Result<(&'a Value, Vec<Value>), RuntimeError> {
+    match args {
+        [function, collection] => Ok((function, elements(function, collection)?)),
+        _ => Err(arity(name, "2", args.len())),
+    }
+}
+
+/// The elements of `collection`, after checking that `function` can be called on them, so
+/// that a wrong argument fails even when the collection is empty.
+fn elements(function: &Value, collection: &Value) -> Result<Vec<Value>, RuntimeError> {
+    if !matches!(function, Value::Function(_) | Value::Native(_)) {
+        return Err(RuntimeError::type_mismatch("function", function));
+    }
+
+    collection
+        .elements()
+        .ok_or_else(|| RuntimeError::type_mismatch("list, map, string or bytes", collection))
+}
+
+fn holds(
+    interpreter: &mut Interpreter,
+    predicate: &Value,
+    element: Value,
+) -> Result<bool, RuntimeError> {
+    match interpreter.call(predicate, vec![element])? {
+        Value::Bool(holds) => Ok(holds),
+        value => Err(RuntimeError::type_mismatch("bool", &value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_higher_order_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        eval(
+            "(defn even? (n) (eq (mod n 2) 0)) (defn plus (a b) (add a b)) \
+             (defn entry ((:list key value)) (format \"{}={}\" key value))",
+        );
+
+        assert_eq!(
+            eval("(map (defn inc (n) (add n 1)) (list 1 2 3))"),
+            "[2, 3, 4]"
+        );
+        assert_eq!(eval("(filter even? (list 1 2 3 4))"), "[2, 4]");
+        assert_eq!(
+            eval("(list (reduce plus (list 1 2 3)) (reduce plus 10 (list)))"),
+            "[6, 10]"
+        );
+        assert_eq!(
+            eval(
+                "(defn scale (k xs) (map (lambda (n) (pow n k)) xs)) \
+                 (list (scale 2 (list 1 2 3)) (filter (lambda (n) (eq n 2)) (list 1 2)))"
+            ),
+            "[[1, 4, 9], [2]]"
+        );
+        assert_eq!(
+            eval("(list (map entry (dict \"a\" 1)) (map char-code \"ab\"))"),
+            "[[a=1], [97, 98]]"
+        );
+        assert_eq!(
+            eval("(list (any? even? (list 1 3)) (any? even? (list 1 2)) (all? even? (list)))"),
+            "[false, true, true]"
+        );
+
+        assert_eq!(
+            eval("(list (sort (list 3 1.5 2 (div 1 2))) (sort \"cab\") (sort (list)))"),
+            "[[1/2, 1.5, 2, 3], [a, b, c], []]"
+        );
+        assert_eq!(
+            eval(
+                "(defn ignoring-case (a b) (compare (lower a) (lower b))) \
+                 (sort (list \"b\" \"A\" \"a\" \"B\") ignoring-case)"
+            ),
+            "[A, a, b, B]"
+        );
+        assert_eq!(
+            eval("(sort (list 1 3 2) (lambda (a b) (compare b a)))"),
+            "[3, 2, 1]"
+        );
+
+        assert!(interpreter.eval_str("(sort (list 1 \"a\"))").is_err());
+        assert!(interpreter.eval_str("(sort (list 1 2) plus)").is_ok());
+        assert!(interpreter.eval_str("(sort (list 1 2) even?)").is_err());
+        assert!(interpreter.eval_str("(reduce plus (list))").is_err());
+        assert!(interpreter.eval_str("(map 1 (list))").is_err());
+        assert!(interpreter.eval_str("(filter inc (list 1))").is_err());
+        assert!(interpreter.eval_str("(any? even? 5)").is_err());
+    }
+}
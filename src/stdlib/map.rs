@@ -0,0 +1,389 @@
+//! Combining and editing maps: `merge`, `deep-merge` and `apply-patch`. All of them return
+//! a new value and leave their arguments untouched.
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    // syntax: (merge <map>... [:on-conflict <strategy>])
+    interpreter.register_native("merge", |interpreter, args| {
+        merge_all(interpreter, "merge", args, false)
+    });
+
+    // syntax: (deep-merge <map>... [:on-conflict <strategy>]), merging nested maps key by key
+    interpreter.register_native("deep-merge", |interpreter, args| {
+        merge_all(interpreter, "deep-merge", args, true)
+    });
+
+    // syntax: (apply-patch <value> <operations>), with RFC 6902 JSON Patch operations
+    interpreter.register_native("apply-patch", |interpreter, args| match args {
+        [value, Value::List(operations)] => {
+            let mut value = value.clone();
+
+            for (index, operation) in operations.iter().enumerate() {
+                apply(interpreter, &mut value, operation)
+                    .map_err(|err| invalid(format!("Patch operation {}: {}", index, err)))?;
+            }
+
+            Ok(value)
+        }
+        [_, value] => Err(RuntimeError::type_mismatch("list", value)),
+        _ => Err(arity("apply-patch", "2", args.len())),
+    });
+}
+
+/// What to keep when both maps have a key. Nested maps don't conflict in `deep-merge`.
+enum Strategy {
+    /// The value of the later map, the default.
+    Last,
+    First,
+    Error,
+    /// `(f key earlier later)` returns the merged value.
+    Function(Value),
+}
+
+fn merge_all(
+    interpreter: &mut Interpreter,
+    name: &str,
+    args: &[Value],
+    deep: bool,
+) -> Result<Value, RuntimeError> {
+    let (maps, strategy) = match args {
+        [maps @ .., Value::Keyword(keyword), strategy] if &**keyword == "on-conflict" => {
+            let strategy = match strategy {
+                Value::Keyword(keyword) if &**keyword == "last" => Strategy::Last,
+                Value::Keyword(keyword) if &**keyword == "first" => Strategy::First,
+                Value::Keyword(keyword) if &**keyword == "error" => Strategy::Error,
+                Value::Function(_) | Value::Native(_) => Strategy::Function(strategy.clone()),
+                value => {
+                    return Err(RuntimeError::type_mismatch(
+                        ":last, :first, :error or a function",
+                        value,
+                    ));
+                }
+            };
+
+            (maps, strategy)
+        }
+        maps => (maps, Strategy::Last),
+    };
+
+    if maps.is_empty() {
+        return Err(arity(name, "at least 1", 0));
+    }
+
+    let mut merged = BTreeMap::new();
+
+    for map in maps {
+        match map {
+            Value::Map(map) => merge(interpreter, &mut merged, map, &strategy, deep, "")?,
+            // Lets optional layers such as a missing local config be passed as they are
+            Value::Null => {}
+            value => return Err(RuntimeError::type_mismatch("map", value)),
+        }
+    }
+
+    Ok(Value::Map(merged.into()))
+}
+
+/// Merges `from` into `into`. `path` names the nested map for conflict errors.
+fn merge(
+    interpreter: &mut Interpreter,
+    into: &mut BTreeMap<String, Value>,
+    from: &BTreeMap<String, Value>,
+    strategy: &Strategy,
+    deep: bool,
+    path: &str,
+) -> Result<(), RuntimeError> {
+    for (key, value) in from {
+        let Some(existing) = into.get_mut(key) else {
+            into.insert(key.clone(), value.clone());
+            continue;
+        };
+
+        let path = format!("{}/{}", path, key);
+
+        let merged = match (&mut *existing, value) {
+            (Value::Map(nested), Value::Map(value)) if deep => {
+                merge(
+                    interpreter,
+                    Rc::make_mut(nested),
+                    value,
+                    strategy,
+                    deep,
+                    &path,
+                )?;
+                continue;
+            }
+            (existing, value) => match strategy {
+                Strategy::Last => value.clone(),
+                Strategy::First => continue,
+                Strategy::Error => {
+                    return Err(RuntimeError::with_code(
+                        ErrorCode::InvalidArgument,
+                        format!(
+                            "Conflicting values for {}: {} and {}",
+                            path, existing, value
+                        ),
+                    ));
+                }
+                Strategy::Function(function) => {
+                    let args = vec![
+                        Value::String(key.as_str().into()),
+                        existing.clone(),
+                        value.clone(),
+                    ];
+                    interpreter.call(function, args)?
+                }
+            },
+        };
+
+        *existing = merged;
+    }
+
+    Ok(())
+}
+
+/// Applies one `{op, path, [value], [from]}` operation.
+fn apply(
+    interpreter: &Interpreter,
+    root: &mut Value,
+    operation: &Value,
+) -> Result<(), RuntimeError> {
+    let Value::Map(operation) = operation else {
+        return Err(RuntimeError::type_mismatch("map", operation));
+    };
+
+    let field = |name: &str| {
+        operation
+            .get(name)
+            .ok_or_else(|| invalid(format!("missing \"{}\"", name)))
+    };
+
+    let pointer = |name: &str| match field(name)? {
+        Value::String(path) => parse_pointer(path),
+        value => Err(RuntimeError::type_mismatch("string", value)),
+    };
+
+    let op = match field("op")? {
+        Value::String(op) | Value::Keyword(op) => &**op,
+        value => return Err(RuntimeError::type_mismatch("string", value)),
+    };
+
+    let path = pointer("path")?;
+
+    match op {
+        "add" => add(root, &path, field("value")?.clone()),
+        "remove" => remove(root, &path).map(|_| ()),
+        "replace" => {
+            remove(root, &path)?;
+            add(root, &path, field("value")?.clone())
+        }
+        "move" => {
+            let from = pointer("from")?;
+
+            if path.len() > from.len() && path.starts_with(&from) {
+                return Err(invalid("cannot move a value into itself".to_string()));
+            }
+
+            let value = remove(root, &from)?;
+            add(root, &path, value)
+        }
+        "copy" => {
+            let value = get(root, &pointer("from")?)?.clone();
+            add(root, &path, value)
+        }
+        "test" => {
+            let (actual, expected) = (get(root, &path)?, field("value")?);
+
+            match interpreter.values_equal(actual, expected)? {
+                true => Ok(()),
+                false => Err(invalid(format!(
+                    "test failed, expected {} but found {}",
+                    expected, actual
+                ))),
+            }
+        }
+        op => Err(invalid(format!("unknown op \"{}\"", op))),
+    }
+}
+
+/// Splits a JSON Pointer such as `/servers/0/host` into its unescaped tokens.
+fn parse_pointer(path: &str) -> Result<Vec<String>, RuntimeError> {
+    if path.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let Some(path) = path.strip_prefix('/') else {
+        return Err(invalid(format!("path \"{}\" must start with /", path)));
+    };
+
+    Ok(path
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+fn get<'a>(value: &'a Value, path: &[String]) -> Result<&'a Value, RuntimeError> {
+    path.iter().try_fold(value, |value, token| {
+        let found = match value {
+            Value::Map(map) => map.get(token),
+            Value::List(list) => index(token, list.len()).ok().and_then(|i| list.get(i)),
+            _ => None,
+        };
+
+        found.ok_or_else(|| not_found(path))
+    })
+}
+
+/// The container holding the last token of `path`.
+fn parent<'a>(root: &'a mut Value, path: &[String]) -> Result<&'a mut Value, RuntimeError> {
+    let mut value = root;
+
+    for token in &path[..path.len() - 1] {
+        value = match value {
+            Value::Map(map) => Rc::make_mut(map).get_mut(token),
+            Value::List(list) => match index(token, list.len()) {
+                Ok(i) => Rc::make_mut(list).get_mut(i),
+                Err(_) => None,
+            },
+            _ => None,
+        }
+        .ok_or_else(|| not_found(path))?;
+    }
+
+    Ok(value)
+}
+
+fn add(root: &mut Value, path: &[String], value: Value) -> Result<(), RuntimeError> {
+    let Some(last) = path.last() else {
+        *root = value;
+        return Ok(());
+    };
+
+    match parent(root, path)? {
+        Value::Map(map) => {
+            Rc::make_mut(map).insert(last.clone(), value);
+        }
+        Value::List(list) if last == "-" => Rc::make_mut(list).push(value),
+        Value::List(list) => {
+            let i = index(last, list.len() + 1).map_err(|_| not_found(path))?;
+            Rc::make_mut(list).insert(i, value);
+        }
+        _ => return Err(not_found(path)),
+    }
+
+    Ok(())
+}
+
+fn remove(root: &mut Value, path: &[String]) -> Result<Value, RuntimeError> {
+    let Some(last) = path.last() else {
+        return Ok(std::mem::replace(root, Value::Null));
+    };
+
+    match parent(root, path)? {
+        Value::Map(map) => Rc::make_mut(map).remove(last),
+        Value::List(list) => index(last, list.len())
+            .ok()
+            .map(|i| Rc::make_mut(list).remove(i)),
+        _ => None,
+    }
+    .ok_or_else(|| not_found(path))
+}
+
+/// Parses a list index, which must be below `len`.
+fn index(token: &str, len: usize) -> Result<usize, ()> {
+    match token.parse::<usize>() {
+        // Leading zeros are not allowed by RFC 6901
+        Ok(i) if i < len && (token == "0" || !token.starts_with('0')) => Ok(i),
+        _ => Err(()),
+    }
+}
+
+fn not_found(path: &[String]) -> RuntimeError {
+    let path = path
+        .iter()
+        .map(|token| format!("/{}", token.replace('~', "~0").replace('/', "~1")))
+        .collect::<String>();
+
+    invalid(format!("no value at \"{}\"", path))
+}
+
+fn invalid(message: String) -> RuntimeError {
+    RuntimeError::with_code(ErrorCode::InvalidArgument, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Interpreter;
+
+    #[test]
+    fn test_merge_and_patch() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        eval(
+            "(set base (dict \"name\" \"app\" \"db\" (dict \"host\" \"localhost\" \"port\" 5432)))
+             (set local (dict \"db\" (dict \"port\" 6543) \"debug\" true))",
+        );
+        assert_eq!(
+            eval("(merge base local)"),
+            "{db: {port: 6543}, debug: true, name: app}"
+        );
+        assert_eq!(
+            eval("(deep-merge base local)"),
+            "{db: {host: localhost, port: 6543}, debug: true, name: app}"
+        );
+        assert_eq!(
+            eval("(deep-merge base local :on-conflict :first)"),
+            "{db: {host: localhost, port: 5432}, debug: true, name: app}"
+        );
+        assert_eq!(
+            eval(
+                "(defn bigger (key a b) (if (eq (mod a 2) 0) a else b))
+                 (deep-merge base local :on-conflict bigger)"
+            ),
+            "{db: {host: localhost, port: 5432}, debug: true, name: app}"
+        );
+
+        assert_eq!(
+            eval(
+                "(apply-patch
+                   (dict \"tags\" (list \"a\" \"c\") \"db\" (dict \"host\" \"x\"))
+                   (list
+                     (dict \"op\" \"add\" \"path\" \"/tags/1\" \"value\" \"b\")
+                     (dict \"op\" \"add\" \"path\" \"/tags/-\" \"value\" \"d\")
+                     (dict \"op\" \"replace\" \"path\" \"/db/host\" \"value\" \"y\")
+                     (dict \"op\" \"copy\" \"from\" \"/db\" \"path\" \"/backup\")
+                     (dict \"op\" \"move\" \"from\" \"/backup/host\" \"path\" \"/backup~1host\")
+                     (dict \"op\" \"remove\" \"path\" \"/tags/0\")
+                     (dict \"op\" \"test\" \"path\" \"/tags/0\" \"value\" \"b\")))"
+            ),
+            "{backup: {}, backup/host: y, db: {host: y}, tags: [b, c, d]}"
+        );
+
+        let err = interpreter
+            .eval_str("(deep-merge base local :on-conflict :error)")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Conflicting values for /db/port: 5432 and 6543"
+        );
+
+        let err = interpreter
+            .eval_str("(apply-patch base (list (dict \"op\" \"remove\" \"path\" \"/db/user\")))")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Patch operation 0: no value at \"/db/user\""
+        );
+    }
+}
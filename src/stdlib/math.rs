@@ -0,0 +1,411 @@
+//! Math builtins. Like `mod`, operations on ints return ints and mixing ints with floats
+//! converts to float; functions without an exact int result always return floats, except
+//! `div`, which returns a rational when ints don't divide evenly.
+
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::rational::Rational;
+use crate::value::Value;
+use crate::{DivMode, Interpreter};
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    interpreter.register_value("pi", Value::Float(std::f64::consts::PI));
+    interpreter.register_value("e", Value::Float(std::f64::consts::E));
+
+    float(interpreter, "sqrt", f64::sqrt);
+    float(interpreter, "sin", f64::sin);
+    float(interpreter, "cos", f64::cos);
+    float(interpreter, "tan", f64::tan);
+
+    rounding(interpreter, "floor", f64::floor, Rational::floor);
+    rounding(interpreter, "ceil", f64::ceil, Rational::ceil);
+    rounding(interpreter, "round", f64::round, Rational::round);
+
+    interpreter.register_fn("abs", |args| match args {
+        [Value::Int(i)] => Ok(i
+            .checked_abs()
+            .map_or_else(|| BigInt::from(*i).abs().into(), Value::Int)),
+        [Value::BigInt(big)] => Ok(big.abs().into()),
+        [Value::Rational(rational)] => Ok(Value::Rational(Rc::new(rational.abs()))),
+        [Value::Float(fl)] => Ok(Value::Float(fl.abs())),
+        [value] => Err(RuntimeError::type_mismatch("number", value)),
+        _ => Err(arity("abs", "1", args.len())),
+    });
+
+    interpreter.register_fn("pow", |args| match args {
+        [Value::Int(base), Value::Int(exponent)] if *exponent >= 0 => {
+            match u32::try_from(*exponent).map(|exponent| base.checked_pow(exponent)) {
+                Ok(Some(power)) => Ok(Value::Int(power)),
+                _ => big_pow(&BigInt::from(*base), *exponent),
+            }
+        }
+        [Value::BigInt(base), Value::Int(exponent)] if *exponent >= 0 => big_pow(base, *exponent),
+        [base, exponent] => Ok(Value::Float(to_float(base)?.powf(to_float(exponent)?))),
+        _ => Err(arity("pow", "2", args.len())),
+    });
+
+    // syntax: (compare <a> <b>), -1, 0 or 1 as a is less than, equal to or greater than b
+    interpreter.register_fn("compare", |args| match args {
+        [a, b] => Ok(Value::Int(a.compare(b)? as i64)),
+        _ => Err(arity("compare", "2", args.len())),
+    });
+
+    extremum(interpreter, "min", Ordering::Less);
+    extremum(interpreter, "max", Ordering::Greater);
+
+    // syntax: (/ <a> <b>), for two ints as set by Interpreter::set_div_mode
+    interpreter.register_native("/", |interpreter, args| match args {
+        [a @ (Value::Int(_) | Value::BigInt(_)), b @ (Value::Int(_) | Value::BigInt(_))] => {
+            match interpreter.div_mode {
+                DivMode::Float => divide(to_float(a)?, to_float(b)?),
+                DivMode::Truncate => int_divide(a, b).map(|(quotient, _)| quotient),
+                DivMode::Strict => Err(RuntimeError::with_code(
+                    ErrorCode::InvalidArgument,
+                    "/ on two ints is ambiguous, use idiv or fdiv",
+                )),
+            }
+        }
+        [a, b] => divide(to_float(a)?, to_float(b)?),
+        _ => Err(arity("/", "2", args.len())),
+    });
+
+    // syntax: (idiv <a> <b>), the int quotient truncated toward zero
+    interpreter.register_fn("idiv", |args| match args {
+        [a, b] => int_divide(a, b).map(|(quotient, _)| quotient),
+        _ => Err(arity("idiv", "2", args.len())),
+    });
+
+    // syntax: (fdiv <a> <b>)
+    interpreter.register_fn("fdiv", |args| match args {
+        [a, b] => divide(to_float(a)?, to_float(b)?),
+        _ => Err(arity("fdiv", "2", args.len())),
+    });
+
+    // syntax: (div <a> <b>), exact for ints and rationals, so (div 1 3) is the rational 1/3
+    interpreter.register_fn("div", |args| match args {
+        [a, b] => match (a.to_rational(), b.to_rational()) {
+            (Some(a), Some(b)) => match a.checked_div(&b) {
+                Some(quotient) => Ok(quotient.into()),
+                None => Err(division_by_zero()),
+            },
+            _ => divide(to_float(a)?, to_float(b)?),
+        },
+        _ => Err(arity("div", "2", args.len())),
+    });
+
+    // syntax: (numerator <int | rational>)
+    interpreter.register_fn("numerator", |args| match args {
+        [value] => match value.to_rational() {
+            Some(rational) => Ok(rational.numerator().clone().into()),
+            None => Err(RuntimeError::type_mismatch("int or rational", value)),
+        },
+        _ => Err(arity("numerator", "1", args.len())),
+    });
+
+    // syntax: (denominator <int | rational>), 1 for ints
+    interpreter.register_fn("denominator", |args| match args {
+        [value] => match value.to_rational() {
+            Some(rational) => Ok(rational.denominator().clone().into()),
+            None => Err(RuntimeError::type_mismatch("int or rational", value)),
+        },
+        _ => Err(arity("denominator", "1", args.len())),
+    });
+
+    // syntax: (divmod <a> <b>), the list of what idiv and mod return
+    interpreter.register_fn("divmod", |args| match args {
+        [a, b] => {
+            let (quotient, remainder) = int_divide(a, b)?;
+            Ok(Value::List((vec![quotient, remainder]).into()))
+        }
+        _ => Err(arity("divmod", "2", args.len())),
+    });
+}
+
+/// The quotient of two ints truncated toward zero and the remainder, with the sign of `a`
+/// like `mod`.
+fn int_divide(a: &Value, b: &Value) -> Result<(Value, Value), RuntimeError> {
+    if let (Value::Int(a), Value::Int(b)) = (a, b) {
+        if let (Some(quotient), Some(remainder)) = (a.checked_div(*b), a.checked_rem(*b)) {
+            return Ok((Value::Int(quotient), Value::Int(remainder)));
+        }
+    }
+
+    match (a.to_big_int(), b.to_big_int()) {
+        (Some(a), Some(b)) => match a.div_rem(&b) {
+            Some((quotient, remainder)) => Ok((quotient.into(), remainder.into())),
+            None => Err(division_by_zero()),
+        },
+        (Some(_), None) => Err(RuntimeError::type_mismatch("int", b)),
+        (None, _) => Err(RuntimeError::type_mismatch("int", a)),
+    }
+}
+
+/// Results of `pow` may have this many bits, about 315,000 decimal digits; larger ones
+/// would take longer to compute than any script should wait.
+const MAX_POW_BITS: u64 = 1 << 20;
+
+fn big_pow(base: &BigInt, exponent: i64) -> Result<Value, RuntimeError> {
+    match base.bits().saturating_mul(exponent as u64) <= MAX_POW_BITS {
+        true => Ok(base.pow(exponent as u32).into()),
+        false => Err(RuntimeError::with_code(
+            ErrorCode::InvalidArgument,
+            "pow result is too large, use a float base",
+        )),
+    }
+}
+
+fn divide(a: f64, b: f64) -> Result<Value, RuntimeError> {
+    match b {
+        0.0 => Err(division_by_zero()),
+        b => Ok(Value::Float(a / b)),
+    }
+}
+
+fn division_by_zero() -> RuntimeError {
+    RuntimeError::with_code(ErrorCode::InvalidArgument, "Division by zero")
+}
+
+fn to_float(value: &Value) -> Result<f64, RuntimeError> {
+    value
+        .as_f64()
+        .ok_or_else(|| RuntimeError::type_mismatch("int or float", value))
+}
+
+/// Registers a builtin that always returns a float.
+fn float(interpreter: &mut Interpreter, name: &'static str, function: fn(f64) -> f64) {
+    interpreter.register_fn(name, move |args| match args {
+        [value] => Ok(Value::Float(function(to_float(value)?))),
+        _ => Err(arity(name, "1", args.len())),
+    });
+}
+
+/// Registers a builtin that rounds floats, rounds rationals exactly to ints and returns
+/// ints unchanged.
+fn rounding(
+    interpreter: &mut Interpreter,
+    name: &'static str,
+    function: fn(f64) -> f64,
+    exact: fn(&Rational) -> BigInt,
+) {
+    interpreter.register_fn(name, move |args| match args {
+        [value @ (Value::Int(_) | Value::BigInt(_))] => Ok(value.clone()),
+        [Value::Rational(rational)] => Ok(exact(rational).into()),
+        [value] => Ok(Value::Float(function(to_float(value)?))),
+        _ => Err(arity(name, "1", args.len())),
+    });
+}
+
+/// Registers `min` or `max`, which take any values `compare` orders.
+fn extremum(interpreter: &mut Interpreter, name: &'static str, better: Ordering) {
+    interpreter.register_fn(name, move |args| {
+        let Some(first) = args.first() else {
+            return Err(arity(name, "at least 1", 0));
+        };
+
+        let mut best = first;
+
+        for value in &args[1..] {
+            if value.compare(best)? == better {
+                best = value;
+            }
+        }
+
+        match best {
+            Value::Int(_) | Value::BigInt(_) | Value::Rational(_)
+                if args.iter().any(|value| matches!(value, Value::Float(_))) =>
+            {
+                Ok(Value::Float(to_float(best)?))
+            }
+            best => Ok(best.clone()),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_math_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(eval("(sqrt 16)"), "4");
+        assert_eq!(eval("(pow 2 10)"), "1024");
+        assert_eq!(eval("(pow 2 -1)"), "0.5");
+        assert_eq!(eval("(pow 4 0.5)"), "2");
+        assert_eq!(eval("(list (abs -3) (abs -1.5))"), "[3, 1.5]");
+        assert_eq!(
+            eval("(list (floor 2.7) (ceil 2.1) (round 2.5) (round 7))"),
+            "[2, 3, 3, 7]"
+        );
+        assert_eq!(
+            eval("(list (min 3 1 2) (max 1 2.5) (max 2))"),
+            "[1, 2.5, 2]"
+        );
+        assert_eq!(eval("(round (cos pi))"), "-1");
+        assert_eq!(eval("(floor (mod e 1))"), "0");
+
+        assert!(matches!(
+            interpreter.eval_str("(floor 2.7)"),
+            Ok(Value::Float(_))
+        ));
+        assert!(matches!(
+            interpreter.eval_str("(min 1 2)"),
+            Ok(Value::Int(1))
+        ));
+        assert!(interpreter.eval_str("(pow 3 1000000)").is_err());
+        assert!(interpreter.eval_str("(min)").is_err());
+        assert!(interpreter.eval_str("(max 1 \"2\")").is_err());
+    }
+
+    #[test]
+    fn test_big_ints() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        eval("(let top 9223372036854775807)");
+        assert_eq!(eval("(add top 1)"), "9223372036854775808");
+        assert_eq!(eval("(let n top) (inc n) (inc n)"), "9223372036854775809");
+        assert_eq!(eval("(add n -2)"), "9223372036854775807");
+        assert!(matches!(
+            interpreter.eval_str("(add n -2)"),
+            Ok(Value::Int(i64::MAX))
+        ));
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(eval("(pow 2 100)"), "1267650600228229401496703205376");
+        assert_eq!(
+            eval(
+                "(list (idiv (pow 10 30) 7) (mod (pow 10 30) 7) (divmod -9223372036854775808 -1))"
+            ),
+            "[142857142857142857142857142857, 1, [9223372036854775808, 0]]"
+        );
+        assert_eq!(
+            eval("(list (abs -9223372036854775808) (int? (pow 2 64)) (type-of (pow 2 64)))"),
+            "[9223372036854775808, true, int]"
+        );
+        assert_eq!(
+            eval("(list (eq (pow 2 64) 18446744073709551616.0) (compare (pow 2 64) top) (max 1.5 (pow 2 64)))"),
+            "[true, 1, 18446744073709552000]"
+        );
+        assert_eq!(eval("(repr (add top top))"), "18446744073709551614");
+        assert_eq!(eval("(sqrt (pow 10 40))"), "100000000000000000000");
+
+        assert!(interpreter.eval_str("(mod (pow 2 64) 0)").is_err());
+        assert!(interpreter.eval_str("(mod 1 0)").is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(
+            eval("(list (compare 1 2) (compare 2.5 2) (compare 3 3.0) (compare \"b\" \"a\"))"),
+            "[-1, 1, 0, 1]"
+        );
+        assert_eq!(
+            eval("(list (compare false true) (compare :a :a) (compare (list 1 2) (list 1 2 0)))"),
+            "[-1, 0, -1]"
+        );
+        assert_eq!(
+            eval("(list (compare 9007199254740993 9007199254740992.0) (compare 1 (sqrt -1)))"),
+            "[1, -1]"
+        );
+        assert_eq!(
+            eval("(list (min \"pear\" \"apple\") (max (list 1 2) (list 1 3)) (min 2 1.5 3))"),
+            "[apple, [1, 3], 1.5]"
+        );
+
+        assert!(interpreter.eval_str("(compare 1 \"1\")").is_err());
+        assert!(interpreter
+            .eval_str("(compare (list 1) (list \"a\"))")
+            .is_err());
+        assert!(interpreter.eval_str("(compare (dict) (dict))").is_err());
+    }
+
+    #[test]
+    fn test_rationals() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(
+            eval("(list (div 1 3) (div 6 -4) (div 6 3) (div 1 0.5))"),
+            "[1/3, -3/2, 2, 2]"
+        );
+        assert_eq!(eval("(add (div 1 3) (div 1 6))"), "1/2");
+        assert_eq!(eval("(add (div 1 3) (div 2 3) 1)"), "2");
+        assert_eq!(eval("(div (div 1 3) (div 2 9))"), "3/2");
+        assert_eq!(
+            eval("(let r (div -22 7)) (list (numerator r) (denominator r) (denominator 5))"),
+            "[-22, 7, 1]"
+        );
+        assert_eq!(
+            eval("(list (floor r) (ceil r) (round r) (to-int r) (abs r) (mod r 1))"),
+            "[-4, -3, -3, -3, 22/7, -1/7]"
+        );
+        assert_eq!(
+            eval("(list (type-of r) (rational? r) (number? r) (to-float (div 1 4)) (repr r))"),
+            "[rational, true, true, 0.25, (div -22 7)]"
+        );
+        assert_eq!(
+            eval("(list (eq (div 1 2) 0.5) (eq (div 1 3) (div 2 6)) (compare (div 1 3) 0.3))"),
+            "[true, true, 1]"
+        );
+        assert_eq!(
+            eval("(list (min (div 1 3) (div 1 4)) (max (div 1 2) 1.0))"),
+            "[1/4, 1]"
+        );
+
+        assert!(matches!(
+            interpreter.eval_str("(add (div 1 2) 0.5)"),
+            Ok(Value::Float(_))
+        ));
+        assert!(interpreter.eval_str("(div 1 0)").is_err());
+        assert!(interpreter.eval_str("(numerator 0.5)").is_err());
+    }
+
+    #[test]
+    fn test_division() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(eval("(list (/ 7 2) (/ 7.5 2.5))"), "[3.5, 3]");
+        assert_eq!(
+            eval("(list (idiv 7 2) (idiv -7 2) (fdiv 1 4))"),
+            "[3, -3, 0.25]"
+        );
+        assert_eq!(eval("(divmod -7 2)"), "[-3, -1]");
+
+        interpreter.set_div_mode(DivMode::Truncate);
+        assert!(matches!(interpreter.eval_str("(/ 7 2)"), Ok(Value::Int(3))));
+        assert!(matches!(
+            interpreter.eval_str("(/ 7 2.0)"),
+            Ok(Value::Float(_))
+        ));
+
+        interpreter.set_div_mode(DivMode::Strict);
+        let err = interpreter.eval_str("(/ 7 2)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "/ on two ints is ambiguous, use idiv or fdiv"
+        );
+
+        let err = interpreter.eval_str("(idiv 1 0)").unwrap_err();
+        assert_eq!(err.to_string(), "Division by zero");
+        assert!(interpreter.eval_str("(idiv 1 2.0)").is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! TCP and UDP socket builtins. Sockets are `Value::Handle`s of kind `tcp-stream`,
+//! `tcp-listener` or `udp-socket`, and every call blocks until it completes.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::rc::Rc;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::{Handle, Value};
+use crate::Interpreter;
+
+use super::arity;
+
+/// Bytes read by `tcp-recv` and `udp-recv` when no maximum is given.
+const DEFAULT_RECV_SIZE: i64 = 4096;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    let names = [
+        "tcp-connect",
+        "tcp-listen",
+        "tcp-accept",
+        "tcp-send",
+        "tcp-recv",
+        "udp-bind",
+        "udp-send",
+        "udp-recv",
+        "socket-addr",
+    ];
+
+    for name in names {
+        interpreter.set_fuel_category(name, FuelCategory::Io);
+    }
+
+    // syntax: (tcp-connect "host:port")
+    interpreter.register_fn("tcp-connect", |args| match args {
+        [Value::String(address)] => TcpStream::connect(&**address)
+            .map(|stream| handle("tcp-stream", stream))
+            .map_err(|err| net_error("connect to", address, err)),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("tcp-connect", "1", args.len())),
+    });
+
+    // syntax: (tcp-listen "host:port"), port 0 picks a free port, see socket-addr
+    interpreter.register_fn("tcp-listen", |args| match args {
+        [Value::String(address)] => TcpListener::bind(&**address)
+            .map(|listener| handle("tcp-listener", listener))
+            .map_err(|err| net_error("listen on", address, err)),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("tcp-listen", "1", args.len())),
+    });
+
+    // syntax: (tcp-accept <listener>), waits for the next connection
+    interpreter.register_fn("tcp-accept", |args| match args {
+        [listener] => with_socket(listener, "tcp-listener", |listener: &mut TcpListener| {
+            listener.accept()
+        })
+        .map(|(stream, _)| handle("tcp-stream", stream)),
+        _ => Err(arity("tcp-accept", "1", args.len())),
+    });
+
+    // syntax: (tcp-send <stream> <string | bytes>)
+    interpreter.register_fn("tcp-send", |args| match args {
+        [stream, data] => {
+            let data = payload(data)?;
+
+            with_socket(stream, "tcp-stream", |stream: &mut TcpStream| {
+                stream.write_all(data)?;
+                stream.flush()
+            })?;
+
+            Ok(Value::Void)
+        }
+        _ => Err(arity("tcp-send", "2", args.len())),
+    });
+
+    // syntax: (tcp-recv <stream> [max-bytes]), an empty string once the peer has closed
+    interpreter.register_fn("tcp-recv", |args| {
+        let (stream, size) = match args {
+            [stream] => (stream, DEFAULT_RECV_SIZE),
+            [stream, Value::Int(size)] => (stream, *size),
+            [_, value] => return Err(RuntimeError::type_mismatch("int", value)),
+            _ => return Err(arity("tcp-recv", "1 or 2", args.len())),
+        };
+
+        let mut buffer = vec![0; recv_size(size)?];
+        let read = with_socket(stream, "tcp-stream", |stream: &mut TcpStream| {
+            stream.read(&mut buffer)
+        })?;
+
+        Ok(Value::String(
+            String::from_utf8_lossy(&buffer[..read]).into(),
+        ))
+    });
+
+    // syntax: (udp-bind "host:port")
+    interpreter.register_fn("udp-bind", |args| match args {
+        [Value::String(address)] => UdpSocket::bind(&**address)
+            .map(|socket| handle("udp-socket", socket))
+            .map_err(|err| net_error("bind", address, err)),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("udp-bind", "1", args.len())),
+    });
+
+    // syntax: (udp-send <socket> "host:port" <string | bytes>)
+    interpreter.register_fn("udp-send", |args| match args {
+        [socket, Value::String(address), data] => {
+            let data = payload(data)?;
+
+            with_socket(socket, "udp-socket", |socket: &mut UdpSocket| {
+                socket.send_to(data, &**address)
+            })?;
+
+            Ok(Value::Void)
+        }
+        [_, value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("udp-send", "3", args.len())),
+    });
+
+    // syntax: (udp-recv <socket> [max-bytes]), returns {data, from}
+    interpreter.register_fn("udp-recv", |args| {
+        let (socket, size) = match args {
+            [socket] => (socket, DEFAULT_RECV_SIZE),
+            [socket, Value::Int(size)] => (socket, *size),
+            [_, value] => return Err(RuntimeError::type_mismatch("int", value)),
+            _ => return Err(arity("udp-recv", "1 or 2", args.len())),
+        };
+
+        let mut buffer = vec![0; recv_size(size)?];
+        let (read, from) = with_socket(socket, "udp-socket", |socket: &mut UdpSocket| {
+            socket.recv_from(&mut buffer)
+        })?;
+
+        Ok(Value::Map(Rc::new(BTreeMap::from([
+            (
+                "data".to_string(),
+                Value::String(String::from_utf8_lossy(&buffer[..read]).into()),
+            ),
+            ("from".to_string(), Value::String(from.to_string().into())),
+        ]))))
+    });
+
+    // syntax: (socket-addr <socket>), the local "host:port" of any socket
+    interpreter.register_fn("socket-addr", |args| {
+        let address = match args {
+            [socket @ Value::Handle(handle)] => match handle.kind {
+                "tcp-stream" => {
+                    with_socket(socket, "tcp-stream", |s: &mut TcpStream| s.local_addr())
+                }
+                "tcp-listener" => {
+                    with_socket(socket, "tcp-listener", |s: &mut TcpListener| s.local_addr())
+                }
+                _ => with_socket(socket, "udp-socket", |s: &mut UdpSocket| s.local_addr()),
+            },
+            [value] => return Err(RuntimeError::type_mismatch("socket", value)),
+            _ => return Err(arity("socket-addr", "1", args.len())),
+        };
+
+        address.map(|address: SocketAddr| Value::String(address.to_string().into()))
+    });
+
+    // syntax: (close <handle>), returns false if it was already closed
+    interpreter.register_fn("close", |args| match args {
+        [Value::Handle(handle)] => Ok(Value::Bool(handle.close())),
+        [value] => Err(RuntimeError::type_mismatch("handle", value)),
+        _ => Err(arity("close", "1", args.len())),
+    });
+}
+
+fn handle(kind: &'static str, resource: impl std::any::Any) -> Value {
+    Value::Handle(Rc::new(Handle::new(kind, resource)))
+}
+
+/// Calls `f` with the socket held by `value`, a handle of the given kind.
+fn with_socket<T: 'static, R>(
+    value: &Value,
+    kind: &str,
+    f: impl FnOnce(&mut T) -> std::io::Result<R>,
+) -> Result<R, RuntimeError> {
+    let handle = match value {
+        Value::Handle(handle) if handle.kind == kind => handle,
+        value => return Err(RuntimeError::type_mismatch(kind, value)),
+    };
+
+    match handle.with(f) {
+        Some(result) => result.map_err(|err| net_error("use", kind, err)),
+        None => Err(RuntimeError::with_code(
+            ErrorCode::InvalidArgument,
+            format!("Cannot use a closed {}", kind),
+        )),
+    }
+}
+
+/// The data to send, a string as UTF-8 or bytes as they are.
+fn payload(value: &Value) -> Result<&[u8], RuntimeError> {
+    match value {
+        Value::String(string) => Ok(string.as_bytes()),
+        Value::Bytes(bytes) => Ok(bytes),
+        value => Err(RuntimeError::type_mismatch("string or bytes", value)),
+    }
+}
+
+fn recv_size(size: i64) -> Result<usize, RuntimeError> {
+    match usize::try_from(size) {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(RuntimeError::with_code(
+            ErrorCode::InvalidArgument,
+            format!("Receive size must be positive, got {}", size),
+        )),
+    }
+}
+
+fn net_error(action: &str, target: &str, err: std::io::Error) -> RuntimeError {
+    RuntimeError::with_code(
+        ErrorCode::Io,
+        format!("Failed to {} {}: {}", action, target, err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        eval("(let server (tcp-listen \"127.0.0.1:0\"))");
+        eval("(let client (tcp-connect (socket-addr server)))");
+        eval("(let peer (tcp-accept server))");
+        eval("(tcp-send client \"ping\")");
+        assert_eq!(eval("(tcp-recv peer)"), "ping");
+        eval("(tcp-send peer \"pong\")");
+        assert_eq!(eval("(tcp-recv client 2)"), "po");
+        assert_eq!(eval("(do client)"), "<handle tcp-stream>");
+
+        assert_eq!(eval("(tcp-recv client)"), "ng");
+
+        assert_eq!(eval("(close client)"), "true");
+        assert_eq!(eval("(close client)"), "false");
+        assert_eq!(eval("(tcp-recv peer)"), "");
+
+        eval("(let a (udp-bind \"127.0.0.1:0\"))");
+        eval("(let b (udp-bind \"127.0.0.1:0\"))");
+        eval("(udp-send a (socket-addr b) \"hello\")");
+        assert_eq!(
+            eval("(let (data from) (udp-recv b)) (list data (eq from (socket-addr a)))"),
+            "[hello, true]"
+        );
+
+        let err = interpreter.eval_str("(tcp-send client \"x\")").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidArgument);
+        assert!(interpreter.eval_str("(tcp-accept a)").is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Parsing numbers from strings and converting between value types. The `parse-` builtins
+//! yield an error value on invalid input, so validation code can branch with `error?`
+//! rather than `try`; the `to-` conversions raise a catchable error instead.
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    // syntax: (parse-int <string> [:radix <2-36>])
+    interpreter.register_fn("parse-int", |args| {
+        let (input, radix) = match args {
+            [Value::String(input)] => (input, 10),
+            [Value::String(input), Value::Keyword(keyword), Value::Int(radix)]
+                if &**keyword == "radix" =>
+            {
+                (input, *radix)
+            }
+            [Value::String(_), Value::Keyword(keyword), value] if &**keyword == "radix" => {
+                return Err(RuntimeError::type_mismatch("int", value));
+            }
+            [value, ..] if !matches!(value, Value::String(_)) => {
+                return Err(RuntimeError::type_mismatch("string", value));
+            }
+            _ => return Err(arity("parse-int", "1, or 3 with :radix", args.len())),
+        };
+
+        if !(2..=36).contains(&radix) {
+            return Err(RuntimeError::new(format!(
+                "parse-int radix must be between 2 and 36, got {}",
+                radix
+            )));
+        }
+
+        Ok(match i64::from_str_radix(input.trim(), radix as u32) {
+            Ok(value) => Value::Int(value),
+            Err(err) => match BigInt::parse_radix(input.trim(), radix as u32) {
+                Some(value) => Value::from(value),
+                None => error_value(input, format!("Invalid int: {}", err)),
+            },
+        })
+    });
+
+    interpreter.register_fn("parse-float", |args| match args {
+        [Value::String(input)] => Ok(match input.trim().parse::<f64>() {
+            Ok(value) => Value::Float(value),
+            Err(err) => error_value(input, format!("Invalid float: {}", err)),
+        }),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("parse-float", "1", args.len())),
+    });
+
+    // syntax: (error? <value>), true for the error values returned by the parse builtins
+    interpreter.register_fn("error?", |args| match args {
+        [Value::Map(map)] => Ok(Value::Bool(map.contains_key("error"))),
+        [_] => Ok(Value::Bool(false)),
+        _ => Err(arity("error?", "1", args.len())),
+    });
+
+    // syntax: (to-int <int | rational | float | string | bool>), truncating toward zero
+    interpreter.register_fn("to-int", |args| match args {
+        [value @ (Value::Int(_) | Value::BigInt(_))] => Ok(value.clone()),
+        [Value::Rational(rational)] => Ok(rational.trunc().into()),
+        [Value::Float(fl)] => float_to_int(*fl),
+        [Value::String(input)] => match BigInt::parse(input.trim()) {
+            Some(value) => Ok(Value::from(value)),
+            None => match input.trim().parse::<f64>() {
+                Ok(value) => float_to_int(value),
+                Err(_) => Err(invalid(format!("Cannot convert \"{}\" to int", input))),
+            },
+        },
+        [Value::Bool(b)] => Ok(Value::Int(*b as i64)),
+        [value] => Err(RuntimeError::type_mismatch(
+            "int, float, string or bool",
+            value,
+        )),
+        _ => Err(arity("to-int", "1", args.len())),
+    });
+
+    // syntax: (to-float <int | rational | float | string | bool>)
+    interpreter.register_fn("to-float", |args| match args {
+        [value @ (Value::Int(_) | Value::BigInt(_) | Value::Rational(_) | Value::Float(_))] => {
+            Ok(Value::Float(value.as_f64().unwrap_or(f64::NAN)))
+        }
+        [Value::String(input)] => match input.trim().parse::<f64>() {
+            Ok(value) => Ok(Value::Float(value)),
+            Err(_) => Err(invalid(format!("Cannot convert \"{}\" to float", input))),
+        },
+        [Value::Bool(b)] => Ok(Value::Float(*b as i64 as f64)),
+        [value] => Err(RuntimeError::type_mismatch(
+            "int, float, string or bool",
+            value,
+        )),
+        _ => Err(arity("to-float", "1", args.len())),
+    });
+
+    // syntax: (to-string <value>), as `print` would show it
+    interpreter.register_fn("to-string", |args| match args {
+        [value] => Ok(Value::String(value.to_string().into())),
+        _ => Err(arity("to-string", "1", args.len())),
+    });
+
+    // syntax: (repr <value>), as source that evaluates back to the value
+    interpreter.register_fn("repr", |args| match args {
+        [value] => Ok(Value::String(value.repr().into())),
+        _ => Err(arity("repr", "1", args.len())),
+    });
+}
+
+fn float_to_int(value: f64) -> Result<Value, RuntimeError> {
+    match BigInt::from_f64(value) {
+        Some(int) => Ok(Value::from(int)),
+        None => Err(invalid(format!("Cannot convert {} to int", value))),
+    }
+}
+
+fn invalid(message: String) -> RuntimeError {
+    RuntimeError::with_code(ErrorCode::InvalidArgument, message)
+}
+
+/// A map `{error: <message>, input: <input>}`.
+fn error_value(input: &str, message: String) -> Value {
+    Value::Map(Rc::new(BTreeMap::from([
+        ("error".to_string(), Value::String(message.into())),
+        ("input".to_string(), Value::String(input.into())),
+    ])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_numbers() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(eval("(parse-int \" -42 \")"), "-42");
+        assert_eq!(eval("(parse-int \"ff\" :radix 16)"), "255");
+        assert_eq!(eval("(parse-float \"2.5\")"), "2.5");
+        assert_eq!(
+            eval("(parse-int \"12a\")"),
+            "{error: Invalid int: invalid digit found in string, input: 12a}"
+        );
+        assert_eq!(
+            eval("(list (error? (parse-float \"x\")) (error? (parse-float \"1e3\")))"),
+            "[true, false]"
+        );
+
+        assert_eq!(
+            eval("(list (to-int -2.7) (to-int \" 12 \") (to-int \"3.9\") (to-int true) (to-float \"1e3\") (float? (to-float 2)))"),
+            "[-2, 12, 3, 1, 1000, true]"
+        );
+        assert_eq!(eval("(to-string (list 1 \"a\"))"), "[1, a]");
+        assert_eq!(
+            eval("(repr (list 2.0 0.1 1e21 \"a\" (dict \"k\" 1.5)))"),
+            "(list 2.0 0.1 1e21 \"a\" (dict \"k\" 1.5))"
+        );
+        assert_eq!(eval("(eq (parse-float (repr 0.1)) 0.1)"), "true");
+        assert_eq!(
+            eval("(try (to-int \"abc\") (catch e (get e)))"),
+            "Cannot convert \"abc\" to int"
+        );
+
+        assert_eq!(
+            eval("(list (to-int 1e20) (to-int \"-99999999999999999999\") (parse-int \"ffffffffffffffff\" :radix 16))"),
+            "[100000000000000000000, -99999999999999999999, 18446744073709551615]"
+        );
+
+        assert!(interpreter.eval_str("(parse-int \"1\" :radix 1)").is_err());
+        assert!(interpreter.eval_str("(parse-int 1)").is_err());
+
+        let err = interpreter.eval_str("(to-int (sqrt -1))").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidArgument);
+        assert!(interpreter.eval_str("(to-float null)").is_err());
+    }
+}
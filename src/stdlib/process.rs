@@ -0,0 +1,116 @@
+//! Running other programs.
+
+use std::collections::BTreeMap;
+use std::process::{Command, Output, Stdio};
+use std::rc::Rc;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::Value;
+use crate::Interpreter;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    interpreter.set_fuel_category("exec", FuelCategory::Exec);
+    interpreter.set_fuel_category("exec-capture", FuelCategory::Exec);
+
+    // syntax: (exec <program> <arg>...), returns the exit code
+    interpreter.register_native("exec", |interpreter, args| {
+        let mut command = command("exec", args)?;
+
+        // Output captured by the embedder (or by `kk test`) must not bypass it
+        let status = match interpreter.has_output_sink() {
+            true => {
+                let output = spawn(command.stdout(Stdio::piped()), args)?;
+                interpreter.write_output(&String::from_utf8_lossy(&output.stdout));
+
+                output.status
+            }
+            false => command.status().map_err(|err| spawn_error(&args[0], err))?,
+        };
+
+        Ok(Value::Int(status.code().unwrap_or(-1) as i64))
+    });
+
+    // syntax: (exec-capture <program> <arg>...), returns {stdout, stderr, exit-code}
+    interpreter.register_fn("exec-capture", |args| {
+        let mut command = command("exec-capture", args)?;
+        let output = spawn(command.stdout(Stdio::piped()).stderr(Stdio::piped()), args)?;
+
+        Ok(Value::Map(Rc::new(BTreeMap::from([
+            (
+                "stdout".to_string(),
+                Value::String(String::from_utf8_lossy(&output.stdout).into()),
+            ),
+            (
+                "stderr".to_string(),
+                Value::String(String::from_utf8_lossy(&output.stderr).into()),
+            ),
+            (
+                "exit-code".to_string(),
+                Value::Int(output.status.code().unwrap_or(-1) as i64),
+            ),
+        ]))))
+    });
+}
+
+/// Builds the command; the program and its arguments must all be strings. A program killed
+/// by a signal reports exit code -1.
+fn command(name: &str, args: &[Value]) -> Result<Command, RuntimeError> {
+    let mut strings = vec![];
+
+    for arg in args {
+        match arg {
+            Value::String(arg) => strings.push(&**arg),
+            value => return Err(RuntimeError::type_mismatch("string", value)),
+        }
+    }
+
+    let Some((program, args)) = strings.split_first() else {
+        return Err(super::arity(name, "at least 1", 0));
+    };
+
+    let mut command = Command::new(program);
+    command.args(args).stdin(Stdio::inherit());
+
+    Ok(command)
+}
+
+fn spawn(command: &mut Command, args: &[Value]) -> Result<Output, RuntimeError> {
+    command.output().map_err(|err| spawn_error(&args[0], err))
+}
+
+fn spawn_error(program: &Value, err: std::io::Error) -> RuntimeError {
+    RuntimeError::with_code(ErrorCode::Io, format!("Unable to run {}: {}", program, err))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec() {
+        let output = std::rc::Rc::new(std::cell::RefCell::new(String::new()));
+        let sink = output.clone();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(move |text| sink.borrow_mut().push_str(text));
+
+        let result = interpreter
+            .eval_str("(list (exec \"sh\" \"-c\" \"echo hi; exit 3\") (exec-capture \"sh\" \"-c\" \"echo out; echo err >&2\"))")
+            .unwrap();
+
+        assert_eq!(
+            result.to_string(),
+            "[3, {exit-code: 0, stderr: err\n, stdout: out\n}]"
+        );
+        assert_eq!(*output.borrow(), "hi\n");
+        assert_eq!(
+            interpreter
+                .eval_str("(exec \"kk-no-such-program\")")
+                .unwrap_err()
+                .code(),
+            ErrorCode::Io
+        );
+    }
+}
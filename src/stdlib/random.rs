@@ -0,0 +1,96 @@
+//! Random numbers. Each interpreter has its own generator, seeded from the clock unless a
+//! script calls `random-seed`. Not suitable for cryptography.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::error::RuntimeError;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+        ^ u64::from(std::process::id()).rotate_left(32);
+
+    let state = Rc::new(Cell::new(seed));
+
+    let generator = state.clone();
+    interpreter.register_fn("random", move |args| match args {
+        // 53 random bits, the precision of an f64 in [0, 1)
+        [] => Ok(Value::Float(
+            (next(&generator) >> 11) as f64 / (1u64 << 53) as f64,
+        )),
+        _ => Err(arity("random", "0", args.len())),
+    });
+
+    // syntax: (random-int <lo> <hi>), both bounds included
+    let generator = state.clone();
+    interpreter.register_fn("random-int", move |args| match args {
+        [Value::Int(lo), Value::Int(hi)] if lo <= hi => {
+            let range = (*hi as i128 - *lo as i128 + 1) as u128;
+            let offset = (next(&generator) as u128 * range) >> 64;
+
+            Ok(Value::Int((*lo as i128 + offset as i128) as i64))
+        }
+        [Value::Int(lo), Value::Int(hi)] => Err(RuntimeError::new(format!(
+            "random-int expects lo <= hi, got {} and {}",
+            lo, hi
+        ))),
+        [Value::Int(_), value] | [value, _] => Err(RuntimeError::type_mismatch("int", value)),
+        _ => Err(arity("random-int", "2", args.len())),
+    });
+
+    interpreter.register_fn("random-seed", move |args| match args {
+        [Value::Int(seed)] => {
+            state.set(*seed as u64);
+            Ok(Value::Void)
+        }
+        [value] => Err(RuntimeError::type_mismatch("int", value)),
+        _ => Err(arity("random-seed", "1", args.len())),
+    });
+}
+
+/// SplitMix64.
+fn next(state: &Cell<u64>) -> u64 {
+    let value = state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+    state.set(value);
+
+    let value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+    value ^ (value >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        let sample = "(list (random-int 1 6) (random-int 1 6) (random-int 1 6) (random))";
+        let first = eval(&format!("(random-seed 42) {}", sample));
+        let second = eval(&format!("(random-seed 42) {}", sample));
+
+        assert_eq!(first, second);
+        assert_eq!(eval("(random-int 7 7)"), "7");
+
+        for _ in 0..100 {
+            let value = interpreter.eval_str("(random)").unwrap();
+            assert!(matches!(value, Value::Float(fl) if (0.0..1.0).contains(&fl)));
+
+            let value = interpreter.eval_str("(random-int -2 2)").unwrap();
+            assert!(matches!(value, Value::Int(i) if (-2..=2).contains(&i)));
+        }
+
+        assert!(interpreter.eval_str("(random-int 2 1)").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! Regular expression builtins, using the syntax of the `regex` crate. Like the other string
+//! builtins they take the string first and the pattern second.
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use regex::{Captures, Regex};
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    for name in ["re-match", "re-find-all", "re-replace", "re-split"] {
+        interpreter.set_fuel_category(name, FuelCategory::Regex);
+    }
+
+    // syntax: (re-match <string> <pattern>), the groups of the first match or null
+    interpreter.register_fn("re-match", |args| match args {
+        [Value::String(s), Value::String(pattern)] => {
+            let regex = compile(pattern)?;
+
+            Ok(regex
+                .captures(s)
+                .map_or(Value::Null, |captures| groups(&regex, &captures)))
+        }
+        [Value::String(_), value] | [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("re-match", "2", args.len())),
+    });
+
+    // syntax: (re-find-all <string> <pattern>), the groups of every match
+    interpreter.register_fn("re-find-all", |args| match args {
+        [Value::String(s), Value::String(pattern)] => {
+            let regex = compile(pattern)?;
+
+            Ok(Value::List(
+                regex
+                    .captures_iter(s)
+                    .map(|captures| groups(&regex, &captures))
+                    .collect::<Vec<_>>()
+                    .into(),
+            ))
+        }
+        [Value::String(_), value] | [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("re-find-all", "2", args.len())),
+    });
+
+    // syntax: (re-replace <string> <pattern> <replacement>), `$1` and `$name` refer to groups
+    interpreter.register_fn("re-replace", |args| match args {
+        [Value::String(s), Value::String(pattern), Value::String(replacement)] => {
+            Ok(Value::String(
+                compile(pattern)?
+                    .replace_all(s, &**replacement)
+                    .into_owned()
+                    .into(),
+            ))
+        }
+        [Value::String(_), Value::String(_), value]
+        | [Value::String(_), value, _]
+        | [value, ..]
+            if args.len() == 3 =>
+        {
+            Err(RuntimeError::type_mismatch("string", value))
+        }
+        _ => Err(arity("re-replace", "3", args.len())),
+    });
+
+    interpreter.register_fn("re-split", |args| match args {
+        [Value::String(s), Value::String(pattern)] => Ok(Value::List(
+            compile(pattern)?
+                .split(s)
+                .map(|part| Value::String(part.into()))
+                .collect::<Vec<_>>()
+                .into(),
+        )),
+        [Value::String(_), value] | [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("re-split", "2", args.len())),
+    });
+}
+
+fn compile(pattern: &str) -> Result<Regex, RuntimeError> {
+    Regex::new(pattern).map_err(|err| {
+        RuntimeError::with_code(
+            ErrorCode::InvalidArgument,
+            format!("Invalid regex {:?}: {}", pattern, err),
+        )
+    })
+}
+
+/// The matched text when the pattern has no groups, a map of the named groups when it has
+/// any, and otherwise a list of the whole match followed by each group. Groups that did not
+/// participate in the match are null.
+fn groups(regex: &Regex, captures: &Captures) -> Value {
+    let text = |group: Option<regex::Match>| {
+        group.map_or(Value::Null, |group| Value::String(group.as_str().into()))
+    };
+
+    if regex.capture_names().flatten().next().is_some() {
+        return Value::Map(
+            (regex
+                .capture_names()
+                .flatten()
+                .map(|name| (name.to_string(), text(captures.name(name))))
+                .collect::<BTreeMap<String, Value>>())
+            .into(),
+        );
+    }
+
+    match captures.len() {
+        1 => text(captures.get(0)),
+        _ => Value::List(Rc::new(captures.iter().map(text).collect())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(eval("(re-match \"v1.22\" \"[0-9]+\")"), "1");
+        assert_eq!(
+            eval("(re-match \"v1.22\" \"(\\d+)\\.(\\d+)(-rc)?\")"),
+            "[1.22, 1, 22, null]"
+        );
+        assert_eq!(
+            eval("(re-match \"a=1\" \"(?P<key>\\w+)=(?P<value>\\w+)\")"),
+            "{key: a, value: 1}"
+        );
+        assert_eq!(eval("(re-match \"abc\" \"[0-9]\")"), "null");
+        assert_eq!(eval("(re-find-all \"a1 b22 c\" \"[0-9]+\")"), "[1, 22]");
+        assert_eq!(
+            eval("(re-replace \"john smith\" \"(\\w+) (\\w+)\" \"$2 $1\")"),
+            "smith john"
+        );
+        assert_eq!(eval("(re-split \"a, b,c\" \",\\s*\")"), "[a, b, c]");
+
+        let err = interpreter.eval_str("(re-match \"a\" \"(\")").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidArgument);
+    }
+}
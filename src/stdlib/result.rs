@@ -0,0 +1,122 @@
+//! Result maps, an alternative to `try` for fallible builtins. Each `try-<name>` variant
+//! returns `{ok: <value>}` on success or `{err: <message>, code: <error code>}` on failure.
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+/// The builtins that get a `try-` variant.
+const FALLIBLE: [(&str, Option<FuelCategory>); 9] = [
+    ("read-file", Some(FuelCategory::Io)),
+    ("write-file", Some(FuelCategory::Io)),
+    ("append-file", Some(FuelCategory::Io)),
+    ("json-parse", None),
+    ("json-stringify", None),
+    ("exec", Some(FuelCategory::Exec)),
+    ("exec-capture", Some(FuelCategory::Exec)),
+    ("http-get", Some(FuelCategory::Http)),
+    ("http-post", Some(FuelCategory::Http)),
+];
+
+/// Must run after the builtins listed in `FALLIBLE` are registered.
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    for (name, category) in FALLIBLE {
+        let Some(Value::Native(native)) = interpreter.native(name) else {
+            unreachable!("{} is not registered", name);
+        };
+
+        let try_name = format!("try-{}", name);
+
+        if let Some(category) = category {
+            interpreter.set_fuel_category(&try_name, category);
+        }
+
+        interpreter.register_native(&try_name, move |interpreter, args| {
+            match (native.function)(interpreter, args) {
+                Ok(value) => Ok(ok_value(value)),
+                Err(err) if err.is_catchable() => Ok(err_value(&err)),
+                Err(err) => Err(err),
+            }
+        });
+    }
+
+    interpreter.register_fn("ok?", |args| match args {
+        [value] => Ok(Value::Bool(result_parts(value)?.0.is_some())),
+        _ => Err(arity("ok?", "1", args.len())),
+    });
+
+    // syntax: (unwrap <result>), raises the error of a failed result
+    interpreter.register_fn("unwrap", |args| match args {
+        [value] => match result_parts(value)? {
+            (Some(value), _) => Ok(value.clone()),
+            (None, err) => Err(RuntimeError::with_code(
+                ErrorCode::InvalidArgument,
+                format!("Called unwrap on an error result: {}", err),
+            )),
+        },
+        _ => Err(arity("unwrap", "1", args.len())),
+    });
+
+    // syntax: (unwrap-or <result> <default>)
+    interpreter.register_fn("unwrap-or", |args| match args {
+        [value, default] => Ok(result_parts(value)?.0.unwrap_or(default).clone()),
+        _ => Err(arity("unwrap-or", "2", args.len())),
+    });
+}
+
+fn ok_value(value: Value) -> Value {
+    Value::Map(BTreeMap::from([("ok".to_string(), value)]).into())
+}
+
+fn err_value(err: &RuntimeError) -> Value {
+    Value::Map(Rc::new(BTreeMap::from([
+        ("err".to_string(), Value::String(err.to_string().into())),
+        (
+            "code".to_string(),
+            Value::String(err.code().to_string().into()),
+        ),
+    ])))
+}
+
+/// Splits a result map into its value, if it succeeded, and its error message.
+fn result_parts(value: &Value) -> Result<(Option<&Value>, &Value), RuntimeError> {
+    match value {
+        Value::Map(map) if map.contains_key("ok") => Ok((map.get("ok"), &Value::Void)),
+        Value::Map(map) if map.contains_key("err") => Ok((None, &map["err"])),
+        value => Err(RuntimeError::type_mismatch("result map", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_maps() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(eval("(try-json-parse \"[1]\")"), "{ok: [1]}");
+        assert!(eval("(try-read-file \"/nonexistent/kk\")").starts_with("{code: E0008, err: "));
+        assert_eq!(
+            eval("(list (ok? (try-json-parse \"1\")) (ok? (try-json-parse \"{\")))"),
+            "[true, false]"
+        );
+        assert_eq!(eval("(unwrap (try-json-parse \"2\"))"), "2");
+        assert_eq!(eval("(unwrap-or (try-json-parse \"{\") 0)"), "0");
+
+        let err = interpreter
+            .eval_str("(unwrap (try-json-parse \"{\"))")
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidArgument);
+        assert!(interpreter.eval_str("(ok? 1)").is_err());
+    }
+}
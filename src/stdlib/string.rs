@@ -0,0 +1,208 @@
+//! String builtins.
+
+use std::rc::Rc;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    let names = [
+        "trim",
+        "upper",
+        "lower",
+        "split",
+        "join",
+        "replace",
+        "contains",
+        "starts-with",
+        "ends-with",
+        "chars",
+        "char-at",
+    ];
+
+    for name in names {
+        interpreter.set_fuel_category(name, FuelCategory::String);
+    }
+
+    unary(interpreter, "trim", |s| s.trim().to_string());
+    unary(interpreter, "upper", str::to_uppercase);
+    unary(interpreter, "lower", str::to_lowercase);
+
+    interpreter.register_fn("split", |args| match args {
+        [Value::String(s), Value::String(separator)] => {
+            let parts = match separator.is_empty() {
+                // An empty separator splits into characters
+                true => s.chars().map(String::from).collect::<Vec<String>>(),
+                false => s.split(&**separator).map(String::from).collect(),
+            };
+
+            Ok(Value::List(Rc::new(
+                parts
+                    .into_iter()
+                    .map(|part| Value::String(part.into()))
+                    .collect(),
+            )))
+        }
+        [Value::String(_), value] | [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("split", "2", args.len())),
+    });
+
+    // syntax: (join <list> <separator>), elements are joined as `print` displays them
+    interpreter.register_fn("join", |args| match args {
+        [Value::List(values), Value::String(separator)] => Ok(Value::String(
+            (values
+                .iter()
+                .map(Value::to_string)
+                .collect::<Vec<String>>()
+                .join(separator))
+            .into(),
+        )),
+        [Value::List(_), value] => Err(RuntimeError::type_mismatch("string", value)),
+        [value, _] => Err(RuntimeError::type_mismatch("list", value)),
+        _ => Err(arity("join", "2", args.len())),
+    });
+
+    interpreter.register_fn("replace", |args| match args {
+        [Value::String(s), Value::String(from), Value::String(to)] => {
+            Ok(Value::String(s.replace(&**from, to).into()))
+        }
+        [_, _, _] => {
+            let value = args
+                .iter()
+                .find(|value| !matches!(value, Value::String(_)))
+                .unwrap();
+
+            Err(RuntimeError::type_mismatch("string", value))
+        }
+        _ => Err(arity("replace", "3", args.len())),
+    });
+
+    // syntax: (contains <string-list-or-map> <value>), maps are searched by key
+    interpreter.register_native("contains", |interpreter, args| match args {
+        [Value::String(s), Value::String(needle)] => Ok(Value::Bool(s.contains(&**needle))),
+        [Value::String(_), value] => Err(RuntimeError::type_mismatch("string", value)),
+        [Value::List(values), needle] => {
+            for value in values.iter() {
+                if interpreter.values_equal(value, needle)? {
+                    return Ok(Value::Bool(true));
+                }
+            }
+
+            Ok(Value::Bool(false))
+        }
+        [Value::Map(map), Value::String(key)] => Ok(Value::Bool(map.contains_key(&**key))),
+        [Value::Map(_), value] => Err(RuntimeError::type_mismatch("string", value)),
+        [value, _] => Err(RuntimeError::type_mismatch("string, list or map", value)),
+        _ => Err(arity("contains", "2", args.len())),
+    });
+    predicate(interpreter, "starts-with", |s, prefix| {
+        s.starts_with(prefix)
+    });
+    predicate(interpreter, "ends-with", |s, suffix| s.ends_with(suffix));
+
+    // syntax: (chars <string>), the list of its chars
+    interpreter.register_fn("chars", |args| match args {
+        [Value::String(s)] => Ok(Value::List(Rc::new(s.chars().map(Value::Char).collect()))),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("chars", "1", args.len())),
+    });
+
+    // syntax: (char-at <string> <index>), counting chars rather than bytes, or null past the end
+    interpreter.register_fn("char-at", |args| match args {
+        [Value::String(s), Value::Int(index)] => Ok(usize::try_from(*index)
+            .ok()
+            .and_then(|index| s.chars().nth(index))
+            .map_or(Value::Null, Value::Char)),
+        [Value::String(_), value] => Err(RuntimeError::type_mismatch("int", value)),
+        [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("char-at", "2", args.len())),
+    });
+
+    // syntax: (char-code <char>), its Unicode code point
+    interpreter.register_fn("char-code", |args| match args {
+        [Value::Char(c)] => Ok(Value::Int(*c as i64)),
+        [value] => Err(RuntimeError::type_mismatch("char", value)),
+        _ => Err(arity("char-code", "1", args.len())),
+    });
+
+    // syntax: (code-char <int>), the char with this Unicode code point
+    interpreter.register_fn("code-char", |args| match args {
+        [Value::Int(code)] => u32::try_from(*code)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Value::Char)
+            .ok_or_else(|| {
+                RuntimeError::with_code(
+                    ErrorCode::InvalidArgument,
+                    format!("{} is not a Unicode code point", code),
+                )
+            }),
+        [value] => Err(RuntimeError::type_mismatch("int", value)),
+        _ => Err(arity("code-char", "1", args.len())),
+    });
+}
+
+/// Registers a builtin taking one string and returning a string.
+fn unary(interpreter: &mut Interpreter, name: &'static str, function: fn(&str) -> String) {
+    interpreter.register_fn(name, move |args| match args {
+        [Value::String(s)] => Ok(Value::String(function(s).into())),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity(name, "1", args.len())),
+    });
+}
+
+/// Registers a builtin taking two strings and returning a bool.
+fn predicate(interpreter: &mut Interpreter, name: &'static str, function: fn(&str, &str) -> bool) {
+    interpreter.register_fn(name, move |args| match args {
+        [Value::String(s), Value::String(other)] => Ok(Value::Bool(function(s, other))),
+        [Value::String(_), value] | [value, _] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity(name, "2", args.len())),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(eval("(split \"a,b,,c\" \",\")"), "[a, b, , c]");
+        assert_eq!(eval("(split \"héj\" \"\")"), "[h, é, j]");
+        assert_eq!(eval("(join (list 1 \"b\" :c) \"-\")"), "1-b-:c");
+        assert_eq!(eval("(upper (trim \"  kk \n\"))"), "KK");
+        assert_eq!(eval("(lower \"KK\")"), "kk");
+        assert_eq!(eval("(replace \"a.b.c\" \".\" \"/\")"), "a/b/c");
+        assert_eq!(
+            eval("(list (contains \"kk-lang\" \"-\") (starts-with \"kk\" \"k\") (ends-with \"kk\" \"x\"))"),
+            "[true, true, false]"
+        );
+
+        assert_eq!(eval("(chars \"héj\")"), "[h, é, j]");
+        assert_eq!(eval("(repr (chars \"a (\"))"), "(list #\\a #\\space #\\()");
+        assert_eq!(
+            eval("(list (char-at \"héj\" 1) (char-at \"héj\" 3) (eq (char-at \"a)\" 1) #\\)))"),
+            "[é, null, true]"
+        );
+        assert_eq!(
+            eval(
+                "(list (char-code #\\A) (code-char 955) (type-of #\\newline) (compare #\\a #\\b))"
+            ),
+            "[65, λ, char, -1]"
+        );
+        assert_eq!(eval("(join (chars \"kk\") \"-\")"), "k-k");
+
+        assert!(interpreter.eval_str("(code-char 55296)").is_err());
+        assert!(interpreter.eval_str("(char-code \"a\")").is_err());
+        assert!(interpreter.eval_str("(upper 1)").is_err());
+        assert!(interpreter.eval_str("(replace \"a\" 1 \"b\")").is_err());
+    }
+}
@@ -0,0 +1,643 @@
+//! Async tasks on a single-threaded event loop. `(async body...)` returns a task whose body
+//! runs when it is first awaited. The `-async` builtins start an I/O operation and return a
+//! task right away; every `await` drives all pending operations, so requests started
+//! together progress concurrently without threads.
+//!
+//! An `async` body is not started ahead of time, so its own I/O only begins once it is
+//! awaited. `await-all` awaits its tasks in list order, running each body to completion
+//! before starting the next: to overlap requests, start them with the `-async` builtins
+//! and await the tasks together.
+//!
+//! `http-get-async` and `http-post-async` speak plain `http://` only, and resolve host names
+//! before returning. Files are read on the event loop's next turn, as regular files never
+//! wait for long. A stream passed to `tcp-recv-async` must not be used until the task is
+//! awaited.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{ErrorKind, Read, Write};
+use std::net::ToSocketAddrs;
+use std::rc::Rc;
+
+use mio::event::Event;
+use mio::net::TcpStream;
+use mio::{Events, Interest, Poll, Token};
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::fuel::FuelCategory;
+use crate::value::{Function, Handle, Value};
+use crate::Interpreter;
+
+use super::arity;
+
+enum Task {
+    /// An `async` body, evaluated when the task is first awaited.
+    Deferred(Rc<Function>),
+    /// The task is being awaited.
+    Running,
+    /// An operation pending on the event loop.
+    Io(Token),
+    Done(Result<Value, RuntimeError>),
+}
+
+/// Wraps an `async` body in a task, for the `async` form.
+pub(crate) fn async_task(body: Rc<Function>) -> Value {
+    task(Task::Deferred(body))
+}
+
+fn task(task: Task) -> Value {
+    Value::Handle(Rc::new(Handle::new("task", task)))
+}
+
+enum Operation {
+    ReadFile(String),
+    Http(Exchange),
+    Recv {
+        stream: TcpStream,
+        /// The stream shared with the script's handle, made blocking again once done.
+        original: std::net::TcpStream,
+        size: usize,
+    },
+}
+
+/// An HTTP/1.1 request over a non-blocking connection, answered when the server closes it.
+struct Exchange {
+    stream: TcpStream,
+    url: String,
+    request: Vec<u8>,
+    written: usize,
+    response: Vec<u8>,
+}
+
+#[derive(Default)]
+struct EventLoop {
+    /// Created with the first network operation.
+    poll: Option<(Poll, Events)>,
+    pending: HashMap<Token, Operation>,
+    finished: HashMap<Token, Result<Value, RuntimeError>>,
+    next_token: usize,
+}
+
+impl EventLoop {
+    fn start(&mut self, mut operation: Operation) -> Result<Value, RuntimeError> {
+        let token = Token(self.next_token);
+        self.next_token += 1;
+
+        let source = match &mut operation {
+            Operation::ReadFile(_) => None,
+            Operation::Http(exchange) => Some((
+                &mut exchange.stream,
+                Interest::READABLE | Interest::WRITABLE,
+            )),
+            Operation::Recv { stream, .. } => Some((stream, Interest::READABLE)),
+        };
+
+        if let Some((stream, interest)) = source {
+            if self.poll.is_none() {
+                let poll = Poll::new().map_err(|err| io_error("start the event loop", err))?;
+                self.poll = Some((poll, Events::with_capacity(64)));
+            }
+
+            if let Some((poll, _)) = &self.poll {
+                poll.registry()
+                    .register(stream, token, interest)
+                    .map_err(|err| io_error("register a socket", err))?;
+            }
+        }
+
+        self.pending.insert(token, operation);
+
+        Ok(task(Task::Io(token)))
+    }
+
+    /// Runs the event loop until the operation of `token` is done.
+    fn run_until(&mut self, token: Token) -> Result<Value, RuntimeError> {
+        loop {
+            if let Some(result) = self.finished.remove(&token) {
+                return result;
+            }
+
+            self.turn()?;
+        }
+    }
+
+    fn turn(&mut self) -> Result<(), RuntimeError> {
+        let files = self
+            .pending
+            .iter()
+            .filter(|(_, operation)| matches!(operation, Operation::ReadFile(_)))
+            .map(|(token, _)| *token)
+            .collect::<Vec<Token>>();
+
+        if !files.is_empty() {
+            for token in files {
+                if let Some(Operation::ReadFile(path)) = self.pending.remove(&token) {
+                    let result = std::fs::read_to_string(&path)
+                        .map(|text| Value::String(text.into()))
+                        .map_err(|err| io_error(&format!("read {}", path), err));
+
+                    self.finished.insert(token, result);
+                }
+            }
+
+            return Ok(());
+        }
+
+        let Some((poll, events)) = &mut self.poll else {
+            return Err(RuntimeError::new("No pending operation to wait for"));
+        };
+
+        match poll.poll(events, None) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::Interrupted => return Ok(()),
+            Err(err) => return Err(io_error("wait for I/O", err)),
+        }
+
+        for event in events.iter() {
+            let token = event.token();
+
+            let Some(operation) = self.pending.get_mut(&token) else {
+                continue;
+            };
+
+            let Some(result) = advance(operation, event) else {
+                continue;
+            };
+
+            if let Some(operation) = self.pending.remove(&token) {
+                let stream = match operation {
+                    Operation::Http(exchange) => Some(exchange.stream),
+                    Operation::Recv {
+                        stream, original, ..
+                    } => {
+                        let _ = original.set_nonblocking(false);
+                        Some(stream)
+                    }
+                    Operation::ReadFile(_) => None,
+                };
+
+                if let Some(mut stream) = stream {
+                    let _ = poll.registry().deregister(&mut stream);
+                }
+            }
+
+            self.finished.insert(token, result);
+        }
+
+        Ok(())
+    }
+}
+
+/// Makes progress on a network operation, returning its result once it is done.
+fn advance(operation: &mut Operation, event: &Event) -> Option<Result<Value, RuntimeError>> {
+    match operation {
+        Operation::ReadFile(_) => None,
+        Operation::Http(exchange) => {
+            if event.is_error() {
+                let err = exchange.stream.take_error().ok().flatten();
+                let err = err.unwrap_or_else(|| ErrorKind::ConnectionRefused.into());
+                return Some(Err(io_error(&format!("request {}", exchange.url), err)));
+            }
+
+            while event.is_writable() && exchange.written < exchange.request.len() {
+                match exchange.stream.write(&exchange.request[exchange.written..]) {
+                    Ok(written) => exchange.written += written,
+                    Err(err) if would_block(&err) => break,
+                    Err(err) => {
+                        return Some(Err(io_error(&format!("request {}", exchange.url), err)));
+                    }
+                }
+            }
+
+            let mut buffer = [0; 4096];
+
+            while event.is_readable() || event.is_read_closed() {
+                match exchange.stream.read(&mut buffer) {
+                    Ok(0) => return Some(parse_response(&exchange.url, &exchange.response)),
+                    Ok(read) => exchange.response.extend_from_slice(&buffer[..read]),
+                    Err(err) if would_block(&err) => break,
+                    Err(err) => {
+                        return Some(Err(io_error(&format!("request {}", exchange.url), err)));
+                    }
+                }
+            }
+
+            None
+        }
+        Operation::Recv { stream, size, .. } => {
+            let mut buffer = vec![0; *size];
+
+            match stream.read(&mut buffer) {
+                Ok(read) => Some(Ok(Value::String(
+                    String::from_utf8_lossy(&buffer[..read]).into(),
+                ))),
+                Err(err) if would_block(&err) => None,
+                Err(err) => Some(Err(io_error("receive", err))),
+            }
+        }
+    }
+}
+
+fn would_block(err: &std::io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::NotConnected)
+}
+
+/// Connects to an `http://` URL and prepares the request to send once connected.
+fn exchange(
+    method: &str,
+    url: &str,
+    body: &str,
+    headers: &BTreeMap<String, Value>,
+) -> Result<Exchange, RuntimeError> {
+    let Some(rest) = url.strip_prefix("http://") else {
+        return Err(RuntimeError::with_code(
+            ErrorCode::InvalidArgument,
+            format!("Async HTTP supports http:// URLs only, got {}", url),
+        ));
+    };
+
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+
+    let address = match authority.contains(':') {
+        true => authority.to_string(),
+        false => format!("{}:80", authority),
+    };
+
+    let address = address
+        .to_socket_addrs()
+        .map_err(|err| io_error(&format!("resolve {}", authority), err))?
+        .next()
+        .ok_or_else(|| {
+            io_error(
+                &format!("resolve {}", authority),
+                ErrorKind::NotFound.into(),
+            )
+        })?;
+
+    let stream =
+        TcpStream::connect(address).map_err(|err| io_error(&format!("connect to {}", url), err))?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        authority,
+        body.len()
+    );
+
+    for (name, value) in headers {
+        match value {
+            Value::String(value) => request.push_str(&format!("{}: {}\r\n", name, value)),
+            value => request.push_str(&format!("{}: {}\r\n", name, value)),
+        }
+    }
+
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    Ok(Exchange {
+        stream,
+        url: url.to_string(),
+        request: request.into_bytes(),
+        written: 0,
+        response: vec![],
+    })
+}
+
+fn parse_response(url: &str, response: &[u8]) -> Result<Value, RuntimeError> {
+    let invalid = || RuntimeError::new(format!("Invalid HTTP response from {}", url));
+
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(invalid)?;
+
+    let head = String::from_utf8_lossy(&response[..split]);
+    let mut body = &response[split + 4..];
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(invalid)?;
+
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| {
+            (
+                name.trim().to_lowercase(),
+                Value::String(value.trim().into()),
+            )
+        })
+        .collect::<BTreeMap<String, Value>>();
+
+    let chunked = matches!(
+        headers.get("transfer-encoding"),
+        Some(Value::String(encoding)) if encoding.eq_ignore_ascii_case("chunked")
+    );
+
+    let decoded;
+
+    if chunked {
+        decoded = decode_chunked(body).ok_or_else(invalid)?;
+        body = &decoded;
+    } else if let Some(Value::String(length)) = headers.get("content-length") {
+        let length = length.parse::<usize>().map_err(|_| invalid())?;
+        body = body.get(..length).ok_or_else(invalid)?;
+    }
+
+    Ok(super::http::response_map(
+        status,
+        headers,
+        String::from_utf8_lossy(body).into_owned(),
+    ))
+}
+
+fn decode_chunked(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+
+        if size == 0 {
+            return Some(decoded);
+        }
+
+        let chunk = body.get(line_end + 2..line_end + 2 + size)?;
+        decoded.extend_from_slice(chunk);
+        body = body.get(line_end + 4 + size..)?;
+    }
+}
+
+fn io_error(action: &str, err: std::io::Error) -> RuntimeError {
+    RuntimeError::with_code(ErrorCode::Io, format!("Failed to {}: {}", action, err))
+}
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    let event_loop = Rc::new(RefCell::new(EventLoop::default()));
+
+    for name in ["http-get-async", "http-post-async"] {
+        interpreter.set_fuel_category(name, FuelCategory::Http);
+    }
+
+    for name in ["read-file-async", "tcp-recv-async"] {
+        interpreter.set_fuel_category(name, FuelCategory::Io);
+    }
+
+    let state = event_loop.clone();
+    // syntax: (await <task>), returns the task's value or raises its error
+    interpreter.register_native("await", move |interpreter, args| match args {
+        [task] => await_task(interpreter, &state, task),
+        _ => Err(arity("await", "1", args.len())),
+    });
+
+    let state = event_loop.clone();
+    // syntax: (await-all <list of tasks>), awaits the tasks in order; an async body starts
+    // only once the ones before it have finished
+    interpreter.register_native("await-all", move |interpreter, args| match args {
+        [Value::List(tasks)] => Ok(Value::List(
+            tasks
+                .iter()
+                .map(|task| await_task(interpreter, &state, task))
+                .collect::<Result<Vec<_>, _>>()?
+                .into(),
+        )),
+        [value] => Err(RuntimeError::type_mismatch("list", value)),
+        _ => Err(arity("await-all", "1", args.len())),
+    });
+
+    let state = event_loop.clone();
+    // syntax: (read-file-async <path>)
+    interpreter.register_fn("read-file-async", move |args| match args {
+        [Value::String(path)] => state
+            .borrow_mut()
+            .start(Operation::ReadFile(path.to_string())),
+        [value] => Err(RuntimeError::type_mismatch("string", value)),
+        _ => Err(arity("read-file-async", "1", args.len())),
+    });
+
+    let state = event_loop.clone();
+    // syntax: (http-get-async <url> [headers])
+    interpreter.register_fn("http-get-async", move |args| {
+        let (url, headers) = match args {
+            [Value::String(url)] => (url, &BTreeMap::new()),
+            [Value::String(url), Value::Map(headers)] => (url, &**headers),
+            [Value::String(_), value] => return Err(RuntimeError::type_mismatch("map", value)),
+            [value] | [value, _] => return Err(RuntimeError::type_mismatch("string", value)),
+            _ => return Err(arity("http-get-async", "1 or 2", args.len())),
+        };
+
+        let exchange = exchange("GET", url, "", headers)?;
+        state.borrow_mut().start(Operation::Http(exchange))
+    });
+
+    let state = event_loop.clone();
+    // syntax: (http-post-async <url> <body> [headers])
+    interpreter.register_fn("http-post-async", move |args| {
+        let (url, body, headers) = match args {
+            [Value::String(url), Value::String(body)] => (url, body, &BTreeMap::new()),
+            [Value::String(url), Value::String(body), Value::Map(headers)] => {
+                (url, body, &**headers)
+            }
+            [Value::String(_), Value::String(_), value] => {
+                return Err(RuntimeError::type_mismatch("map", value));
+            }
+            [Value::String(_), value] | [Value::String(_), value, _] | [value, ..]
+                if (2..=3).contains(&args.len()) =>
+            {
+                return Err(RuntimeError::type_mismatch("string", value));
+            }
+            _ => return Err(arity("http-post-async", "2 or 3", args.len())),
+        };
+
+        let exchange = exchange("POST", url, body, headers)?;
+        state.borrow_mut().start(Operation::Http(exchange))
+    });
+
+    let state = event_loop;
+    // syntax: (tcp-recv-async <stream> [max-bytes])
+    interpreter.register_fn("tcp-recv-async", move |args| {
+        let (handle, size) = match args {
+            [Value::Handle(handle)] if handle.kind == "tcp-stream" => (handle, 4096),
+            [Value::Handle(handle), Value::Int(size)]
+                if handle.kind == "tcp-stream" && *size > 0 =>
+            {
+                (handle, *size as usize)
+            }
+            [Value::Handle(handle), value] if handle.kind == "tcp-stream" => {
+                return Err(RuntimeError::type_mismatch("positive int", value));
+            }
+            [value] | [value, _] => return Err(RuntimeError::type_mismatch("tcp-stream", value)),
+            _ => return Err(arity("tcp-recv-async", "1 or 2", args.len())),
+        };
+
+        let original = handle
+            .with(|stream: &mut std::net::TcpStream| stream.try_clone())
+            .ok_or_else(|| {
+                RuntimeError::with_code(
+                    ErrorCode::InvalidArgument,
+                    "Cannot use a closed tcp-stream",
+                )
+            })?
+            .map_err(|err| io_error("receive", err))?;
+
+        let stream = original
+            .try_clone()
+            .map_err(|err| io_error("receive", err))?;
+        stream
+            .set_nonblocking(true)
+            .map_err(|err| io_error("receive", err))?;
+
+        state.borrow_mut().start(Operation::Recv {
+            stream: TcpStream::from_std(stream),
+            original,
+            size,
+        })
+    });
+}
+
+fn await_task(
+    interpreter: &mut Interpreter,
+    event_loop: &RefCell<EventLoop>,
+    value: &Value,
+) -> Result<Value, RuntimeError> {
+    let handle = match value {
+        Value::Handle(handle) if handle.kind == "task" => handle,
+        value => return Err(RuntimeError::type_mismatch("task", value)),
+    };
+
+    let task = handle
+        .with(|task: &mut Task| std::mem::replace(task, Task::Running))
+        .ok_or_else(|| {
+            RuntimeError::with_code(ErrorCode::InvalidArgument, "Cannot await a closed task")
+        })?;
+
+    let result = match task {
+        Task::Deferred(body) => interpreter.call(&Value::Function(body), vec![]),
+        Task::Io(token) => event_loop.borrow_mut().run_until(token),
+        Task::Done(result) => result,
+        Task::Running => {
+            return Err(RuntimeError::with_code(
+                ErrorCode::InvalidArgument,
+                "A task cannot await itself",
+            ));
+        }
+    };
+
+    // Later awaits return the same result
+    handle.with(|task: &mut Task| *task = Task::Done(result.clone()));
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_async_tasks() {
+        // Answers only once both requests have arrived, in reverse order, so the requests
+        // must be in flight at the same time
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let mut clients = vec![];
+
+            for stream in listener.incoming().take(2) {
+                let stream = stream.unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                while !matches!(reader.read_line(&mut String::new()), Ok(0..=2)) {}
+
+                clients.push((reader, request_line));
+            }
+
+            for (mut reader, request_line) in clients.into_iter().rev() {
+                let path = request_line.split(' ').nth(1).unwrap().to_string();
+
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                    path.len(),
+                    path
+                )
+                .unwrap();
+            }
+        });
+
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        eval(&format!(
+            "(let tasks (list (http-get-async \"{url}/a\") (http-get-async \"{url}/b\")))"
+        ));
+        eval("(let (first second) (await-all tasks))");
+        assert_eq!(eval("(list first second)").matches("body: /").count(), 2);
+        assert!(eval("(do first)").contains("body: /a"));
+        assert!(eval("(do second)").contains("body: /b"));
+
+        server.join().unwrap();
+
+        eval("(let runs 0)");
+        eval("(let task (async (set runs (add runs 1)) runs))");
+        assert_eq!(eval("(add runs 0)"), "0");
+        assert_eq!(eval("(list (await task) (await task))"), "[1, 1]");
+
+        // Each body runs to completion, across its own awaits, before the next one starts
+        let path = std::env::temp_dir().join(format!("kk-async-log-{}.txt", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        eval("(let log \"\")");
+        eval(&format!(
+            "(let tasks (list (async (set log (format \"${{log}}a<\")) (await (read-file-async {:?})) (set log (format \"${{log}}a>\"))) (async (set log (format \"${{log}}b\")))))",
+            path.display().to_string()
+        ));
+        assert_eq!(eval("(format \"[${log}]\")"), "[]");
+        eval("(await-all tasks)");
+        assert_eq!(eval("(do log)"), "a<a>b");
+        std::fs::remove_file(&path).unwrap();
+
+        let path = std::env::temp_dir().join(format!("kk-async-{}.txt", std::process::id()));
+        std::fs::write(&path, "contents").unwrap();
+        assert_eq!(
+            eval(&format!(
+                "(await (read-file-async {:?}))",
+                path.display().to_string()
+            )),
+            "contents"
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        eval("(let server (tcp-listen \"127.0.0.1:0\"))");
+        eval("(let client (tcp-connect (socket-addr server)))");
+        eval("(let peer (tcp-accept server))");
+        eval("(let reply (tcp-recv-async client))");
+        eval("(tcp-send peer \"pong\")");
+        assert_eq!(eval("(await reply)"), "pong");
+        eval("(tcp-send client \"blocking again\")");
+        assert_eq!(eval("(tcp-recv peer)"), "blocking again");
+
+        let err = interpreter
+            .eval_str("(await (http-get-async \"https://example.com\"))")
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidArgument);
+    }
+}
@@ -0,0 +1,743 @@
+//! Threads and channels. A thread started with `spawn` runs its body in a fresh interpreter
+//! that inherits the eq and div modes, the fuel settings, the enums and protocols declared
+//! so far and the output sinks, and draws from the same fuel budget. Values are copied
+//! between threads, never shared: the variables the body refers to are copied in when it
+//! starts, functions with the bindings they closed over, and its result is copied out by
+//! `thread-join`. Functions the host registered can't be copied, and fail the spawn.
+//! Channels are the only state threads have in common, besides stdout: lines printed by
+//! different threads are written whole, never mixed.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::bigint::BigInt;
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::interpreter::Printed;
+use crate::rational::Rational;
+use crate::sexpr::SExpr;
+use crate::symbol::Symbol;
+use crate::value::{Function, Handle, Param, Value, Variant, Vars};
+use crate::Interpreter;
+
+use super::arity;
+
+/// A thread's result, or the code and message of the error that ended it.
+type ThreadResult = Result<Shared, (ErrorCode, String)>;
+
+/// A value copied to or from another thread.
+enum Shared {
+    Int(i64),
+    BigInt(BigInt),
+    Rational(Rational),
+    Float(f64),
+    String(String),
+    Char(char),
+    Bytes(Vec<u8>),
+    Bool(bool),
+    Keyword(String),
+    List(Vec<Shared>),
+    Map(BTreeMap<String, Shared>),
+    /// A script function, rebuilt from its definition in the receiving interpreter.
+    Function(Box<FunctionSource>),
+    /// A builtin, enum constructor or protocol method, looked up by name in the receiving
+    /// interpreter.
+    Native(String),
+    Channel(Arc<Channel>),
+    Variant {
+        enum_name: String,
+        name: String,
+        fields: Vec<Shared>,
+    },
+    Null,
+    Void,
+}
+
+struct FunctionSource {
+    name: Symbol,
+    /// Name, default and destructuring pattern of each parameter.
+    params: Vec<(Symbol, Option<SExpr>, Option<SExpr>)>,
+    rest: Option<Symbol>,
+    body: Vec<SExpr>,
+    /// The bindings of its defining frame that the function refers to.
+    closure: Vec<(Symbol, Shared)>,
+}
+
+impl FunctionSource {
+    fn new(function: &Function, interpreter: &Interpreter) -> Result<FunctionSource, RuntimeError> {
+        let closure = references(function)
+            .into_iter()
+            .filter_map(|name| Some((name, function.closure.get(&name)?)))
+            .map(|(name, value)| Ok((name, Shared::from_value(value, interpreter)?)))
+            .collect::<Result<_, RuntimeError>>()?;
+
+        Ok(FunctionSource {
+            name: function.name,
+            params: function
+                .params
+                .iter()
+                .map(|param| (param.name, param.default.clone(), param.pattern.clone()))
+                .collect(),
+            rest: function.rest,
+            body: function.body.clone(),
+            closure,
+        })
+    }
+
+    /// The function, seeing the receiving thread's globals, where the globals it refers to
+    /// are defined.
+    fn build(self, interpreter: &Interpreter) -> Result<Function, RuntimeError> {
+        Ok(Function {
+            name: self.name,
+            params: self
+                .params
+                .into_iter()
+                .map(|(name, default, pattern)| Param {
+                    name,
+                    default,
+                    pattern,
+                })
+                .collect(),
+            rest: self.rest,
+            body: self.body,
+            closure: self
+                .closure
+                .into_iter()
+                .map(|(name, value)| Ok((name, value.into_value(interpreter)?)))
+                .collect::<Result<_, RuntimeError>>()?,
+            globals: interpreter.env.vars.clone(),
+            file: None,
+        })
+    }
+}
+
+impl Shared {
+    /// Copies `value` out of `interpreter`.
+    fn from_value(value: &Value, interpreter: &Interpreter) -> Result<Shared, RuntimeError> {
+        Ok(match value {
+            Value::Int(i) => Shared::Int(*i),
+            Value::BigInt(big) => Shared::BigInt((**big).clone()),
+            Value::Rational(rational) => Shared::Rational((**rational).clone()),
+            Value::Float(fl) => Shared::Float(*fl),
+            Value::String(s) => Shared::String(s.to_string()),
+            Value::Char(c) => Shared::Char(*c),
+            Value::Bytes(b) => Shared::Bytes((**b).clone()),
+            Value::Bool(b) => Shared::Bool(*b),
+            Value::Keyword(k) => Shared::Keyword(k.to_string()),
+            Value::List(list) => Shared::List(
+                list.iter()
+                    .map(|value| Shared::from_value(value, interpreter))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Map(map) => Shared::Map(
+                map.iter()
+                    .map(|(key, value)| Ok((key.clone(), Shared::from_value(value, interpreter)?)))
+                    .collect::<Result<_, RuntimeError>>()?,
+            ),
+            Value::Function(function) => {
+                Shared::Function(Box::new(FunctionSource::new(function, interpreter)?))
+            }
+            Value::Native(native) if interpreter.is_inherited(native) => {
+                Shared::Native(native.name.clone())
+            }
+            Value::Native(native) => {
+                return Err(RuntimeError::with_code(
+                    ErrorCode::InvalidArgument,
+                    format!(
+                        "The host function {} cannot be sent to another thread",
+                        native.name
+                    ),
+                ));
+            }
+            Value::Handle(handle) => {
+                match handle.with(|channel: &mut Arc<Channel>| channel.clone()) {
+                    Some(channel) => Shared::Channel(channel),
+                    None => {
+                        return Err(RuntimeError::with_code(
+                            ErrorCode::InvalidArgument,
+                            format!("A {} handle cannot be sent to another thread", handle.kind),
+                        ));
+                    }
+                }
+            }
+            Value::Variant(variant) => Shared::Variant {
+                enum_name: variant.enum_name.clone(),
+                name: variant.name.clone(),
+                fields: variant
+                    .fields
+                    .iter()
+                    .map(|value| Shared::from_value(value, interpreter))
+                    .collect::<Result<_, _>>()?,
+            },
+            Value::Null => Shared::Null,
+            Value::Void => Shared::Void,
+        })
+    }
+
+    /// Rebuilds the value in `interpreter`.
+    fn into_value(self, interpreter: &Interpreter) -> Result<Value, RuntimeError> {
+        Ok(match self {
+            Shared::Int(i) => Value::Int(i),
+            Shared::BigInt(big) => Value::BigInt(Rc::new(big)),
+            Shared::Rational(rational) => Value::Rational(Rc::new(rational)),
+            Shared::Float(fl) => Value::Float(fl),
+            Shared::String(s) => Value::String(s.into()),
+            Shared::Char(c) => Value::Char(c),
+            Shared::Bytes(b) => Value::Bytes(Rc::new(b)),
+            Shared::Bool(b) => Value::Bool(b),
+            Shared::Keyword(k) => Value::Keyword(k.into()),
+            Shared::List(list) => Value::List(
+                list.into_iter()
+                    .map(|value| value.into_value(interpreter))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into(),
+            ),
+            Shared::Map(map) => Value::Map(
+                map.into_iter()
+                    .map(|(key, value)| Ok((key, value.into_value(interpreter)?)))
+                    .collect::<Result<BTreeMap<_, _>, RuntimeError>>()?
+                    .into(),
+            ),
+            Shared::Function(source) => Value::Function(Rc::new(source.build(interpreter)?)),
+            // Enum constructors and protocol methods are globals, builtins natives
+            Shared::Native(name) => {
+                let global = interpreter
+                    .env
+                    .vars
+                    .borrow()
+                    .get(&Symbol::intern(&name))
+                    .cloned();
+
+                match global.or_else(|| interpreter.native(&name)) {
+                    Some(native @ Value::Native(_)) => native,
+                    _ => {
+                        return Err(RuntimeError::with_code(
+                            ErrorCode::InvalidArgument,
+                            format!("{} is not defined in the receiving thread", name),
+                        ));
+                    }
+                }
+            }
+            Shared::Channel(channel) => channel_handle(channel),
+            Shared::Variant {
+                enum_name,
+                name,
+                fields,
+            } => Value::Variant(Rc::new(Variant {
+                enum_name,
+                name,
+                fields: fields
+                    .into_iter()
+                    .map(|value| value.into_value(interpreter))
+                    .collect::<Result<_, _>>()?,
+            })),
+            Shared::Null => Value::Null,
+            Shared::Void => Value::Void,
+        })
+    }
+}
+
+/// A queue any number of threads can send to and receive from.
+#[derive(Default)]
+struct Channel {
+    queue: Mutex<VecDeque<Shared>>,
+    ready: Condvar,
+}
+
+fn channel_handle(channel: Arc<Channel>) -> Value {
+    Value::Handle(Rc::new(Handle::new("channel", channel)))
+}
+
+/// Starts a thread evaluating `body`, for the `spawn` form.
+pub(crate) fn spawn(
+    interpreter: &mut Interpreter,
+    body: Vec<SExpr>,
+) -> Result<Value, RuntimeError> {
+    let captured = capture(interpreter, &body)?;
+    let (eq_mode, div_mode, fuel) = (
+        interpreter.eq_mode,
+        interpreter.div_mode,
+        interpreter.fuel.clone(),
+    );
+    let max_depth = interpreter.max_depth;
+    let slow_builtin_threshold = interpreter.slow_builtin_threshold;
+    let console = interpreter.console.clone();
+    let task = console.next_task();
+    let (output, error_output) = interpreter.output_relays();
+
+    let variants = interpreter
+        .variants
+        .iter()
+        .map(|(name, (enum_name, arity))| (name.clone(), enum_name.clone(), *arity))
+        .collect::<Vec<_>>();
+    let protocols = interpreter
+        .protocols
+        .iter()
+        .map(|(protocol, methods)| (protocol.clone(), methods.clone()))
+        .collect::<Vec<_>>();
+    let methods = interpreter
+        .methods
+        .iter()
+        .map(|(key, function)| Ok((key.clone(), FunctionSource::new(function, interpreter)?)))
+        .collect::<Result<Vec<_>, RuntimeError>>()?;
+
+    let thread = std::thread::Builder::new()
+        .stack_size(Interpreter::stack_size(max_depth))
+        .spawn(move || -> ThreadResult {
+            let mut interpreter = Interpreter::new();
+            interpreter.eq_mode = eq_mode;
+            interpreter.div_mode = div_mode;
+            interpreter.fuel = fuel;
+            interpreter.set_max_depth(max_depth);
+            interpreter.set_slow_builtin_threshold(slow_builtin_threshold);
+            interpreter.console = console;
+            interpreter.task = task;
+
+            if let Some(output) = output {
+                interpreter.set_output(move |text| {
+                    output.send(Printed::Output(text.to_string())).ok();
+                });
+            }
+
+            if let Some(output) = error_output {
+                interpreter.set_error_output(move |text| {
+                    output.send(Printed::Error(text.to_string())).ok();
+                });
+            }
+
+            let mut enums = HashSet::new();
+
+            for (name, enum_name, arity) in variants {
+                interpreter.define_variant(&enum_name, &name, arity);
+                enums.insert(enum_name);
+            }
+
+            for enum_name in enums {
+                interpreter.define_enum(&enum_name);
+            }
+
+            for (protocol, methods) in protocols {
+                interpreter.define_protocol(&protocol, methods);
+            }
+
+            let failed = |err: RuntimeError| (err.code(), err.to_string());
+
+            for (key, source) in methods {
+                let function = Rc::new(source.build(&interpreter).map_err(failed)?);
+                interpreter.methods.insert(key, function);
+            }
+
+            for (name, value) in captured.globals {
+                let value = value.into_value(&interpreter).map_err(failed)?;
+                interpreter.env.vars.borrow_mut().insert(name, value);
+            }
+
+            if let Some(locals) = captured.locals {
+                let frame = locals
+                    .into_iter()
+                    .map(|(name, value)| Ok((name, value.into_value(&interpreter)?)))
+                    .collect::<Result<Vars, RuntimeError>>()
+                    .map_err(failed)?;
+
+                interpreter.env.frames.push(frame);
+            }
+
+            let mut result = Value::Void;
+
+            for sexpr in &body {
+                result = interpreter.eval(sexpr).map_err(failed)?;
+            }
+
+            Shared::from_value(&result, &interpreter).map_err(failed)
+        })
+        .map_err(|err| RuntimeError::with_code(ErrorCode::Io, format!("Cannot spawn: {}", err)))?;
+
+    Ok(Value::Handle(Rc::new(Handle::new("thread", Some(thread)))))
+}
+
+/// The variables a thread starts with.
+#[derive(Default)]
+struct Captured {
+    /// The globals its body, its protocol implementations and the functions among its
+    /// variables refer to.
+    globals: Vec<(Symbol, Shared)>,
+    /// The bindings its body refers to in the frame of the function that spawned it, if any.
+    locals: Option<Vec<(Symbol, Shared)>>,
+}
+
+/// Copies the variables `body` and the protocol implementations refer to, and those the
+/// functions among them refer to. A function keeps the bindings of its defining frame, and
+/// only the names it finds in the globals of its module become globals of the thread.
+fn capture(interpreter: &Interpreter, body: &[SExpr]) -> Result<Captured, RuntimeError> {
+    let share = |name: Symbol, value: &Value| {
+        Shared::from_value(value, interpreter).map_err(|err| {
+            RuntimeError::with_code(err.code(), format!("Cannot spawn with {}: {}", name, err))
+        })
+    };
+
+    let mut captured = Captured::default();
+    let mut functions = interpreter.methods.values().cloned().collect::<Vec<_>>();
+    let mut pending = vec![];
+
+    let names = atoms(body).into_iter().collect::<HashSet<_>>();
+
+    match interpreter.env.frames.last() {
+        Some(frame) => {
+            let mut locals = vec![];
+
+            for name in names {
+                match frame.get(&name) {
+                    Some(value) => {
+                        functions_in(value, &mut functions);
+                        locals.push((name, share(name, value)?));
+                    }
+                    None => pending.push((name, interpreter.env.vars.clone())),
+                }
+            }
+
+            captured.locals = Some(locals);
+        }
+        None => pending.extend(
+            names
+                .into_iter()
+                .map(|name| (name, interpreter.env.vars.clone())),
+        ),
+    }
+
+    let mut scanned = HashSet::new();
+    let mut seen = HashSet::new();
+
+    loop {
+        while let Some(function) = functions.pop() {
+            if !scanned.insert(Rc::as_ptr(&function)) {
+                continue;
+            }
+
+            for name in references(&function) {
+                match function.closure.get(&name) {
+                    Some(value) => functions_in(value, &mut functions),
+                    None => pending.push((name, function.globals.clone())),
+                }
+            }
+        }
+
+        let Some((name, globals)) = pending.pop() else {
+            break;
+        };
+
+        if !seen.insert(name) {
+            continue;
+        }
+
+        let value = globals.borrow().get(&name).cloned();
+
+        // Builtins and undefined names are resolved by the new interpreter, which lacks
+        // the functions registered by the host
+        let value = value.or_else(|| {
+            interpreter.native(name.as_str()).filter(
+                |value| matches!(value, Value::Native(native) if !interpreter.is_inherited(native)),
+            )
+        });
+
+        let Some(value) = value else {
+            continue;
+        };
+
+        functions_in(&value, &mut functions);
+        captured.globals.push((name, share(name, &value)?));
+    }
+
+    Ok(captured)
+}
+
+/// Collects the functions in `value`, including those in its lists, maps and variants.
+fn functions_in(value: &Value, functions: &mut Vec<Rc<Function>>) {
+    match value {
+        Value::Function(function) => functions.push(function.clone()),
+        Value::List(list) => list.iter().for_each(|value| functions_in(value, functions)),
+        Value::Map(map) => map
+            .values()
+            .for_each(|value| functions_in(value, functions)),
+        Value::Variant(variant) => variant
+            .fields
+            .iter()
+            .for_each(|value| functions_in(value, functions)),
+        _ => {}
+    }
+}
+
+/// The names in the parameters and body of `function`.
+fn references(function: &Function) -> HashSet<Symbol> {
+    let sources = function
+        .params
+        .iter()
+        .flat_map(|param| param.default.iter().chain(&param.pattern))
+        .chain(&function.body)
+        .cloned()
+        .collect::<Vec<SExpr>>();
+
+    atoms(&sources).into_iter().collect()
+}
+
+fn atoms(sexprs: &[SExpr]) -> Vec<Symbol> {
+    let mut atoms = vec![];
+    let mut pending = sexprs.iter().collect::<Vec<&SExpr>>();
+
+    while let Some(sexpr) = pending.pop() {
+        match sexpr {
+            SExpr::Atom(atom, _) => atoms.push(*atom),
+            SExpr::List(list, _) => pending.extend(list),
+            SExpr::Keyword(..) | SExpr::String(..) => {}
+        }
+    }
+
+    atoms
+}
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    // syntax: (thread-join <thread>), waits for the thread and returns the value of its body
+    interpreter.register_native("thread-join", |interpreter, args| {
+        let thread = match args {
+            [Value::Handle(handle)] if handle.kind == "thread" => handle
+                .with(|thread: &mut Option<JoinHandle<ThreadResult>>| thread.take())
+                .flatten(),
+            [value] => return Err(RuntimeError::type_mismatch("thread", value)),
+            _ => return Err(arity("thread-join", "1", args.len())),
+        };
+
+        let Some(thread) = thread else {
+            return Err(RuntimeError::with_code(
+                ErrorCode::InvalidArgument,
+                "The thread was already joined",
+            ));
+        };
+
+        let result = thread.join();
+        interpreter.relay_output();
+
+        match result {
+            Ok(Ok(result)) => result.into_value(interpreter),
+            Ok(Err((code, message))) => Err(RuntimeError::with_code(
+                code,
+                format!("Thread failed: {}", message),
+            )),
+            Err(_) => Err(RuntimeError::new("Thread panicked")),
+        }
+    });
+
+    interpreter.register_fn("chan", |args| match args {
+        [] => Ok(channel_handle(Arc::default())),
+        _ => Err(arity("chan", "0", args.len())),
+    });
+
+    // syntax: (send <channel> <value>)
+    interpreter.register_native("send", |interpreter, args| match args {
+        [channel, value] => {
+            let channel = channel_of(channel)?;
+            let value = Shared::from_value(value, interpreter)?;
+
+            channel.queue.lock().unwrap().push_back(value);
+            channel.ready.notify_one();
+
+            Ok(Value::Void)
+        }
+        _ => Err(arity("send", "2", args.len())),
+    });
+
+    // syntax: (recv <channel> [timeout-ms]), null if the timeout passes first
+    interpreter.register_native("recv", |interpreter, args| {
+        let (channel, timeout) = match args {
+            [channel] => (channel_of(channel)?, None),
+            [channel, Value::Int(ms)] if *ms >= 0 => (
+                channel_of(channel)?,
+                Some(Duration::from_millis(*ms as u64)),
+            ),
+            [_, value] => return Err(RuntimeError::type_mismatch("non-negative int", value)),
+            _ => return Err(arity("recv", "1 or 2", args.len())),
+        };
+
+        let queue = channel.queue.lock().unwrap();
+
+        let mut queue = match timeout {
+            Some(timeout) => {
+                channel
+                    .ready
+                    .wait_timeout_while(queue, timeout, |queue| queue.is_empty())
+                    .unwrap()
+                    .0
+            }
+            None => channel
+                .ready
+                .wait_while(queue, |queue| queue.is_empty())
+                .unwrap(),
+        };
+
+        match queue.pop_front() {
+            Some(value) => value.into_value(interpreter),
+            None => Ok(Value::Null),
+        }
+    });
+}
+
+fn channel_of(value: &Value) -> Result<Arc<Channel>, RuntimeError> {
+    match value {
+        Value::Handle(handle) if handle.kind == "channel" => handle
+            .with(|channel: &mut Arc<Channel>| channel.clone())
+            .ok_or_else(|| {
+                RuntimeError::with_code(ErrorCode::InvalidArgument, "Cannot use a closed channel")
+            }),
+        value => Err(RuntimeError::type_mismatch("channel", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Console;
+    use crate::test_util::Buffer;
+
+    #[test]
+    fn test_threads_and_channels() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        eval("(defn double (x) (add x x))");
+        eval("(let base 20)");
+        assert_eq!(eval("(thread-join (spawn (double (add base 1))))"), "42");
+
+        eval("(let results (chan))");
+        eval(
+            "(let workers (list
+               (spawn (send results (double 1)))
+               (spawn (send results (double 2)))))",
+        );
+        assert_eq!(
+            eval("(let a (recv results)) (let b (recv results)) (add a b)"),
+            "6"
+        );
+        assert_eq!(eval("(recv results 10)"), "null");
+
+        eval("(let failing (spawn (throw \"boom\")))");
+        assert!(interpreter.eval_str("(thread-join failing)").is_err());
+        let err = interpreter.eval_str("(thread-join failing)").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidArgument);
+
+        let err = interpreter
+            .eval_str("(let self (spawn 1)) (spawn (thread-join self))")
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Cannot spawn with self: "));
+    }
+
+    #[test]
+    fn test_thread_inherits_state() {
+        let mut interpreter = Interpreter::new();
+        let printed = Rc::new(std::cell::RefCell::new(String::new()));
+
+        let sink = printed.clone();
+        interpreter.set_output(move |text| sink.borrow_mut().push_str(text));
+        interpreter.set_div_mode(crate::DivMode::Truncate);
+        interpreter.register_fn("host-answer", |_| Ok(Value::Int(42)));
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        eval(
+            "(defenum Shape (Circle r) Dot)
+             (defprotocol Area (area shape))
+             (defn tripled (n) (add n n n))
+             (extend-protocol Area Circle (area (shape) (match shape ((Circle r) (tripled r)))))",
+        );
+        assert_eq!(
+            eval(
+                "(thread-join (spawn
+                   (print \"from the thread\")
+                   (list (area (Circle 2)) (Shape? Dot) (/ 7 2) (map upper (list \"a\")))))"
+            ),
+            "[6, true, 3, [A]]"
+        );
+        assert_eq!(printed.borrow().as_str(), "from the thread\n");
+
+        let err = interpreter.eval_str("(spawn (host-answer))").unwrap_err();
+        assert_eq!(err.code(), ErrorCode::InvalidArgument);
+        assert!(err.to_string().contains("host-answer cannot be sent"));
+
+        interpreter.register_fn("upper", |_| Ok(Value::Null));
+        assert!(interpreter.eval_str("(spawn (upper \"a\"))").is_err());
+        assert!(interpreter.eval_str("(thread-join \"a\")").is_err());
+    }
+
+    #[test]
+    fn test_thread_closures_and_fuel() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        eval(
+            "(defn make (x) (lambda (y) (add x y)))
+             (let f1 (make 1))
+             (let f2 (make 100))
+             (let x 5)",
+        );
+        assert_eq!(
+            eval("(thread-join (spawn (list (f1 0) (f2 0) x)))"),
+            "[1, 100, 5]"
+        );
+        assert_eq!(
+            eval("(defn run (x) (thread-join (spawn (list (f2 x) x)))) (run 7)"),
+            "[107, 7]"
+        );
+
+        interpreter.set_fuel(Some(200));
+        let single = "(thread-join (spawn (count i from 0 to 60 ((add i 1)))))";
+        assert!(interpreter.eval_str(single).is_ok());
+
+        interpreter.set_fuel(Some(200));
+        let err = interpreter
+            .eval_str(
+                "(let threads (list
+                   (spawn (count i from 0 to 60 ((add i 1))))
+                   (spawn (count i from 0 to 60 ((add i 1))))
+                   (spawn (count i from 0 to 60 ((add i 1))))))
+                 (map thread-join threads)",
+            )
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::OutOfFuel);
+        assert_eq!(interpreter.fuel(), Some(0));
+    }
+
+    #[test]
+    fn test_print_from_threads() {
+        let buffer = Buffer::default();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.console = Arc::new(Console::new(Box::new(buffer.clone())));
+        interpreter.set_trace_tasks(true);
+        interpreter.set_input(std::io::Cursor::new("kk\n"));
+
+        interpreter
+            .eval_str(
+                "(let a (spawn (count i from 0 to 200 ((print \"one\")))))
+                 (let b (spawn (count i from 0 to 200 ((print \"two\")))))
+                 (thread-join a) (thread-join b)
+                 (input \"name? \") (print \"hi\")",
+            )
+            .unwrap();
+
+        let output = String::from_utf8(buffer.contents()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 401);
+        assert_eq!(
+            lines.iter().filter(|line| **line == "[task 1] one").count(),
+            200
+        );
+        assert_eq!(
+            lines.iter().filter(|line| **line == "[task 2] two").count(),
+            200
+        );
+        assert_eq!(lines[400], "[task 0] name? hi");
+    }
+}
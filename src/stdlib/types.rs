@@ -0,0 +1,83 @@
+//! Type introspection: `type-of` and one predicate per value type, so scripts can check a
+//! value before an operation that would raise a type mismatch.
+
+use crate::value::Value;
+use crate::Interpreter;
+
+use super::arity;
+
+pub(crate) fn register(interpreter: &mut Interpreter) {
+    // syntax: (type-of <value>)
+    interpreter.register_fn("type-of", |args| match args {
+        [value] => Ok(Value::String(value.type_name().into())),
+        _ => Err(arity("type-of", "1", args.len())),
+    });
+
+    predicate(interpreter, "int?", |value| {
+        matches!(value, Value::Int(_) | Value::BigInt(_))
+    });
+    predicate(interpreter, "float?", |value| {
+        matches!(value, Value::Float(_))
+    });
+    predicate(interpreter, "rational?", |value| {
+        matches!(value, Value::Rational(_))
+    });
+    predicate(interpreter, "number?", |value| {
+        matches!(
+            value,
+            Value::Int(_) | Value::BigInt(_) | Value::Rational(_) | Value::Float(_)
+        )
+    });
+    predicate(interpreter, "string?", |value| {
+        matches!(value, Value::String(_))
+    });
+    predicate(interpreter, "char?", |value| {
+        matches!(value, Value::Char(_))
+    });
+    predicate(interpreter, "bytes?", |value| {
+        matches!(value, Value::Bytes(_))
+    });
+    predicate(interpreter, "bool?", |value| {
+        matches!(value, Value::Bool(_))
+    });
+    predicate(interpreter, "keyword?", |value| {
+        matches!(value, Value::Keyword(_))
+    });
+    predicate(interpreter, "list?", |value| {
+        matches!(value, Value::List(_))
+    });
+    predicate(interpreter, "map?", |value| matches!(value, Value::Map(_)));
+    predicate(interpreter, "function?", |value| {
+        matches!(value, Value::Function(_) | Value::Native(_))
+    });
+    predicate(interpreter, "null?", |value| matches!(value, Value::Null));
+}
+
+/// Registers a one-argument builtin returning whether its argument passes `test`.
+fn predicate(interpreter: &mut Interpreter, name: &'static str, test: fn(&Value) -> bool) {
+    interpreter.register_fn(name, move |args| match args {
+        [value] => Ok(Value::Bool(test(value))),
+        _ => Err(arity(name, "1", args.len())),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Interpreter;
+
+    #[test]
+    fn test_type_of() {
+        let mut interpreter = Interpreter::new();
+
+        let mut eval = |source: &str| interpreter.eval_str(source).unwrap().to_string();
+
+        assert_eq!(
+            eval("(list (type-of 1) (type-of 1.5) (type-of \"s\") (type-of true) (type-of null) (type-of (list)) (type-of (dict)) (type-of print))"),
+            "[int, float, string, bool, null, list, map, function]"
+        );
+        assert_eq!(
+            eval("(list (int? 1) (int? 1.0) (number? 1.0) (string? 1) (list? (list 1)) (null? null) (function? type-of))"),
+            "[true, false, true, false, true, true, true]"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Interned names. The parser turns every atom into a `Symbol`, an index into a string
+//! table shared by all interpreters, so comparing names and hashing variable keys never
+//! touches the characters again.
+//!
+//! Interned strings live until the process exits: a name is only added once, however many
+//! times it is parsed.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Mutex, OnceLock};
+
+use crate::value::NameHasher;
+
+/// An interned name. Two symbols are equal exactly when their names are.
+#[derive(Clone, Copy)]
+pub struct Symbol {
+    id: u32,
+    name: &'static str,
+}
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, u32, BuildHasherDefault<NameHasher>>,
+    names: Vec<&'static str>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+
+    INTERNER.get_or_init(|| {
+        let mut interner = Interner::default();
+
+        // Special forms come first, so that telling them from calls is a comparison
+        for name in crate::interpreter::special_forms() {
+            interner.insert(name);
+        }
+
+        Mutex::new(interner)
+    })
+}
+
+impl Interner {
+    fn insert(&mut self, name: &str) -> Symbol {
+        if let Some(&id) = self.ids.get(name) {
+            return Symbol {
+                id,
+                name: self.names[id as usize],
+            };
+        }
+
+        let name: &'static str = Box::leak(name.into());
+        let id = self.names.len() as u32;
+
+        self.names.push(name);
+        self.ids.insert(name, id);
+
+        Symbol { id, name }
+    }
+}
+
+impl Symbol {
+    /// The symbol named `name`, added to the table if it's new.
+    pub fn intern(name: &str) -> Symbol {
+        interner()
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(name)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.name
+    }
+
+    /// Whether this names a form matched by `eval_form` rather than a function.
+    pub fn is_special_form(&self) -> bool {
+        (self.id as usize) < crate::interpreter::special_forms().len()
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u32(self.id);
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Symbol) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Symbols sort by name, like the strings they replace.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Symbol) -> std::cmp::Ordering {
+        self.name.cmp(other.name)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.name == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.name == *other
+    }
+}
+
+impl PartialEq<String> for Symbol {
+    fn eq(&self, other: &String) -> bool {
+        self.name == other
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.name
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Symbol {
+        Symbol::intern(name)
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(name: &String) -> Symbol {
+        Symbol::intern(name)
+    }
+}
+
+impl From<&Symbol> for Symbol {
+    fn from(symbol: &Symbol) -> Symbol {
+        *symbol
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.name, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let a = Symbol::intern("interned-name");
+        let b = Symbol::from(&"interned-name".to_string());
+
+        assert_eq!(a, b);
+        assert_eq!(a.id, b.id);
+        assert_eq!(a, "interned-name");
+        assert_ne!(a, Symbol::intern("other-name"));
+
+        assert!(Symbol::intern("defn").is_special_form());
+        assert!(!a.is_special_form());
+    }
+}
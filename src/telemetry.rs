@@ -0,0 +1,207 @@
+//! Spans for function calls, emitted through `tracing` when kk is built with the
+//! `telemetry` feature and `Interpreter::set_telemetry(true)` was called. Embedders can
+//! forward them to OpenTelemetry with `tracing-opentelemetry`.
+
+use crate::error::RuntimeError;
+use crate::value::Value;
+
+/// Argument summaries longer than this are shortened.
+#[cfg(feature = "telemetry")]
+const ARGS_SUMMARY_WIDTH: usize = 80;
+
+/// A writer that keeps at most `ARGS_SUMMARY_WIDTH` characters and fails once it is full,
+/// so that formatting a large argument stops early.
+#[cfg(feature = "telemetry")]
+#[derive(Default)]
+struct Summary {
+    text: String,
+    chars: usize,
+    truncated: bool,
+}
+
+#[cfg(feature = "telemetry")]
+impl std::fmt::Write for Summary {
+    fn write_str(&mut self, str: &str) -> std::fmt::Result {
+        for char in str.chars() {
+            if self.chars == ARGS_SUMMARY_WIDTH {
+                self.truncated = true;
+                return Err(std::fmt::Error);
+            }
+
+            self.text.push(char);
+            self.chars += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// The arguments joined by `, `, shortened with `...` to `ARGS_SUMMARY_WIDTH` characters.
+#[cfg(feature = "telemetry")]
+fn summarize(args: &[Value]) -> String {
+    use std::fmt::Write;
+
+    let mut summary = Summary::default();
+
+    for (index, arg) in args.iter().enumerate() {
+        let separator = if index == 0 { "" } else { ", " };
+
+        if write!(summary, "{}{}", separator, arg).is_err() {
+            break;
+        }
+    }
+
+    if summary.truncated {
+        let kept = summary.text.chars().take(ARGS_SUMMARY_WIDTH - 3);
+        kept.collect::<String>() + "..."
+    } else {
+        summary.text
+    }
+}
+
+/// An open span around a call, closed when dropped.
+pub(crate) struct CallSpan {
+    #[cfg(feature = "telemetry")]
+    inner: Option<(tracing::span::EnteredSpan, std::time::Instant)>,
+}
+
+/// Opens a span for a call to `name`. `kind` is `"function"` for script functions and
+/// `"builtin"` for native ones.
+#[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+pub(crate) fn call_span(enabled: bool, kind: &str, name: &str, args: &[Value]) -> CallSpan {
+    #[cfg(feature = "telemetry")]
+    {
+        if !enabled {
+            return CallSpan { inner: None };
+        }
+
+        let summary = summarize(args);
+
+        let span = tracing::info_span!(
+            "kk.call",
+            otel.name = name,
+            kk.kind = kind,
+            kk.function = name,
+            kk.args = summary.as_str(),
+            kk.duration_us = tracing::field::Empty,
+            otel.status_code = tracing::field::Empty,
+            error = tracing::field::Empty,
+        );
+
+        CallSpan {
+            inner: Some((span.entered(), std::time::Instant::now())),
+        }
+    }
+
+    #[cfg(not(feature = "telemetry"))]
+    CallSpan {}
+}
+
+impl CallSpan {
+    /// Records the outcome of the call on the span.
+    #[cfg_attr(not(feature = "telemetry"), allow(unused_variables))]
+    pub(crate) fn record<T>(&self, result: &Result<T, RuntimeError>) {
+        #[cfg(feature = "telemetry")]
+        if let (Some((span, _)), Err(err)) = (&self.inner, result) {
+            span.record("otel.status_code", "ERROR");
+            span.record("error", err.to_string().as_str());
+        }
+    }
+}
+
+#[cfg(feature = "telemetry")]
+impl Drop for CallSpan {
+    fn drop(&mut self) {
+        if let Some((span, start)) = &self.inner {
+            span.record("kk.duration_us", start.elapsed().as_micros() as u64);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "telemetry"))]
+mod tests {
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use super::*;
+    use crate::Interpreter;
+
+    /// Collects the `kk.function` and `kk.kind` fields of every new span.
+    #[derive(Default)]
+    struct Spans(Arc<Mutex<Vec<String>>>);
+
+    impl Visit for Spans {
+        fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "kk.kind" || field.name() == "kk.function" {
+                self.0.lock().unwrap().push(value.to_string());
+            }
+        }
+    }
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            span.record(&mut Spans(self.0.clone()));
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, _span: &Id) {}
+
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn test_call_spans() {
+        let spans = Spans::default();
+        let recorded = spans.0.clone();
+
+        tracing::subscriber::with_default(spans, || {
+            let mut interpreter = Interpreter::new();
+
+            interpreter
+                .eval_str("(defn greet (name) (format \"hi {}\" name)) (greet \"kk\")")
+                .unwrap();
+
+            interpreter.set_telemetry(true);
+
+            interpreter.eval_str("(greet \"kk\")").unwrap();
+        });
+
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            ["function", "greet", "builtin", "format"]
+        );
+    }
+
+    #[test]
+    fn test_args_summary() {
+        assert_eq!(
+            summarize(&[Value::Int(1), Value::String("two".into())]),
+            "1, two"
+        );
+
+        let long = Value::List(Rc::new((0..100_000).map(Value::Int).collect()));
+        let summary = summarize(&[Value::Int(1), long]);
+        assert_eq!(summary.chars().count(), ARGS_SUMMARY_WIDTH);
+        assert!(summary.starts_with("1, [0, 1, 2"));
+        assert!(summary.ends_with("..."));
+
+        let features = Interpreter::new().eval_str("(kk-features)").unwrap();
+        assert!(features.to_string().contains("telemetry"));
+    }
+}
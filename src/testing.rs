@@ -0,0 +1,275 @@
+//! The runner behind `kk test`. Every test runs on its own thread in a fresh interpreter
+//! that evaluates the file again, with its output captured and a temporary directory of
+//! its own, available to the test as `(test-tmpdir)`. The file is evaluated once to find
+//! its tests and once more for every attempt of each, so its top-level side effects, such
+//! as printing or writing files, happen that many times.
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::diagnostics::ErrorCode;
+use crate::error::RuntimeError;
+use crate::value::Value;
+use crate::Interpreter;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestOptions {
+    /// Only run tests whose name contains this.
+    pub filter: Option<String>,
+    /// Run a failing test up to this many more times; a test that passes on a rerun is
+    /// reported as flaky.
+    pub retries: u32,
+    /// Number of tests run at once.
+    pub jobs: usize,
+}
+
+impl Default for TestOptions {
+    fn default() -> Self {
+        TestOptions {
+            filter: None,
+            retries: 0,
+            jobs: std::thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    /// How many times the test ran.
+    pub attempts: u32,
+    /// The error of the last attempt, with its trace, if it failed.
+    pub error: Option<String>,
+    /// Everything the last attempt printed.
+    pub output: String,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+
+    pub fn flaky(&self) -> bool {
+        self.passed() && self.attempts > 1
+    }
+}
+
+/// Runs the tests declared in `path`, returning their results in declaration order.
+/// Fails if the file itself can't be evaluated.
+pub fn run(path: &Path, options: &TestOptions) -> Result<Vec<TestResult>, RuntimeError> {
+    let filename = path.to_string_lossy().to_string();
+
+    let source = std::fs::read_to_string(path).map_err(|err| {
+        RuntimeError::with_code(
+            ErrorCode::Io,
+            format!("Unable to read {}: {}", filename, err),
+        )
+    })?;
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(|_| {});
+    interpreter.eval_source(&source, &filename)?;
+
+    let names = interpreter
+        .test_names()
+        .into_iter()
+        .filter(|name| match &options.filter {
+            Some(filter) => name.contains(filter.as_str()),
+            None => true,
+        })
+        .collect::<Vec<String>>();
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![None; names.len()]);
+
+    // Tests must reach the recursion limit with an error, not overflow the stack
+    let stack_size = Interpreter::stack_size(Interpreter::DEFAULT_MAX_DEPTH);
+
+    std::thread::scope(|scope| -> Result<(), RuntimeError> {
+        for _ in 0..options.jobs.clamp(1, names.len().max(1)) {
+            let worker = || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+
+                let Some(name) = names.get(index) else {
+                    break;
+                };
+
+                let mut attempts = 0;
+
+                let (error, output) = loop {
+                    attempts += 1;
+
+                    let (error, output) = run_one(&source, &filename, name, index, attempts);
+
+                    if error.is_none() || attempts > options.retries {
+                        break (error, output);
+                    }
+                };
+
+                results.lock().unwrap()[index] = Some(TestResult {
+                    name: name.clone(),
+                    attempts,
+                    error,
+                    output,
+                });
+            };
+
+            std::thread::Builder::new()
+                .stack_size(stack_size)
+                .spawn_scoped(scope, worker)
+                .map_err(|err| {
+                    RuntimeError::with_code(
+                        ErrorCode::Io,
+                        format!("Unable to start a test thread: {}", err),
+                    )
+                })?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// Runs one attempt of a test, returning its error and output.
+fn run_one(
+    source: &str,
+    filename: &str,
+    name: &str,
+    index: usize,
+    attempt: u32,
+) -> (Option<String>, String) {
+    let tmpdir = std::env::temp_dir().join(format!(
+        "kk-test-{}-{}-{}",
+        std::process::id(),
+        index,
+        attempt
+    ));
+
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = output.clone();
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_output(move |text| sink.borrow_mut().push_str(text));
+
+    let dir = tmpdir.clone();
+    interpreter.register_fn("test-tmpdir", move |_| test_tmpdir(&dir));
+
+    let result = interpreter
+        .eval_source(source, filename)
+        .and_then(|()| interpreter.run_test(name));
+
+    let error = result.err().map(|err| {
+        let mut message = format!("error[{}]: {}", err.code(), err);
+
+        for frame in interpreter.take_trace() {
+            message += &format!("\n  {}", frame);
+        }
+
+        message
+    });
+
+    let _ = std::fs::remove_dir_all(&tmpdir);
+
+    let output = output.borrow().clone();
+
+    (error, output)
+}
+
+/// The test's temporary directory, created on first use.
+fn test_tmpdir(dir: &PathBuf) -> Result<Value, RuntimeError> {
+    std::fs::create_dir_all(dir).map_err(|err| {
+        RuntimeError::with_code(
+            ErrorCode::Io,
+            format!("Unable to create {}: {}", dir.display(), err),
+        )
+    })?;
+
+    Ok(Value::String(dir.to_string_lossy().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_tests() {
+        let dir = std::env::temp_dir().join(format!("kk-runner-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Fails on its first attempt only, using a marker file outside its temp dir
+        let marker = dir.join("marker").to_string_lossy().replace('\\', "/");
+
+        std::fs::write(
+            dir.join("suite.kk"),
+            format!(
+                "(defn double (x) (add x x))
+                 (deftest \"double\" (print \"checking\") (assert-eq (double 2) 4))
+                 (deftest \"broken\" (assert (eq (double 1) 3) \"1 + 1 is 2\"))
+                 (deftest \"flaky\"
+                   (let seen (file-exists? \"{marker}\"))
+                   (write-file \"{marker}\" \"x\")
+                   (assert seen))
+                 (deftest \"tmpdir\"
+                   (write-file (format \"{{}}/a\" (test-tmpdir)) \"a\"))
+                 (defn down (n) (if (eq n 0) 0 else (add 1 (down (add n -1)))))
+                 (deftest \"deep\" (assert-eq (down 600) 600))
+                 (deftest \"too deep\" (down 100000))",
+                marker = marker
+            ),
+        )
+        .unwrap();
+
+        let options = TestOptions {
+            retries: 1,
+            ..TestOptions::default()
+        };
+
+        let results = run(&dir.join("suite.kk"), &options).unwrap();
+
+        let summary = results
+            .iter()
+            .map(|result| (result.name.as_str(), result.passed(), result.flaky()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            [
+                ("double", true, false),
+                ("broken", false, false),
+                ("flaky", true, true),
+                ("tmpdir", true, false),
+                ("deep", true, false),
+                ("too deep", false, false),
+            ]
+        );
+        assert_eq!(results[0].output, "checking\n");
+        assert!(results[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .starts_with("error[E0021]: Assertion failed: 1 + 1 is 2"));
+        assert!(results[5]
+            .error
+            .as_ref()
+            .unwrap()
+            .starts_with("error[E0022]"));
+
+        let options = TestOptions {
+            filter: Some("doub".to_string()),
+            ..TestOptions::default()
+        };
+
+        assert_eq!(run(&dir.join("suite.kk"), &options).unwrap().len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,28 +1,713 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::bigint::BigInt;
+use crate::error::RuntimeError;
+use crate::parser;
+use crate::rational::Rational;
+use crate::sexpr::SExpr;
+use crate::symbol::Symbol;
+use crate::Interpreter;
+
+/// Variables by name. Every variable access hashes a name, so these maps use the cheap
+/// `NameHasher` rather than the DoS-resistant default.
+pub type Vars = HashMap<Symbol, Value, BuildHasherDefault<NameHasher>>;
+
+/// Global bindings of a module, shared by the functions defined in it.
+pub type Scope = Rc<RefCell<Vars>>;
+
+/// FxHash, a fast non-cryptographic hash well suited to short names.
+#[derive(Default)]
+pub struct NameHasher(u64);
+
+impl Hasher for NameHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0.rotate_left(5) ^ byte as u64).wrapping_mul(0x517c_c1b7_2722_0a95);
+        }
+    }
+
+    /// Symbols hash as their id, in one round.
+    fn write_u32(&mut self, id: u32) {
+        self.0 = (self.0.rotate_left(5) ^ id as u64).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Strings, lists and maps are reference-counted, so cloning a value (as reading a variable
+/// or passing an argument does) never copies its contents. Lists and maps are copied on
+/// write, with `Rc::make_mut`, only when still shared.
 #[derive(Debug, Clone)]
 pub enum Value {
     Int(i64),
+    /// An int outside the `i64` range, see `bigint`.
+    BigInt(Rc<BigInt>),
+    /// A fraction that isn't whole, see `rational`.
+    Rational(Rc<Rational>),
     Float(f64),
-    String(String),
+    String(Rc<str>),
+    /// A single Unicode scalar value, written `#\a`, see `parser::char_literal`.
+    Char(char),
+    /// Binary data, such as a file read with `read-bytes`.
+    Bytes(Rc<Vec<u8>>),
     Bool(bool),
+    Keyword(Rc<str>),
+    List(Rc<Vec<Value>>),
+    Map(Rc<BTreeMap<String, Value>>),
+    Function(Rc<Function>),
+    /// A function implemented in Rust, see `Interpreter::register_fn`.
+    Native(Rc<NativeFunction>),
+    /// An opaque resource such as a socket, see `Handle`.
+    Handle(Rc<Handle>),
+    /// A value built by a `defenum` constructor.
+    Variant(Rc<Variant>),
     Null,
     Void,
 }
 
 impl Value {
-    fn to_string(&self) -> String {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::BigInt(_) => "int",
+            Value::Rational(_) => "rational",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Char(_) => "char",
+            Value::Bytes(_) => "bytes",
+            Value::Bool(_) => "bool",
+            Value::Keyword(_) => "keyword",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Handle(_) => "handle",
+            Value::Variant(_) => "variant",
+            Value::Null => "null",
+            Value::Void => "void",
+        }
+    }
+
+    /// What `for` and the higher-order builtins iterate over: the elements of a list, the
+    /// `(key value)` pairs of a map, the chars of a string or the bytes of bytes as ints.
+    pub(crate) fn elements(&self) -> Option<Vec<Value>> {
+        match self {
+            Value::List(list) => Some(list.to_vec()),
+            Value::Map(map) => Some(
+                map.iter()
+                    .map(|(key, value)| {
+                        let pair = [Value::String(key.as_str().into()), value.clone()];
+                        Value::List(pair.to_vec().into())
+                    })
+                    .collect(),
+            ),
+            Value::String(string) => Some(string.chars().map(Value::Char).collect()),
+            Value::Bytes(bytes) => {
+                Some(bytes.iter().map(|byte| Value::Int(*byte as i64)).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// The number as a float, rounded when it is an int no float represents exactly.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::BigInt(big) => Some(big.to_f64()),
+            Value::Rational(rational) => Some(rational.to_f64()),
+            Value::Float(fl) => Some(*fl),
+            _ => None,
+        }
+    }
+
+    /// The int, of either size, as a `BigInt`.
+    pub fn to_big_int(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(i) => Some(BigInt::from(*i)),
+            Value::BigInt(big) => Some((**big).clone()),
+            _ => None,
+        }
+    }
+
+    /// The int or rational as a `Rational`.
+    pub fn to_rational(&self) -> Option<Rational> {
+        match self {
+            Value::Rational(rational) => Some((**rational).clone()),
+            value => value.to_big_int().map(Rational::from),
+        }
+    }
+
+    /// Orders two values of the same kind: numbers by value (ints and floats together, NaN
+    /// after every other number), strings and keywords by code point, `false` before `true`
+    /// and lists element by element. Other pairs fail with a type mismatch.
+    pub fn compare(&self, other: &Value) -> Result<Ordering, RuntimeError> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Ok(a.cmp(b)),
+            (Value::Float(a), Value::Float(b)) => Ok(compare_floats(*a, *b)),
+            (Value::Int(a), Value::Float(b)) => Ok(compare_int_float(*a, *b)),
+            (Value::Float(a), Value::Int(b)) => Ok(compare_int_float(*b, *a).reverse()),
+            (Value::BigInt(a), Value::BigInt(b)) => Ok(a.cmp(b)),
+            (Value::BigInt(a), Value::Int(b)) => Ok((**a).cmp(&BigInt::from(*b))),
+            (Value::Int(a), Value::BigInt(b)) => Ok(BigInt::from(*a).cmp(b)),
+            (Value::BigInt(a), Value::Float(b)) => Ok(compare_big_float(a, *b)),
+            (Value::Float(a), Value::BigInt(b)) => Ok(compare_big_float(b, *a).reverse()),
+            (Value::Rational(a), Value::Float(b)) => Ok(compare_rational_float(a, *b)),
+            (Value::Float(a), Value::Rational(b)) => Ok(compare_rational_float(b, *a).reverse()),
+            (Value::Rational(_), Value::Int(_) | Value::BigInt(_) | Value::Rational(_))
+            | (Value::Int(_) | Value::BigInt(_), Value::Rational(_)) => {
+                match (self.to_rational(), other.to_rational()) {
+                    (Some(a), Some(b)) => Ok(a.cmp(&b)),
+                    _ => unreachable!("ints and rationals convert to rationals"),
+                }
+            }
+            (Value::String(a), Value::String(b)) | (Value::Keyword(a), Value::Keyword(b)) => {
+                Ok(a.cmp(b))
+            }
+            (Value::Char(a), Value::Char(b)) => Ok(a.cmp(b)),
+            (Value::Bytes(a), Value::Bytes(b)) => Ok(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Ok(a.cmp(b)),
+            (Value::List(a), Value::List(b)) => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    match a.compare(b)? {
+                        Ordering::Equal => {}
+                        ordering => return Ok(ordering),
+                    }
+                }
+
+                Ok(a.len().cmp(&b.len()))
+            }
+            (Value::Int(_) | Value::BigInt(_) | Value::Rational(_) | Value::Float(_), _) => {
+                Err(RuntimeError::type_mismatch("number", other))
+            }
+            (
+                Value::String(_)
+                | Value::Keyword(_)
+                | Value::Char(_)
+                | Value::Bytes(_)
+                | Value::Bool(_)
+                | Value::List(_),
+                _,
+            ) => Err(RuntimeError::type_mismatch(self.type_name(), other)),
+            _ => Err(RuntimeError::type_mismatch("comparable value", self)),
+        }
+    }
+
+    /// The key for this value. A whole float, such as `2.0`, gives the key of the int it
+    /// equals, as the two are `eq`; other floats have no key, since NaN isn't equal to itself
+    /// and rounding would make keys collide. Lists, maps and the other values have none either.
+    pub fn to_key(&self) -> Result<Key, RuntimeError> {
+        match self {
+            Value::Int(i) => Ok(Key::Int(*i)),
+            Value::BigInt(big) => Ok(Key::BigInt(big.clone())),
+            Value::Float(fl) if fl.fract() == 0.0 => match BigInt::from_f64(*fl).map(Value::from) {
+                Some(Value::Int(i)) => Ok(Key::Int(i)),
+                Some(Value::BigInt(big)) => Ok(Key::BigInt(big)),
+                _ => unreachable!("whole floats are ints"),
+            },
+            Value::String(s) => Ok(Key::String(s.clone())),
+            Value::Char(c) => Ok(Key::Char(*c)),
+            Value::Bytes(b) => Ok(Key::Bytes(b.clone())),
+            Value::Bool(b) => Ok(Key::Bool(*b)),
+            Value::Keyword(k) => Ok(Key::Keyword(k.clone())),
+            _ => Err(RuntimeError::type_mismatch(
+                "int, string, char, bytes, bool or keyword key",
+                self,
+            )),
+        }
+    }
+
+    /// Converts the value to JSON. Keywords and chars become strings, bytes arrays of ints
+    /// and void becomes null; functions, handles and variants have no JSON form, nor do
+    /// non-finite floats by default.
+    pub fn to_json(&self) -> Result<serde_json::Value, RuntimeError> {
+        self.to_json_with(NonFinite::Error)
+    }
+
+    /// Like `to_json`, writing NaN and the infinities as `non_finite` says.
+    pub fn to_json_with(&self, non_finite: NonFinite) -> Result<serde_json::Value, RuntimeError> {
+        Ok(match self {
+            Value::Int(i) => serde_json::Value::from(*i),
+            // JSON numbers are floats to most readers, so nothing is lost that they'd keep
+            Value::BigInt(big) => serde_json::Value::from(big.to_f64()),
+            Value::Rational(rational) => serde_json::Value::from(rational.to_f64()),
+            Value::Float(fl) => match (serde_json::Number::from_f64(*fl), non_finite) {
+                (Some(number), _) => serde_json::Value::Number(number),
+                (None, NonFinite::Error) => {
+                    return Err(RuntimeError::type_mismatch("finite float", self))
+                }
+                (None, NonFinite::Null) => serde_json::Value::Null,
+                (None, NonFinite::String) => serde_json::Value::String(
+                    match *fl {
+                        fl if fl.is_nan() => "NaN",
+                        fl if fl > 0.0 => "Infinity",
+                        _ => "-Infinity",
+                    }
+                    .to_string(),
+                ),
+            },
+            Value::String(s) | Value::Keyword(s) => serde_json::Value::String(s.to_string()),
+            Value::Char(c) => serde_json::Value::String(c.to_string()),
+            Value::Bool(b) => serde_json::Value::Bool(*b),
+            Value::List(list) => serde_json::Value::Array(
+                list.iter()
+                    .map(|value| value.to_json_with(non_finite))
+                    .collect::<Result<_, RuntimeError>>()?,
+            ),
+            Value::Map(map) => serde_json::Value::Object(
+                map.iter()
+                    .map(|(key, value)| Ok((key.clone(), value.to_json_with(non_finite)?)))
+                    .collect::<Result<_, RuntimeError>>()?,
+            ),
+            Value::Bytes(bytes) => serde_json::Value::from(bytes.to_vec()),
+            Value::Function(_) | Value::Native(_) | Value::Handle(_) | Value::Variant(_) => {
+                return Err(RuntimeError::type_mismatch("JSON-compatible value", self))
+            }
+            Value::Null | Value::Void => serde_json::Value::Null,
+        })
+    }
+
+    /// Converts JSON to a value. Numbers that fit an `i64` become ints, others floats.
+    pub fn from_json(json: &serde_json::Value) -> Value {
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(*b),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(i) => Value::Int(i),
+                None => Value::Float(number.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Value::String(s.as_str().into()),
+            serde_json::Value::Array(list) => {
+                Value::List(Rc::new(list.iter().map(Value::from_json).collect()))
+            }
+            serde_json::Value::Object(map) => Value::Map(Rc::new(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), Value::from_json(value)))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// The value as kk source that evaluates back to it, for values that have one. Strings
+    /// are quoted, and floats are written with the fewest digits that parse back to the same
+    /// float, keeping a `.0` or exponent so they don't read back as ints.
+    pub fn repr(&self) -> String {
         match self {
-            Value::Int(i) => i.to_string(),
-            Value::Float(f) => f.to_string(),
-            Value::String(s) => s.clone(),
-            Value::Bool(b) => b.to_string(),
-            Value::Null => "null".to_string(),
-            Value::Void => "void".to_string(),
+            // Debug formatting is the shortest round-trip form, and ignores the locale
+            Value::Float(fl) => format!("{:?}", fl),
+            // `$${` keeps a literal `${` from reading back as an interpolation
+            Value::String(s) => format!("\"{}\"", s.replace("${", "$${")),
+            Value::Char(c) => parser::char_atom(*c),
+            Value::Bytes(bytes) => {
+                let items = bytes.iter().map(|byte| format!(" {}", byte));
+                format!("(bytes{})", items.collect::<String>())
+            }
+            Value::Rational(rational) => {
+                format!("(div {} {})", rational.numerator(), rational.denominator())
+            }
+            Value::List(list) => {
+                let items = list.iter().map(|value| format!(" {}", value.repr()));
+                format!("(list{})", items.collect::<String>())
+            }
+            Value::Map(map) => {
+                let entries = map.iter().map(|(key, value)| {
+                    format!(" \"{}\" {}", key.replace("${", "$${"), value.repr())
+                });
+                format!("(dict{})", entries.collect::<String>())
+            }
+            Value::Variant(variant) if !variant.fields.is_empty() => {
+                let fields = variant
+                    .fields
+                    .iter()
+                    .map(|value| format!(" {}", value.repr()));
+                format!("({}{})", variant.name, fields.collect::<String>())
+            }
+            value => value.to_string(),
+        }
+    }
+}
+
+/// Equality as `eq` sees it. Lists, maps and enum variants are equal when their elements
+/// are; functions and handles only to themselves. An int equals a float with exactly its
+/// value, so `1` equals `1.0` but no int equals `0.5` or NaN. Values of other different
+/// types are never equal.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(left), Value::Int(right)) => left == right,
+            (Value::Float(left), Value::Float(right)) => left == right,
+            (Value::Int(int), Value::Float(float)) | (Value::Float(float), Value::Int(int)) => {
+                // The float is then a whole number; 2^63 is the first one out of the int range
+                *int as f64 == *float && *float < i64::MAX as f64 && *float as i64 == *int
+            }
+            (Value::BigInt(left), Value::BigInt(right)) => left == right,
+            (Value::BigInt(big), Value::Float(float))
+            | (Value::Float(float), Value::BigInt(big)) => {
+                !float.is_nan() && compare_big_float(big, *float) == Ordering::Equal
+            }
+            // Rationals are never whole, so they equal no int
+            (Value::Rational(left), Value::Rational(right)) => left == right,
+            (Value::Rational(rational), Value::Float(float))
+            | (Value::Float(float), Value::Rational(rational)) => {
+                !float.is_nan() && compare_rational_float(rational, *float) == Ordering::Equal
+            }
+            (Value::String(left), Value::String(right))
+            | (Value::Keyword(left), Value::Keyword(right)) => left == right,
+            (Value::Char(left), Value::Char(right)) => left == right,
+            (Value::Bytes(left), Value::Bytes(right)) => left == right,
+            (Value::Bool(left), Value::Bool(right)) => left == right,
+            (Value::List(left), Value::List(right)) => left == right,
+            (Value::Map(left), Value::Map(right)) => left == right,
+            (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+            (Value::Native(left), Value::Native(right)) => Rc::ptr_eq(left, right),
+            (Value::Handle(left), Value::Handle(right)) => Rc::ptr_eq(left, right),
+            (Value::Variant(left), Value::Variant(right)) => {
+                left.enum_name == right.enum_name
+                    && left.name == right.name
+                    && left.fields == right.fields
+            }
+            (Value::Null, Value::Null) | (Value::Void, Value::Void) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Orders an int outside the `i64` range against a float exactly.
+fn compare_big_float(big: &BigInt, float: f64) -> Ordering {
+    let Some(whole) = BigInt::from_f64(float) else {
+        return match float {
+            float if float.is_nan() || float > 0.0 => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+    };
+
+    big.cmp(&whole)
+        .then_with(|| compare_floats(float.trunc(), float))
+}
+
+/// Orders a fraction against a float exactly.
+fn compare_rational_float(rational: &Rational, float: f64) -> Ordering {
+    match Rational::from_f64(float) {
+        Some(float) => rational.cmp(&float),
+        None if float == f64::NEG_INFINITY => Ordering::Greater,
+        None => Ordering::Less,
+    }
+}
+
+/// Orders floats by value with NaN last, so sorting never sees an incomparable pair.
+fn compare_floats(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+/// Orders an int against a float exactly, without rounding the int to the nearest float.
+fn compare_int_float(int: i64, float: f64) -> Ordering {
+    // 2^63 is the first whole float out of the int range
+    if float.is_nan() || float >= i64::MAX as f64 {
+        return Ordering::Less;
+    }
+
+    if float < i64::MIN as f64 {
+        return Ordering::Greater;
+    }
+
+    let whole = float.trunc();
+
+    int.cmp(&(whole as i64))
+        .then_with(|| compare_floats(whole, float))
+}
+
+/// A value usable as a map key or set element: it can be hashed and ordered, and two keys
+/// are equal exactly when their values are. See `Value::to_key`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Key {
+    Int(i64),
+    BigInt(Rc<BigInt>),
+    String(Rc<str>),
+    Char(char),
+    Bytes(Rc<Vec<u8>>),
+    Bool(bool),
+    Keyword(Rc<str>),
+}
+
+/// Whole fractions become ints.
+impl From<Rational> for Value {
+    fn from(rational: Rational) -> Value {
+        match rational.is_whole() {
+            true => Value::from(rational.numerator().clone()),
+            false => Value::Rational(Rc::new(rational)),
         }
     }
 }
 
+/// Ints that fit an `i64` become `Value::Int`, others `Value::BigInt`.
+impl From<BigInt> for Value {
+    fn from(big: BigInt) -> Value {
+        match big.to_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::BigInt(Rc::new(big)),
+        }
+    }
+}
+
+impl From<Key> for Value {
+    fn from(key: Key) -> Value {
+        match key {
+            Key::Int(i) => Value::Int(i),
+            Key::BigInt(big) => Value::BigInt(big),
+            Key::String(s) => Value::String(s),
+            Key::Char(c) => Value::Char(c),
+            Key::Bytes(b) => Value::Bytes(b),
+            Key::Bool(b) => Value::Bool(b),
+            Key::Keyword(k) => Value::Keyword(k),
+        }
+    }
+}
+
+/// How `Value::to_json_with` writes NaN and the infinities, which JSON can't represent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonFinite {
+    /// Fail with a type mismatch, the default.
+    Error,
+    Null,
+    /// The strings `"NaN"`, `"Infinity"` and `"-Infinity"`, as JavaScript names them.
+    String,
+}
+
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::BigInt(big) => write!(f, "{}", big),
+            Value::Rational(rational) => write!(f, "{}", rational),
+            Value::Float(fl) => write!(f, "{}", fl),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Char(c) => write!(f, "{}", c),
+            Value::Bytes(bytes) => {
+                write!(f, "<bytes")?;
+
+                for byte in bytes.iter() {
+                    write!(f, " {:02x}", byte)?;
+                }
+
+                write!(f, ">")
+            }
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Keyword(k) => write!(f, ":{}", k),
+            Value::List(list) => {
+                write!(f, "[")?;
+
+                for (i, value) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", value)?;
+                }
+
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                write!(f, "{{")?;
+
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}: {}", key, value)?;
+                }
+
+                write!(f, "}}")
+            }
+            Value::Function(function) => write!(f, "<fn {}>", function.name),
+            Value::Native(native) => write!(f, "<native fn {}>", native.name),
+            Value::Handle(handle) => write!(f, "<handle {}>", handle.kind),
+            Value::Variant(variant) => write!(f, "{}", variant),
+            Value::Null => write!(f, "null"),
+            Value::Void => write!(f, "void"),
+        }
+    }
+}
+
+pub struct Function {
+    pub name: Symbol,
+    pub params: Vec<Param>,
+    pub rest: Option<Symbol>,
+    pub body: Vec<SExpr>,
+    /// Bindings of the frame the function was defined in.
+    pub closure: Vars,
+    /// Globals of the module the function was defined in.
+    pub globals: Scope,
+    /// The file the function was defined in, for error traces.
+    pub file: Option<PathBuf>,
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The module globals usually contain the function itself
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("params", &self.params)
+            .field("rest", &self.rest)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct Param {
+    /// The parameter name, or the source text of `pattern`.
+    pub name: Symbol,
+    pub default: Option<SExpr>,
+    /// A destructuring pattern the argument is unpacked into, as in `(defn f ((:list x y)) ...)`.
+    pub pattern: Option<SExpr>,
+}
+
+/// The Rust side of a native function. It gets the interpreter so that builtins such as
+/// `print` can reach its output.
+pub type NativeFn = dyn Fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError>;
+
+pub struct NativeFunction {
+    pub name: String,
+    pub function: Box<NativeFn>,
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// A value of an enum declared with `defenum`, such as `(Circle 2)`.
+#[derive(Debug)]
+pub struct Variant {
+    /// The enum the variant belongs to, such as `Shape`.
+    pub enum_name: String,
+    pub name: String,
+    pub fields: Vec<Value>,
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.fields.is_empty() {
+            return write!(f, "{}", self.name);
+        }
+
+        write!(f, "({}", self.name)?;
+
+        for field in &self.fields {
+            write!(f, " {}", field)?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+/// A resource owned by a builtin module, such as a socket. Scripts can only pass handles
+/// around; they compare by identity and release the resource when closed or dropped.
+pub struct Handle {
+    /// The kind of resource, such as `tcp-stream`, checked by the builtins that take it.
+    pub kind: &'static str,
+    resource: RefCell<Option<Box<dyn Any>>>,
+}
+
+impl Handle {
+    pub fn new(kind: &'static str, resource: impl Any) -> Handle {
+        Handle {
+            kind,
+            resource: RefCell::new(Some(Box::new(resource))),
+        }
+    }
+
+    /// Calls `f` with the resource, or returns `None` if the handle is closed or holds a
+    /// different type.
+    pub fn with<T: 'static, R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut resource = self.resource.borrow_mut();
+        resource.as_mut()?.downcast_mut().map(f)
+    }
+
+    /// Releases the resource, returning whether it was still open.
+    pub fn close(&self) -> bool {
+        self.resource.borrow_mut().take().is_some()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.resource.borrow().is_none()
+    }
+}
+
+impl std::fmt::Debug for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handle")
+            .field("kind", &self.kind)
+            .field("closed", &self.is_closed())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let keys = [
+            Value::Int(1),
+            Value::Float(1.0),
+            Value::String("1".into()),
+            Value::Keyword("1".into()),
+            Value::Bool(true),
+        ]
+        .iter()
+        .map(|value| value.to_key().unwrap())
+        .collect::<HashSet<_>>();
+
+        assert_eq!(keys.len(), 4);
+        assert_eq!(
+            Value::Float(1e19).to_key().unwrap(),
+            Value::from(BigInt::parse("10000000000000000000").unwrap())
+                .to_key()
+                .unwrap()
+        );
+        assert!(keys.contains(&Key::Int(1)));
+        assert_eq!(
+            Value::from(Key::Keyword("1".into())),
+            Value::Keyword("1".into())
+        );
+
+        for value in [
+            Value::Float(0.5),
+            Value::Float(f64::NAN),
+            Value::Float(f64::INFINITY),
+            Value::List(Rc::new(vec![])),
+            Value::Null,
+        ] {
+            assert!(value.to_key().is_err());
+        }
+    }
+
+    #[test]
+    fn test_repr_round_trip() {
+        let mut interpreter = crate::Interpreter::new();
+        let mut eval = |source: &str| interpreter.eval_str(&format!("(do {})", source)).unwrap();
+
+        for source in [
+            "\"cost: $${x}\"",
+            "(dict \"$${key}\" (list \"$$${x}\" #\\a (div 1 3) 0.1))",
+        ] {
+            let value = eval(source);
+            assert_eq!(eval(&value.repr()), value);
+        }
+
+        assert_eq!(Value::String("cost: ${x}".into()).repr(), "\"cost: $${x}\"");
     }
 }
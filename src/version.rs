@@ -0,0 +1,65 @@
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Checks `VERSION` against a requirement such as `">=0.3"` or `">=0.1, <0.3"`.
+pub fn satisfies(requirement: &str) -> Result<bool, String> {
+    let current = parse(VERSION)?;
+
+    for constraint in requirement.split(',').map(str::trim) {
+        let (op, version) = match constraint.find(|c: char| c.is_ascii_digit()) {
+            Some(index) => constraint.split_at(index),
+            None => {
+                return Err(format!("Invalid version requirement: {}", constraint));
+            }
+        };
+
+        let version = parse(version)?;
+
+        let ok = match op.trim() {
+            ">=" => current >= version,
+            ">" => current > version,
+            "<=" => current <= version,
+            "<" => current < version,
+            "" | "=" | "==" => current == version,
+            op => {
+                return Err(format!("Invalid version operator: {}", op));
+            }
+        };
+
+        if !ok {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn parse(version: &str) -> Result<(u64, u64, u64), String> {
+    let mut parts = version.trim().split('.').map(|part| {
+        part.parse::<u64>()
+            .map_err(|_| format!("Invalid version: {}", version))
+    });
+
+    let major = parts.next().unwrap_or(Ok(0))?;
+    let minor = parts.next().unwrap_or(Ok(0))?;
+    let patch = parts.next().unwrap_or(Ok(0))?;
+
+    if parts.next().is_some() {
+        return Err(format!("Invalid version: {}", version));
+    }
+
+    Ok((major, minor, patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_requirements() {
+        assert_eq!(parse("1.2"), Ok((1, 2, 0)));
+        assert!(satisfies(&format!(">={}", VERSION)).unwrap());
+        assert!(satisfies(">=0.0.1, <100").unwrap());
+        assert!(!satisfies(">=100").unwrap());
+        assert!(satisfies("~1").is_err());
+    }
+}